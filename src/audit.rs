@@ -0,0 +1,155 @@
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::plan::Plan;
+
+/// One line of an `--audit` file: what a run was asked to do, what it read and what it produced
+#[derive(Debug, Serialize)]
+pub struct AuditRecord<'a> {
+    /// When the run started, in RFC 3339 UTC
+    pub timestamp: String,
+    pub version: &'static str,
+    /// The command line as given
+    pub args: Vec<String>,
+    /// The resolved configuration the run actually used
+    pub plan: &'a Plan,
+    pub inputs: Vec<InputDigest>,
+    pub rows_read: u64,
+    pub rows_written: u64,
+    /// `ok`, `failed` if checks failed, `aborted` if the error budget ran out, or the error
+    pub status: String,
+}
+
+/// The SHA-256 of one input and its size in bytes
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InputDigest {
+    /// The input's path, or `-` for stdin
+    pub name: String,
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+impl<'a> AuditRecord<'a> {
+    /// A record for a run of `plan` starting now, with the process's own arguments
+    pub fn new(plan: &'a Plan) -> AuditRecord<'a> {
+        AuditRecord {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            version: env!("CARGO_PKG_VERSION"),
+            args: std::env::args().collect(),
+            plan,
+            inputs: Vec::new(),
+            rows_read: 0,
+            rows_written: 0,
+            status: String::from("ok"),
+        }
+    }
+
+    /// Appends the record to `path` as a single line of JSON, creating the file if needed
+    pub fn append(&self, path: &Path) -> io::Result<()> {
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        // One write per record, so that concurrent runs appending to the same file don't
+        // interleave
+        OpenOptions::new().create(true).append(true).open(path)?.write_all(&line)
+    }
+}
+
+/// Passes reads through while hashing every byte consumed
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<R: BufRead> HashingReader<R> {
+    pub fn new(inner: R) -> HashingReader<R> {
+        HashingReader { inner, hasher: Sha256::new(), bytes: 0 }
+    }
+
+    /// Reads whatever is left, so that the digest covers the whole input, and returns it
+    pub fn finish(mut self, name: &str) -> io::Result<InputDigest> {
+        io::copy(&mut self, &mut io::sink())?;
+        Ok(InputDigest {
+            name: name.to_string(),
+            sha256: format!("{:x}", self.hasher.finalize()),
+            bytes: self.bytes,
+        })
+    }
+}
+
+impl<R: BufRead> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let available = self.fill_buf()?;
+            let n = available.len().min(buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for HashingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // The buffer was just filled, so this doesn't read anything
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.hasher.update(&buf[..amt]);
+        }
+        self.bytes += amt as u64;
+        self.inner.consume(amt);
+    }
+}
+
+/// Hashes all of `reader`
+pub fn digest<R: BufRead>(reader: R, name: &str) -> io::Result<InputDigest> {
+    HashingReader::new(reader).finish(name)
+}
+
+#[cfg(test)]
+mod test_audit {
+    use super::*;
+
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn test_hashing_reader() {
+        let mut reader = HashingReader::new("abc".as_bytes());
+        let mut first = String::new();
+        reader.read_line(&mut first).unwrap();
+        assert_eq!(first, "abc");
+        let res = reader.finish("-").unwrap();
+        assert_eq!(res, InputDigest { name: String::from("-"), sha256: String::from(ABC_SHA256),
+                                      bytes: 3 });
+        // Input left unread still counts
+        let mut reader = HashingReader::new("abc".as_bytes());
+        reader.consume(1);
+        assert_eq!(reader.finish("-").unwrap().sha256, ABC_SHA256);
+    }
+    #[test]
+    fn test_append() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let plan = Plan::default();
+        for rows in [1, 2].iter() {
+            let mut record = AuditRecord::new(&plan);
+            record.rows_read = *rows;
+            record.append(&path).unwrap();
+        }
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path).unwrap().lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["rows_read"], 2);
+        assert_eq!(lines[0]["status"], "ok");
+        assert_eq!(lines[0]["plan"]["offset"], 1);
+    }
+}
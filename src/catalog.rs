@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::dialect::{self, Dialect, Permissive, WithDelimiter};
+use crate::input;
+use crate::line;
+
+/// Where the catalog is kept unless `CCUT_CATALOG` names another file
+pub const DEFAULT_PATH: &str = ".ccut-catalog.json";
+
+/// Extensions of the files `scan` adds, before any `.gz` or `.zst`
+pub const TABLE_EXTENSIONS: &[&str] = &["csv", "tsv", "psv"];
+
+/// The catalog file to use: `CCUT_CATALOG`, or `DEFAULT_PATH` in the current directory
+pub fn default_path() -> PathBuf {
+    env::var_os("CCUT_CATALOG").map(PathBuf::from).unwrap_or_else(|| PathBuf::from(DEFAULT_PATH))
+}
+
+/// What the catalog knows of a file: its header and delimiter, and its size and modification
+/// time when they were read, to tell whether it has changed since
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub delimiter: char,
+    /// The column names, unquoted
+    pub header: Vec<String>,
+    pub len: u64,
+    /// Milliseconds since the Unix epoch
+    pub modified: u64,
+}
+
+impl Entry {
+    /// Reads the header of a file, guessing its delimiter as `--sniff` does
+    pub fn read(path: &Path) -> io::Result<Entry> {
+        let (len, modified) = stamp(path)?;
+        let paths = [path.to_path_buf()];
+        let sample = input::peek(&paths, dialect::SNIFF_BYTES)?;
+        let delimiter = dialect::sniff_delimiter(&String::from_utf8_lossy(&sample)).unwrap_or(',');
+        let (_, mut reader) = input::open(&paths, None)?.pop().expect("one input was opened");
+        let mut first = String::new();
+        reader.read_line(&mut first)?;
+        let dialect = WithDelimiter::new(Box::new(Permissive), delimiter);
+        let header = line::split_line_with(&first, &dialect).iter()
+            .map(|name| dialect.unquote(name.trim()).into_owned())
+            .collect();
+        Ok(Entry { delimiter, header, len, modified })
+    }
+
+    /// The columns whose whole name matches `re`, as `--regex-cols` picks them
+    pub fn matching(&self, re: &Regex) -> Vec<usize> {
+        self.header.iter().enumerate()
+            .filter(|(_, name)| re.is_match(name))
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+/// A local record of the headers and dialects of files, so they can be looked up without
+/// reading the files again. Files are keyed by their canonical path.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Catalog {
+    pub files: BTreeMap<PathBuf, Entry>,
+}
+
+impl Catalog {
+    /// Reads a catalog, or starts an empty one if there's no file yet
+    pub fn load(path: &Path) -> io::Result<Catalog> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Catalog::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the catalog as pretty-printed JSON
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+
+    /// Reads a file's header into the catalog, replacing what it had for it
+    pub fn add(&mut self, path: &Path) -> io::Result<&Entry> {
+        let key = fs::canonicalize(path)?;
+        let entry = Entry::read(&key)?;
+        self.files.insert(key.clone(), entry);
+        Ok(&self.files[&key])
+    }
+
+    /// The entry of a file, if it's in the catalog
+    pub fn get(&self, path: &Path) -> Option<&Entry> {
+        self.files.get(&fs::canonicalize(path).ok()?)
+    }
+
+    /// The entry of a file, if it's in the catalog and hasn't changed since it was added
+    pub fn fresh(&self, path: &Path) -> Option<&Entry> {
+        let entry = self.get(path)?;
+        let stamp = stamp(path).ok()?;
+        if stamp == (entry.len, entry.modified) { Some(entry) } else { None }
+    }
+}
+
+/// The files under `dir` whose names end in one of `TABLE_EXTENSIONS`, compressed or not, in
+/// order of their paths
+pub fn scan(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut res = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if is_table(&path) {
+                res.push(path);
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+/// Whether a file's name ends in one of `TABLE_EXTENSIONS`, then `.gz` or `.zst` if it's
+/// compressed
+fn is_table(path: &Path) -> bool {
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let name = name.strip_suffix(".gz").or_else(|| name.strip_suffix(".zst")).unwrap_or(&name);
+    name.rsplit_once('.').is_some_and(|(_, ext)| TABLE_EXTENSIONS.contains(&ext))
+}
+
+/// A file's size and modification time in milliseconds
+fn stamp(path: &Path) -> io::Result<(u64, u64)> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    Ok((metadata.len(), modified))
+}
+
+#[cfg(test)]
+mod test_catalog {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.tsv");
+        fs::write(&path, "id\t\"first name\"\tage\n1\tal\t3\n").unwrap();
+        let mut catalog = Catalog::default();
+        catalog.add(&path).unwrap();
+        let entry = catalog.fresh(&path).unwrap();
+        assert_eq!(entry.delimiter, '\t');
+        assert_eq!(entry.header, vec!["id", "first name", "age"]);
+        assert_eq!(entry.matching(&Regex::new("^(?:.*name|age)$").unwrap()), vec![1, 2]);
+
+        let saved = dir.path().join("catalog.json");
+        catalog.save(&saved).unwrap();
+        assert_eq!(Catalog::load(&saved).unwrap(), catalog);
+        fs::write(&path, "id\n").unwrap();
+        assert!(catalog.fresh(&path).is_none());
+        assert!(catalog.get(&path).is_some());
+    }
+    #[test]
+    fn test_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        for name in ["b.csv", "a.txt", "sub/c.tsv.gz", "sub/d.csv.bak"].iter() {
+            fs::write(dir.path().join(name), "x\n").unwrap();
+        }
+        assert_eq!(scan(dir.path()).unwrap(),
+                   vec![dir.path().join("b.csv"), dir.path().join("sub/c.tsv.gz")]);
+    }
+    #[test]
+    fn test_missing_catalog_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Catalog::load(&dir.path().join("none.json")).unwrap(), Catalog::default());
+    }
+}
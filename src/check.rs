@@ -0,0 +1,442 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::dialect::Dialect;
+use crate::expr::{self, Expr};
+use crate::key::{Key, KeyKind};
+
+/// Per-row data-quality assertions given with `--check`, e.g. `col3 <= col4`. A row passes a check
+/// only if the expression evaluates to true, so rows where it can't be evaluated at all (missing
+/// or non-numeric fields) count as violations too.
+pub struct Checks {
+    checks: Vec<(String, Expr)>,
+    violations: Vec<usize>,
+}
+
+impl Checks {
+    pub fn new(specs: &[String], offset: usize) -> Checks {
+        Checks {
+            checks: specs.iter().map(|s| (s.clone(), expr::parse_expr(s, offset))).collect(),
+            violations: vec![0; specs.len()],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checks.is_empty()
+    }
+
+    /// Evaluates every check against one record, returning the text of those that failed
+    pub fn check(&mut self, fields: &[&str]) -> Vec<&str> {
+        let totals = expr::Totals::new();
+        let mut failed = Vec::new();
+        for (i, (spec, e)) in self.checks.iter().enumerate() {
+            if !expr::is_true(&e.eval(fields, &totals)) {
+                self.violations[i] += 1;
+                failed.push(spec.as_str());
+            }
+        }
+        failed
+    }
+
+    /// One summary line per check with its violation count
+    pub fn summary(&self) -> Vec<String> {
+        self.checks.iter().zip(self.violations.iter())
+            .map(|((spec, _), n)| {
+                format!("{}: {} violation{}", spec, n, if *n == 1 { "" } else { "s" })
+            })
+            .collect()
+    }
+}
+
+/// Verifies that a column is in non-decreasing order (`--assert-sorted`). Values that can't be
+/// parsed as the requested kind, such as a header row, are skipped and counted separately.
+pub struct SortCheck {
+    col: usize,
+    kind: KeyKind,
+    prev: Option<Key>,
+    out_of_order: usize,
+    unparseable: usize,
+}
+
+impl SortCheck {
+    pub fn new(col: usize, kind: KeyKind) -> SortCheck {
+        SortCheck { col, kind, prev: None, out_of_order: 0, unparseable: 0 }
+    }
+
+    /// Checks the next record, returning a description of the problem if it's out of order
+    pub fn check(&mut self, fields: &[&str]) -> Option<String> {
+        let field = fields.get(self.col).unwrap_or(&"");
+        let key = match Key::parse(self.kind, field) {
+            Some(key) => key,
+            None => {
+                self.unparseable += 1;
+                return None;
+            },
+        };
+        let res = match &self.prev {
+            Some(prev) if key < *prev => {
+                self.out_of_order += 1;
+                Some(format!("out of order: {} after {}", field, key_text(prev)))
+            },
+            _ => None,
+        };
+        // Compare against the latest record even if it was out of order, so a single misplaced
+        // record is reported once rather than every record until the order recovers
+        self.prev = Some(key);
+        res
+    }
+
+    pub fn summary(&self) -> String {
+        format!("sorted: {} out-of-order record{}, {} skipped unparseable value{}",
+                self.out_of_order, if self.out_of_order == 1 { "" } else { "s" },
+                self.unparseable, if self.unparseable == 1 { "" } else { "s" })
+    }
+}
+
+/// Reports missing values in a supposedly contiguous sequence column (`--gaps`), e.g. missing
+/// minutes in a metrics export. `step` is the expected spacing, in seconds for timestamp columns.
+/// Unparseable values are skipped, and going backwards isn't a gap (see `SortCheck` for that).
+pub struct GapCheck {
+    col: usize,
+    kind: KeyKind,
+    step: f64,
+    prev: Option<Key>,
+    gaps: usize,
+    missing: u64,
+}
+
+impl GapCheck {
+    pub fn new(col: usize, kind: KeyKind, step: f64) -> GapCheck {
+        GapCheck { col, kind, step, prev: None, gaps: 0, missing: 0 }
+    }
+
+    /// Checks the next record, returning a description of the gap before it, if any
+    pub fn check(&mut self, fields: &[&str]) -> Option<String> {
+        let key = Key::parse(self.kind, fields.get(self.col).unwrap_or(&""))?;
+        let prev = self.prev.take();
+        let step = self.step;
+        let res = match (&prev, &key) {
+            (Some(Key::Num(a)), Key::Num(b)) => self.gap(*a, *b).map(|n| {
+                format!("gap: missing {} value{} from {} to {}",
+                        n, if n == 1 { "" } else { "s" }, a + step, b - step)
+            }),
+            (Some(Key::Date(a)), Key::Date(b)) => {
+                let secs = (*b - *a).num_milliseconds() as f64 / 1000.0;
+                self.gap(0.0, secs).map(|n| {
+                    let step = chrono::Duration::milliseconds((step * 1000.0) as i64);
+                    format!("gap: missing {} period{} from {} to {}",
+                            n, if n == 1 { "" } else { "s" }, *a + step, *b - step)
+                })
+            },
+            _ => None,
+        };
+        self.prev = Some(key);
+        res
+    }
+
+    /// Number of steps missing between `a` and `b`, if any
+    fn gap(&mut self, a: f64, b: f64) -> Option<u64> {
+        let steps = ((b - a) / self.step).round();
+        if steps <= 1.0 {
+            return None;
+        }
+        let n = steps as u64 - 1;
+        self.gaps += 1;
+        self.missing += n;
+        Some(n)
+    }
+
+    pub fn summary(&self) -> String {
+        format!("gaps: {} gap{}, {} missing",
+                self.gaps, if self.gaps == 1 { "" } else { "s" }, self.missing)
+    }
+}
+
+/// What `--ragged` does with a record that has fewer or more fields than the header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Ragged {
+    /// Add empty fields to short records
+    Pad,
+    /// Leave ragged records out
+    Skip,
+    /// Stop at the first ragged record
+    Error,
+    /// Drop the fields of long records past the header's
+    Truncate,
+}
+
+/// Parses a `--ragged` argument
+pub fn parse_ragged(s: &str) -> Ragged {
+    match s.trim() {
+        "pad" => Ragged::Pad,
+        "skip" => Ragged::Skip,
+        "error" => Ragged::Error,
+        "truncate" => Ragged::Truncate,
+        other => panic!("Invalid ragged policy {}, expected pad, skip, error or truncate", other),
+    }
+}
+
+/// Holds every record to the width of the first (the header, if there is one) as `--ragged`
+/// says. Records ragged the other way from what `Pad` or `Truncate` fix pass as they are.
+pub struct RaggedCheck {
+    policy: Ragged,
+    width: Option<usize>,
+    ragged: u64,
+}
+
+impl RaggedCheck {
+    pub fn new(policy: Ragged) -> RaggedCheck {
+        RaggedCheck { policy, width: None, ragged: 0 }
+    }
+
+    pub fn policy(&self) -> Ragged {
+        self.policy
+    }
+
+    /// The width records are held to, once the first has been seen
+    pub fn width(&self) -> Option<usize> {
+        self.width
+    }
+
+    /// Checks the next record, returning it padded or truncated to the width, or the number of
+    /// fields it has if it should be skipped or is an error
+    pub fn check<'a>(&mut self, line: &'a str, dialect: &dyn Dialect)
+                     -> Result<Cow<'a, str>, usize> {
+        let mut ends = Vec::new();
+        dialect.field_ranges(line, &mut |_, end| ends.push(end));
+        let width = *self.width.get_or_insert(ends.len());
+        if ends.len() == width {
+            return Ok(Cow::Borrowed(line));
+        }
+        self.ragged += 1;
+        match self.policy {
+            Ragged::Pad if ends.len() < width => {
+                let padding = dialect.delimiter().to_string().repeat(width - ends.len());
+                Ok(Cow::Owned(format!("{}{}", line, padding)))
+            },
+            Ragged::Truncate if ends.len() > width => Ok(Cow::Borrowed(&line[..ends[width - 1]])),
+            Ragged::Pad | Ragged::Truncate => Ok(Cow::Borrowed(line)),
+            Ragged::Skip | Ragged::Error => Err(ends.len()),
+        }
+    }
+
+    /// Ragged records seen, whatever was done with them
+    pub fn ragged(&self) -> u64 {
+        self.ragged
+    }
+}
+
+/// How many bad rows (rows failing any check) a run tolerates (`--max-errors`), and how many of
+/// them are reported in detail. Without a limit every bad row is reported and any bad row fails
+/// the run; with one, only the first few are shown and the run fails (immediately) only once the
+/// limit is exceeded.
+pub struct ErrorBudget {
+    max_errors: Option<usize>,
+    examples: usize,
+    bad_rows: usize,
+}
+
+impl ErrorBudget {
+    /// `examples` defaults to 10 when there's a limit, and to unlimited otherwise
+    pub fn new(max_errors: Option<usize>, examples: Option<usize>) -> ErrorBudget {
+        let default_examples = if max_errors.is_some() { 10 } else { usize::MAX };
+        ErrorBudget { max_errors, examples: examples.unwrap_or(default_examples), bad_rows: 0 }
+    }
+
+    /// Records a bad row, returning whether its details should be reported
+    pub fn record(&mut self) -> bool {
+        self.bad_rows += 1;
+        self.bad_rows <= self.examples
+    }
+
+    /// Whether there have been more bad rows than tolerated, so the run should stop now
+    pub fn exceeded(&self) -> bool {
+        self.max_errors.is_some_and(|m| self.bad_rows > m)
+    }
+
+    /// Whether the run as a whole should be considered failed
+    pub fn failed(&self) -> bool {
+        match self.max_errors {
+            Some(_) => self.exceeded(),
+            None => self.bad_rows > 0,
+        }
+    }
+
+    pub fn bad_rows(&self) -> usize {
+        self.bad_rows
+    }
+
+    pub fn max_errors(&self) -> Option<usize> {
+        self.max_errors
+    }
+
+    /// How many bad rows were counted but not reported in detail
+    pub fn unreported(&self) -> usize {
+        self.bad_rows.saturating_sub(self.examples)
+    }
+}
+
+fn key_text(key: &Key) -> String {
+    match key {
+        Key::Text(s) => s.clone(),
+        Key::Num(x) => x.to_string(),
+        Key::Date(d) => d.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test_checks {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let mut checks = Checks::new(&[String::from("col1 <= col2"),
+                                       String::from("col1 * col2 == col3")], 1);
+        assert_eq!(checks.check(&["1", "2", "2"]), Vec::<&str>::new());
+        assert_eq!(checks.check(&["3", "2", "6"]), vec!["col1 <= col2"]);
+        assert_eq!(checks.check(&["3"]), vec!["col1 <= col2", "col1 * col2 == col3"]);
+        assert_eq!(checks.summary(), vec!["col1 <= col2: 2 violations",
+                                          "col1 * col2 == col3: 1 violation"]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_bad_check_fails() {
+        Checks::new(&[String::from("col1 <=")], 1);
+    }
+}
+
+#[cfg(test)]
+mod test_sort_check {
+    use super::*;
+
+    #[test]
+    fn test_sorted() {
+        let mut sc = SortCheck::new(0, KeyKind::Num);
+        for f in ["n", "1", "2", "2", "10"].iter() {
+            assert_eq!(sc.check(&[f]), None);
+        }
+        assert_eq!(sc.summary(), "sorted: 0 out-of-order records, 1 skipped unparseable value");
+    }
+    #[test]
+    fn test_out_of_order() {
+        let mut sc = SortCheck::new(1, KeyKind::Date);
+        assert_eq!(sc.check(&["a", "2020-01-02"]), None);
+        assert_eq!(sc.check(&["b", "2020-01-01"]),
+                   Some(String::from("out of order: 2020-01-01 after 2020-01-02 00:00:00")));
+        assert_eq!(sc.check(&["c", "2020-01-03"]), None);
+        assert_eq!(sc.summary(), "sorted: 1 out-of-order record, 0 skipped unparseable values");
+    }
+    #[test]
+    fn test_text() {
+        let mut sc = SortCheck::new(0, KeyKind::Text);
+        assert_eq!(sc.check(&["9"]), None);
+        assert!(sc.check(&["10"]).is_some());
+    }
+}
+
+#[cfg(test)]
+mod test_gap_check {
+    use super::*;
+
+    #[test]
+    fn test_numeric() {
+        let mut gc = GapCheck::new(0, KeyKind::Num, 1.0);
+        assert_eq!(gc.check(&["id"]), None);
+        assert_eq!(gc.check(&["1"]), None);
+        assert_eq!(gc.check(&["2"]), None);
+        assert_eq!(gc.check(&["5"]), Some(String::from("gap: missing 2 values from 3 to 4")));
+        assert_eq!(gc.check(&["6"]), None);
+        assert_eq!(gc.check(&["8"]), Some(String::from("gap: missing 1 value from 7 to 7")));
+        assert_eq!(gc.summary(), "gaps: 2 gaps, 3 missing");
+    }
+    #[test]
+    fn test_time() {
+        let mut gc = GapCheck::new(0, KeyKind::Date, 60.0);
+        assert_eq!(gc.check(&["2020-01-01 00:00"]), None);
+        assert_eq!(gc.check(&["2020-01-01 00:01"]), None);
+        assert_eq!(gc.check(&["2020-01-01 00:04"]), Some(String::from(
+            "gap: missing 2 periods from 2020-01-01 00:02:00 to 2020-01-01 00:03:00")));
+    }
+    #[test]
+    fn test_backwards_is_not_a_gap() {
+        let mut gc = GapCheck::new(0, KeyKind::Num, 1.0);
+        assert_eq!(gc.check(&["5"]), None);
+        assert_eq!(gc.check(&["1"]), None);
+        assert_eq!(gc.summary(), "gaps: 0 gaps, 0 missing");
+    }
+}
+
+#[cfg(test)]
+mod test_ragged_check {
+    use super::*;
+    use crate::dialect::Permissive;
+
+    #[test]
+    fn test_pad() {
+        let mut rc = RaggedCheck::new(parse_ragged("pad"));
+        assert_eq!(rc.check("a,b,c", &Permissive), Ok(Cow::Borrowed("a,b,c")));
+        assert_eq!(rc.check("1", &Permissive), Ok(Cow::Borrowed("1,,")));
+        assert_eq!(rc.check("1,2,3,4", &Permissive), Ok(Cow::Borrowed("1,2,3,4")));
+        assert_eq!(rc.ragged(), 2);
+    }
+    #[test]
+    fn test_truncate() {
+        let mut rc = RaggedCheck::new(Ragged::Truncate);
+        assert_eq!(rc.check("a,\"b,c\"", &Permissive), Ok(Cow::Borrowed("a,\"b,c\"")));
+        assert_eq!(rc.check("1,\"2,3\",4,5", &Permissive), Ok(Cow::Borrowed("1,\"2,3\"")));
+        assert_eq!(rc.check("1", &Permissive), Ok(Cow::Borrowed("1")));
+        assert_eq!(rc.width(), Some(2));
+    }
+    #[test]
+    fn test_skip() {
+        let mut rc = RaggedCheck::new(Ragged::Skip);
+        assert!(rc.check("a,b", &Permissive).is_ok());
+        assert_eq!(rc.check("1,2,3", &Permissive), Err(3));
+        assert_eq!(rc.check("1", &Permissive), Err(1));
+    }
+    #[test]
+    #[should_panic]
+    fn test_parse_ragged_fails() {
+        parse_ragged("fill");
+    }
+}
+
+#[cfg(test)]
+mod test_error_budget {
+    use super::*;
+
+    #[test]
+    fn test_unlimited() {
+        let mut budget = ErrorBudget::new(None, None);
+        assert!(!budget.failed());
+        for _ in 0..100 {
+            assert!(budget.record());
+        }
+        assert!(!budget.exceeded());
+        assert!(budget.failed());
+        assert_eq!(budget.unreported(), 0);
+    }
+    #[test]
+    fn test_limited() {
+        let mut budget = ErrorBudget::new(Some(3), Some(2));
+        assert!(budget.record());
+        assert!(budget.record());
+        assert!(!budget.record());
+        assert!(!budget.exceeded());
+        assert!(!budget.failed());
+        budget.record();
+        assert!(budget.exceeded());
+        assert!(budget.failed());
+        assert_eq!(budget.bad_rows(), 4);
+        assert_eq!(budget.unreported(), 2);
+    }
+    #[test]
+    fn test_zero_tolerance() {
+        let mut budget = ErrorBudget::new(Some(0), None);
+        budget.record();
+        assert!(budget.exceeded());
+    }
+}
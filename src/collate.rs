@@ -0,0 +1,157 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::expr;
+
+/// How text values are ordered when sorting or grouping
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Collation {
+    /// By the bytes of the UTF-8 text, so `B` comes before `a`
+    #[default]
+    Bytewise,
+    /// Dictionary order: case and accents are ignored (`apple`, `Éclair`, `zebra`), with the
+    /// bytewise order breaking ties
+    Locale,
+    /// Runs of digits compare as numbers, so `file2` comes before `file10`
+    Natural,
+    /// Whole values compare as numbers, so `-5` comes before `1.5` and `10`; values that aren't
+    /// numbers come after them, bytewise
+    Numeric,
+}
+
+impl Collation {
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            Collation::Bytewise => a.cmp(b),
+            Collation::Locale => fold(a).cmp(fold(b)).then_with(|| a.cmp(b)),
+            Collation::Natural => natural_cmp(a, b).then_with(|| a.cmp(b)),
+            Collation::Numeric => {
+                let num = |s: &str| expr::parse_num(s).filter(|x| !x.is_nan());
+                match (num(a), num(b)) {
+                    (Some(x), Some(y)) => x.total_cmp(&y),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                }.then_with(|| a.cmp(b))
+            },
+        }
+    }
+}
+
+/// Parses a `--collate` argument
+pub fn parse_collation(s: &str) -> Collation {
+    match s.trim() {
+        "bytewise" | "bytes" => Collation::Bytewise,
+        "locale" => Collation::Locale,
+        "natural" => Collation::Natural,
+        "numeric" => Collation::Numeric,
+        other => panic!("Invalid collation {}, expected locale, bytewise, natural or numeric",
+                        other),
+    }
+}
+
+/// Folds case for case-insensitive matching, with full Unicode case folding rather than ASCII
+/// lowercasing, so `Straße`, `STRASSE` and `strasse` all fold to the same value
+pub fn fold_case(s: &str) -> Cow<'_, str> {
+    if s.bytes().all(|b| b.is_ascii() && !b.is_ascii_uppercase()) {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(caseless::default_case_fold_str(s))
+    }
+}
+
+/// Lowercases and strips accents, decomposing characters so that `é` compares as `e`
+fn fold(s: &str) -> impl Iterator<Item = char> + '_ {
+    s.nfd().filter(|c| !is_combining_mark(*c)).flat_map(char::to_lowercase)
+}
+
+/// Compares digit runs by their numeric value and everything else character by character
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (na, rest_a) = split_digits(a);
+                let (nb, rest_b) = split_digits(b);
+                // Compare without leading zeros: a longer number is larger, otherwise compare
+                // digit by digit. This works for numbers of any length.
+                let (ta, tb) = (na.trim_start_matches('0'), nb.trim_start_matches('0'));
+                let ord = ta.len().cmp(&tb.len()).then_with(|| ta.cmp(tb));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a = rest_a;
+                b = rest_b;
+            },
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a = &a[x.len_utf8()..];
+                b = &b[y.len_utf8()..];
+            },
+        }
+    }
+}
+
+fn split_digits(s: &str) -> (&str, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+#[cfg(test)]
+mod test_collation {
+    use super::*;
+
+    fn sorted(collation: Collation, values: &[&str]) -> Vec<String> {
+        let mut res: Vec<String> = values.iter().map(|s| s.to_string()).collect();
+        res.sort_by(|a, b| collation.compare(a, b));
+        res
+    }
+
+    #[test]
+    fn test_bytewise() {
+        assert_eq!(sorted(Collation::Bytewise, &["b", "a", "B"]), vec!["B", "a", "b"]);
+    }
+    #[test]
+    fn test_locale() {
+        assert_eq!(sorted(Collation::Locale, &["zebra", "Éclair", "apple", "eclair", "Apple"]),
+                   vec!["Apple", "apple", "eclair", "Éclair", "zebra"]);
+    }
+    #[test]
+    fn test_natural() {
+        assert_eq!(sorted(Collation::Natural, &["file10", "file2", "file1", "file02", "file"]),
+                   vec!["file", "file1", "file02", "file2", "file10"]);
+        assert_eq!(sorted(Collation::Natural, &["v1.10", "v1.9", "v1.9.1"]),
+                   vec!["v1.9", "v1.9.1", "v1.10"]);
+        let (big, bigger) = ("99999999999999999999999", "100000000000000000000000");
+        assert_eq!(Collation::Natural.compare(big, bigger), Ordering::Less);
+    }
+    #[test]
+    fn test_numeric() {
+        assert_eq!(sorted(Collation::Numeric, &["10", "n/a", "-5", "1.5", "", "1e1", "2"]),
+                   vec!["-5", "1.5", "2", "10", "1e1", "", "n/a"]);
+    }
+
+    #[test]
+    fn test_fold_case() {
+        assert_eq!(fold_case("Straße"), fold_case("STRASSE"));
+        assert_eq!(fold_case("ΣΊΣΥΦΟΣ"), fold_case("σίσυφος"));
+        assert_eq!(fold_case("Hello"), "hello");
+        assert!(matches!(fold_case("plain"), Cow::Borrowed("plain")));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_bad_collation_fails() {
+        parse_collation("klingon");
+    }
+}
@@ -0,0 +1,160 @@
+use crate::expr;
+
+/// Running means, variances and covariance of a pair of columns, updated one value at a time as
+/// by Welford's algorithm, so they stay accurate however many rows there are
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Comoment {
+    n: u64,
+    mean_x: f64,
+    mean_y: f64,
+    m2_x: f64,
+    m2_y: f64,
+    c_xy: f64,
+}
+
+impl Comoment {
+    pub fn push(&mut self, x: f64, y: f64) {
+        self.n += 1;
+        let n = self.n as f64;
+        let dx = x - self.mean_x;
+        self.mean_x += dx / n;
+        let dy = y - self.mean_y;
+        self.mean_y += dy / n;
+        self.m2_x += dx * (x - self.mean_x);
+        self.m2_y += dy * (y - self.mean_y);
+        self.c_xy += dx * (y - self.mean_y);
+    }
+
+    /// Pairs of values seen
+    pub fn count(&self) -> u64 {
+        self.n
+    }
+
+    /// Pearson's correlation coefficient, or `None` if either column is constant
+    pub fn pearson(&self) -> Option<f64> {
+        let denom = (self.m2_x * self.m2_y).sqrt();
+        if self.n < 2 || denom == 0.0 { None } else { Some(self.c_xy / denom) }
+    }
+}
+
+/// Pairwise correlations of some columns (`--correlate`). Pearson's coefficients are gathered as
+/// the rows go by; Spearman's need the values ranked, so the numbers of each row are kept until
+/// the end. A pair only counts rows where both of its values are numbers.
+#[derive(Debug, Clone)]
+pub struct Correlations {
+    cols: Vec<usize>,
+    pairs: Vec<Comoment>,
+    rows: Vec<Vec<Option<f64>>>,
+}
+
+/// One pair's results, as returned by `Correlations::results`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairResult {
+    pub a: usize,
+    pub b: usize,
+    pub n: u64,
+    pub pearson: Option<f64>,
+    pub spearman: Option<f64>,
+}
+
+impl Correlations {
+    pub fn new(cols: Vec<usize>) -> Correlations {
+        let pairs = vec![Comoment::default(); cols.len() * cols.len().saturating_sub(1) / 2];
+        Correlations { cols, pairs, rows: Vec::new() }
+    }
+
+    pub fn insert(&mut self, fields: &[&str]) {
+        let values: Vec<Option<f64>> = self.cols.iter()
+            .map(|c| fields.get(*c).and_then(|f| expr::parse_num(f)).filter(|x| x.is_finite()))
+            .collect();
+        for ((i, j), pair) in self.pair_indices().zip(self.pairs.iter_mut()) {
+            if let (Some(x), Some(y)) = (values[i], values[j]) {
+                pair.push(x, y);
+            }
+        }
+        if values.iter().filter(|v| v.is_some()).count() >= 2 {
+            self.rows.push(values);
+        }
+    }
+
+    /// Every pair of columns, in the order given
+    pub fn results(&self) -> Vec<PairResult> {
+        self.pair_indices().zip(self.pairs.iter())
+            .map(|((i, j), pair)| {
+                let (xs, ys): (Vec<f64>, Vec<f64>) = self.rows.iter()
+                    .filter_map(|row| Some((row[i]?, row[j]?)))
+                    .unzip();
+                let mut ranked = Comoment::default();
+                for (x, y) in ranks(&xs).into_iter().zip(ranks(&ys)) {
+                    ranked.push(x, y);
+                }
+                PairResult { a: self.cols[i], b: self.cols[j], n: pair.count(),
+                             pearson: pair.pearson(), spearman: ranked.pearson() }
+            })
+            .collect()
+    }
+
+    fn pair_indices(&self) -> impl Iterator<Item = (usize, usize)> {
+        let n = self.cols.len();
+        (0..n).flat_map(move |i| (i + 1..n).map(move |j| (i, j)))
+    }
+}
+
+/// The rank of each value among all of them, from 1, with tied values all getting the mean of the
+/// ranks they span
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|a, b| values[*a].total_cmp(&values[*b]));
+    let mut res = vec![0.0; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let end = (start..order.len()).find(|k| values[order[*k]] != values[order[start]])
+            .unwrap_or(order.len());
+        let rank = (start + end + 1) as f64 / 2.0;
+        for k in start..end {
+            res[order[k]] = rank;
+        }
+        start = end;
+    }
+    res
+}
+
+#[cfg(test)]
+mod test_correlate {
+    use super::*;
+
+    fn close(a: Option<f64>, b: f64) -> bool {
+        a.is_some_and(|a| (a - b).abs() < 1e-9)
+    }
+
+    #[test]
+    fn test_pearson() {
+        let mut c = Comoment::default();
+        for (x, y) in [(1.0, 2.0), (2.0, 4.0), (3.0, 6.5)].iter() {
+            c.push(*x, *y);
+        }
+        assert!(close(c.pearson(), 0.9979487157886733));
+        let mut constant = Comoment::default();
+        constant.push(1.0, 1.0);
+        constant.push(1.0, 2.0);
+        assert_eq!(constant.pearson(), None);
+    }
+    #[test]
+    fn test_ranks() {
+        assert_eq!(ranks(&[10.0, 30.0, 20.0, 20.0]), vec![1.0, 4.0, 2.5, 2.5]);
+    }
+    #[test]
+    fn test_correlations() {
+        let mut c = Correlations::new(vec![0, 1, 2]);
+        for row in ["1,1,5", "2,4,x", "3,9,3", "4,16,\"2\""].iter() {
+            c.insert(&row.split(',').collect::<Vec<_>>());
+        }
+        let res = c.results();
+        assert_eq!(res.iter().map(|r| (r.a, r.b, r.n)).collect::<Vec<_>>(),
+                   vec![(0, 1, 4), (0, 2, 3), (1, 2, 3)]);
+        // Monotonic but not linear
+        assert!(res[0].pearson.unwrap() < 0.99);
+        assert!(close(res[0].spearman, 1.0));
+        assert!(close(res[1].spearman, -1.0));
+    }
+}
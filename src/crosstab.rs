@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collate;
+use crate::expr;
+
+/// Distinct values of each column `--crosstab-count` gives a row or column of their own by default
+pub const MAX_DISTINCT: usize = 50;
+
+/// Label of the row and column that count the values past the limit
+pub const OTHER: &str = "(other)";
+
+/// `--crosstab-count COL1,COL2`: counts of every combination of the values of two columns
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CrosstabSpec {
+    pub row_col: usize,
+    pub col_col: usize,
+    /// Distinct values of each column counted on their own; the rest are counted together
+    pub max_distinct: usize,
+}
+
+/// The values of one column seen so far, each with its index, up to a limit
+#[derive(Debug, Clone, Default)]
+struct Axis {
+    values: Vec<String>,
+    index: HashMap<String, usize>,
+    other: bool,
+}
+
+impl Axis {
+    /// The index of a value, adding it if there's room, or `max` if there isn't
+    fn index(&mut self, value: &str, max: usize) -> usize {
+        if let Some(i) = self.index.get(value) {
+            return *i;
+        }
+        if self.values.len() >= max {
+            self.other = true;
+            return max;
+        }
+        self.index.insert(value.to_string(), self.values.len());
+        self.values.push(value.to_string());
+        self.values.len() - 1
+    }
+
+    /// The labels of the values, then of the others if there were any
+    fn labels(&self) -> Vec<String> {
+        let other = if self.other { Some(String::from(OTHER)) } else { None };
+        self.values.iter().cloned().chain(other).collect()
+    }
+}
+
+/// A contingency table of two columns: how many rows have each combination of their values.
+/// Values are trimmed and unquoted; only the first `max_distinct` distinct values of each column
+/// get a row or column of their own, so memory stays bounded, and later ones are counted under
+/// `OTHER`.
+#[derive(Debug, Clone)]
+pub struct Crosstab {
+    spec: CrosstabSpec,
+    fold_case: bool,
+    rows: Axis,
+    cols: Axis,
+    counts: HashMap<(usize, usize), u64>,
+}
+
+impl Crosstab {
+    /// With `fold_case`, values that differ only in case are counted together
+    pub fn new(spec: CrosstabSpec, fold_case: bool) -> Crosstab {
+        Crosstab { spec, fold_case, rows: Axis::default(), cols: Axis::default(),
+                   counts: HashMap::new() }
+    }
+
+    pub fn insert(&mut self, fields: &[&str]) {
+        let max = self.spec.max_distinct;
+        let value = |col: usize| {
+            let value = expr::unquote(fields.get(col).unwrap_or(&"").trim());
+            if self.fold_case { collate::fold_case(value).into_owned() } else { value.to_string() }
+        };
+        let (row, col) = (value(self.spec.row_col), value(self.spec.col_col));
+        let key = (self.rows.index(&row, max), self.cols.index(&col, max));
+        *self.counts.entry(key).or_insert(0) += 1;
+    }
+
+    /// The table as rows of cells, unquoted: a header row of the column values, then a row per
+    /// row value with its counts, each followed by a total. Values are in order of their totals,
+    /// largest first, with `OTHER` last. `corner` goes above the row values.
+    pub fn into_rows(self, corner: &str) -> Vec<Vec<String>> {
+        let max = self.spec.max_distinct;
+        let mut row_totals: HashMap<usize, u64> = HashMap::new();
+        let mut col_totals: HashMap<usize, u64> = HashMap::new();
+        for ((r, c), n) in self.counts.iter() {
+            *row_totals.entry(*r).or_insert(0) += n;
+            *col_totals.entry(*c).or_insert(0) += n;
+        }
+        let order = |axis: &Axis, totals: &HashMap<usize, u64>| -> Vec<usize> {
+            let mut order: Vec<usize> = (0..axis.values.len()).collect();
+            order.sort_by_key(|i| std::cmp::Reverse(totals.get(i).copied().unwrap_or(0)));
+            order.extend(axis.other.then_some(max));
+            order
+        };
+        let row_order = order(&self.rows, &row_totals);
+        let col_order = order(&self.cols, &col_totals);
+        let (row_labels, col_labels) = (self.rows.labels(), self.cols.labels());
+        let label = |labels: &[String], i: usize| labels[i.min(labels.len() - 1)].clone();
+        let mut res = vec![std::iter::once(corner.to_string())
+                               .chain(col_order.iter().map(|c| label(&col_labels, *c)))
+                               .chain(std::iter::once(String::from("total")))
+                               .collect::<Vec<String>>()];
+        for r in row_order.iter() {
+            let counts: Vec<u64> = col_order.iter()
+                .map(|c| self.counts.get(&(*r, *c)).copied().unwrap_or(0))
+                .collect();
+            let total: u64 = counts.iter().sum();
+            res.push(std::iter::once(label(&row_labels, *r))
+                         .chain(counts.iter().map(|n| n.to_string()))
+                         .chain(std::iter::once(total.to_string()))
+                         .collect());
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod test_crosstab {
+    use super::*;
+
+    fn table(rows: &[&str], max_distinct: usize, fold_case: bool) -> Vec<Vec<String>> {
+        let spec = CrosstabSpec { row_col: 0, col_col: 2, max_distinct };
+        let mut crosstab = Crosstab::new(spec, fold_case);
+        for row in rows.iter() {
+            crosstab.insert(&row.split(',').collect::<Vec<_>>());
+        }
+        crosstab.into_rows("1/3")
+    }
+
+    fn strings(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter().map(|r| r.iter().map(|s| s.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn test_crosstab() {
+        let rows = ["a,x,ok", "b,x,fail", "\"b\",y,ok", "b,y, ok", "c,z,ok"];
+        assert_eq!(table(&rows, MAX_DISTINCT, false),
+                   strings(&[&["1/3", "ok", "fail", "total"], &["b", "2", "1", "3"],
+                             &["a", "1", "0", "1"], &["c", "1", "0", "1"]]));
+    }
+    #[test]
+    fn test_other() {
+        let rows = ["a,,ok", "b,,fail", "c,,ok", "C,,ok", "d,,ok"];
+        assert_eq!(table(&rows, 2, true),
+                   strings(&[&["1/3", "ok", "fail", "total"], &["a", "1", "0", "1"],
+                             &["b", "0", "1", "1"], &["(other)", "3", "0", "3"]]));
+    }
+}
@@ -0,0 +1,354 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io::BufRead;
+use std::marker::PhantomData;
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+use crate::record::{RecordParser, RecordView};
+
+/// Why a record couldn't be deserialized, or the input couldn't be read
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeError(String);
+
+impl fmt::Display for DeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for DeError {}
+
+impl de::Error for DeError {
+    fn custom<T: fmt::Display>(msg: T) -> DeError {
+        DeError(msg.to_string())
+    }
+}
+
+/// Deserializes a single record. With `headers`, structs and maps are filled by column name;
+/// without, they are filled by position like tuples. Fields missing from a ragged record are
+/// left out, so they can be `Option`s or `#[serde(default)]`.
+pub fn from_record<'a, T: de::Deserialize<'a>>(record: RecordView<'a>,
+                                               headers: Option<&'a [String]>)
+                                               -> Result<T, DeError> {
+    T::deserialize(RecordDeserializer { record, headers })
+}
+
+/// Reads records from a `BufRead` and deserializes each into a `T`, similar to the csv crate's
+/// reader but using ccut's permissive splitting
+pub struct Reader<R> {
+    reader: R,
+    headers: Option<Vec<String>>,
+    parser: RecordParser,
+    line: String,
+    line_no: usize,
+}
+
+impl<R: BufRead> Reader<R> {
+    /// If `has_headers`, the first line names the columns
+    pub fn new(reader: R, has_headers: bool) -> Result<Reader<R>, DeError> {
+        Reader::with_parser(reader, has_headers, RecordParser::new())
+    }
+
+    /// Like `new`, but splitting records with `parser`, e.g. one for another dialect
+    pub fn with_parser(mut reader: R, has_headers: bool, mut parser: RecordParser)
+                       -> Result<Reader<R>, DeError> {
+        let mut line = String::new();
+        let mut line_no = 0;
+        let headers = if has_headers {
+            reader.read_line(&mut line).map_err(|e| DeError(e.to_string()))?;
+            line_no += 1;
+            let record = parser.parse(&line);
+            let headers = (0..record.len())
+                .map(|i| record.unquoted(i).unwrap().into_owned())
+                .collect();
+            Some(headers)
+        } else {
+            None
+        };
+        Ok(Reader { reader, headers, parser, line, line_no })
+    }
+
+    pub fn headers(&self) -> Option<&[String]> {
+        self.headers.as_deref()
+    }
+
+    /// Deserializes the next record, or returns `None` at the end of input. Errors name the line
+    /// they happened on.
+    pub fn next_record<T: DeserializeOwned>(&mut self) -> Option<Result<T, DeError>> {
+        self.line.clear();
+        match self.reader.read_line(&mut self.line) {
+            Ok(0) => return None,
+            Ok(_) => {},
+            Err(error) => return Some(Err(DeError(error.to_string()))),
+        }
+        self.line_no += 1;
+        let record = self.parser.parse(&self.line);
+        let line_no = self.line_no;
+        Some(from_record(record, self.headers.as_deref())
+             .map_err(|e| DeError(format!("line {}: {}", line_no, e))))
+    }
+
+    /// Iterates over the remaining records deserialized into `T`
+    pub fn deserialize<T: DeserializeOwned>(&mut self) -> DeserializeRecords<'_, R, T> {
+        DeserializeRecords { reader: self, _marker: PhantomData }
+    }
+}
+
+/// Iterator returned by `Reader::deserialize`
+pub struct DeserializeRecords<'r, R, T> {
+    reader: &'r mut Reader<R>,
+    _marker: PhantomData<T>,
+}
+
+impl<'r, R: BufRead, T: DeserializeOwned> Iterator for DeserializeRecords<'r, R, T> {
+    type Item = Result<T, DeError>;
+
+    fn next(&mut self) -> Option<Result<T, DeError>> {
+        self.reader.next_record()
+    }
+}
+
+struct RecordDeserializer<'a> {
+    record: RecordView<'a>,
+    headers: Option<&'a [String]>,
+}
+
+impl<'de> de::Deserializer<'de> for RecordDeserializer<'de> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self.headers {
+            Some(headers) => visitor.visit_map(FieldMap { record: self.record, headers, i: 0 }),
+            None => visitor.visit_seq(FieldSeq { record: self.record, i: 0 }),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_seq(FieldSeq { record: self.record, i: 0 })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V)
+                                          -> Result<V::Value, DeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize,
+                                                 visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+                                                   -> Result<V::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Fields of a record with headers, as a map from column name to value
+struct FieldMap<'a> {
+    record: RecordView<'a>,
+    headers: &'a [String],
+    i: usize,
+}
+
+impl<'de> de::MapAccess<'de> for FieldMap<'de> {
+    type Error = DeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K)
+                                              -> Result<Option<K::Value>, DeError> {
+        if self.i >= self.headers.len().min(self.record.len()) {
+            return Ok(None);
+        }
+        seed.deserialize(self.headers[self.i].as_str().into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, DeError> {
+        let field = self.record.unquoted(self.i).unwrap();
+        self.i += 1;
+        seed.deserialize(FieldDeserializer(field))
+            .map_err(|e| DeError(format!("column {}: {}", self.headers[self.i - 1], e)))
+    }
+}
+
+/// Fields of a record in order
+struct FieldSeq<'a> {
+    record: RecordView<'a>,
+    i: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for FieldSeq<'de> {
+    type Error = DeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T)
+                                                  -> Result<Option<T::Value>, DeError> {
+        match self.record.unquoted(self.i) {
+            Some(field) => {
+                self.i += 1;
+                seed.deserialize(FieldDeserializer(field)).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// A single unquoted field. Numbers and booleans are parsed from the text, ignoring surrounding
+/// whitespace; an empty field is `None`.
+struct FieldDeserializer<'a>(Cow<'a, str>);
+
+impl<'a> FieldDeserializer<'a> {
+    fn parse<T: std::str::FromStr>(&self, what: &str) -> Result<T, DeError> {
+        self.0.trim().parse().map_err(|_| DeError(format!("invalid {}: {:?}", what, self.0)))
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident, $what:expr;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+                visitor.$visit(self.parse($what)?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for FieldDeserializer<'de> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self.0 {
+            Cow::Borrowed(s) => visitor.visit_borrowed_str(s),
+            Cow::Owned(s) => visitor.visit_string(s),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8, "integer";
+        deserialize_i16 => visit_i16, "integer";
+        deserialize_i32 => visit_i32, "integer";
+        deserialize_i64 => visit_i64, "integer";
+        deserialize_i128 => visit_i128, "integer";
+        deserialize_u8 => visit_u8, "integer";
+        deserialize_u16 => visit_u16, "integer";
+        deserialize_u32 => visit_u32, "integer";
+        deserialize_u64 => visit_u64, "integer";
+        deserialize_u128 => visit_u128, "integer";
+        deserialize_f32 => visit_f32, "number";
+        deserialize_f64 => visit_f64, "number";
+        deserialize_char => visit_char, "character";
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self.0.trim().to_ascii_lowercase().as_str() {
+            "true" | "t" | "yes" | "y" | "1" => visitor.visit_bool(true),
+            "false" | "f" | "no" | "n" | "0" => visitor.visit_bool(false),
+            _ => Err(DeError(format!("invalid boolean: {:?}", self.0))),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        if self.0.trim().is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+                                                   -> Result<V::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str,
+                                         _variants: &'static [&'static str], visitor: V)
+                                         -> Result<V::Value, DeError> {
+        // Only unit variants can be spelled in a single field
+        visitor.visit_enum(self.0.trim().to_string().into_deserializer())
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+#[cfg(test)]
+mod test_from_record {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Row {
+        name: String,
+        age: u32,
+        score: Option<f64>,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    enum Color { Red, Blue }
+
+    #[test]
+    fn test_headers() {
+        let headers = vec![String::from("age"), String::from("name"), String::from("score")];
+        let mut parser = RecordParser::new();
+        let row: Row = from_record(parser.parse(r#"42,"Doe, Jane",9.5"#), Some(&headers)).unwrap();
+        assert_eq!(row, Row { name: String::from("Doe, Jane"), age: 42, score: Some(9.5) });
+        let row: Row = from_record(parser.parse("7,Bob,"), Some(&headers)).unwrap();
+        assert_eq!(row.score, None);
+        // Ragged records leave out the missing columns
+        let row: Row = from_record(parser.parse("7,Bob"), Some(&headers)).unwrap();
+        assert_eq!(row.score, None);
+    }
+    #[test]
+    fn test_positional() {
+        let mut parser = RecordParser::new();
+        let row: (String, u32, bool, Color) =
+            from_record(parser.parse("'x', 3 ,yes,Blue"), None).unwrap();
+        assert_eq!(row, (String::from("x"), 3, true, Color::Blue));
+        let row: Vec<&str> = from_record(parser.parse("a,b"), None).unwrap();
+        assert_eq!(row, vec!["a", "b"]);
+        let row: (Color,) = from_record(parser.parse("Red"), None).unwrap();
+        assert_eq!(row, (Color::Red,));
+    }
+    #[test]
+    fn test_errors() {
+        let headers = vec![String::from("name"), String::from("age")];
+        let mut parser = RecordParser::new();
+        let err = from_record::<Row>(parser.parse("Bob,old"), Some(&headers)).unwrap_err();
+        assert_eq!(err.to_string(), r#"column age: invalid integer: "old""#);
+    }
+}
+
+#[cfg(test)]
+mod test_reader {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Row {
+        id: u32,
+        tag: String,
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let input = "\"tag\",id\na,1\n'b,c',2\nd,x\n";
+        let mut reader = Reader::new(input.as_bytes(), true).unwrap();
+        assert_eq!(reader.headers(), Some(&[String::from("tag"), String::from("id")][..]));
+        let rows: Vec<Result<Row, DeError>> = reader.deserialize().collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], Ok(Row { id: 1, tag: String::from("a") }));
+        assert_eq!(rows[1], Ok(Row { id: 2, tag: String::from("b,c") }));
+        assert_eq!(rows[2].as_ref().unwrap_err().to_string(),
+                   r#"line 4: column id: invalid integer: "x""#);
+    }
+}
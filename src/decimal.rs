@@ -0,0 +1,256 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Most decimals a result keeps. Products add up their factors' decimals, so they're rounded back
+/// to this.
+pub const MAX_SCALE: u32 = 18;
+/// Decimals a quotient gets beyond those of its operands
+pub const DIV_EXTRA_SCALE: u32 = 6;
+
+/// `--decimal`: exact fixed-point arithmetic instead of floating point
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DecimalSpec {
+    /// Decimals to read some (zero-indexed) columns with, rounding their values half-even.
+    /// Other columns keep the decimals they're written with.
+    pub scales: Vec<(usize, u32)>,
+}
+
+impl DecimalSpec {
+    /// Reads a field of column `col` as a decimal
+    pub fn parse_field(&self, col: usize, field: &str) -> Option<Decimal> {
+        let d = Decimal::parse(field)?;
+        match self.scales.iter().find(|(c, _)| *c == col) {
+            Some((_, scale)) => d.rescale(*scale),
+            None => Some(d),
+        }
+    }
+}
+
+/// Parses a `--scale COL=N` argument. `offset` is as in `parse_arg_cols`.
+pub fn parse_scale(spec: &str, offset: usize) -> (usize, u32) {
+    let (col, scale) = spec.split_once('=')
+        .unwrap_or_else(|| panic!("Invalid scale {}, expected COL=DECIMALS", spec));
+    let col = crate::parse_args::parse_arg_col(col, offset);
+    let scale: u32 = scale.trim().parse().expect("Invalid number of decimals");
+    assert!(scale <= MAX_SCALE, "At most {} decimals are supported", MAX_SCALE);
+    (col, scale)
+}
+
+/// A decimal number: `mantissa / 10^scale`, exactly
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+impl Decimal {
+    pub fn new(mantissa: i128, scale: u32) -> Decimal {
+        Decimal { mantissa, scale }
+    }
+
+    pub fn zero() -> Decimal {
+        Decimal::new(0, 0)
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// Parses plain decimal notation such as `-12.50`, ignoring surrounding whitespace and quotes
+    /// as `expr::parse_num` does. Exponents aren't accepted.
+    pub fn parse(s: &str) -> Option<Decimal> {
+        let s = s.trim().trim_matches(|c| c == '"' || c == '\'').trim();
+        let (neg, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        if int.is_empty() && frac.is_empty()
+            || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let mut mantissa: i128 = 0;
+        for b in int.bytes().chain(frac.bytes()) {
+            mantissa = mantissa.checked_mul(10)?.checked_add((b - b'0') as i128)?;
+        }
+        let d = Decimal::new(if neg { -mantissa } else { mantissa }, frac.len() as u32);
+        if d.scale > MAX_SCALE { d.rescale(MAX_SCALE) } else { Some(d) }
+    }
+
+    /// The shortest decimal that reads back as `x`
+    pub fn from_f64(x: f64) -> Option<Decimal> {
+        if x.is_finite() { Decimal::parse(&x.to_string()) } else { None }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.mantissa as f64 / 10f64.powi(self.scale as i32)
+    }
+
+    /// The same number with `scale` decimals, rounding half-even if that drops any
+    pub fn rescale(&self, scale: u32) -> Option<Decimal> {
+        if scale >= self.scale {
+            let factor = 10i128.checked_pow(scale - self.scale)?;
+            return Some(Decimal::new(self.mantissa.checked_mul(factor)?, scale));
+        }
+        let factor = 10i128.checked_pow(self.scale - scale)?;
+        Some(Decimal::new(div_half_even(self.mantissa, factor), scale))
+    }
+
+    /// Both numbers at the larger of their scales
+    fn align(self, other: Decimal) -> Option<(i128, i128, u32)> {
+        let scale = self.scale.max(other.scale);
+        Some((self.rescale(scale)?.mantissa, other.rescale(scale)?.mantissa, scale))
+    }
+
+    pub fn checked_add(self, other: Decimal) -> Option<Decimal> {
+        let (a, b, scale) = self.align(other)?;
+        Some(Decimal::new(a.checked_add(b)?, scale))
+    }
+
+    pub fn checked_sub(self, other: Decimal) -> Option<Decimal> {
+        let (a, b, scale) = self.align(other)?;
+        Some(Decimal::new(a.checked_sub(b)?, scale))
+    }
+
+    pub fn checked_mul(self, other: Decimal) -> Option<Decimal> {
+        let d = Decimal::new(self.mantissa.checked_mul(other.mantissa)?, self.scale + other.scale);
+        if d.scale > MAX_SCALE { d.rescale(MAX_SCALE) } else { Some(d) }
+    }
+
+    /// The quotient with `DIV_EXTRA_SCALE` more decimals than the more precise operand, rounded
+    /// half-even; `None` when dividing by zero
+    pub fn checked_div(self, other: Decimal) -> Option<Decimal> {
+        if other.mantissa == 0 {
+            return None;
+        }
+        let scale = (self.scale.max(other.scale) + DIV_EXTRA_SCALE).min(MAX_SCALE);
+        // self / other = (a / 10^sa) / (b / 10^sb); scaled by 10^scale that's
+        // a * 10^(scale + sb - sa) / b
+        let shift = scale + other.scale;
+        let a = if shift >= self.scale {
+            self.mantissa.checked_mul(10i128.checked_pow(shift - self.scale)?)?
+        } else {
+            div_half_even(self.mantissa, 10i128.checked_pow(self.scale - shift)?)
+        };
+        Some(Decimal::new(div_half_even(a, other.mantissa), scale))
+    }
+}
+
+/// `a / b` rounded to the nearest integer, halves to even
+fn div_half_even(a: i128, b: i128) -> i128 {
+    let (q, r) = (a / b, a % b);
+    let twice = r.unsigned_abs() * 2;
+    let away = match twice.cmp(&b.unsigned_abs()) {
+        Ordering::Greater => true,
+        Ordering::Equal => q % 2 != 0,
+        Ordering::Less => false,
+    };
+    if !away {
+        q
+    } else if (a < 0) != (b < 0) {
+        q - 1
+    } else {
+        q + 1
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.align(*other) {
+            Some((a, b, _)) => a.cmp(&b),
+            // Too large to align exactly, so far apart that floats tell them apart
+            None => self.to_f64().total_cmp(&other.to_f64()),
+        }
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        if scale == 0 {
+            return write!(f, "{}{}", sign, digits);
+        }
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (int, frac) = digits.split_at(digits.len() - scale);
+        write!(f, "{}{}.{}", sign, int, frac)
+    }
+}
+
+#[cfg(test)]
+mod test_decimal {
+    use super::*;
+
+    fn d(s: &str) -> Decimal {
+        Decimal::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        for s in ["0", "12.50", "-0.05", "123456789012345678.9", "7."].iter() {
+            assert_eq!(d(s).to_string(), s.trim_end_matches('.'));
+        }
+        assert_eq!(d(" \"+.5\" ").to_string(), "0.5");
+        assert_eq!(Decimal::parse("1e5"), None);
+        assert_eq!(Decimal::parse("."), None);
+        assert_eq!(Decimal::parse(""), None);
+        assert_eq!(Decimal::from_f64(0.1).unwrap().to_string(), "0.1");
+    }
+    #[test]
+    fn test_exact_sums() {
+        let mut sum = Decimal::zero();
+        for _ in 0..10 {
+            sum = sum.checked_add(d("0.10")).unwrap();
+        }
+        assert_eq!(sum.to_string(), "1.00");
+        assert_eq!(d("0.1").checked_add(d("0.2")).unwrap(), d("0.3"));
+        assert_eq!(d("1.5").checked_sub(d("2.25")).unwrap().to_string(), "-0.75");
+    }
+    #[test]
+    fn test_mul_div() {
+        assert_eq!(d("19.99").checked_mul(d("3")).unwrap().to_string(), "59.97");
+        assert_eq!(d("1").checked_div(d("3")).unwrap().to_string(), "0.333333");
+        assert_eq!(d("2.00").checked_div(d("3")).unwrap().to_string(), "0.66666667");
+        assert_eq!(d("-1").checked_div(d("8")).unwrap().to_string(), "-0.125000");
+        assert_eq!(d("1").checked_div(d("0")), None);
+    }
+    #[test]
+    fn test_rescale() {
+        assert_eq!(d("2.345").rescale(2).unwrap().to_string(), "2.34");
+        assert_eq!(d("2.355").rescale(2).unwrap().to_string(), "2.36");
+        assert_eq!(d("-2.355").rescale(2).unwrap().to_string(), "-2.36");
+        assert_eq!(d("2").rescale(2).unwrap().to_string(), "2.00");
+    }
+    #[test]
+    fn test_overflow() {
+        let big = Decimal::new(i128::MAX, 0);
+        assert_eq!(big.checked_add(d("1")), None);
+        assert_eq!(big.checked_mul(d("2")), None);
+        assert!(big > d("1"));
+    }
+    #[test]
+    fn test_spec() {
+        let spec = DecimalSpec { scales: vec![parse_scale("2=2", 1)] };
+        assert_eq!(spec.parse_field(1, "1.005").unwrap().to_string(), "1.00");
+        assert_eq!(spec.parse_field(0, "1.005").unwrap().to_string(), "1.005");
+        assert_eq!(spec.parse_field(1, "abc"), None);
+    }
+}
@@ -0,0 +1,116 @@
+use std::error::Error;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// How `--errors-format` writes problems found in the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorsFormat {
+    /// A line of text each, as `line 3: check failed: ...`
+    #[default]
+    Text,
+    /// A JSON object each, on a line of its own
+    Json,
+}
+
+/// Parses an `--errors-format` argument
+pub fn parse_errors_format(s: &str) -> ErrorsFormat {
+    match s.trim() {
+        "text" => ErrorsFormat::Text,
+        "json" => ErrorsFormat::Json,
+        other => panic!("Invalid errors format {}, expected text or json", other),
+    }
+}
+
+/// A problem found in the input, or a summary of several, with where it was found as far as
+/// that's known. It's an error too, so one that stops the run can travel in an `io::Error`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    /// What kind of problem it is, e.g. `check-failed`, which stays the same from release to
+    /// release for scripts to match
+    pub code: String,
+    /// The record it's in, from 1
+    pub line: Option<u64>,
+    /// The column it's in, numbered as on the command line
+    pub column: Option<usize>,
+    /// The input the record came from, and the byte offset it starts at there
+    pub file: Option<String>,
+    pub offset: Option<u64>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(code: &str, message: String) -> Diagnostic {
+        Diagnostic { code: code.to_string(), line: None, column: None, file: None, offset: None,
+                     message }
+    }
+
+    pub fn at_line(mut self, line: u64) -> Diagnostic {
+        self.line = Some(line);
+        self
+    }
+
+    pub fn at_column(mut self, column: usize) -> Diagnostic {
+        self.column = Some(column);
+        self
+    }
+
+    pub fn at_offset(mut self, file: &str, offset: u64) -> Diagnostic {
+        self.file = Some(file.to_string());
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn render(&self, format: ErrorsFormat) -> String {
+        match format {
+            ErrorsFormat::Text => self.to_string(),
+            ErrorsFormat::Json => {
+                serde_json::to_string(self).expect("diagnostics always serialize")
+            },
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl Error for Diagnostic {}
+
+#[cfg(test)]
+mod test_diagnostic {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_render() {
+        let diag = Diagnostic::new("cell-overflow", String::from("column 2 is over 8 bytes"))
+            .at_line(3)
+            .at_column(2)
+            .at_offset("a.csv", 40);
+        assert_eq!(diag.render(ErrorsFormat::Text), "line 3: column 2 is over 8 bytes");
+        assert_eq!(diag.render(ErrorsFormat::Json),
+                   r#"{"code":"cell-overflow","line":3,"column":2,"file":"a.csv","offset":40,"#
+                   .to_string() + r#""message":"column 2 is over 8 bytes"}"#);
+        let summary = Diagnostic::new("unreported-rows", String::from("2 more bad rows"));
+        assert_eq!(summary.render(ErrorsFormat::Text), "2 more bad rows");
+    }
+    #[test]
+    fn test_in_io_error() {
+        let diag = Diagnostic::new("ragged", String::from("2 fields, expected 3")).at_line(4);
+        let error = io::Error::new(io::ErrorKind::InvalidData, diag.clone());
+        assert_eq!(error.to_string(), "line 4: 2 fields, expected 3");
+        assert_eq!(error.get_ref().and_then(|e| e.downcast_ref::<Diagnostic>()), Some(&diag));
+    }
+    #[test]
+    #[should_panic]
+    fn test_unknown_format_fails() {
+        parse_errors_format("xml");
+    }
+}
@@ -0,0 +1,792 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// How records are split into fields and how fields are quoted: the delimiter, which characters
+/// quote a field, how quotes and other special characters are escaped, and what ends a record.
+/// Implement this to make the splitter understand a format ccut doesn't ship.
+pub trait Dialect: fmt::Debug + Send + Sync {
+    /// Separates fields within a record
+    fn delimiter(&self) -> char;
+
+    /// Characters that start and end a quoted field, inside which the delimiter is literal. Empty
+    /// if the format has no quoting.
+    fn quotes(&self) -> &[char] {
+        &[]
+    }
+
+    /// Escapes the next character, e.g. `\` in `"a \"b\""`
+    fn escape(&self) -> Option<char> {
+        None
+    }
+
+    /// Whether the escape character also works outside quoted fields, as in PostgreSQL's COPY
+    /// format where a delimiter can be escaped in place
+    fn escape_unquoted(&self) -> bool {
+        false
+    }
+
+    /// Whether a quote is escaped by doubling it, as in RFC 4180's `"a ""b"""`
+    fn doubled_quotes(&self) -> bool {
+        false
+    }
+
+    /// Ends a record on output
+    fn terminator(&self) -> &str {
+        "\n"
+    }
+
+    /// Whether whitespace around a record is ignored
+    fn trim(&self) -> bool {
+        false
+    }
+
+    /// What an escape sequence `\c` stands for. Most dialects just take `c` literally.
+    fn unescape(&self, c: char) -> char {
+        c
+    }
+
+    /// Calls `f` with the byte range of each field within `line`. Fields are left as written,
+    /// quotes and escapes included.
+    fn field_ranges(&self, line: &str, f: &mut dyn FnMut(usize, usize)) {
+        let (base, line) = if self.trim() {
+            (line.len() - line.trim_start().len(), line.trim())
+        } else {
+            (0, line.trim_end_matches(['\n', '\r']))
+        };
+        let rules = Rules {
+            delimiter: self.delimiter(),
+            quotes: self.quotes(),
+            escape: self.escape(),
+            escape_unquoted: self.escape_unquoted(),
+            doubled_quotes: self.doubled_quotes(),
+        };
+        scan(&rules, line, &mut |start, end| f(base + start, base + end));
+    }
+
+    /// The value of a raw field: surrounding quotes removed and escapes resolved. Only allocates
+    /// if there is something to resolve.
+    fn unquote<'a>(&self, field: &'a str) -> Cow<'a, str> {
+        let (quote, inner) = match field.chars().next() {
+            Some(q) if self.quotes().contains(&q) && field.len() >= 2 * q.len_utf8()
+                && field.ends_with(q) => {
+                (Some(q), &field[q.len_utf8()..field.len() - q.len_utf8()])
+            },
+            _ => (None, field),
+        };
+        let escape = self.escape().filter(|_| quote.is_some() || self.escape_unquoted());
+        let doubled = quote.filter(|_| self.doubled_quotes());
+        if !escape.is_some_and(|e| inner.contains(e))
+            && !doubled.is_some_and(|q| inner.contains(q)) {
+            return Cow::Borrowed(inner);
+        }
+        let mut res = String::with_capacity(inner.len());
+        let mut chars = inner.chars().peekable();
+        while let Some(c) = chars.next() {
+            if Some(c) == escape {
+                if let Some(escaped) = chars.next() {
+                    res.push(self.unescape(escaped));
+                    continue;
+                }
+            } else if Some(c) == doubled && chars.peek() == Some(&c) {
+                chars.next();
+            }
+            res.push(c);
+        }
+        Cow::Owned(res)
+    }
+
+    /// Writes a value so that it reads back as a single field with `unquote`: it's quoted with
+    /// the first quote character if it contains anything special, or has its special characters
+    /// escaped if the dialect has no quoting. Quotes within are escaped with the escape character
+    /// if there is one, and doubled otherwise.
+    fn quote<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        let delimiter = self.delimiter();
+        let escape = self.escape();
+        let special = |c: char| {
+            c == delimiter || c == '\n' || c == '\r' || self.quotes().contains(&c)
+                || (Some(c) == escape && self.quotes().is_empty())
+        };
+        if !value.contains(special) {
+            return Cow::Borrowed(value);
+        }
+        match (self.quotes().first(), escape) {
+            (Some(&q), Some(e)) => {
+                let mut res = String::with_capacity(value.len() + 2);
+                res.push(q);
+                for c in value.chars() {
+                    if c == q || c == e {
+                        res.push(e);
+                    }
+                    res.push(c);
+                }
+                res.push(q);
+                Cow::Owned(res)
+            },
+            (Some(&q), None) if self.doubled_quotes() => {
+                Cow::Owned(format!("{}{}{}", q, value.replace(q, &format!("{}{}", q, q)), q))
+            },
+            (Some(&q), None) => Cow::Owned(format!("{}{}{}", q, value, q)),
+            (None, Some(e)) => {
+                let mut res = String::with_capacity(value.len() + 2);
+                for c in value.chars() {
+                    match c {
+                        '\n' => { res.push(e); res.push('n') },
+                        '\r' => { res.push(e); res.push('r') },
+                        '\t' if delimiter == '\t' => { res.push(e); res.push('t') },
+                        c if special(c) => { res.push(e); res.push(c) },
+                        c => res.push(c),
+                    }
+                }
+                Cow::Owned(res)
+            },
+            // Nothing can protect the value; the best that can be done is to keep it on one line
+            (None, None) => Cow::Owned(value.replace(['\n', '\r'], " ")
+                                       .replace(delimiter, " ")),
+        }
+    }
+}
+
+/// What `scan` needs to know of a dialect
+struct Rules<'a> {
+    delimiter: char,
+    quotes: &'a [char],
+    escape: Option<char>,
+    escape_unquoted: bool,
+    doubled_quotes: bool,
+}
+
+/// Where `scan` is within a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State { Normal, Quoted(char), QuoteEnd(char), Escape(Option<char>) }
+
+impl State {
+    /// Whether a record that stops here stops inside a quoted field
+    fn in_quotes(self) -> bool {
+        matches!(self, State::Quoted(_) | State::Escape(Some(_)))
+    }
+}
+
+/// The state after `c`. Outside quotes a delimiter ends the field, and the next starts normally.
+#[inline(always)]
+fn step(rules: &Rules, state: State, c: char) -> State {
+    match state {
+        State::Escape(None) => State::Normal,
+        State::Escape(Some(q)) => State::Quoted(q),
+        State::Quoted(q) if Some(c) == rules.escape && c != q => State::Escape(Some(q)),
+        State::Quoted(q) if c == q && rules.doubled_quotes => State::QuoteEnd(q),
+        State::Quoted(q) if c == q => State::Normal,
+        State::Quoted(q) => State::Quoted(q),
+        // A doubled quote is a literal quote and the field carries on
+        State::QuoteEnd(q) if c == q => State::Quoted(q),
+        State::Normal | State::QuoteEnd(_) => {
+            if c == rules.delimiter {
+                State::Normal
+            } else if rules.quotes.contains(&c) {
+                State::Quoted(c)
+            } else if Some(c) == rules.escape && rules.escape_unquoted {
+                State::Escape(None)
+            } else {
+                State::Normal
+            }
+        },
+    }
+}
+
+/// Calls `f` with the byte range of each field within `line`, returning whether the line ends
+/// inside a quoted field
+fn scan(rules: &Rules, line: &str, f: &mut dyn FnMut(usize, usize)) -> bool {
+    // Without quoting or escapes every delimiter ends a field, and searching for them is much
+    // faster than stepping through each character
+    if rules.quotes.is_empty() && !(rules.escape_unquoted && rules.escape.is_some()) {
+        let mut field_start: usize = 0;
+        for (i, d) in line.match_indices(rules.delimiter) {
+            f(field_start, i);
+            field_start = i + d.len();
+        }
+        f(field_start, line.len());
+        return false;
+    }
+    let mut state = State::Normal;
+    let mut field_start: usize = 0;
+    for (i, c) in line.char_indices() {
+        if c == rules.delimiter && matches!(state, State::Normal | State::QuoteEnd(_)) {
+            f(field_start, i);
+            field_start = i + c.len_utf8();
+        }
+        state = step(rules, state, c);
+    }
+    f(field_start, line.len());
+    state.in_quotes()
+}
+
+/// A dialect's quoting, detached from the dialect, for telling where records that span several
+/// lines end
+#[derive(Debug, Clone)]
+pub struct Quoting {
+    delimiter: char,
+    quotes: Vec<char>,
+    escape: Option<char>,
+    escape_unquoted: bool,
+    doubled_quotes: bool,
+}
+
+impl Quoting {
+    pub fn of(dialect: &dyn Dialect) -> Quoting {
+        Quoting {
+            delimiter: dialect.delimiter(),
+            quotes: dialect.quotes().to_vec(),
+            escape: dialect.escape(),
+            escape_unquoted: dialect.escape_unquoted(),
+            doubled_quotes: dialect.doubled_quotes(),
+        }
+    }
+
+    /// Whether `text` ends inside a quoted field, so that the record carries on past it
+    pub fn ends_quoted(&self, text: &str) -> bool {
+        let mut tracker = self.tracker();
+        tracker.feed(text)
+    }
+
+    /// Tells whether a record read a piece at a time ends inside a quoted field so far, going
+    /// over each piece only once
+    pub fn tracker(&self) -> QuoteTracker<'_> {
+        let rules = Rules {
+            delimiter: self.delimiter,
+            quotes: &self.quotes,
+            escape: self.escape,
+            escape_unquoted: self.escape_unquoted,
+            doubled_quotes: self.doubled_quotes,
+        };
+        QuoteTracker { rules, state: State::Normal }
+    }
+}
+
+/// Returned by `Quoting::tracker`
+pub struct QuoteTracker<'a> {
+    rules: Rules<'a>,
+    state: State,
+}
+
+impl QuoteTracker<'_> {
+    /// Takes in the next piece of the record, returning whether the record ends inside a quoted
+    /// field if it ends there
+    pub fn feed(&mut self, text: &str) -> bool {
+        for c in text.chars() {
+            self.state = step(&self.rules, self.state, c);
+        }
+        self.state.in_quotes()
+    }
+}
+
+/// Checks that `fields`, written as a record in `dialect`, read back as the same fields: that none
+/// holds a delimiter or line break that isn't quoted, or a quote that isn't escaped. Otherwise
+/// returns the first field that doesn't, from 0, and what's read back in its place.
+pub fn check_roundtrip(fields: &[&str], dialect: &dyn Dialect) -> Result<(), (usize, String)> {
+    // An empty line is as close as a record can get to having no fields
+    if fields.is_empty() {
+        return Ok(());
+    }
+    let line = fields.join(&dialect.delimiter().to_string());
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    dialect.field_ranges(&line, &mut |start, end| ranges.push((start, end)));
+    let trim = dialect.trim();
+    for i in 0..fields.len().max(ranges.len()) {
+        let written = fields.get(i).map(|f| if trim { f.trim() } else { f });
+        let read = ranges.get(i).map(|(start, end)| &line[*start..*end]);
+        if written != read.map(|f| if trim { f.trim() } else { f }) {
+            return Err((i, read.unwrap_or("").to_string()));
+        }
+    }
+    // A line break outside quotes would end the record early when it's read back
+    let quoting = Quoting::of(dialect);
+    for (at, _) in line.match_indices('\n') {
+        if !quoting.ends_quoted(&line[..at]) {
+            let i = ranges.iter().position(|(start, end)| (*start..=*end).contains(&at));
+            let (start, _) = ranges[i.unwrap_or(0)];
+            return Err((i.unwrap_or(0), line[start..at].to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// ccut's own forgiving dialect: commas, single or double quotes, backslash escapes inside quotes,
+/// and surrounding whitespace ignored. Quotes never have to be closed. A quote can also be escaped
+/// by doubling it as in RFC 4180, so Excel-style `"say ""hi"""` reads as `say "hi"`; written
+/// values are still backslash escaped.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Permissive;
+
+impl Dialect for Permissive {
+    fn delimiter(&self) -> char {
+        ','
+    }
+    fn quotes(&self) -> &[char] {
+        &['"', '\'']
+    }
+    fn escape(&self) -> Option<char> {
+        Some('\\')
+    }
+    fn doubled_quotes(&self) -> bool {
+        true
+    }
+    fn trim(&self) -> bool {
+        true
+    }
+}
+
+/// Strict CSV as in RFC 4180: double quotes, doubled to escape them, and CRLF line endings
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rfc4180;
+
+impl Dialect for Rfc4180 {
+    fn delimiter(&self) -> char {
+        ','
+    }
+    fn quotes(&self) -> &[char] {
+        &['"']
+    }
+    fn doubled_quotes(&self) -> bool {
+        true
+    }
+    fn terminator(&self) -> &str {
+        "\r\n"
+    }
+}
+
+/// Tab-separated values as registered with IANA: no quoting or escaping at all, fields simply
+/// can't contain tabs or newlines
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tsv;
+
+impl Dialect for Tsv {
+    fn delimiter(&self) -> char {
+        '\t'
+    }
+}
+
+/// PostgreSQL's COPY text format: tab-separated, no quoting, and backslash escapes anywhere
+/// (`\t`, `\n`, `\\`, ...). `\N` marks a NULL, which unquotes to `N` like any unknown escape.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PgCopy;
+
+impl Dialect for PgCopy {
+    fn delimiter(&self) -> char {
+        '\t'
+    }
+    fn escape(&self) -> Option<char> {
+        Some('\\')
+    }
+    fn escape_unquoted(&self) -> bool {
+        true
+    }
+    fn unescape(&self, c: char) -> char {
+        match c {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'v' => '\u{b}',
+            c => c,
+        }
+    }
+}
+
+/// Another dialect with a different delimiter, e.g. for pipe-delimited exports. A whitespace
+/// delimiter turns off the dialect's trimming, which would otherwise eat leading empty fields.
+#[derive(Debug)]
+pub struct WithDelimiter {
+    inner: Box<dyn Dialect>,
+    delimiter: char,
+}
+
+impl WithDelimiter {
+    pub fn new(inner: Box<dyn Dialect>, delimiter: char) -> WithDelimiter {
+        assert!(!inner.quotes().contains(&delimiter) && inner.escape() != Some(delimiter)
+                && delimiter != '\n' && delimiter != '\r',
+                "{:?} can't be the delimiter, it already means something else", delimiter);
+        WithDelimiter { inner, delimiter }
+    }
+}
+
+impl Dialect for WithDelimiter {
+    fn delimiter(&self) -> char {
+        self.delimiter
+    }
+    fn quotes(&self) -> &[char] {
+        self.inner.quotes()
+    }
+    fn escape(&self) -> Option<char> {
+        self.inner.escape()
+    }
+    fn escape_unquoted(&self) -> bool {
+        self.inner.escape_unquoted()
+    }
+    fn doubled_quotes(&self) -> bool {
+        self.inner.doubled_quotes()
+    }
+    fn terminator(&self) -> &str {
+        self.inner.terminator()
+    }
+    fn trim(&self) -> bool {
+        self.inner.trim() && !self.delimiter.is_whitespace()
+    }
+    fn unescape(&self, c: char) -> char {
+        self.inner.unescape(c)
+    }
+}
+
+/// Delimiters `sniff_delimiter` chooses between, in order of preference when they do equally well
+pub const SNIFF_DELIMITERS: &[char] = &[',', '\t', ';', '|'];
+
+/// Bytes of input `--sniff` looks at
+pub const SNIFF_BYTES: usize = 8 * 1024;
+
+/// Guesses the delimiter of the lines in `sample`, split permissively: the one of
+/// `SNIFF_DELIMITERS` that most consistently splits lines into the same number of fields, more
+/// than one, and on a tie the one that makes more fields. `None` if none of them splits any line.
+pub fn sniff_delimiter(sample: &str) -> Option<char> {
+    let lines: Vec<&str> = sample.lines().filter(|l| !l.trim().is_empty()).collect();
+    let mut best: Option<(usize, usize, char)> = None;
+    for d in SNIFF_DELIMITERS.iter() {
+        let dialect = WithDelimiter::new(Box::new(Permissive), *d);
+        let mut counts: Vec<usize> = lines.iter()
+            .map(|line| {
+                let mut n = 0;
+                dialect.field_ranges(line, &mut |_, _| n += 1);
+                n
+            })
+            .collect();
+        counts.sort_unstable();
+        // The most common number of fields, and how many lines have it
+        let (mut fields, mut agree) = (0, 0);
+        for run in counts.chunk_by(|a, b| a == b) {
+            if run.len() > agree || (run.len() == agree && run[0] > fields) {
+                (fields, agree) = (run[0], run.len());
+            }
+        }
+        if fields > 1 && best.is_none_or(|(a, f, _)| (agree, fields) > (a, f)) {
+            best = Some((agree, fields, *d));
+        }
+    }
+    best.map(|(_, _, d)| d)
+}
+
+/// Another dialect with a different set of quote characters, e.g. backticks for some database
+/// exports, or none but double quotes where apostrophes are just part of the text. The first one
+/// quotes written values.
+#[derive(Debug)]
+pub struct WithQuotes {
+    inner: Box<dyn Dialect>,
+    quotes: Vec<char>,
+}
+
+impl WithQuotes {
+    pub fn new(inner: Box<dyn Dialect>, quotes: Vec<char>) -> WithQuotes {
+        for q in quotes.iter() {
+            assert!(*q != inner.delimiter() && inner.escape() != Some(*q) && *q != '\n'
+                    && *q != '\r',
+                    "{:?} can't be a quote, it already means something else", q);
+        }
+        WithQuotes { inner, quotes }
+    }
+}
+
+impl Dialect for WithQuotes {
+    fn delimiter(&self) -> char {
+        self.inner.delimiter()
+    }
+    fn quotes(&self) -> &[char] {
+        &self.quotes
+    }
+    fn escape(&self) -> Option<char> {
+        self.inner.escape()
+    }
+    fn escape_unquoted(&self) -> bool {
+        self.inner.escape_unquoted()
+    }
+    fn doubled_quotes(&self) -> bool {
+        self.inner.doubled_quotes()
+    }
+    fn terminator(&self) -> &str {
+        self.inner.terminator()
+    }
+    fn trim(&self) -> bool {
+        self.inner.trim()
+    }
+    fn unescape(&self, c: char) -> char {
+        self.inner.unescape(c)
+    }
+}
+
+/// Rewrites a raw field read in dialect `from` so that it reads back as the same value in `to`,
+/// quoting and escaping it as `to` does
+pub fn convert_field<'a>(field: &'a str, from: &dyn Dialect, to: &dyn Dialect) -> Cow<'a, str> {
+    let field = if from.trim() { field.trim() } else { field };
+    match from.unquote(field) {
+        Cow::Borrowed(value) => to.quote(value),
+        Cow::Owned(value) => Cow::Owned(to.quote(&value).into_owned()),
+    }
+}
+
+/// How `--requote` quotes the fields written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Requote {
+    /// Quote every field, whether or not it needs it
+    Always,
+    /// Quote only the fields that would otherwise read back differently
+    Minimal,
+    /// Write the bare values, quotes and escapes removed (`--unquote`)
+    Never,
+}
+
+/// Parses a `--requote` argument
+pub fn parse_requote(s: &str) -> Requote {
+    match s.trim() {
+        "always" => Requote::Always,
+        "minimal" => Requote::Minimal,
+        "never" => Requote::Never,
+        other => panic!("Invalid requote {}, expected always, minimal or never", other),
+    }
+}
+
+/// Rewrites a raw field of `dialect` quoted as `style` says. A dialect without quote characters
+/// can't quote every field, so `Always` escapes as `Minimal` does for it.
+pub fn requote_field<'a>(field: &'a str, dialect: &dyn Dialect, style: Requote) -> Cow<'a, str> {
+    let value = dialect.unquote(if dialect.trim() { field.trim() } else { field });
+    if style == Requote::Never {
+        return value;
+    }
+    let quoted = match dialect.quotes().first() {
+        // Quoted and escaped as `quote` does a value that needs it
+        Some(&q) if style == Requote::Always => {
+            let mut res = String::with_capacity(value.len() + 2);
+            res.push(q);
+            for c in value.chars() {
+                match dialect.escape() {
+                    Some(e) if c == q || c == e => res.push(e),
+                    None if c == q && dialect.doubled_quotes() => res.push(q),
+                    _ => {},
+                }
+                res.push(c);
+            }
+            res.push(q);
+            res
+        },
+        _ => dialect.quote(&value).into_owned(),
+    };
+    match value {
+        Cow::Borrowed(value) if quoted == value => Cow::Borrowed(value),
+        _ => Cow::Owned(quoted),
+    }
+}
+
+/// Names accepted by `by_name`
+pub const DIALECT_NAMES: &[&str] = &["permissive", "rfc4180", "tsv", "psv", "pgcopy"];
+
+/// Looks up one of the provided dialects by name
+pub fn by_name(name: &str) -> Option<Box<dyn Dialect>> {
+    match name.to_ascii_lowercase().as_str() {
+        "permissive" => Some(Box::new(Permissive)),
+        "rfc4180" | "csv" => Some(Box::new(Rfc4180)),
+        "tsv" => Some(Box::new(Tsv)),
+        "psv" => Some(Box::new(WithDelimiter::new(Box::new(Rfc4180), '|'))),
+        "pgcopy" | "copy" => Some(Box::new(PgCopy)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test_split {
+    use super::*;
+
+    fn split<'a>(dialect: &dyn Dialect, line: &'a str) -> Vec<&'a str> {
+        let mut fields = Vec::new();
+        dialect.field_ranges(line, &mut |start, end| fields.push(&line[start..end]));
+        fields
+    }
+
+    #[test]
+    fn test_permissive() {
+        assert_eq!(split(&Permissive, r#" a,"b,\",c",'d,e' "#), vec!["a", r#""b,\",c""#, "'d,e'"]);
+        assert_eq!(split(&Permissive, r#""say ""hi, you""",'it''s',x"#),
+                   vec![r#""say ""hi, you""""#, "'it''s'", "x"]);
+    }
+    #[test]
+    fn test_rfc4180() {
+        assert_eq!(split(&Rfc4180, "a,\"b,\"\"c\"\"\",'d,e'\r\n"),
+                   vec!["a", r#""b,""c""""#, "'d", "e'"]);
+        assert_eq!(split(&Rfc4180, " a , b"), vec![" a ", " b"]);
+    }
+    #[test]
+    fn test_tsv() {
+        assert_eq!(split(&Tsv, "a\t\"b\tc\"\n"), vec!["a", "\"b", "c\""]);
+    }
+    #[test]
+    fn test_pgcopy() {
+        assert_eq!(split(&PgCopy, "a\\\tb\tc\\\\\t\\N"), vec!["a\\\tb", "c\\\\", "\\N"]);
+    }
+    #[test]
+    fn test_custom() {
+        #[derive(Debug)]
+        struct Pipes;
+        impl Dialect for Pipes {
+            fn delimiter(&self) -> char {
+                '|'
+            }
+            fn quotes(&self) -> &[char] {
+                &['`']
+            }
+        }
+        assert_eq!(split(&Pipes, "a|`b|c`|d"), vec!["a", "`b|c`", "d"]);
+        assert_eq!(Pipes.unquote("`b|c`"), "b|c");
+    }
+    #[test]
+    fn test_with_delimiter() {
+        let pipes = WithDelimiter::new(Box::new(Permissive), '|');
+        assert_eq!(split(&pipes, r#" a|"b|c"|d,e "#), vec!["a", r#""b|c""#, "d,e"]);
+        assert_eq!(pipes.quote("a|b"), r#""a|b""#);
+        let tabs = WithDelimiter::new(Box::new(Permissive), '\t');
+        assert_eq!(split(&tabs, "\tb\t'c\td'"), vec!["", "b", "'c\td'"]);
+    }
+    #[test]
+    fn test_check_roundtrip() {
+        assert_eq!(check_roundtrip(&["a", r#""b,c""#, " d "], &Permissive), Ok(()));
+        assert_eq!(check_roundtrip(&["a", "b,c", "d"], &Permissive), Err((1, String::from("b"))));
+        assert_eq!(check_roundtrip(&["a", r#""b"#, "d"], &Permissive),
+                   Err((1, String::from(r#""b,d"#))));
+        assert_eq!(check_roundtrip(&["\"a\nb\"", "c"], &Permissive), Ok(()));
+        assert_eq!(check_roundtrip(&["a", "b\nc"], &Permissive), Err((1, String::from("b"))));
+        assert_eq!(check_roundtrip(&["a\tb"], &Tsv), Err((0, String::from("a"))));
+        assert_eq!(check_roundtrip(&[], &Tsv), Ok(()));
+    }
+    #[test]
+    fn test_convert_field() {
+        let psv = by_name("psv").unwrap();
+        assert_eq!(convert_field(r#" "a, \"b\"" "#, &Permissive, &Rfc4180), r#""a, ""b""""#);
+        assert_eq!(convert_field("a|b", &Rfc4180, psv.as_ref()), r#""a|b""#);
+        assert_eq!(convert_field(r#""a""b""#, &Rfc4180, &Tsv), r#"a"b"#);
+        assert_eq!(convert_field("a\\tb\\n", &PgCopy, &Rfc4180), "\"a\tb\n\"");
+        assert_eq!(convert_field("plain", &Tsv, &PgCopy), "plain");
+    }
+    #[test]
+    fn test_with_quotes() {
+        let backticks = WithQuotes::new(Box::new(Permissive), vec!['`']);
+        assert_eq!(split(&backticks, r#"`a,b`,"c,d",it's"#), vec!["`a,b`", "\"c", "d\"", "it's"]);
+        assert_eq!(backticks.unquote("`a\\`b`"), "a`b");
+        assert_eq!(backticks.quote("a,b"), "`a,b`");
+        let no_single = WithQuotes::new(Box::new(Permissive), vec!['"']);
+        assert_eq!(split(&no_single, r#"it's,"a,b",'c"#), vec!["it's", r#""a,b""#, "'c"]);
+        assert_eq!(no_single.quote("it's"), "it's");
+        let unquoted = WithQuotes::new(Box::new(Rfc4180), Vec::new());
+        assert_eq!(split(&unquoted, r#""a,b""#), vec!["\"a", "b\""]);
+        let unquoted = WithQuotes::new(Box::new(Permissive), Vec::new());
+        assert_eq!(split(&unquoted, r#" 'a,\,é,"b"  "#), vec!["'a", "\\", "é", "\"b\""]);
+        assert_eq!(split(&unquoted, ""), vec![""]);
+        assert_eq!(split(&unquoted, ",,"), vec!["", "", ""]);
+    }
+    #[test]
+    #[should_panic]
+    fn test_delimiter_quote_fails() {
+        WithQuotes::new(Box::new(Permissive), vec![',']);
+    }
+    #[test]
+    #[should_panic]
+    fn test_quote_delimiter_fails() {
+        WithDelimiter::new(Box::new(Rfc4180), '"');
+    }
+}
+
+#[cfg(test)]
+mod test_quoting {
+    use super::*;
+
+    #[test]
+    fn test_ends_quoted() {
+        let csv = Quoting::of(&Rfc4180);
+        assert!(csv.ends_quoted(r#"a,"b"#));
+        assert!(csv.ends_quoted("a,\"b\nc \"\"d\"\""));
+        assert!(!csv.ends_quoted("a,\"b\nc\",d"));
+        assert!(!csv.ends_quoted("it's"));
+        assert!(Quoting::of(&Permissive).ends_quoted(r#""a\""#));
+        assert!(!Quoting::of(&PgCopy).ends_quoted(r"a\"));
+    }
+    #[test]
+    fn test_unquote() {
+        assert_eq!(Permissive.unquote(r#""x\"y""#), r#"x"y"#);
+        assert_eq!(Permissive.unquote(r#"a\b"#), r#"a\b"#);
+        assert_eq!(Permissive.unquote(r#""say ""hi""""#), r#"say "hi""#);
+        assert_eq!(Permissive.unquote(r#""a\"""b""#), r#"a""b"#);
+        assert_eq!(Rfc4180.unquote(r#""say ""hi""""#), r#"say "hi""#);
+        assert_eq!(Rfc4180.unquote(r#""a\b""#), r#"a\b"#);
+        assert_eq!(PgCopy.unquote(r"a\tb\\c"), "a\tb\\c");
+        assert!(matches!(Rfc4180.unquote(r#""a,b""#), Cow::Borrowed("a,b")));
+    }
+    #[test]
+    fn test_round_trip() {
+        let dialects: [&dyn Dialect; 4] = [&Permissive, &Rfc4180, &PgCopy, &Tsv];
+        for dialect in dialects.iter() {
+            for value in ["plain", "a,b", "say \"hi\"", "it's", "back\\slash", "tab\there"].iter() {
+                // Plain TSV can't hold a tab at all
+                if dialect.delimiter() == '\t' && dialect.escape().is_none()
+                    && value.contains('\t') {
+                    continue;
+                }
+                let quoted = dialect.quote(value);
+                let mut ranges = Vec::new();
+                dialect.field_ranges(&quoted, &mut |start, end| ranges.push((start, end)));
+                assert_eq!(ranges.len(), 1, "{:?} {:?}", dialect, quoted);
+                assert_eq!(dialect.unquote(&quoted), *value, "{:?}", dialect);
+            }
+        }
+    }
+    #[test]
+    fn test_requote() {
+        assert_eq!(requote_field(r#""a""#, &Rfc4180, Requote::Minimal), "a");
+        assert_eq!(requote_field("a,b", &Permissive, Requote::Minimal), r#""a,b""#);
+        assert_eq!(requote_field("a", &Rfc4180, Requote::Always), r#""a""#);
+        assert_eq!(requote_field(r#""say ""hi""""#, &Rfc4180, Requote::Always),
+                   r#""say ""hi""""#);
+        assert_eq!(requote_field(r#""say \"hi\"""#, &Permissive, Requote::Never), r#"say "hi""#);
+        assert_eq!(requote_field(r#""a\\b""#, &Permissive, Requote::Always), r#""a\\b""#);
+        assert_eq!(requote_field("a", &Tsv, Requote::Always), "a");
+        assert_eq!(parse_requote("never"), Requote::Never);
+    }
+    #[test]
+    #[should_panic]
+    fn test_unknown_requote_fails() {
+        parse_requote("some");
+    }
+    #[test]
+    fn test_by_name() {
+        assert_eq!(by_name("RFC4180").unwrap().terminator(), "\r\n");
+        assert!(by_name("excel").is_none());
+    }
+}
+
+#[cfg(test)]
+mod test_sniff {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff_delimiter("a,b,c\n1,2,3\n"), Some(','));
+        assert_eq!(sniff_delimiter("name;price\nfoo;1,5\nbar;2\n\nbaz;3,25\n"), Some(';'));
+        assert_eq!(sniff_delimiter("a\tb, c\n1\t2\n"), Some('\t'));
+        assert_eq!(sniff_delimiter("id|note\n1|\"a|b\"\n2|c,d,e\n"), Some('|'));
+    }
+    #[test]
+    fn test_tie() {
+        // Both split every line alike, so the comma wins by making more fields, then by order
+        assert_eq!(sniff_delimiter("a,b;c\nd,e;f\n"), Some(','));
+        assert_eq!(sniff_delimiter("a,b;c\nd;e,f\n"), Some(','));
+    }
+    #[test]
+    fn test_nothing_to_split() {
+        assert_eq!(sniff_delimiter("one\ntwo\n"), None);
+        assert_eq!(sniff_delimiter(""), None);
+    }
+}
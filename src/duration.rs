@@ -0,0 +1,140 @@
+/// Seconds per duration unit suffix, longest suffix first so `ms` isn't read as `m`
+const UNITS: &[(&str, f64)] = &[
+    ("ms", 0.001),
+    ("w", 604800.0),
+    ("d", 86400.0),
+    ("h", 3600.0),
+    ("m", 60.0),
+    ("s", 1.0),
+];
+
+/// Parses a duration into seconds. Accepts unit strings like `95s`, `1h02m` or `1.5d`, and clock
+/// strings like `00:01:35` or `01:35` (minutes and seconds). Surrounding quotes and whitespace are
+/// ignored; a leading `-` makes the duration negative.
+pub fn parse_duration(s: &str) -> Option<f64> {
+    let s = crate::expr::unquote(s.trim()).trim();
+    let (sign, s) = match s.strip_prefix('-') {
+        Some(rest) => (-1.0, rest),
+        None => (1.0, s),
+    };
+    if s.is_empty() {
+        return None;
+    }
+    if s.contains(':') {
+        return parse_clock(s).map(|x| sign * x);
+    }
+
+    let mut total = 0.0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let num_len = rest.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+        if num_len == 0 {
+            return None;
+        }
+        let n: f64 = rest[..num_len].parse().ok()?;
+        rest = &rest[num_len..];
+        let (unit, secs) = UNITS.iter().find(|(u, _)| rest.starts_with(u))?;
+        total += n * secs;
+        rest = &rest[unit.len()..];
+    }
+    Some(sign * total)
+}
+
+/// Parses `HH:MM:SS(.fff)` or `MM:SS(.fff)`
+fn parse_clock(s: &str) -> Option<f64> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    let mut total = 0.0;
+    for (i, part) in parts.iter().enumerate() {
+        let last = i == parts.len() - 1;
+        if part.is_empty() || (!last && !part.chars().all(|c| c.is_ascii_digit())) {
+            return None;
+        }
+        let n: f64 = part.parse().ok()?;
+        if i > 0 && n >= 60.0 {
+            return None;
+        }
+        total = total * 60.0 + n;
+    }
+    Some(total)
+}
+
+/// Formats seconds as a duration string like `1h02m35s`, starting at the largest non-zero unit
+/// (days at most). The inverse of `parse_duration`.
+pub fn format_duration(secs: f64) -> String {
+    let sign = if secs < 0.0 { "-" } else { "" };
+    let secs = secs.abs();
+    let whole = secs.trunc() as u64;
+    let frac = secs - secs.trunc();
+
+    let parts = [(whole / 86400, "d"), (whole / 3600 % 24, "h"), (whole / 60 % 60, "m")];
+    let mut res = String::from(sign);
+    for (n, unit) in parts.iter() {
+        if res.len() > sign.len() {
+            res.push_str(&format!("{:02}{}", n, unit));
+        } else if *n > 0 {
+            res.push_str(&format!("{}{}", n, unit));
+        }
+    }
+    let s = (whole % 60) as f64 + frac;
+    // Round away float noise in the fraction
+    let s = (s * 1e6).round() / 1e6;
+    if res.len() > sign.len() && s < 10.0 {
+        res.push('0');
+    }
+    res.push_str(&format!("{}s", s));
+    res
+}
+
+#[cfg(test)]
+mod test_parse_duration {
+    use super::*;
+
+    #[test]
+    fn test_units() {
+        assert_eq!(parse_duration("95s"), Some(95.0));
+        assert_eq!(parse_duration("1h02m"), Some(3720.0));
+        assert_eq!(parse_duration("1.5d"), Some(129600.0));
+        assert_eq!(parse_duration("250ms"), Some(0.25));
+        assert_eq!(parse_duration("1w1m"), Some(604860.0));
+        assert_eq!(parse_duration("-2m"), Some(-120.0));
+    }
+    #[test]
+    fn test_clock() {
+        assert_eq!(parse_duration("00:01:35"), Some(95.0));
+        assert_eq!(parse_duration("\"1:35\""), Some(95.0));
+        assert_eq!(parse_duration("10:00:00.5"), Some(36000.5));
+    }
+    #[test]
+    fn test_invalid() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("95"), None);
+        assert_eq!(parse_duration("1y"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("00:75"), None);
+        assert_eq!(parse_duration("1:2:3:4"), None);
+    }
+}
+
+#[cfg(test)]
+mod test_format_duration {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_duration(95.0), "1m35s");
+        assert_eq!(format_duration(3720.0), "1h02m00s");
+        assert_eq!(format_duration(5.0), "5s");
+        assert_eq!(format_duration(0.25), "0.25s");
+        assert_eq!(format_duration(90061.0), "1d01h01m01s");
+        assert_eq!(format_duration(-61.5), "-1m01.5s");
+    }
+    #[test]
+    fn test_round_trip() {
+        for x in [0.0, 1.0, 59.0, 3599.0, 86400.0, 123456.0].iter() {
+            assert_eq!(parse_duration(&format_duration(*x)), Some(*x));
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+use crate::pipeline::{Pipeline, WriterSink};
+use crate::shard::FileAt;
+use crate::slice::Slice;
+
+/// Number of places a file is sampled at
+pub const SAMPLES: usize = 16;
+/// Lines read at each place
+pub const SAMPLE_LINES: u64 = 1000;
+
+/// The output a pipeline would produce from the whole input, extrapolated from a sample
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Estimate {
+    pub rows: u64,
+    pub bytes: u64,
+    pub input_bytes: u64,
+    pub sampled_bytes: u64,
+}
+
+impl Estimate {
+    /// Whether the sample was the whole input, so the numbers are exact
+    pub fn exact(&self) -> bool {
+        self.sampled_bytes >= self.input_bytes
+    }
+
+    /// Caps the row count, e.g. for `--top`, scaling the bytes to match
+    pub fn cap_rows(self, max: u64) -> Estimate {
+        if self.rows <= max {
+            return self;
+        }
+        Estimate { rows: max, bytes: scale(self.bytes, max, self.rows), ..self }
+    }
+}
+
+/// Counts the bytes written to it and throws them away
+#[derive(Default)]
+struct Counter(u64);
+
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Estimates from `SAMPLES` runs of `SAMPLE_LINES` lines spread evenly over a file, so that
+/// inputs whose rows change along the file (e.g. sorted by date) are still represented
+pub fn sample_file(file: &File, pipeline: &mut Pipeline) -> io::Result<Estimate> {
+    let len = file.metadata()?.len();
+    let mut sample = Vec::new();
+    let mut sampled_bytes = 0;
+    let mut complete = true;
+    for i in 0..SAMPLES as u64 {
+        let slice = Slice {
+            seek: i * len / SAMPLES as u64,
+            align_record: i > 0,
+            end: Some((i + 1) * len / SAMPLES as u64),
+            lines: Some((1, Some(SAMPLE_LINES))),
+        };
+        let mut reader = BufReader::new(FileAt::new(file));
+        let start = slice.start(&mut reader)?;
+        let mut n = 0;
+        for line in slice.lines(reader, start) {
+            let line = line?;
+            sampled_bytes += line.len() as u64 + 1;
+            n += 1;
+            sample.push(line);
+        }
+        complete &= n < SAMPLE_LINES;
+    }
+    // If every part was read to its end, the sample is the whole file. Counting it as such
+    // also makes up for line lengths not including dropped carriage returns.
+    let sampled_bytes = if complete { len } else { sampled_bytes.min(len) };
+    estimate(sample, sampled_bytes, len, pipeline)
+}
+
+/// Estimates from the first `SAMPLES * SAMPLE_LINES` lines of a stream, reading the rest only
+/// to measure it
+pub fn sample_stream<R: BufRead>(mut reader: R, pipeline: &mut Pipeline) -> io::Result<Estimate> {
+    let mut sample = Vec::new();
+    let mut sampled_bytes = 0;
+    let mut line = String::new();
+    while sample.len() < SAMPLES * SAMPLE_LINES as usize {
+        line.clear();
+        let n = reader.read_line(&mut line)?;
+        if n == 0 {
+            break;
+        }
+        sampled_bytes += n as u64;
+        sample.push(line.trim_end_matches(&['\r', '\n'][..]).to_string());
+    }
+    let rest = io::copy(&mut reader, &mut io::sink())?;
+    estimate(sample, sampled_bytes, sampled_bytes + rest, pipeline)
+}
+
+fn estimate(sample: Vec<String>, sampled_bytes: u64, input_bytes: u64, pipeline: &mut Pipeline)
+            -> io::Result<Estimate> {
+    if pipeline.needs_totals() {
+        pipeline.gather_totals(sample.iter().map(|l| Ok(l.clone())))?;
+    }
+    let mut sink = WriterSink::new(Counter::default(), pipeline.get_dialect());
+    pipeline.run_lines(sample.into_iter().map(Ok), &mut sink)?;
+    let (rows, bytes) = (sink.records(), sink.get_mut().0);
+    Ok(Estimate {
+        rows: scale(rows, input_bytes, sampled_bytes),
+        bytes: scale(bytes, input_bytes, sampled_bytes),
+        input_bytes,
+        sampled_bytes,
+    })
+}
+
+/// `x * num / den`, rounded, without overflowing
+fn scale(x: u64, num: u64, den: u64) -> u64 {
+    if den == 0 {
+        return x;
+    }
+    ((x as u128 * num as u128 + den as u128 / 2) / den as u128) as u64
+}
+
+#[cfg(test)]
+mod test_estimate {
+    use super::*;
+    use crate::expr::parse_expr;
+
+    fn pipeline() -> Pipeline {
+        Pipeline::new().select(vec![0]).filter(parse_expr("col2 != 0", 1))
+    }
+
+    #[test]
+    fn test_exact_when_small() {
+        let input = "a,1\nbb,0\nccc,2\n";
+        let est = sample_stream(input.as_bytes(), &mut pipeline()).unwrap();
+        assert_eq!(est, Estimate { rows: 2, bytes: 6, input_bytes: 15, sampled_bytes: 15 });
+        assert!(est.exact());
+
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(input.as_bytes()).unwrap();
+        assert_eq!(sample_file(&file, &mut pipeline()).unwrap(), est);
+    }
+    #[test]
+    fn test_extrapolate() {
+        let mut file = tempfile::tempfile().unwrap();
+        let mut input = String::new();
+        for i in 0..100_000 {
+            input.push_str(&format!("{:05},{}\n", i, i % 4));
+        }
+        file.write_all(input.as_bytes()).unwrap();
+        for est in [sample_file(&file, &mut pipeline()).unwrap(),
+                    sample_stream(input.as_bytes(), &mut pipeline()).unwrap()].iter() {
+            assert!(!est.exact());
+            assert_eq!(est.input_bytes, input.len() as u64);
+            // Every line is the same size and 3 of 4 pass the filter
+            assert!((74_000..=76_000).contains(&est.rows), "{:?}", est);
+            assert!((444_000..=456_000).contains(&est.bytes), "{:?}", est);
+        }
+    }
+    #[test]
+    fn test_cap_rows() {
+        let est = Estimate { rows: 100, bytes: 1000, input_bytes: 5000, sampled_bytes: 50 };
+        assert_eq!(est.cap_rows(10), Estimate { rows: 10, bytes: 100, ..est });
+        assert_eq!(est.cap_rows(1000), est);
+    }
+}
@@ -0,0 +1,900 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::decimal::{Decimal, DecimalSpec};
+use crate::duration;
+use crate::geo;
+use crate::ipaddr;
+use crate::key;
+use crate::web;
+
+/// A parsed computed-column expression. Column references (`col3`) are stored zero-indexed, the
+/// same as the output of `parse_arg_cols`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Col(usize),
+    Num(f64),
+    Str(String),
+    Call(String, Vec<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add, Sub, Mul, Div,
+    Eq, Ne, Lt, Le, Gt, Ge,
+    And, Or,
+}
+
+/// The result of evaluating an expression against one record. Values that can't be computed
+/// (e.g. a non-numeric field passed to a numeric function) come out as `Null`, which prints as an
+/// empty field, in the same spirit as `cut_line` absorbing out-of-bounds columns.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Num(f64),
+    Str(String),
+    Bool(bool),
+    Null,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Num(x) => write!(f, "{}", x),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Null => Ok(()),
+        }
+    }
+}
+
+/// Running totals for one column, accumulated during the first pass over the input. Uses
+/// Welford's method so the variance stays accurate over long inputs.
+#[derive(Debug, Default, Clone)]
+pub struct Moments {
+    pub count: usize,
+    pub sum: f64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Moments {
+    pub fn push(&mut self, x: f64) {
+        self.count += 1;
+        self.sum += x;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Population standard deviation
+    pub fn stddev(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            (self.m2 / self.count as f64).sqrt()
+        }
+    }
+}
+
+/// Per-column totals keyed by zero-indexed column
+pub type Totals = HashMap<usize, Moments>;
+
+/// Functions that need totals over the whole input before they can produce their first value
+const TOTAL_FUNCS: &[&str] = &["pct_of_total", "zscore"];
+
+impl Expr {
+    /// Returns the columns whose totals must be gathered in a first pass before this expression
+    /// can be evaluated. Empty if the expression can be evaluated in a single streaming pass.
+    pub fn total_cols(&self) -> Vec<usize> {
+        let mut res = Vec::new();
+        if let Expr::Call(name, args) = self {
+            if TOTAL_FUNCS.contains(&name.as_str()) {
+                if let Some(Expr::Col(i)) = args.first() {
+                    res.push(*i);
+                }
+            }
+        }
+        for child in self.children() {
+            res.extend(child.total_cols());
+        }
+        res
+    }
+
+    /// Whether the expression calls the function `name` anywhere
+    pub fn calls(&self, name: &str) -> bool {
+        if let Expr::Call(n, _) = self {
+            if n == name {
+                return true;
+            }
+        }
+        self.children().iter().any(|c| c.calls(name))
+    }
+
+    fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Call(_, args) => args.iter().collect(),
+            Expr::Binary(_, a, b) => vec![a, b],
+            Expr::Neg(a) | Expr::Not(a) => vec![a],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether the expression is only arithmetic on columns and numbers, which `eval_decimal`
+    /// can evaluate exactly
+    pub fn is_arithmetic(&self) -> bool {
+        match self {
+            Expr::Col(_) | Expr::Num(_) => true,
+            Expr::Binary(BinOp::Add, a, b) | Expr::Binary(BinOp::Sub, a, b)
+            | Expr::Binary(BinOp::Mul, a, b) | Expr::Binary(BinOp::Div, a, b) => {
+                a.is_arithmetic() && b.is_arithmetic()
+            },
+            Expr::Neg(a) => a.is_arithmetic(),
+            _ => false,
+        }
+    }
+
+    /// Evaluates an arithmetic expression (see `is_arithmetic`) in fixed-point decimals, reading
+    /// columns as `spec` says. `None` wherever `eval` would give `Null`, and on overflow.
+    pub fn eval_decimal(&self, fields: &[&str], spec: &DecimalSpec) -> Option<Decimal> {
+        match self {
+            Expr::Col(i) => spec.parse_field(*i, fields.get(*i)?),
+            Expr::Num(x) => Decimal::from_f64(*x),
+            Expr::Binary(op, a, b) => {
+                let (x, y) = (a.eval_decimal(fields, spec)?, b.eval_decimal(fields, spec)?);
+                match op {
+                    BinOp::Add => x.checked_add(y),
+                    BinOp::Sub => x.checked_sub(y),
+                    BinOp::Mul => x.checked_mul(y),
+                    BinOp::Div => x.checked_div(y),
+                    _ => None,
+                }
+            },
+            Expr::Neg(a) => Decimal::zero().checked_sub(a.eval_decimal(fields, spec)?),
+            _ => None,
+        }
+    }
+
+    /// Evaluates the expression against the fields of one record
+    pub fn eval(&self, fields: &[&str], totals: &Totals) -> Value {
+        match self {
+            Expr::Col(i) => match fields.get(*i) {
+                Some(s) => Value::Str(s.to_string()),
+                None => Value::Null,
+            },
+            Expr::Num(x) => Value::Num(*x),
+            Expr::Str(s) => Value::Str(s.clone()),
+            Expr::Call(name, args) => {
+                let vals: Vec<Value> = args.iter().map(|a| a.eval(fields, totals)).collect();
+                call(name, args, &vals, totals)
+            },
+            Expr::Binary(op, a, b) => binary(*op, a.eval(fields, totals), b.eval(fields, totals)),
+            Expr::Neg(a) => match as_num(&a.eval(fields, totals)) {
+                Some(x) => Value::Num(-x),
+                None => Value::Null,
+            },
+            Expr::Not(a) => Value::Bool(!is_true(&a.eval(fields, totals))),
+        }
+    }
+}
+
+/// Evaluates a binary operator. Arithmetic on non-numbers gives `Null`, except that subtracting
+/// one timestamp from another gives the seconds between them. Comparisons are numeric
+/// if both sides are numbers and textual (ignoring surrounding quotes) otherwise; equality between
+/// numbers allows for floating point error so that checks like `col2*col3 == col4` behave.
+fn binary(op: BinOp, a: Value, b: Value) -> Value {
+    match op {
+        BinOp::And => return Value::Bool(is_true(&a) && is_true(&b)),
+        BinOp::Or => return Value::Bool(is_true(&a) || is_true(&b)),
+        _ => {},
+    }
+    if a == Value::Null || b == Value::Null {
+        return Value::Null;
+    }
+    let nums = (as_num(&a), as_num(&b));
+    match op {
+        BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div => match nums {
+            (Some(x), Some(y)) => match op {
+                BinOp::Add => Value::Num(x + y),
+                BinOp::Sub => Value::Num(x - y),
+                BinOp::Mul => Value::Num(x * y),
+                _ if y == 0.0 => Value::Null,
+                _ => Value::Num(x / y),
+            },
+            // Subtracting two timestamps gives the duration between them in seconds
+            (None, None) if op == BinOp::Sub => {
+                match (key::parse_datetime(&as_str(&a)), key::parse_datetime(&as_str(&b))) {
+                    (Some(x), Some(y)) => Value::Num((x - y).num_milliseconds() as f64 / 1000.0),
+                    _ => Value::Null,
+                }
+            },
+            _ => Value::Null,
+        },
+        _ => {
+            let ord = match nums {
+                (Some(x), Some(y)) => {
+                    if (x - y).abs() <= 1e-9 * x.abs().max(y.abs()).max(1.0) {
+                        Ordering::Equal
+                    } else {
+                        x.total_cmp(&y)
+                    }
+                },
+                // A number is no more or less than text such as N/A or an empty field
+                _ if matches!(a, Value::Num(_)) || matches!(b, Value::Num(_)) => {
+                    return Value::Null
+                },
+                _ => as_str(&a).cmp(&as_str(&b)),
+            };
+            Value::Bool(match op {
+                BinOp::Eq => ord == Ordering::Equal,
+                BinOp::Ne => ord != Ordering::Equal,
+                BinOp::Lt => ord == Ordering::Less,
+                BinOp::Le => ord != Ordering::Greater,
+                BinOp::Gt => ord == Ordering::Greater,
+                _ => ord != Ordering::Less,
+            })
+        },
+    }
+}
+
+/// Dispatches a function call. `args` are the unevaluated arguments, needed by the total-based
+/// functions to find which column's totals to use.
+fn call(name: &str, args: &[Expr], vals: &[Value], totals: &Totals) -> Value {
+    match name {
+        "pct_of_total" => {
+            let total = match args.first() {
+                Some(Expr::Col(i)) => totals.get(i).map(|m| m.sum),
+                _ => None,
+            };
+            match (as_num(&vals[0]), total) {
+                (Some(x), Some(t)) if t != 0.0 => Value::Num(100.0 * x / t),
+                _ => Value::Null,
+            }
+        },
+        "zscore" => {
+            let moments = match args.first() {
+                Some(Expr::Col(i)) => totals.get(i),
+                _ => None,
+            };
+            match (as_num(&vals[0]), moments) {
+                (Some(x), Some(m)) if m.stddev() != 0.0 => Value::Num((x - m.mean()) / m.stddev()),
+                _ => Value::Null,
+            }
+        },
+        "seconds" => match &vals[0] {
+            Value::Num(x) => Value::Num(*x),
+            Value::Str(s) => duration::parse_duration(s).map_or(Value::Null, Value::Num),
+            _ => Value::Null,
+        },
+        "duration" => match as_num(&vals[0]) {
+            Some(x) => Value::Str(duration::format_duration(x)),
+            None => Value::Null,
+        },
+        "round" => match (as_num(&vals[0]), as_num(&vals[1])) {
+            (Some(x), Some(n)) => Value::Num(geo::round_to(x, n as i32)),
+            _ => Value::Null,
+        },
+        "geohash" => {
+            let precision = vals.get(2).map_or(Some(9.0), as_num);
+            match (as_num(&vals[0]), as_num(&vals[1]), precision) {
+                (Some(lat), Some(lon), Some(p)) if p >= 1.0 => {
+                    geo::geohash(lat, lon, p as usize).map_or(Value::Null, Value::Str)
+                },
+                _ => Value::Null,
+            }
+        },
+        "haversine" => {
+            let xs: Vec<f64> = vals.iter().filter_map(as_num).collect();
+            if xs.len() == 4 {
+                Value::Num(geo::haversine(xs[0], xs[1], xs[2], xs[3]))
+            } else {
+                Value::Null
+            }
+        },
+        "contains" => match (&vals[0], &vals[1]) {
+            (Value::Null, _) | (_, Value::Null) => Value::Null,
+            (a, b) => Value::Bool(as_str(a).contains(&as_str(b))),
+        },
+        "ip_in" => {
+            let ip = ipaddr::parse_ip(&as_str(&vals[0]));
+            match (ip, ipaddr::parse_cidr(&as_str(&vals[1]))) {
+                (Some(ip), Some((net, prefix))) => Value::Bool(ipaddr::ip_in(&ip, &net, prefix)),
+                _ => Value::Bool(false),
+            }
+        },
+        "ip_to_int" => match ipaddr::parse_ip(&as_str(&vals[0])).map(|ip| ipaddr::ip_to_int(&ip)) {
+            // Integers beyond 2^53 (i.e. most IPv6 addresses) aren't exact as floats
+            Some(n) if n < 1 << 53 => Value::Num(n as f64),
+            Some(n) => Value::Str(n.to_string()),
+            None => Value::Null,
+        },
+        "ip_anon" => match ipaddr::parse_ip(&as_str(&vals[0])) {
+            Some(ip) => Value::Str(ipaddr::anonymize(&ip).to_string()),
+            None => Value::Null,
+        },
+        "rdns" => match ipaddr::parse_ip(&as_str(&vals[0])).and_then(|ip| ipaddr::reverse_dns(&ip))
+        {
+            Some(host) => Value::Str(host),
+            None => Value::Null,
+        },
+        "url_scheme" | "url_host" | "url_path" | "url_query" => match &vals[0] {
+            Value::Str(s) => {
+                let parts = web::split_url(s);
+                Value::Str(match name {
+                    "url_scheme" => parts.scheme,
+                    "url_host" => parts.host,
+                    "url_path" => parts.path,
+                    _ => parts.query,
+                }.to_string())
+            },
+            _ => Value::Null,
+        },
+        "ua_family" => Value::Str(web::ua_family(&as_str(&vals[0])).to_string()),
+        _ => unreachable!("Unknown function {}", name),
+    }
+}
+
+/// Interprets a value as a number, if possible
+pub fn as_num(val: &Value) -> Option<f64> {
+    match val {
+        Value::Num(x) => Some(*x),
+        Value::Str(s) => parse_num(s),
+        Value::Bool(_) | Value::Null => None,
+    }
+}
+
+/// Interprets a value as text, with any surrounding quotes on a raw field removed
+pub fn as_str(val: &Value) -> String {
+    match val {
+        Value::Str(s) => unquote(s.trim()).to_string(),
+        _ => val.to_string(),
+    }
+}
+
+/// Whether a value counts as true in a condition: only `true` itself does
+pub fn is_true(val: &Value) -> bool {
+    *val == Value::Bool(true)
+}
+
+/// Strips one pair of matching single or double quotes from a field
+pub fn unquote(s: &str) -> &str {
+    if s.len() >= 2 && ((s.starts_with('"') && s.ends_with('"'))
+                        || (s.starts_with('\'') && s.ends_with('\''))) {
+        &s[1..s.len() - 1]
+    } else {
+        s
+    }
+}
+
+/// Parses a raw field as a number, ignoring surrounding whitespace and quotes
+pub fn parse_num(field: &str) -> Option<f64> {
+    let field = field.trim();
+    let field = field.trim_matches(|c| c == '"' || c == '\'').trim();
+    field.parse().ok()
+}
+
+/// Parses an expression such as `zscore(col3)` or `col3 * col4 == col5`. `offset` has the same
+/// meaning as in `parse_arg_cols`: it's the index of the first column.
+///
+/// From loosest to tightest binding, the operators are `or`, `and`, comparisons (`==` (or `=`),
+/// `!=`, `<`, `<=`, `>`, `>=`), `+` and `-`, `*` and `/`, then unary `-` and `not`. String
+/// literals are double quoted.
+pub fn parse_expr(s: &str, offset: usize) -> Expr {
+    assert!(offset == 0 || offset == 1, "Invalid offset, {}", offset);
+
+    let tokens = tokenize(s);
+    let mut parser = Parser { tokens: &tokens, pos: 0, offset };
+    let expr = parser.parse_or();
+    assert!(parser.pos == tokens.len(), "Unexpected trailing input in expression {}", s);
+    expr
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Num(f64),
+    Str(String),
+    Op(String),
+    LParen,
+    RParen,
+    Comma,
+}
+
+fn tokenize(s: &str) -> Vec<Token> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else if c == ',' {
+            tokens.push(Token::Comma);
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            tokens.push(Token::Num(text.parse().expect("Invalid number in expression")));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c == '"' {
+            // String literal, with backslash escapes
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some('"') => break,
+                    Some('\\') => {
+                        text.push(*chars.get(i + 1).expect("Unterminated string in expression"));
+                        i += 2;
+                    },
+                    Some(ch) => {
+                        text.push(*ch);
+                        i += 1;
+                    },
+                    None => panic!("Unterminated string in expression"),
+                }
+            }
+            i += 1;
+            tokens.push(Token::Str(text));
+        } else if "=!<>".contains(c) && next == Some('=') {
+            tokens.push(Token::Op(format!("{}=", c)));
+            i += 2;
+        } else if "+-*/<>=".contains(c) {
+            tokens.push(Token::Op(c.to_string()));
+            i += 1;
+        } else {
+            panic!("Unexpected character '{}' in expression", c);
+        }
+    }
+    tokens
+}
+
+/// Recursive descent parser over the token stream, one method per precedence level
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_op(&self) -> Option<&str> {
+        match self.peek() {
+            Some(Token::Op(op)) => Some(op.as_str()),
+            Some(Token::Ident(kw)) if kw == "and" || kw == "or" => Some(kw.as_str()),
+            _ => None,
+        }
+    }
+
+    fn parse_or(&mut self) -> Expr {
+        let mut lhs = self.parse_and();
+        while self.peek_op() == Some("or") {
+            self.pos += 1;
+            lhs = Expr::Binary(BinOp::Or, Box::new(lhs), Box::new(self.parse_and()));
+        }
+        lhs
+    }
+
+    fn parse_and(&mut self) -> Expr {
+        let mut lhs = self.parse_cmp();
+        while self.peek_op() == Some("and") {
+            self.pos += 1;
+            lhs = Expr::Binary(BinOp::And, Box::new(lhs), Box::new(self.parse_cmp()));
+        }
+        lhs
+    }
+
+    fn parse_cmp(&mut self) -> Expr {
+        let lhs = self.parse_sum();
+        let op = match self.peek_op() {
+            Some("=") | Some("==") => BinOp::Eq,
+            Some("!=") => BinOp::Ne,
+            Some("<") => BinOp::Lt,
+            Some("<=") => BinOp::Le,
+            Some(">") => BinOp::Gt,
+            Some(">=") => BinOp::Ge,
+            _ => return lhs,
+        };
+        self.pos += 1;
+        Expr::Binary(op, Box::new(lhs), Box::new(self.parse_sum()))
+    }
+
+    fn parse_sum(&mut self) -> Expr {
+        let mut lhs = self.parse_product();
+        loop {
+            let op = match self.peek_op() {
+                Some("+") => BinOp::Add,
+                Some("-") => BinOp::Sub,
+                _ => return lhs,
+            };
+            self.pos += 1;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.parse_product()));
+        }
+    }
+
+    fn parse_product(&mut self) -> Expr {
+        let mut lhs = self.parse_unary();
+        loop {
+            let op = match self.peek_op() {
+                Some("*") => BinOp::Mul,
+                Some("/") => BinOp::Div,
+                _ => return lhs,
+            };
+            self.pos += 1;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.parse_unary()));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Expr {
+        match self.peek() {
+            Some(Token::Op(op)) if op == "-" => {
+                self.pos += 1;
+                Expr::Neg(Box::new(self.parse_unary()))
+            },
+            Some(Token::Ident(kw)) if kw == "not" => {
+                self.pos += 1;
+                Expr::Not(Box::new(self.parse_unary()))
+            },
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Expr {
+        let tok = self.peek().expect("Unexpected end of expression").clone();
+        self.pos += 1;
+        match tok {
+            Token::Num(x) => Expr::Num(x),
+            Token::Str(s) => Expr::Str(s),
+            Token::LParen => {
+                let expr = self.parse_or();
+                assert!(self.peek() == Some(&Token::RParen), "Expected ')' in expression");
+                self.pos += 1;
+                expr
+            },
+            Token::Ident(name) => {
+                if self.peek() != Some(&Token::LParen) {
+                    return parse_col_ref(&name, self.offset);
+                }
+                self.pos += 1;
+                let mut args = Vec::new();
+                if self.peek() == Some(&Token::RParen) {
+                    self.pos += 1;
+                } else {
+                    loop {
+                        args.push(self.parse_or());
+                        match self.peek() {
+                            Some(Token::Comma) => self.pos += 1,
+                            Some(Token::RParen) => {
+                                self.pos += 1;
+                                break;
+                            },
+                            _ => panic!("Expected ',' or ')' in arguments to {}", name),
+                        }
+                    }
+                }
+                check_call(&name, &args);
+                Expr::Call(name, args)
+            },
+            _ => panic!("Unexpected token {:?} in expression", tok),
+        }
+    }
+}
+
+/// Parses a column reference like `col3`
+fn parse_col_ref(name: &str, offset: usize) -> Expr {
+    assert!(name.starts_with("col"), "Unknown identifier {}", name);
+    let i: usize = name[3..].parse()
+        .expect("Invalid column reference");
+    assert!(offset <= i, "Column index must be at least {}", offset);
+    Expr::Col(i - offset)
+}
+
+/// Validates the name and arity of a function call
+fn check_call(name: &str, args: &[Expr]) {
+    match name {
+        "pct_of_total" | "zscore" => {
+            assert!(args.len() == 1, "{} takes one argument", name);
+            match args[0] {
+                Expr::Col(_) => {},
+                _ => panic!("{} takes a column reference", name),
+            }
+        },
+        "seconds" | "duration" => {
+            assert!(args.len() == 1, "{} takes one argument", name);
+        },
+        "round" | "contains" | "ip_in" => {
+            assert!(args.len() == 2, "{} takes two arguments", name);
+        },
+        "ip_to_int" | "ip_anon" | "rdns" | "url_scheme" | "url_host" | "url_path" | "url_query"
+        | "ua_family" => {
+            assert!(args.len() == 1, "{} takes one argument", name);
+        },
+        "geohash" => {
+            assert!(args.len() == 2 || args.len() == 3, "{} takes two or three arguments", name);
+        },
+        "haversine" => {
+            assert!(args.len() == 4, "{} takes four arguments", name);
+        },
+        _ => panic!("Unknown function {}", name),
+    }
+}
+
+#[cfg(test)]
+mod test_parse_expr {
+    use super::*;
+
+    #[test]
+    fn test_parse_col() {
+        assert_eq!(parse_expr("col3", 1), Expr::Col(2));
+        assert_eq!(parse_expr("col3", 0), Expr::Col(3));
+    }
+    #[test]
+    fn test_parse_call() {
+        assert_eq!(parse_expr("zscore(col2)", 1),
+                   Expr::Call(String::from("zscore"), vec![Expr::Col(1)]));
+        assert_eq!(parse_expr(" pct_of_total( col1 ) ", 1),
+                   Expr::Call(String::from("pct_of_total"), vec![Expr::Col(0)]));
+    }
+    #[test]
+    fn test_parse_precedence() {
+        let res = parse_expr("col1 + col2 * 2 <= 3 and not col3", 1);
+        let sum = Expr::Binary(BinOp::Add, Box::new(Expr::Col(0)),
+            Box::new(Expr::Binary(BinOp::Mul, Box::new(Expr::Col(1)), Box::new(Expr::Num(2.0)))));
+        let cmp = Expr::Binary(BinOp::Le, Box::new(sum), Box::new(Expr::Num(3.0)));
+        assert_eq!(res, Expr::Binary(BinOp::And, Box::new(cmp),
+                                     Box::new(Expr::Not(Box::new(Expr::Col(2))))));
+    }
+    #[test]
+    fn test_parse_string() {
+        assert_eq!(parse_expr(r#"col1 == "a \"b\"""#, 1),
+                   Expr::Binary(BinOp::Eq, Box::new(Expr::Col(0)),
+                                Box::new(Expr::Str(String::from(r#"a "b""#)))));
+    }
+    #[test]
+    fn test_total_cols() {
+        assert_eq!(parse_expr("zscore(col2)", 1).total_cols(), vec![1]);
+        assert_eq!(parse_expr("col2", 1).total_cols(), Vec::<usize>::new());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_unknown_function_fails() {
+        parse_expr("foo(col1)", 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_bad_arity_fails() {
+        parse_expr("zscore(col1, col2)", 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_bad_geo_arity_fails() {
+        parse_expr("haversine(col1, col2, col3)", 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_offset_fails() {
+        parse_expr("zscore(col0)", 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_unterminated_string_fails() {
+        parse_expr(r#"col1 == "abc"#, 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_unbalanced_parens_fails() {
+        parse_expr("(col1 + col2", 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_trailing_input_fails() {
+        parse_expr("zscore(col1))", 1);
+    }
+}
+
+#[cfg(test)]
+mod test_eval {
+    use super::*;
+
+    fn totals_of(col: usize, xs: &[f64]) -> Totals {
+        let mut totals = Totals::new();
+        for x in xs.iter() {
+            totals.entry(col).or_default().push(*x);
+        }
+        totals
+    }
+
+    #[test]
+    fn test_pct_of_total() {
+        let totals = totals_of(1, &[1.0, 3.0]);
+        let expr = parse_expr("pct_of_total(col2)", 1);
+        assert_eq!(expr.eval(&["a", "1"], &totals), Value::Num(25.0));
+        assert_eq!(expr.eval(&["a", "\"3\""], &totals), Value::Num(75.0));
+        assert_eq!(expr.eval(&["a", "x"], &totals), Value::Null);
+        assert_eq!(expr.eval(&["a"], &totals), Value::Null);
+    }
+    #[test]
+    fn test_zscore() {
+        let totals = totals_of(0, &[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        let expr = parse_expr("zscore(col1)", 1);
+        assert_eq!(expr.eval(&["9"], &totals), Value::Num(2.0));
+        assert_eq!(expr.eval(&["3"], &totals), Value::Num(-1.0));
+    }
+    #[test]
+    fn test_zero_spread() {
+        let totals = totals_of(0, &[2.0, 2.0]);
+        assert_eq!(parse_expr("zscore(col1)", 1).eval(&["2"], &totals), Value::Null);
+        let totals = totals_of(0, &[0.0]);
+        assert_eq!(parse_expr("pct_of_total(col1)", 1).eval(&["0"], &totals), Value::Null);
+    }
+    #[test]
+    fn test_arithmetic() {
+        let totals = Totals::new();
+        assert_eq!(parse_expr("col1 * col2 - 1", 1).eval(&["3", "0.5"], &totals), Value::Num(0.5));
+        assert_eq!(parse_expr("-col1 / 2", 1).eval(&["3"], &totals), Value::Num(-1.5));
+        assert_eq!(parse_expr("col1 / 0", 1).eval(&["3"], &totals), Value::Null);
+        assert_eq!(parse_expr("col1 + 1", 1).eval(&["x"], &totals), Value::Null);
+    }
+    #[test]
+    fn test_decimal() {
+        let spec = DecimalSpec::default();
+        let eval = |s: &str, fields: &[&str]| {
+            let expr = parse_expr(s, 1);
+            assert!(expr.is_arithmetic());
+            expr.eval_decimal(fields, &spec).map(|d| d.to_string())
+        };
+        assert_eq!(eval("col1 + col2", &["0.1", "0.2"]), Some(String::from("0.3")));
+        assert_eq!(eval("-col1 * 3 - 0.5", &["19.99"]), Some(String::from("-60.47")));
+        assert_eq!(eval("col1 / 0", &["3"]), None);
+        assert_eq!(eval("col1 + 1", &["x"]), None);
+        assert!(!parse_expr("round(col1, 2)", 1).is_arithmetic());
+        assert!(!parse_expr("col1 > 2", 1).is_arithmetic());
+    }
+    #[test]
+    fn test_comparison() {
+        let totals = Totals::new();
+        let t = Value::Bool(true);
+        let f = Value::Bool(false);
+        assert_eq!(parse_expr("col1 <= col2", 1).eval(&["3", "10"], &totals), t);
+        assert_eq!(parse_expr("col1 <= col2", 1).eval(&["b", "a"], &totals), f);
+        assert_eq!(parse_expr("col1 * col2 = col3", 1).eval(&["0.1", "3", "0.3"], &totals), t);
+        assert_eq!(parse_expr(r#"col1 == "ERR""#, 1).eval(&["\"ERR\""], &totals), t);
+        assert_eq!(parse_expr("col1 < 1 or col2 < 1", 1).eval(&["5", "0"], &totals), t);
+        assert_eq!(parse_expr("col1 < 1 and col2 < 1", 1).eval(&["5", "0"], &totals), f);
+        assert_eq!(parse_expr("col3 < 1", 1).eval(&["5"], &totals), Value::Null);
+    }
+    #[test]
+    fn test_number_against_text() {
+        let totals = Totals::new();
+        for field in ["N/A", "", "x", "\"\""].iter() {
+            for op in ["<", "<=", ">", ">=", "=", "!="].iter() {
+                let expr = parse_expr(&format!("col1 {} 0", op), 1);
+                assert_eq!(expr.eval(&[field], &totals), Value::Null, "{:?} {}", field, op);
+                let expr = parse_expr(&format!("col1 * 2 {} col2", op), 1);
+                assert_eq!(expr.eval(&["1", field], &totals), Value::Null, "{:?} {}", field, op);
+            }
+        }
+        // A column of mixed numbers and text compares its numbers and leaves the rest out
+        let expr = parse_expr("col1 > 5", 1);
+        let kept: Vec<&str> = ["7", "N/A", "3", "", "10", "high"].iter().cloned()
+            .filter(|f| is_true(&expr.eval(&[f], &totals)))
+            .collect();
+        assert_eq!(kept, vec!["7", "10"]);
+        // Two columns are still compared as text when either isn't a number
+        assert_eq!(parse_expr("col1 < col2", 1).eval(&["10", "N/A"], &totals), Value::Bool(true));
+    }
+    #[test]
+    fn test_contains() {
+        let totals = Totals::new();
+        let fields = ["\"disk ERROR: full\"", "ERROR"];
+        assert_eq!(parse_expr(r#"contains(col1, "ERROR")"#, 1).eval(&fields, &totals),
+                   Value::Bool(true));
+        assert_eq!(parse_expr("contains(col2, col1)", 1).eval(&fields, &totals),
+                   Value::Bool(false));
+        assert_eq!(parse_expr(r#"contains(col3, "x")"#, 1).eval(&fields, &totals), Value::Null);
+    }
+    #[test]
+    fn test_durations() {
+        let totals = Totals::new();
+        assert_eq!(parse_expr("seconds(col1)", 1).eval(&["1h02m"], &totals), Value::Num(3720.0));
+        assert_eq!(parse_expr("seconds(col1)", 1).eval(&["00:01:35"], &totals), Value::Num(95.0));
+        assert_eq!(parse_expr("seconds(col1)", 1).eval(&["soon"], &totals), Value::Null);
+        assert_eq!(parse_expr("duration(col1)", 1).eval(&["95"], &totals),
+                   Value::Str(String::from("1m35s")));
+        assert_eq!(parse_expr("duration(seconds(col1) * 2)", 1).eval(&["1m"], &totals),
+                   Value::Str(String::from("2m00s")));
+    }
+    #[test]
+    fn test_timestamp_difference() {
+        let totals = Totals::new();
+        let expr = parse_expr("col2 - col1", 1);
+        assert_eq!(expr.eval(&["2020-01-01 00:00:00", "2020-01-01 00:01:35"], &totals),
+                   Value::Num(95.0));
+        assert_eq!(expr.eval(&["2020-01-02", "2020-01-01"], &totals), Value::Num(-86400.0));
+        assert_eq!(expr.eval(&["2020-01-02", "tomorrow"], &totals), Value::Null);
+        assert_eq!(parse_expr("duration(col2 - col1)", 1)
+                       .eval(&["2020-01-01T00:00:00Z", "2020-01-01T01:02:00Z"], &totals),
+                   Value::Str(String::from("1h02m00s")));
+    }
+    #[test]
+    fn test_geo() {
+        let totals = Totals::new();
+        let fields = ["51.5074", "-0.1278", "48.8566", "2.3522"];
+        assert_eq!(parse_expr("round(col1, 2)", 1).eval(&fields, &totals), Value::Num(51.51));
+        assert_eq!(parse_expr("geohash(col1, col2, 5)", 1).eval(&fields, &totals),
+                   Value::Str(String::from("gcpvj")));
+        assert_eq!(parse_expr("geohash(col1, col2)", 1).eval(&fields, &totals),
+                   Value::Str(String::from("gcpvj0duq")));
+        assert_eq!(parse_expr("round(haversine(col1, col2, col3, col4), 0)", 1)
+                       .eval(&fields, &totals), Value::Num(344.0));
+        assert_eq!(parse_expr("haversine(col1, col2, col3, col5)", 1).eval(&fields, &totals),
+                   Value::Null);
+    }
+    #[test]
+    fn test_ip() {
+        let totals = Totals::new();
+        let fields = ["10.1.2.3", "\"10.0.0.0/8\"", "2001:db8::1", "nope"];
+        let t = Value::Bool(true);
+        let f = Value::Bool(false);
+        assert_eq!(parse_expr(r#"ip_in(col1, "10.0.0.0/8")"#, 1).eval(&fields, &totals), t);
+        assert_eq!(parse_expr("ip_in(col1, col2)", 1).eval(&fields, &totals), t);
+        assert_eq!(parse_expr(r#"ip_in(col1, "11.0.0.0/8")"#, 1).eval(&fields, &totals), f);
+        assert_eq!(parse_expr(r#"ip_in(col4, "11.0.0.0/8")"#, 1).eval(&fields, &totals), f);
+        assert_eq!(parse_expr("ip_to_int(col1)", 1).eval(&fields, &totals),
+                   Value::Num(167838211.0));
+        assert_eq!(parse_expr("ip_to_int(col3)", 1).eval(&fields, &totals),
+                   Value::Str(String::from("42540766411282592856903984951653826561")));
+        assert_eq!(parse_expr("ip_anon(col1)", 1).eval(&fields, &totals),
+                   Value::Str(String::from("10.1.2.0")));
+        assert_eq!(parse_expr("ip_anon(col4)", 1).eval(&fields, &totals), Value::Null);
+    }
+    #[test]
+    fn test_web() {
+        let totals = Totals::new();
+        let fields = ["https://example.com/a?b=c", "curl/8.4.0"];
+        let eval = |s: &str| parse_expr(s, 1).eval(&fields, &totals).to_string();
+        assert_eq!(eval("url_scheme(col1)"), "https");
+        assert_eq!(eval("url_host(col1)"), "example.com");
+        assert_eq!(eval("url_path(col1)"), "/a");
+        assert_eq!(eval("url_query(col1)"), "b=c");
+        assert_eq!(eval("ua_family(col2)"), "curl");
+        assert_eq!(eval("url_host(col3)"), "");
+    }
+    #[test]
+    fn test_calls() {
+        assert!(parse_expr("ip_anon(rdns(col1))", 1).calls("rdns"));
+        assert!(!parse_expr("ip_anon(col1)", 1).calls("rdns"));
+    }
+    #[test]
+    fn test_display() {
+        assert_eq!(Value::Num(1.5).to_string(), "1.5");
+        assert_eq!(Value::Null.to_string(), "");
+    }
+}
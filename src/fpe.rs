@@ -0,0 +1,227 @@
+use std::env;
+
+use aes::Aes256;
+use fpe::ff1::{FlexibleNumeralString, FF1};
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::parse_args;
+
+/// Fewest values the letters and digits of a value may take for FF1 to encipher them, as NIST
+/// SP 800-38G Rev. 1 requires
+const MIN_DOMAIN: u32 = 1_000_000;
+
+/// `--fpe COL:key=ENVVAR`: encrypts a column in place with the key held in an environment
+/// variable. The plan only records the variable's name, so saved plans don't hold the key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FpeSpec {
+    pub col: usize,
+    pub key_var: String,
+}
+
+/// Parses an `--fpe` argument such as `3:key=VENDOR_KEY`. `offset` is as in `parse_arg_cols`.
+pub fn parse_fpe(spec: &str, offset: usize) -> FpeSpec {
+    let (col, option) = spec.trim().split_once(':')
+        .unwrap_or_else(|| panic!("Invalid --fpe {}, expected COL:key=ENVVAR", spec));
+    let key_var = match option.split_once('=') {
+        Some(("key", var)) if !var.trim().is_empty() => var.trim().to_string(),
+        _ => panic!("Invalid --fpe option {}, expected key=ENVVAR", option),
+    };
+    FpeSpec { col: parse_args::parse_arg_col(col, offset), key_var }
+}
+
+/// Format-preserving encryption of one column: each ASCII digit stays a digit and each letter a
+/// letter of the same case, while everything else, such as quotes, dashes and spaces, stays as
+/// it is. The same value always encrypts the same way under the same key, so encrypted columns
+/// can still be joined on, and values that only differ in punctuation still match.
+///
+/// The letters and digits are read as one number in mixed radix, which FF1 (NIST SP 800-38G)
+/// with AES-256 enciphers as a string of bits, cycle-walking until the result is in range again.
+/// The layout of the letters and digits is the tweak. The AES key is the SHA-256 of the key
+/// given. FF1 needs a million possible values at least, so a value with fewer letters and digits
+/// than that, like a short code, can't be encrypted; those with none at all are left as they are.
+#[derive(Debug, Clone)]
+pub struct Fpe {
+    col: usize,
+    key: [u8; 32],
+}
+
+impl Fpe {
+    pub fn new(col: usize, key: &[u8]) -> Fpe {
+        Fpe { col, key: Sha256::digest(key).into() }
+    }
+
+    /// Reads the key from the environment variable the spec names, which must be set and not
+    /// empty
+    pub fn from_env(spec: &FpeSpec) -> Result<Fpe, String> {
+        match env::var(&spec.key_var) {
+            Ok(key) if !key.is_empty() => Ok(Fpe::new(spec.col, key.as_bytes())),
+            Ok(_) => Err(format!("{} is empty", spec.key_var)),
+            Err(error) => Err(format!("{}: {}", spec.key_var, error)),
+        }
+    }
+
+    /// Column this encrypts, zero-indexed
+    pub fn col(&self) -> usize {
+        self.col
+    }
+
+    /// Fails, without saying what the value is, if it has too few letters and digits
+    pub fn encrypt(&self, value: &str) -> Result<String, String> {
+        self.run(value, false)
+    }
+
+    /// Undoes `encrypt`
+    pub fn decrypt(&self, value: &str) -> Result<String, String> {
+        self.run(value, true)
+    }
+
+    fn run(&self, value: &str, decrypt: bool) -> Result<String, String> {
+        let symbols: Vec<(Class, u8)> = value.bytes().filter_map(Class::of).collect();
+        if symbols.is_empty() {
+            return Ok(value.to_string());
+        }
+        let domain = symbols.iter()
+            .fold(BigUint::from(1u32), |n, (class, _)| n * u32::from(class.radix()));
+        if domain < BigUint::from(MIN_DOMAIN) {
+            return Err(String::from("--fpe can't encrypt a value with fewer than a million \
+                                     possible letters and digits, like a short code"));
+        }
+        let rank = symbols.iter()
+            .fold(BigUint::from(0u32), |n, (class, v)| n * u32::from(class.radix()) + *v);
+        let tweak: Vec<u8> = symbols.iter().map(|(class, _)| *class as u8).collect();
+        let bits = (&domain - 1u32).bits();
+        let ff1 = FF1::<Aes256>::new(&self.key, 2).expect("2 is a valid radix");
+        // Each step permutes the numbers of `bits` bits, so repeating it from a number in the
+        // domain comes back into the domain
+        let mut x = rank;
+        loop {
+            let ns = FlexibleNumeralString::from((0..bits).rev()
+                .map(|i| u16::from(x.bit(i)))
+                .collect::<Vec<u16>>());
+            let ns = if decrypt { ff1.decrypt(&tweak, &ns) } else { ff1.encrypt(&tweak, &ns) }
+                .expect("the domain is large enough for FF1");
+            x = Vec::from(ns).iter().fold(BigUint::from(0u32), |n, b| n * 2u32 + u32::from(*b));
+            if x < domain {
+                break;
+            }
+        }
+        let mut digits: Vec<u8> = symbols.iter().rev()
+            .map(|(class, _)| {
+                let radix = u32::from(class.radix());
+                let d = (&x % radix).to_u32_digits().first().copied().unwrap_or(0);
+                x /= radix;
+                d as u8
+            })
+            .collect();
+        let mut symbols = symbols.iter();
+        Ok(value.chars()
+            .map(|c| match Some(c).filter(char::is_ascii).and_then(|c| Class::of(c as u8)) {
+                Some(_) => {
+                    let (class, _) = symbols.next().unwrap();
+                    class.char(digits.pop().unwrap())
+                },
+                None => c,
+            })
+            .collect())
+    }
+}
+
+/// The kinds of characters that are enciphered, each within itself
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Class {
+    Digit,
+    Lower,
+    Upper,
+}
+
+impl Class {
+    /// The class of an ASCII character and its value within it
+    fn of(c: u8) -> Option<(Class, u8)> {
+        match c {
+            b'0'..=b'9' => Some((Class::Digit, c - b'0')),
+            b'a'..=b'z' => Some((Class::Lower, c - b'a')),
+            b'A'..=b'Z' => Some((Class::Upper, c - b'A')),
+            _ => None,
+        }
+    }
+
+    fn radix(self) -> u8 {
+        if self == Class::Digit { 10 } else { 26 }
+    }
+
+    fn char(self, v: u8) -> char {
+        let base = match self {
+            Class::Digit => b'0',
+            Class::Lower => b'a',
+            Class::Upper => b'A',
+        };
+        (base + v) as char
+    }
+}
+
+#[cfg(test)]
+mod test_fpe {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_fpe("3:key=VENDOR_KEY", 1),
+                   FpeSpec { col: 2, key_var: String::from("VENDOR_KEY") });
+    }
+    #[test]
+    #[should_panic]
+    fn test_parse_fails() {
+        parse_fpe("3:secret=abc", 1);
+    }
+    #[test]
+    fn test_preserves_format() {
+        let fpe = Fpe::new(0, b"secret");
+        let value = "\"Ab-123 456.x\"";
+        let encrypted = fpe.encrypt(value).unwrap();
+        assert_ne!(encrypted, value);
+        assert_eq!(encrypted.len(), value.len());
+        for (a, b) in value.chars().zip(encrypted.chars()) {
+            assert_eq!(Class::of(a as u8).map(|c| c.0), Class::of(b as u8).map(|c| c.0));
+            if Class::of(a as u8).is_none() {
+                assert_eq!(a, b);
+            }
+        }
+        assert_eq!(fpe.decrypt(&encrypted).unwrap(), value);
+    }
+    #[test]
+    fn test_deterministic() {
+        let fpe = Fpe::new(0, b"secret");
+        assert_eq!(fpe.encrypt("555-0100"), fpe.encrypt("555-0100"));
+        assert_eq!(fpe.encrypt("555-0100").unwrap().replace('-', ""),
+                   fpe.encrypt("5550100").unwrap());
+        assert_ne!(fpe.encrypt("5550100"), Fpe::new(0, b"other").encrypt("5550100"));
+        assert_eq!(fpe.encrypt("née-1234").unwrap().chars().nth(1), Some('é'));
+    }
+    #[test]
+    fn test_permutation() {
+        // Six digits are only a little over the minimum, so most values cycle-walk
+        let fpe = Fpe::new(0, b"secret");
+        let mut seen: Vec<String> = (0..2000)
+            .map(|i| {
+                let value = format!("{:06}", i * 499);
+                let encrypted = fpe.encrypt(&value).unwrap();
+                assert_eq!(fpe.decrypt(&encrypted).unwrap(), value);
+                encrypted
+            })
+            .collect();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 2000);
+    }
+    #[test]
+    fn test_too_few_values() {
+        let fpe = Fpe::new(0, b"secret");
+        assert!(fpe.encrypt("12345").is_err());
+        assert!(fpe.encrypt("ab-12").is_err());
+        assert!(fpe.encrypt("abcd12").is_ok());
+        assert_eq!(fpe.encrypt("").unwrap(), "");
+        assert_eq!(fpe.encrypt("\"-.\"").unwrap(), "\"-.\"");
+    }
+}
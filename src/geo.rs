@@ -0,0 +1,96 @@
+/// Mean Earth radius in kilometres, as used by the haversine formula
+const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+const GEOHASH_ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Great-circle distance in kilometres between two points given in decimal degrees
+pub fn haversine(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let dphi = (lat2 - lat1).to_radians();
+    let dlambda = (lon2 - lon1).to_radians();
+    let a = (dphi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (dlambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// Encodes a point as a geohash of `precision` characters. Returns `None` for coordinates out of
+/// range.
+pub fn geohash(lat: f64, lon: f64, precision: usize) -> Option<String> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return None;
+    }
+    let mut lat_rg = (-90.0, 90.0);
+    let mut lon_rg = (-180.0, 180.0);
+    let mut res = String::with_capacity(precision);
+    // Bits alternate between longitude and latitude, starting with longitude
+    let mut even = true;
+    let mut bits = 0;
+    let mut idx = 0;
+    while res.len() < precision {
+        let (rg, x) = if even { (&mut lon_rg, lon) } else { (&mut lat_rg, lat) };
+        let mid = (rg.0 + rg.1) / 2.0;
+        idx <<= 1;
+        if x >= mid {
+            idx |= 1;
+            rg.0 = mid;
+        } else {
+            rg.1 = mid;
+        }
+        even = !even;
+        bits += 1;
+        if bits == 5 {
+            res.push(GEOHASH_ALPHABET[idx] as char);
+            bits = 0;
+            idx = 0;
+        }
+    }
+    Some(res)
+}
+
+/// Rounds to `digits` decimal places
+pub fn round_to(x: f64, digits: i32) -> f64 {
+    let scale = 10f64.powi(digits);
+    (x * scale).round() / scale
+}
+
+#[cfg(test)]
+mod test_haversine {
+    use super::*;
+
+    #[test]
+    fn test_haversine() {
+        // London to Paris is about 344 km
+        let d = haversine(51.5074, -0.1278, 48.8566, 2.3522);
+        assert!((d - 343.6).abs() < 1.0, "got {}", d);
+        assert_eq!(haversine(10.0, 20.0, 10.0, 20.0), 0.0);
+    }
+}
+
+#[cfg(test)]
+mod test_geohash {
+    use super::*;
+
+    #[test]
+    fn test_geohash() {
+        assert_eq!(geohash(57.64911, 10.40744, 11), Some(String::from("u4pruydqqvj")));
+        assert_eq!(geohash(42.6, -5.6, 5), Some(String::from("ezs42")));
+        assert_eq!(geohash(0.0, 0.0, 1), Some(String::from("s")));
+    }
+    #[test]
+    fn test_out_of_range() {
+        assert_eq!(geohash(91.0, 0.0, 5), None);
+        assert_eq!(geohash(0.0, -181.0, 5), None);
+    }
+}
+
+#[cfg(test)]
+mod test_round_to {
+    use super::*;
+
+    #[test]
+    fn test_round_to() {
+        assert_eq!(round_to(40.712776, 2), 40.71);
+        assert_eq!(round_to(-74.005974, 3), -74.006);
+        assert_eq!(round_to(1234.5, 0), 1235.0);
+        assert_eq!(round_to(1234.5, -2), 1200.0);
+    }
+}
@@ -0,0 +1,75 @@
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::dialect::Dialect;
+use crate::parse_args;
+
+/// `--grep COL:REGEX` and `--grep-v COL:REGEX`: keeps only the records whose value in a column
+/// matches, or doesn't match, a regex
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GrepSpec {
+    pub col: usize,
+    pub pattern: String,
+    /// Keep the records that match, rather than those that don't
+    pub keep: bool,
+}
+
+/// Parses a `COL:REGEX` argument of `--grep` (`keep`) or `--grep-v`. The regex is everything
+/// after the first colon, so it may have colons of its own. `offset` is as in `parse_arg_cols`.
+pub fn parse_grep(spec: &str, keep: bool, offset: usize) -> GrepSpec {
+    let (col, pattern) = spec.split_once(':')
+        .unwrap_or_else(|| panic!("Invalid --grep {}, expected COL:REGEX", spec));
+    GrepSpec { col: parse_args::parse_arg_col(col, offset), pattern: pattern.to_string(), keep }
+}
+
+/// A `GrepSpec` with its regex compiled
+#[derive(Debug, Clone)]
+pub struct ColumnGrep {
+    col: usize,
+    regex: Regex,
+    keep: bool,
+}
+
+impl ColumnGrep {
+    /// With `fold_case`, the regex ignores case
+    pub fn new(spec: &GrepSpec, fold_case: bool) -> Result<ColumnGrep, regex::Error> {
+        let regex = RegexBuilder::new(&spec.pattern).case_insensitive(fold_case).build()?;
+        Ok(ColumnGrep { col: spec.col, regex, keep: spec.keep })
+    }
+
+    /// Whether a record passes. The regex sees the field's value, unquoted, anywhere in which it
+    /// may match; a record without the column has nothing to match.
+    pub fn keep(&self, fields: &[&str], dialect: &dyn Dialect) -> bool {
+        let found = fields.get(self.col).is_some_and(|field| {
+            self.regex.is_match(&dialect.unquote(if dialect.trim() { field.trim() } else { field }))
+        });
+        found == self.keep
+    }
+}
+
+#[cfg(test)]
+mod test_grep {
+    use super::*;
+    use crate::dialect::{Permissive, Rfc4180};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_grep("3:^a:b$", true, 1),
+                   GrepSpec { col: 2, pattern: String::from("^a:b$"), keep: true });
+    }
+    #[test]
+    #[should_panic]
+    fn test_no_regex_fails() {
+        parse_grep("3", true, 1);
+    }
+    #[test]
+    fn test_keep() {
+        let grep = ColumnGrep::new(&parse_grep("2:^b,c$", true, 1), false).unwrap();
+        assert!(grep.keep(&["a", "\"b,c\""], &Rfc4180));
+        assert!(!grep.keep(&["a", "\"B,c\""], &Rfc4180));
+        assert!(!grep.keep(&["a"], &Rfc4180));
+        let grep_v = ColumnGrep::new(&parse_grep("2:^b,c$", false, 1), true).unwrap();
+        assert!(!grep_v.keep(&["a", "'B,C'"], &Permissive));
+        assert!(grep_v.keep(&["a"], &Permissive));
+    }
+}
@@ -0,0 +1,755 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collate;
+use crate::decimal::{Decimal, DecimalSpec};
+use crate::dialect::Dialect;
+use crate::expr;
+use crate::precision::{self, Precision};
+
+/// An aggregate computed over the rows of each group
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Agg {
+    Count,
+    Sum(usize),
+    Mean(usize),
+    Min(usize),
+    Max(usize),
+    /// Mean of the first column weighted by the second
+    Wavg(usize, usize),
+    /// Sum of the products of two columns
+    SumProduct(usize, usize),
+    /// Sum of the first column over the sum of the second
+    Ratio(usize, usize),
+    /// The group's share of the rows, in percent
+    Percent,
+}
+
+impl Agg {
+    /// The aggregate as it's written for `--agg`, e.g. for messages. `offset` is as in
+    /// `parse_arg_cols`.
+    pub fn describe(&self, offset: usize) -> String {
+        let col = |i: &usize| format!("col{}", i + offset);
+        match self {
+            Agg::Count => String::from("count"),
+            Agg::Percent => String::from("percent"),
+            Agg::Sum(c) => format!("sum({})", col(c)),
+            Agg::Mean(c) => format!("mean({})", col(c)),
+            Agg::Min(c) => format!("min({})", col(c)),
+            Agg::Max(c) => format!("max({})", col(c)),
+            Agg::Wavg(a, b) => format!("wavg({}, {})", col(a), col(b)),
+            Agg::SumProduct(a, b) => format!("sumproduct({}, {})", col(a), col(b)),
+            Agg::Ratio(a, b) => format!("ratio(sum({}), sum({}))", col(a), col(b)),
+        }
+    }
+}
+
+/// What aggregates do with a value that isn't a finite number (text, an empty or missing field,
+/// NaN or infinity), or that would make a total overflow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NullPolicy {
+    /// Leave it out, as if the row didn't have it; a weighted pair is left out whole
+    #[default]
+    Skip,
+    /// Count it as zero. A total that would overflow is kept as it was.
+    Zero,
+    /// Stop with an error
+    Abort,
+}
+
+/// Parses an `--agg-nulls` argument
+pub fn parse_null_policy(s: &str) -> NullPolicy {
+    match s.trim() {
+        "skip" => NullPolicy::Skip,
+        "zero" => NullPolicy::Zero,
+        "abort" => NullPolicy::Abort,
+        other => panic!("Invalid null policy {}, expected skip, zero or abort", other),
+    }
+}
+
+/// Parses an `--agg` argument: `count` or `percent`; `sum`, `mean`, `min` or `max` of a column
+/// written as in expressions, e.g. `sum(col3)`; `wavg(value, weight)` or `sumproduct(a, b)` of
+/// two columns; or `ratio(sum(a), sum(b))`. `offset` is as in `parse_arg_cols`.
+pub fn parse_agg(spec: &str, offset: usize) -> Agg {
+    let spec = spec.trim();
+    match spec {
+        "count" => return Agg::Count,
+        "percent" => return Agg::Percent,
+        _ => {},
+    }
+    let (name, args) = parse_call(spec)
+        .unwrap_or_else(|| panic!("Invalid aggregate {}, expected e.g. count or sum(col3)", spec));
+    let col = |i: usize| parse_col_ref(args[i], offset);
+    let arity = match name {
+        "sum" | "mean" | "avg" | "min" | "max" => 1,
+        "wavg" | "sumproduct" | "ratio" => 2,
+        other => panic!("Unknown aggregate {}, expected count, percent, sum, mean, min, max, \
+                         wavg, sumproduct or ratio", other),
+    };
+    assert!(args.len() == arity, "{} takes {} argument(s), got {}", name, arity, args.len());
+    match name {
+        "sum" => Agg::Sum(col(0)),
+        "mean" | "avg" => Agg::Mean(col(0)),
+        "min" => Agg::Min(col(0)),
+        "max" => Agg::Max(col(0)),
+        "wavg" => Agg::Wavg(col(0), col(1)),
+        "sumproduct" => Agg::SumProduct(col(0), col(1)),
+        _ => {
+            let sum = |arg: &str| match parse_call(arg) {
+                Some(("sum", inner)) if inner.len() == 1 => parse_col_ref(inner[0], offset),
+                _ => panic!("ratio takes two sums, as in ratio(sum(col3), sum(col4))"),
+            };
+            Agg::Ratio(sum(args[0]), sum(args[1]))
+        },
+    }
+}
+
+/// Splits `name(arg, ...)` into the name and its top-level arguments
+fn parse_call(s: &str) -> Option<(&str, Vec<&str>)> {
+    let (name, rest) = s.trim().strip_suffix(')')?.split_once('(')?;
+    let mut args = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in rest.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(rest[start..i].trim());
+                start = i + 1;
+            },
+            _ => (),
+        }
+    }
+    args.push(rest[start..].trim());
+    Some((name.trim(), args))
+}
+
+/// Parses a column reference such as `col3` to a zero-indexed column
+fn parse_col_ref(s: &str, offset: usize) -> usize {
+    let n = s.trim().strip_prefix("col")
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or_else(|| panic!("Invalid column reference {}, expected e.g. col3", s.trim()));
+    assert!(offset <= n, "Column index must be at least {}", offset);
+    n - offset
+}
+
+/// `--group-by` and `--freq`: which columns make up the key and what to compute per group
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupSpec {
+    pub keys: Vec<usize>,
+    pub aggs: Vec<Agg>,
+    /// Write the key as one field, its values joined with this separator, instead of one field
+    /// per key column
+    pub key_sep: Option<String>,
+    /// Order groups by descending count rather than by first appearance, as for `--freq`
+    pub by_count: bool,
+    #[serde(default)]
+    pub nulls: NullPolicy,
+    /// Only keep this many groups, the first in order, e.g. the most frequent with `--freq`
+    #[serde(default)]
+    pub top: Option<usize>,
+}
+
+impl GroupSpec {
+    /// The header row of the records `GroupBy` makes, given the input's header: the names of the
+    /// key columns, then each aggregate as it's written for `--agg`. `offset` is as in
+    /// `parse_arg_cols`.
+    pub fn header(&self, names: &[&str], offset: usize, dialect: &dyn Dialect) -> Vec<String> {
+        let keys: Vec<String> = self.keys.iter()
+            .map(|k| match names.get(*k) {
+                Some(name) => expr::unquote(name.trim()).to_string(),
+                None => format!("col{}", k + offset),
+            })
+            .collect();
+        let mut header: Vec<String> = match &self.key_sep {
+            Some(sep) => vec![composite_key(&keys, sep)],
+            None => keys,
+        };
+        header.extend(self.aggs.iter().map(|a| a.describe(offset)));
+        header.iter().map(|h| dialect.quote(h).into_owned()).collect()
+    }
+}
+
+/// A number being aggregated: floating point, or a fixed-point decimal with `--decimal`
+#[derive(Debug, Clone, Copy)]
+enum Number {
+    Float(f64),
+    Decimal(Decimal),
+}
+
+impl Number {
+    fn zero(decimal: bool) -> Number {
+        if decimal { Number::Decimal(Decimal::zero()) } else { Number::Float(0.0) }
+    }
+
+    fn to_f64(self) -> f64 {
+        match self {
+            Number::Float(x) => x,
+            Number::Decimal(d) => d.to_f64(),
+        }
+    }
+
+    /// `None` on overflow
+    fn add(self, other: Number) -> Option<Number> {
+        match (self, other) {
+            (Number::Decimal(a), Number::Decimal(b)) => a.checked_add(b).map(Number::Decimal),
+            (a, b) => Some(Number::Float(a.to_f64() + b.to_f64())).filter(Number::is_finite),
+        }
+    }
+
+    /// `None` on overflow
+    fn mul(self, other: Number) -> Option<Number> {
+        match (self, other) {
+            (Number::Decimal(a), Number::Decimal(b)) => a.checked_mul(b).map(Number::Decimal),
+            (a, b) => Some(Number::Float(a.to_f64() * b.to_f64())).filter(Number::is_finite),
+        }
+    }
+
+    fn is_finite(&self) -> bool {
+        match self {
+            Number::Float(x) => x.is_finite(),
+            Number::Decimal(_) => true,
+        }
+    }
+
+    /// `None` when dividing by zero
+    fn div(self, other: Number) -> Option<Number> {
+        match (self, other) {
+            (Number::Decimal(a), Number::Decimal(b)) => a.checked_div(b).map(Number::Decimal),
+            (_, b) if b.to_f64() == 0.0 => None,
+            (a, b) => Some(Number::Float(a.to_f64() / b.to_f64())),
+        }
+    }
+
+    fn less_than(self, other: Number) -> bool {
+        match (self, other) {
+            (Number::Decimal(a), Number::Decimal(b)) => a < b,
+            (a, b) => a.to_f64() < b.to_f64(),
+        }
+    }
+
+    fn format(self, precision: Option<&Precision>) -> String {
+        match self {
+            Number::Float(x) => precision::format_num(x, precision),
+            Number::Decimal(d) => precision::format_dec(d, precision),
+        }
+    }
+}
+
+/// One row's values as the aggregates read them, with bad values handled per the null policy
+struct Row<'a> {
+    fields: &'a [&'a str],
+    decimal: Option<&'a DecimalSpec>,
+    nulls: NullPolicy,
+    /// Values skipped or counted as zero so far
+    bad: u64,
+}
+
+impl<'a> Row<'a> {
+    fn zero(&self) -> Number {
+        Number::zero(self.decimal.is_some())
+    }
+
+    /// The number in column `col`; `None` if it's to be skipped
+    fn num(&mut self, col: usize) -> Result<Option<Number>, String> {
+        let field = self.fields.get(col).copied().unwrap_or("");
+        let x = match self.decimal {
+            Some(spec) => spec.parse_field(col, field).map(Number::Decimal),
+            None => expr::parse_num(field).map(Number::Float).filter(Number::is_finite),
+        };
+        if x.is_some() {
+            return Ok(x);
+        }
+        self.bad += 1;
+        match self.nulls {
+            NullPolicy::Skip => Ok(None),
+            NullPolicy::Zero => Ok(Some(self.zero())),
+            NullPolicy::Abort => Err(format!("{:?} isn't a number", expr::unquote(field.trim()))),
+        }
+    }
+
+    /// Checks the result of an operation; `None` if it overflowed and is to be skipped
+    fn checked<T>(&mut self, x: Option<T>) -> Result<Option<T>, String> {
+        if x.is_none() {
+            self.bad += 1;
+            if self.nulls == NullPolicy::Abort {
+                return Err(String::from("the result overflows"));
+            }
+        }
+        Ok(x)
+    }
+}
+
+/// Running state of one aggregate
+#[derive(Debug, Clone)]
+enum Acc {
+    Count(u64),
+    Sum(Number),
+    Mean(Number, u64),
+    Min(Option<Number>),
+    Max(Option<Number>),
+    /// Sums of weight times value and of weight
+    Wavg(Number, Number),
+    SumProduct(Number),
+    /// Sums of the numerator and denominator columns
+    Ratio(Number, Number),
+    /// Rows in the group, to divide by the rows in every group
+    Percent(u64),
+}
+
+impl Acc {
+    fn new(agg: &Agg, decimal: bool) -> Acc {
+        let zero = Number::zero(decimal);
+        match agg {
+            Agg::Count => Acc::Count(0),
+            Agg::Sum(_) => Acc::Sum(zero),
+            Agg::Mean(_) => Acc::Mean(zero, 0),
+            Agg::Min(_) => Acc::Min(None),
+            Agg::Max(_) => Acc::Max(None),
+            Agg::Wavg(_, _) => Acc::Wavg(zero, zero),
+            Agg::SumProduct(_, _) => Acc::SumProduct(zero),
+            Agg::Ratio(_, _) => Acc::Ratio(zero, zero),
+            Agg::Percent => Acc::Percent(0),
+        }
+    }
+
+    /// Adds one row, leaving the accumulator as it was if one of its values is skipped
+    fn update(&mut self, agg: &Agg, row: &mut Row) -> Result<(), String> {
+        match (self, agg) {
+            (Acc::Count(n), _) | (Acc::Percent(n), _) => *n += 1,
+            (Acc::Sum(s), Agg::Sum(col)) => {
+                if let Some(x) = row.num(*col)? {
+                    if let Some(total) = row.checked(s.add(x))? {
+                        *s = total;
+                    }
+                }
+            },
+            (Acc::Mean(s, n), Agg::Mean(col)) => {
+                if let Some(x) = row.num(*col)? {
+                    if let Some(total) = row.checked(s.add(x))? {
+                        *s = total;
+                        *n += 1;
+                    }
+                }
+            },
+            (Acc::Min(m), Agg::Min(col)) => {
+                if let Some(x) = row.num(*col)? {
+                    *m = Some(m.filter(|m| m.less_than(x)).unwrap_or(x));
+                }
+            },
+            (Acc::Max(m), Agg::Max(col)) => {
+                if let Some(x) = row.num(*col)? {
+                    *m = Some(m.filter(|m| x.less_than(*m)).unwrap_or(x));
+                }
+            },
+            (Acc::Wavg(wx, w), Agg::Wavg(x_col, w_col)) => {
+                let (x, weight) = (row.num(*x_col)?, row.num(*w_col)?);
+                if let (Some(x), Some(weight)) = (x, weight) {
+                    let totals = weight.mul(x).and_then(|p| Some((wx.add(p)?, w.add(weight)?)));
+                    if let Some((new_wx, new_w)) = row.checked(totals)? {
+                        *wx = new_wx;
+                        *w = new_w;
+                    }
+                }
+            },
+            (Acc::SumProduct(s), Agg::SumProduct(a, b)) => {
+                let (a, b) = (row.num(*a)?, row.num(*b)?);
+                if let (Some(a), Some(b)) = (a, b) {
+                    if let Some(total) = row.checked(a.mul(b).and_then(|p| s.add(p)))? {
+                        *s = total;
+                    }
+                }
+            },
+            (Acc::Ratio(n, d), Agg::Ratio(n_col, d_col)) => {
+                for (total, col) in [(n, n_col), (d, d_col)] {
+                    if let Some(x) = row.num(*col)? {
+                        if let Some(sum) = row.checked(total.add(x))? {
+                            *total = sum;
+                        }
+                    }
+                }
+            },
+            _ => unreachable!("accumulator doesn't match its aggregate"),
+        }
+        Ok(())
+    }
+
+    /// The aggregate's value, out of `rows` in every group
+    fn value(&self, precision: Option<&Precision>, rows: u64) -> String {
+        let num = |x: Number| x.format(precision);
+        match self {
+            Acc::Count(n) => n.to_string(),
+            // Two decimals unless asked otherwise, since shares rarely come out round
+            Acc::Percent(n) => {
+                let default = Precision { digits: 2, mode: Default::default() };
+                precision::format_num(*n as f64 * 100.0 / rows as f64,
+                                      Some(precision.unwrap_or(&default)))
+            },
+            Acc::Sum(s) | Acc::SumProduct(s) => num(*s),
+            Acc::Mean(_, 0) => String::new(),
+            Acc::Mean(s, n) => {
+                let n = match s {
+                    Number::Float(_) => Number::Float(*n as f64),
+                    Number::Decimal(_) => Number::Decimal(Decimal::new(*n as i128, 0)),
+                };
+                s.div(n).map(num).unwrap_or_default()
+            },
+            Acc::Min(m) | Acc::Max(m) => m.map(num).unwrap_or_default(),
+            // Undefined without any weight, or with nothing to divide by
+            Acc::Wavg(n, d) | Acc::Ratio(n, d) => n.div(*d).map(num).unwrap_or_default(),
+        }
+    }
+}
+
+/// A group's key values, as first seen, and its aggregates
+type Group = (Vec<String>, Vec<Acc>);
+
+/// Groups rows by their values in the key columns and aggregates each group, in memory
+pub struct GroupBy {
+    spec: GroupSpec,
+    fold_case: bool,
+    /// Index of each key in `groups`
+    index: HashMap<Vec<String>, usize>,
+    groups: Vec<Group>,
+    /// Rows in each group, for ordering by count
+    counts: Vec<u64>,
+    precision: Option<Precision>,
+    decimal: Option<DecimalSpec>,
+    /// Values each aggregate skipped or counted as zero, over all groups
+    bad: Vec<u64>,
+}
+
+impl GroupBy {
+    /// With `fold_case`, keys that differ only in case fall in the same group, which is named
+    /// after the first spelling seen
+    pub fn new(spec: GroupSpec, fold_case: bool) -> GroupBy {
+        GroupBy {
+            bad: vec![0; spec.aggs.len()],
+            spec,
+            fold_case,
+            index: HashMap::new(),
+            groups: Vec::new(),
+            counts: Vec::new(),
+            precision: None,
+            decimal: None,
+        }
+    }
+
+    /// Rounds the aggregates to a fixed number of decimals
+    pub fn with_precision(mut self, precision: Option<Precision>) -> GroupBy {
+        self.precision = precision;
+        self
+    }
+
+    /// Aggregates in fixed-point decimals, so sums of amounts such as money come out exact
+    pub fn with_decimal(mut self, decimal: Option<DecimalSpec>) -> GroupBy {
+        self.decimal = decimal;
+        self
+    }
+
+    /// Adds a row to its group. With `NullPolicy::Abort`, fails on the first bad value with the
+    /// index of the aggregate and what's wrong.
+    pub fn insert(&mut self, fields: &[&str]) -> Result<(), (usize, String)> {
+        let key: Vec<&str> = self.spec.keys.iter()
+            .map(|i| expr::unquote(fields.get(*i).unwrap_or(&"").trim()))
+            .collect();
+        let fold_case = self.fold_case;
+        let lookup: Vec<String> = key.iter()
+            .map(|k| if fold_case { collate::fold_case(k).into_owned() } else { k.to_string() })
+            .collect();
+        let i = match self.index.get(&lookup) {
+            Some(i) => *i,
+            None => {
+                let decimal = self.decimal.is_some();
+                let accs = self.spec.aggs.iter().map(|a| Acc::new(a, decimal)).collect();
+                self.groups.push((key.iter().map(|k| k.to_string()).collect(), accs));
+                self.counts.push(0);
+                self.index.insert(lookup, self.groups.len() - 1);
+                self.groups.len() - 1
+            },
+        };
+        self.counts[i] += 1;
+        let accs = self.groups[i].1.iter_mut().zip(self.spec.aggs.iter());
+        for (j, (acc, agg)) in accs.enumerate() {
+            let mut row = Row { fields, decimal: self.decimal.as_ref(), nulls: self.spec.nulls,
+                                bad: 0 };
+            let res = acc.update(agg, &mut row);
+            self.bad[j] += row.bad;
+            res.map_err(|e| (j, e))?;
+        }
+        Ok(())
+    }
+
+    /// How many values each aggregate skipped, or counted as zero, so far
+    pub fn bad_values(&self) -> &[u64] {
+        &self.bad
+    }
+
+    /// One output record per group, or per group of the `top` first: the key, then each
+    /// aggregate, quoted for `dialect` where needed
+    pub fn into_records(self, dialect: &dyn Dialect) -> Vec<Vec<String>> {
+        let GroupBy { spec, groups, counts, precision, .. } = self;
+        let rows: u64 = counts.iter().sum();
+        let mut groups: Vec<(u64, Group)> = counts.into_iter()
+            .zip(groups)
+            .collect();
+        if spec.by_count {
+            // Stable, so ties stay in order of first appearance
+            groups.sort_by_key(|(n, _)| std::cmp::Reverse(*n));
+        }
+        groups.truncate(spec.top.unwrap_or(usize::MAX));
+        groups.into_iter()
+            .map(|(_, (key, accs))| {
+                let mut record: Vec<String> = match &spec.key_sep {
+                    Some(sep) => vec![dialect.quote(&composite_key(&key, sep)).into_owned()],
+                    None => key.iter().map(|k| dialect.quote(k).into_owned()).collect(),
+                };
+                record.extend(accs.iter()
+                    .map(|a| dialect.quote(&a.value(precision.as_ref(), rows)).into_owned()));
+                record
+            })
+            .collect()
+    }
+}
+
+/// Joins key values with `sep`, escaping backslashes and separators within the values with a
+/// backslash, so the composite key can be split back into the same values
+pub fn composite_key<S: AsRef<str>>(values: &[S], sep: &str) -> String {
+    let mut res = String::new();
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            res.push_str(sep);
+        }
+        let value = value.as_ref().replace('\\', "\\\\");
+        if sep.is_empty() {
+            res.push_str(&value);
+        } else {
+            res.push_str(&value.replace(sep, &format!("\\{}", sep)));
+        }
+    }
+    res
+}
+
+/// Splits a key made by `composite_key` back into its values
+pub fn split_composite_key(key: &str, sep: &str) -> Vec<String> {
+    assert!(!sep.is_empty(), "An empty separator can't be split on");
+    let mut res = vec![String::new()];
+    let mut rest = key;
+    while !rest.is_empty() {
+        if let Some(escaped) = rest.strip_prefix('\\') {
+            if let Some(after) = escaped.strip_prefix(sep) {
+                res.last_mut().unwrap().push_str(sep);
+                rest = after;
+            } else {
+                let c = escaped.chars().next().unwrap_or('\\');
+                res.last_mut().unwrap().push(c);
+                rest = &escaped[c.len_utf8().min(escaped.len())..];
+            }
+        } else if let Some(after) = rest.strip_prefix(sep) {
+            res.push(String::new());
+            rest = after;
+        } else {
+            let c = rest.chars().next().unwrap();
+            res.last_mut().unwrap().push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test_parse_agg {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_agg("count", 1), Agg::Count);
+        assert_eq!(parse_agg("sum(col3)", 1), Agg::Sum(2));
+        assert_eq!(parse_agg(" mean( col1 ) ", 0), Agg::Mean(1));
+        assert_eq!(parse_agg("max(col2)", 1), Agg::Max(1));
+        assert_eq!(parse_agg("wavg(col3, col4)", 1), Agg::Wavg(2, 3));
+        assert_eq!(parse_agg("sumproduct(col3,col4)", 1), Agg::SumProduct(2, 3));
+        assert_eq!(parse_agg("ratio(sum(col3), sum(col4))", 1), Agg::Ratio(2, 3));
+    }
+    #[test]
+    #[should_panic]
+    fn test_ratio_of_non_sums_fails() {
+        parse_agg("ratio(col3, col4)", 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_wrong_arity_fails() {
+        parse_agg("wavg(col3)", 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_unknown_fails() {
+        parse_agg("median(col1)", 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_bad_col_fails() {
+        parse_agg("sum(3)", 1);
+    }
+}
+
+#[cfg(test)]
+mod test_group_by {
+    use super::*;
+    use crate::dialect::{Permissive, Tsv};
+    use crate::line;
+
+    fn group(spec: GroupSpec, fold_case: bool, rows: &[&str]) -> Vec<String> {
+        let mut group = GroupBy::new(spec, fold_case);
+        for row in rows.iter() {
+            group.insert(&line::split_line(row)).unwrap();
+        }
+        group.into_records(&Permissive).iter().map(|r| r.join(",")).collect()
+    }
+
+    fn spec(keys: Vec<usize>, aggs: Vec<Agg>) -> GroupSpec {
+        GroupSpec { keys, aggs, key_sep: None, by_count: false, nulls: NullPolicy::Skip,
+                    top: None }
+    }
+
+    #[test]
+    fn test_multi_key() {
+        let rows = ["us,web,3", "eu,web,1", "us,app,2", "us,web,4"];
+        let spec = spec(vec![0, 1], vec![Agg::Count, Agg::Sum(2), Agg::Mean(2), Agg::Max(2)]);
+        assert_eq!(group(spec, false, &rows),
+                   vec!["us,web,2,7,3.5,4", "eu,web,1,1,1,1", "us,app,1,2,2,2"]);
+    }
+    #[test]
+    fn test_weighted() {
+        // region, price, quantity
+        let rows = ["us,10,1", "us,20,3", "eu,5,0", "eu,x,2"];
+        let aggs = vec![Agg::Wavg(1, 2), Agg::SumProduct(1, 2), Agg::Ratio(1, 2)];
+        assert_eq!(group(spec(vec![0], aggs), false, &rows), vec!["us,17.5,70,7.5", "eu,,0,2.5"]);
+    }
+    #[test]
+    fn test_header() {
+        let names = ["region", "\"channel\"", "price", "quantity"];
+        let spec = spec(vec![0, 1], vec![Agg::Count, Agg::Wavg(2, 3)]);
+        assert_eq!(spec.header(&names, 1, &Permissive),
+                   vec!["region", "channel", "count", "\"wavg(col3, col4)\""]);
+        let spec = GroupSpec { keys: vec![1, 5], key_sep: Some(String::from("|")), ..spec };
+        assert_eq!(spec.header(&names, 1, &Tsv)[0], "channel|col6");
+    }
+    #[test]
+    fn test_precision() {
+        let mut group = GroupBy::new(spec(vec![], vec![Agg::Sum(0), Agg::Mean(0), Agg::Count]),
+                                     false)
+            .with_precision(Some(Precision { digits: 2, mode: Default::default() }));
+        for row in ["0.1", "0.2", "0.005"].iter() {
+            group.insert(&[row]).unwrap();
+        }
+        assert_eq!(group.into_records(&Permissive), vec![vec!["0.31", "0.10", "3"]]);
+    }
+    #[test]
+    fn test_decimal() {
+        let aggs = vec![Agg::Sum(0), Agg::Mean(0), Agg::Min(0), Agg::Ratio(0, 1)];
+        let rows = ["0.10,3", "0.20,4"];
+        let mut group = GroupBy::new(spec(vec![], aggs.clone()), false)
+            .with_decimal(Some(DecimalSpec::default()));
+        for row in rows.iter() {
+            group.insert(&line::split_line(row)).unwrap();
+        }
+        assert_eq!(group.into_records(&Permissive),
+                   vec![vec!["0.30", "0.15000000", "0.10", "0.04285714"]]);
+        let mut group = GroupBy::new(spec(vec![], aggs), false);
+        for row in rows.iter() {
+            group.insert(&line::split_line(row)).unwrap();
+        }
+        assert_eq!(group.into_records(&Permissive)[0][0], "0.30000000000000004");
+    }
+    #[test]
+    fn test_null_policies() {
+        let aggs = vec![Agg::Sum(1), Agg::Mean(1), Agg::Min(1), Agg::Wavg(1, 2)];
+        let rows = ["a,4,1", "a,x,1", "a,NaN,1", "a,,1", "b,1e308,1", "b,1e308,1"];
+        let run = |nulls| {
+            let mut group = GroupBy::new(GroupSpec { nulls, ..spec(vec![0], aggs.clone()) },
+                                         false);
+            for row in rows.iter() {
+                group.insert(&line::split_line(row))?;
+            }
+            let bad = group.bad_values().to_vec();
+            Ok::<_, (usize, String)>((group.into_records(&Permissive), bad))
+        };
+        let (records, bad) = run(NullPolicy::Skip).unwrap();
+        assert_eq!(records[0], vec!["a", "4", "4", "4", "4"]);
+        // The second 1e308 would overflow the sum and the mean
+        assert_eq!(records[1][1], 1e308.to_string());
+        assert_eq!(bad, vec![4, 4, 3, 4]);
+        let (records, bad) = run(NullPolicy::Zero).unwrap();
+        assert_eq!(records[0], vec!["a", "4", "1", "0", "1"]);
+        assert_eq!(bad, vec![4, 4, 3, 4]);
+        assert_eq!(run(NullPolicy::Abort).err().unwrap(),
+                   (0, String::from("\"x\" isn't a number")));
+    }
+    #[test]
+    fn test_decimal_overflow() {
+        let mut group = GroupBy::new(spec(vec![], vec![Agg::Sum(0)]), false)
+            .with_decimal(Some(DecimalSpec::default()));
+        let big = "100000000000000000000000000000000000000";
+        for row in [big, big, "1"].iter() {
+            group.insert(&[row]).unwrap();
+        }
+        assert_eq!(group.bad_values(), &[1]);
+        assert_eq!(group.into_records(&Permissive)[0][0],
+                   "100000000000000000000000000000000000001");
+    }
+    #[test]
+    #[should_panic]
+    fn test_bad_null_policy_fails() {
+        parse_null_policy("ignore");
+    }
+    #[test]
+    fn test_describe() {
+        assert_eq!(parse_agg("ratio(sum(col3), sum(col4))", 1).describe(1),
+                   "ratio(sum(col3), sum(col4))");
+        assert_eq!(Agg::Wavg(0, 1).describe(0), "wavg(col0, col1)");
+    }
+    #[test]
+    fn test_by_count() {
+        let rows = ["a", "b", "b", "c", "c"];
+        let spec = GroupSpec { by_count: true, ..spec(vec![0], vec![Agg::Count]) };
+        assert_eq!(group(spec, false, &rows), vec!["b,2", "c,2", "a,1"]);
+    }
+    #[test]
+    fn test_top_percent() {
+        let rows = ["a", "b", "b", "c", "c", "c"];
+        let spec = GroupSpec { by_count: true, top: Some(2),
+                               ..spec(vec![0], vec![Agg::Count, parse_agg("percent", 1)]) };
+        assert_eq!(group(spec, false, &rows), vec!["c,3,50.00", "b,2,33.33"]);
+    }
+    #[test]
+    fn test_fold_case() {
+        let spec = spec(vec![0], vec![Agg::Count]);
+        assert_eq!(group(spec.clone(), true, &["Straße", "STRASSE"]), vec!["Straße,2"]);
+        assert_eq!(group(spec, false, &["Straße", "STRASSE"]), vec!["Straße,1", "STRASSE,1"]);
+    }
+    #[test]
+    fn test_key_sep() {
+        let rows = ["\"a,b\",c", "a|b,c"];
+        let spec = GroupSpec { key_sep: Some(String::from("|")), ..spec(vec![0, 1], vec![]) };
+        assert_eq!(group(spec, false, &rows), vec!["\"a,b|c\"", "a\\|b|c"]);
+    }
+    #[test]
+    fn test_dialect() {
+        let mut group = GroupBy::new(spec(vec![0], vec![Agg::Count]), false);
+        group.insert(&["a,b"]).unwrap();
+        assert_eq!(group.into_records(&Tsv), vec![vec!["a,b", "1"]]);
+    }
+    #[test]
+    fn test_composite_key_round_trip() {
+        for values in [vec!["a", "b"], vec!["a|b", "c\\"], vec!["", "||"], vec!["x\\|y"]].iter() {
+            for sep in ["|", "::", ","].iter() {
+                let key = composite_key(values, sep);
+                assert_eq!(split_composite_key(&key, sep), *values, "{:?} {}", key, sep);
+            }
+        }
+    }
+}
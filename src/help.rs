@@ -0,0 +1,227 @@
+use crate::dialect::{self, Dialect};
+use crate::options;
+
+/// The topics `ccut help` explains, each with what it covers
+pub const TOPICS: &[(&str, &str)] = &[
+    ("columns", "the grammar of column lists, with examples"),
+    ("dialects", "the dialects ccut reads and writes, as convert --from and --to name them"),
+    ("examples", "commands for common tasks"),
+];
+
+/// The grammar of the column lists `parse_args::parse_arg_cols` reads
+pub const COLUMN_GRAMMAR: &str = "\
+list   = item { \",\" item }
+item   = index | range
+range  = index \"-\" [ index ]
+index  = [ \"-\" ] digits
+
+Columns are numbered from 1, or from 0 with -0, when ranges are half-open.
+A negative index counts from the end of each row, -1 being the last column.
+A range with no end runs to the end of each row; both ends of a closed range
+must count from the same end. Columns may repeat and come in any order.";
+
+/// Column lists shown under the grammar, with what they select
+pub const COLUMN_EXAMPLES: &[(&str, &str)] = &[
+    ("1,3", "the first and third columns"),
+    ("3,1", "the third column, then the first"),
+    ("2-4", "the second to fourth columns"),
+    ("5-", "the fifth column and every one after it"),
+    ("-1", "the last column"),
+    ("-3--1", "the last three columns"),
+    ("1,1", "the first column twice"),
+];
+
+/// The options listed under the column list grammar, described as `--help` describes them
+pub const COLUMN_OPTIONS: &[&str] = &["--zero", "--one", "--complement", "--regex-cols"];
+
+/// The options listed under the dialects, described as `--help` describes them
+pub const DIALECT_OPTIONS: &[&str] = &["--delimiter", "--quote", "--no-single-quote", "--no-quote",
+                                       "--requote", "--sniff", "--multiline"];
+
+/// A command for a common task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Example {
+    /// What kind of task it's for, which examples are grouped by
+    pub task: &'static str,
+    pub command: &'static str,
+    pub does: &'static str,
+}
+
+pub const EXAMPLES: &[Example] = &[
+    Example { task: "Selecting", command: "ccut 1,3 data.csv",
+              does: "Print the first and third columns" },
+    Example { task: "Selecting", command: "ccut --complement 2 data.csv",
+              does: "Print every column but the second" },
+    Example { task: "Selecting", command: "ccut --header --regex-cols 'ts_.*|status' data.csv",
+              does: "Print the columns whose header names match" },
+    Example { task: "Selecting", command: "ccut -d tab 2- data.tsv",
+              does: "Drop the first column of a tab-separated file" },
+    Example { task: "Filtering", command: "ccut -w 'col5 > 100' 1,5 data.csv",
+              does: "Keep the rows whose fifth column is over 100" },
+    Example { task: "Filtering", command: "ccut --grep '3:^(WARN|ERROR)$' 1- app.csv",
+              does: "Keep the rows whose third column is WARN or ERROR" },
+    Example { task: "Filtering", command: "ccut --unique-by 1 1- data.csv",
+              does: "Keep the first record of each value of the first column" },
+    Example { task: "Filtering", command: "ccut --header --skip 10 -n 5 1- data.csv",
+              does: "Print the header and records 11 to 15" },
+    Example { task: "Computing", command: "ccut -c 'round(col3 * col4, 2)' 1 orders.csv",
+              does: "Append the product of two columns to the first" },
+    Example { task: "Summarizing", command: "ccut --group-by 2 --agg count --agg 'sum(col4)' \
+                                             sales.csv",
+              does: "Count and total the rows of each value of the second column" },
+    Example { task: "Summarizing", command: "ccut --header --freq 3 --top 10 data.csv",
+              does: "Show the ten most frequent values of the third column, with their counts \
+                     and percentages" },
+    Example { task: "Summarizing", command: "ccut --stats 3,4 data.csv",
+              does: "Describe the values of two columns" },
+    Example { task: "Inspecting", command: "ccut --preview-sample data.csv",
+              does: "Show rows from the head, middle and tail, under their column numbers" },
+    Example { task: "Converting", command: "ccut convert --to tsv data.csv",
+              does: "Rewrite a CSV as TSV, keeping every field" },
+    Example { task: "Converting", command: "ccut --requote always 1- data.csv",
+              does: "Quote every field of the output" },
+];
+
+/// The text of a topic, or `None` if there's no such topic
+pub fn topic(name: &str) -> Option<String> {
+    match name {
+        "columns" => {
+            let examples: Vec<String> = COLUMN_EXAMPLES.iter()
+                .map(|(cols, selects)| format!("  {:8}{}", cols, selects))
+                .collect();
+            Some(format!("{}\n\nExamples:\n{}\n\nOptions:\n{}", COLUMN_GRAMMAR,
+                         examples.join("\n"), describe_options(COLUMN_OPTIONS)))
+        },
+        "dialects" => {
+            let dialects: String = dialect::DIALECT_NAMES.iter()
+                .map(|name| {
+                    let dialect = dialect::by_name(name).expect("every listed dialect exists");
+                    format!("{}\n  {}\n", name, describe(dialect.as_ref()))
+                })
+                .collect();
+            Some(format!("{}\nOptions:\n{}", dialects, describe_options(DIALECT_OPTIONS)))
+        },
+        "examples" => {
+            let mut res = String::new();
+            for (i, example) in EXAMPLES.iter().enumerate() {
+                if i == 0 || EXAMPLES[i - 1].task != example.task {
+                    res.push_str(&format!("{}{}:\n", if i == 0 { "" } else { "\n" },
+                                          example.task));
+                }
+                res.push_str(&format!("  {}\n      {}\n", example.does, example.command));
+            }
+            Some(res)
+        },
+        _ => None,
+    }
+}
+
+/// The names and help of some options, taken from the table the argument parser registers, with
+/// the help wrapped under the names
+fn describe_options(names: &[&str]) -> String {
+    let mut res = String::new();
+    for name in names {
+        let option = options::find(name).unwrap_or_else(|| panic!("Unknown option {}", name));
+        res.push_str(&format!("  {}{}\n", option.names.join(", "),
+                              if option.takes_value { " VALUE" } else { "" }));
+        let mut line = String::new();
+        for word in option.help.split_whitespace() {
+            if !line.is_empty() && line.len() + 1 + word.len() > 72 {
+                res.push_str(&format!("      {}\n", line));
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        res.push_str(&format!("      {}\n", line));
+    }
+    res
+}
+
+/// A sentence on how a dialect delimits, quotes and escapes fields, read off the dialect itself
+pub fn describe(dialect: &dyn Dialect) -> String {
+    let name = |c: char| match c {
+        '\t' => String::from("tab"),
+        '\\' => String::from("backslash"),
+        c => format!("{:?}", c),
+    };
+    let mut parts = vec![format!("fields end at {}", name(dialect.delimiter()))];
+    let quotes: Vec<String> = dialect.quotes().iter().map(|q| name(*q)).collect();
+    parts.push(if quotes.is_empty() {
+        String::from("nothing quotes them")
+    } else {
+        format!("quoted with {}", quotes.join(" or "))
+    });
+    match (dialect.escape(), dialect.escape_unquoted()) {
+        (Some(e), true) => parts.push(format!("{} escapes anywhere", name(e))),
+        (Some(e), false) => parts.push(format!("{} escapes within quotes", name(e))),
+        (None, _) => {},
+    }
+    if dialect.doubled_quotes() {
+        parts.push(String::from("a doubled quote stands for one"));
+    }
+    if dialect.trim() {
+        parts.push(String::from("spaces around records are ignored"));
+    }
+    if dialect.terminator() == "\r\n" {
+        parts.push(String::from("records end in CRLF"));
+    }
+    parts.join("; ")
+}
+
+#[cfg(test)]
+mod test_help {
+    use super::*;
+    use crate::dialect::{PgCopy, Rfc4180};
+    use crate::parse_args;
+
+    #[test]
+    fn test_column_examples_parse() {
+        for (cols, _) in COLUMN_EXAMPLES.iter() {
+            assert!(parse_args::try_parse_arg_cols(cols, 1).is_ok(), "{}", cols);
+        }
+    }
+    #[test]
+    fn test_topics() {
+        for (name, _) in TOPICS.iter() {
+            assert!(topic(name).is_some(), "{}", name);
+        }
+        assert!(topic("nothing").is_none());
+    }
+    #[test]
+    fn test_topic_options() {
+        let columns = topic("columns").unwrap();
+        assert!(columns.contains("  -0, --zero\n      Zero-index columns."), "{}", columns);
+        assert!(columns.contains("  --regex-cols VALUE\n"), "{}", columns);
+        assert!(topic("dialects").unwrap().contains("  -d, --delimiter VALUE\n"));
+        assert!(describe_options(DIALECT_OPTIONS).lines().all(|l| l.len() <= 80));
+    }
+    #[test]
+    fn test_examples_use_options() {
+        for example in EXAMPLES.iter() {
+            let mut words = example.command.split_whitespace().skip(1).peekable();
+            if words.peek() == Some(&"convert") {
+                continue;
+            }
+            while let Some(word) = words.next() {
+                if word.starts_with('-') && !parse_args::is_col_list(word) {
+                    let option = options::find(word)
+                        .unwrap_or_else(|| panic!("{} in {}", word, example.command));
+                    if option.takes_value {
+                        assert!(words.next().is_some(), "{}", example.command);
+                    }
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_describe() {
+        assert_eq!(describe(&Rfc4180),
+                   "fields end at ','; quoted with '\"'; a doubled quote stands for one; records \
+                    end in CRLF");
+        assert_eq!(describe(&PgCopy),
+                   "fields end at tab; nothing quotes them; backslash escapes anywhere");
+    }
+}
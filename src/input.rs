@@ -0,0 +1,597 @@
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+#[cfg(not(unix))]
+use std::{io::{Seek, SeekFrom}, sync::Mutex};
+
+use flate2::bufread::MultiGzDecoder;
+
+use crate::dialect::Quoting;
+
+/// The file name that stands for stdin
+pub const STDIN: &str = "-";
+
+/// The bytes gzip and zstd streams start with
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Bytes a `ChunkReader` starts reading at a time, and the most it grows to
+pub const MIN_CHUNK_BYTES: usize = 64 * 1024;
+pub const MAX_CHUNK_BYTES: usize = 16 * 1024 * 1024;
+
+/// Names of the inputs to read, in order: stdin if none were given
+pub fn names(paths: &[PathBuf]) -> Vec<String> {
+    if paths.is_empty() {
+        vec![STDIN.to_string()]
+    } else {
+        paths.iter().map(|p| p.display().to_string()).collect()
+    }
+}
+
+/// The one input file, if there's exactly one and it isn't stdin, so it can be read directly
+/// rather than as a stream
+pub fn single_file(paths: &[PathBuf]) -> Option<&Path> {
+    match paths {
+        [path] if path.as_os_str() != STDIN => Some(path),
+        _ => None,
+    }
+}
+
+/// Opens every input, failing on the first one that can't be, before any is read. Files are
+/// only held open while they're read, so that any number of them can be read one after the
+/// other. Inputs compressed with gzip or zstd are decompressed as they're read. With
+/// `buffer_bytes`, as `--buffer-bytes` gives it, they're read that many bytes at a time, rather
+/// than in chunks that grow with the records.
+pub fn open(paths: &[PathBuf], buffer_bytes: Option<usize>)
+            -> io::Result<Vec<(String, Box<dyn BufRead>)>> {
+    names(paths).into_iter()
+        .map(|name| {
+            let reader: Box<dyn BufRead> = if name == STDIN {
+                // Stdin's own buffer still comes first, with whatever `peek` left in it
+                decompress(Box::new(ChunkReader::new(io::stdin(), buffer_bytes)), buffer_bytes)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", name, e)))?
+            } else {
+                File::open(&name)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", name, e)))?;
+                Box::new(LazyFile { name: name.clone(), buffer_bytes, reader: None,
+                                    done: false })
+            };
+            Ok((name, reader))
+        })
+        .collect()
+}
+
+/// An input file that's opened when it's first read and closed once it's been read to the end
+struct LazyFile {
+    name: String,
+    buffer_bytes: Option<usize>,
+    reader: Option<Box<dyn BufRead>>,
+    done: bool,
+}
+
+impl LazyFile {
+    fn open(&self) -> io::Result<Box<dyn BufRead>> {
+        let named = |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", self.name, e));
+        let file = File::open(&self.name).map_err(named)?;
+        decompress(Box::new(ChunkReader::new(file, self.buffer_bytes)), self.buffer_bytes)
+            .map_err(named)
+    }
+}
+
+impl Read for LazyFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let available = self.fill_buf()?;
+            let n = available.len().min(buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for LazyFile {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.done {
+            return Ok(&[]);
+        }
+        if self.reader.is_none() {
+            self.reader = Some(self.open()?);
+        }
+        if self.reader.as_mut().expect("just opened").fill_buf()?.is_empty() {
+            self.reader = None;
+            self.done = true;
+            return Ok(&[]);
+        }
+        self.reader.as_mut().expect("just opened").fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Some(reader) = &mut self.reader {
+            reader.consume(amt);
+        }
+    }
+}
+
+/// Decompresses `reader` if it starts like a gzip or zstd stream, whatever its name, and passes
+/// it through as it is otherwise, reading what it decompresses `buffer_bytes` at a time if set
+pub fn decompress<'a>(mut reader: Box<dyn BufRead + 'a>, buffer_bytes: Option<usize>)
+                      -> io::Result<Box<dyn BufRead + 'a>> {
+    let start = reader.fill_buf()?;
+    if start.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(ChunkReader::new(MultiGzDecoder::new(reader), buffer_bytes)))
+    } else if start.starts_with(ZSTD_MAGIC) {
+        Ok(Box::new(ChunkReader::new(zstd::Decoder::with_buffer(reader)?, buffer_bytes)))
+    } else {
+        Ok(reader)
+    }
+}
+
+/// Up to `n` bytes from the start of the first input, decompressed, without consuming them if
+/// it's stdin, cut back to the end of the last line there that's known to be whole. Compressed
+/// stdin can't be looked at without consuming it, so it gives nothing.
+pub fn peek(paths: &[PathBuf], n: usize) -> io::Result<Vec<u8>> {
+    let mut res = Vec::new();
+    match names(paths).first().map(|s| s.as_str()) {
+        Some(STDIN) => {
+            // Stdin's buffer is shared, so what's left in it is read again by the next lock
+            let mut stdin = io::stdin().lock();
+            let start = stdin.fill_buf()?;
+            if !start.starts_with(GZIP_MAGIC) && !start.starts_with(ZSTD_MAGIC) {
+                res.extend_from_slice(&start[..start.len().min(n)]);
+            }
+        },
+        Some(_) => {
+            let (_, reader) = open(&paths[..1], None)?.pop().expect("one input was opened");
+            reader.take(n as u64).read_to_end(&mut res)?;
+        },
+        None => {},
+    }
+    if let Some(end) = res.iter().rposition(|b| *b == b'\n') {
+        res.truncate(end + 1);
+    }
+    Ok(res)
+}
+
+/// Reads from `file` at byte `offset`, leaving the file's cursor where it was, so that several
+/// threads can read different parts of one file at the same time
+#[cfg(unix)]
+pub fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    file.read_at(buf, offset)
+}
+
+/// Reads from `file` at byte `offset`, leaving the file's cursor where it was. Without a
+/// positioned read, it seeks there and back, one thread at a time.
+#[cfg(not(unix))]
+pub fn read_at(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    static SEEKING: Mutex<()> = Mutex::new(());
+    let _seeking = SEEKING.lock().unwrap_or_else(|e| e.into_inner());
+    let pos = file.stream_position()?;
+    file.seek(SeekFrom::Start(offset))?;
+    let res = file.read(buf);
+    file.seek(SeekFrom::Start(pos))?;
+    res
+}
+
+/// Whether a file is compressed, so it has to be read through `open` rather than in place
+pub fn is_compressed(file: &File) -> io::Result<bool> {
+    let mut start = [0; 4];
+    let n = read_at(file, &mut start, 0)?;
+    Ok(start[..n].starts_with(GZIP_MAGIC) || start[..n].starts_with(ZSTD_MAGIC))
+}
+
+/// A buffered reader whose buffer grows with the records read through it. It starts at
+/// `MIN_CHUNK_BYTES` and doubles, up to `MAX_CHUNK_BYTES`, whenever a whole chunk held no line
+/// break, so a long record takes a few large reads rather than many small ones, each a round trip
+/// on a slow source. With `--buffer-bytes`, chunks stay the size given.
+pub struct ChunkReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    /// The unread bytes of `buf`
+    pos: usize,
+    filled: usize,
+    /// Keep the buffer at the size it started with
+    fixed: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Reads `buffer_bytes` at a time if set, else in chunks that grow with the records. Either
+    /// way a chunk holds enough to tell whether the input is compressed.
+    pub fn new(inner: R, buffer_bytes: Option<usize>) -> ChunkReader<R> {
+        match buffer_bytes {
+            None => ChunkReader::with_capacity(inner, MIN_CHUNK_BYTES, false),
+            Some(n) => ChunkReader::with_capacity(inner, n.max(ZSTD_MAGIC.len()), true),
+        }
+    }
+
+    /// Starts with `capacity` bytes, and keeps to it if `fixed`
+    pub fn with_capacity(inner: R, capacity: usize, fixed: bool) -> ChunkReader<R> {
+        ChunkReader { inner, buf: vec![0; capacity.max(1)], pos: 0, filled: 0, fixed }
+    }
+
+    /// How many bytes are read at a time now
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+}
+
+impl<R: Read> BufRead for ChunkReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos >= self.filled {
+            // The last chunk was all one record, so records are longer than a chunk
+            if !self.fixed && self.filled == self.buf.len() && self.buf.len() < MAX_CHUNK_BYTES
+                && !self.buf.contains(&b'\n') {
+                self.buf.resize((self.buf.len() * 2).min(MAX_CHUNK_BYTES), 0);
+            }
+            self.pos = 0;
+            self.filled = 0;
+            self.filled = loop {
+                match self.inner.read(&mut self.buf) {
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    res => break res?,
+                }
+            };
+        }
+        Ok(&self.buf[self.pos..self.filled])
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.pos = (self.pos + n).min(self.filled);
+    }
+}
+
+impl<R: Read> Read for ChunkReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        // A read as large as the buffer needn't go through it
+        if self.pos >= self.filled && out.len() >= self.buf.len() {
+            return self.inner.read(out);
+        }
+        let n = {
+            let available = self.fill_buf()?;
+            let n = available.len().min(out.len());
+            out[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+/// Where a line of the input starts: the index of the input it's in, and its byte offset and
+/// line number (from 1) there
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Origin {
+    pub input: usize,
+    pub offset: u64,
+    pub line: u64,
+}
+
+/// Reads several inputs one after the other, as if they were one. A line break is added after an
+/// input that doesn't end with one, so its last record isn't joined to the next input's first.
+pub struct Concat<R> {
+    parts: Vec<(String, R)>,
+    current: usize,
+    /// Whether everything read from the current input so far ends with a line break
+    at_line_start: bool,
+    /// A line break is owed between the previous input and the current one
+    pending_newline: bool,
+    /// Bytes and complete lines read from the current input so far
+    part_offset: u64,
+    part_lines: u64,
+    /// Set to where the line read last started, if tracked
+    origin: Option<Rc<Cell<Origin>>>,
+}
+
+impl<R: BufRead> Concat<R> {
+    pub fn new(parts: Vec<(String, R)>) -> Concat<R> {
+        Concat {
+            parts,
+            current: 0,
+            at_line_start: true,
+            pending_newline: false,
+            part_offset: 0,
+            part_lines: 0,
+            origin: None,
+        }
+    }
+
+    /// Keeps `origin` set to where the line read last started. Lines must be read one at a time,
+    /// e.g. with `read_line`, for the line numbers to be right.
+    pub fn track_origin(&mut self, origin: Rc<Cell<Origin>>) {
+        self.origin = Some(origin);
+    }
+
+    /// The inputs with their names, e.g. to finish hashing them
+    pub fn into_parts(self) -> Vec<(String, R)> {
+        self.parts
+    }
+}
+
+impl<R: BufRead> Read for Concat<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let available = self.fill_buf()?;
+            let n = available.len().min(buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Concat<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pending_newline {
+            return Ok(b"\n");
+        }
+        while self.current < self.parts.len() {
+            if !self.parts[self.current].1.fill_buf()?.is_empty() {
+                break;
+            }
+            self.current += 1;
+            self.part_offset = 0;
+            self.part_lines = 0;
+            if !self.at_line_start {
+                self.at_line_start = true;
+                self.pending_newline = true;
+                return Ok(b"\n");
+            }
+        }
+        match self.parts.get_mut(self.current) {
+            Some((_, reader)) => reader.fill_buf(),
+            None => Ok(&[]),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.pending_newline {
+            self.pending_newline = amt == 0;
+            return;
+        }
+        if amt == 0 {
+            return;
+        }
+        if let Some((_, reader)) = self.parts.get_mut(self.current) {
+            if self.at_line_start {
+                if let Some(origin) = &self.origin {
+                    origin.set(Origin {
+                        input: self.current,
+                        offset: self.part_offset,
+                        line: self.part_lines + 1,
+                    });
+                }
+            }
+            // `fill_buf` just returned at least `amt` buffered bytes, so this doesn't read
+            if let Ok(buf) = reader.fill_buf() {
+                self.at_line_start = buf[amt - 1] == b'\n';
+            }
+            reader.consume(amt);
+            self.part_offset += amt as u64;
+            self.part_lines += self.at_line_start as u64;
+        }
+    }
+}
+
+/// The lines of `reader` with their line endings, for `Records` to keep the line breaks within
+/// quoted fields as they were
+pub fn raw_lines<R: BufRead>(mut reader: R) -> impl Iterator<Item = io::Result<String>> {
+    std::iter::from_fn(move || {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(Ok(line)),
+            Err(error) => Some(Err(error)),
+        }
+    })
+}
+
+/// Joins lines into records, for `--multiline`: a line that ends inside a quoted field carries on
+/// with the next line. Without quoting, every line is a record. Records come without the line
+/// ending after them; the line breaks within them are kept as read, if the lines come with their
+/// endings as `raw_lines` reads them, and are plain `\n` otherwise.
+pub struct Records<I> {
+    lines: I,
+    quoting: Option<Quoting>,
+}
+
+impl<I> Records<I> {
+    pub fn new(lines: I, quoting: Option<Quoting>) -> Records<I> {
+        Records { lines, quoting }
+    }
+}
+
+impl<I: Iterator<Item = io::Result<String>>> Iterator for Records<I> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        let mut record = match self.lines.next()? {
+            Ok(line) => line,
+            Err(error) => return Some(Err(error)),
+        };
+        if let Some(quoting) = &self.quoting {
+            // Each line is only scanned once, however long the record grows
+            let mut tracker = quoting.tracker();
+            let mut quoted = tracker.feed(&record);
+            // An unclosed quote on the last line ends the record with the input
+            while quoted {
+                match self.lines.next() {
+                    Some(Ok(line)) => {
+                        if !record.ends_with('\n') {
+                            record.push('\n');
+                            tracker.feed("\n");
+                        }
+                        quoted = tracker.feed(&line);
+                        record.push_str(&line);
+                    },
+                    Some(Err(error)) => return Some(Err(error)),
+                    None => break,
+                }
+            }
+        }
+        if record.ends_with('\n') {
+            record.pop();
+            if record.ends_with('\r') {
+                record.pop();
+            }
+        }
+        Some(Ok(record))
+    }
+}
+
+#[cfg(test)]
+mod test_chunk_reader {
+    use super::*;
+
+    #[test]
+    fn test_grows_with_records() {
+        let input = format!("{}\n{}\n", "a".repeat(100), "b".repeat(10));
+        let mut reader = ChunkReader::with_capacity(input.as_bytes(), 8, false);
+        let lines: Vec<String> = (&mut reader).lines().map(|l| l.unwrap()).collect();
+        assert_eq!(lines, vec!["a".repeat(100), "b".repeat(10)]);
+        assert_eq!(reader.capacity(), 64);
+    }
+    #[test]
+    fn test_fixed() {
+        let input = format!("{}\n", "a".repeat(100));
+        let mut reader = ChunkReader::with_capacity(input.as_bytes(), 8, true);
+        let mut res = String::new();
+        reader.read_to_string(&mut res).unwrap();
+        assert_eq!(res, input);
+        assert_eq!(reader.capacity(), 8);
+    }
+    #[test]
+    fn test_short_records_keep_size() {
+        let input = "a,b\n".repeat(100);
+        let mut reader = ChunkReader::with_capacity(input.as_bytes(), 16, false);
+        assert_eq!((&mut reader).lines().count(), 100);
+        assert_eq!(reader.capacity(), 16);
+    }
+}
+
+#[cfg(test)]
+mod test_concat {
+    use super::*;
+    use crate::dialect::Rfc4180;
+
+    fn concat(parts: &[&'static str]) -> String {
+        let parts = parts.iter().map(|p| (String::new(), p.as_bytes())).collect();
+        let mut res = String::new();
+        Concat::new(parts).read_to_string(&mut res).unwrap();
+        res
+    }
+
+    #[test]
+    fn test_concat() {
+        assert_eq!(concat(&["a\nb\n", "c\n"]), "a\nb\nc\n");
+        assert_eq!(concat(&["a\nb", "", "c"]), "a\nb\nc\n");
+        assert_eq!(concat(&["", "a"]), "a\n");
+        assert_eq!(concat(&[]), "");
+    }
+    #[test]
+    fn test_lines() {
+        let parts = vec![(String::new(), "a,1\nb,2".as_bytes()), (String::new(), "c,3".as_bytes())];
+        let lines: Vec<String> = Concat::new(parts).lines().map(|l| l.unwrap()).collect();
+        assert_eq!(lines, vec!["a,1", "b,2", "c,3"]);
+    }
+    #[test]
+    fn test_origin() {
+        let parts = vec![(String::new(), "a,1\r\nb,2".as_bytes()),
+                         (String::new(), "c,3\n".as_bytes())];
+        let mut concat = Concat::new(parts);
+        let origin = Rc::new(Cell::new(Origin::default()));
+        concat.track_origin(origin.clone());
+        let mut origins = Vec::new();
+        let mut line = String::new();
+        while concat.read_line(&mut line).unwrap() > 0 {
+            origins.push(origin.get());
+        }
+        assert_eq!(origins, vec![Origin { input: 0, offset: 0, line: 1 },
+                                 Origin { input: 0, offset: 5, line: 2 },
+                                 Origin { input: 1, offset: 0, line: 1 }]);
+    }
+    #[test]
+    fn test_records() {
+        let records = |input: &str, quoting: Option<Quoting>| -> Vec<String> {
+            Records::new(input.as_bytes().lines(), quoting).map(|r| r.unwrap()).collect()
+        };
+        let input = "a,\"b\nc\",d\ne,\"f\"\"\ng\"\"\"\nh,\"i";
+        assert_eq!(records(input, Some(Quoting::of(&Rfc4180))),
+                   vec!["a,\"b\nc\",d", "e,\"f\"\"\ng\"\"\"", "h,\"i"]);
+        assert_eq!(records(input, None).len(), 5);
+        let input = "a,\"b\r\nc\"\r\nd\r\n";
+        let quoting = Some(Quoting::of(&Rfc4180));
+        let records: Vec<String> = Records::new(raw_lines(input.as_bytes()), quoting)
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(records, vec!["a,\"b\r\nc\"", "d"]);
+    }
+    #[test]
+    fn test_names() {
+        assert_eq!(names(&[]), vec!["-"]);
+        assert_eq!(single_file(&[PathBuf::from("-")]), None);
+        assert_eq!(single_file(&[PathBuf::from("a.csv")]), Some(Path::new("a.csv")));
+    }
+    #[test]
+    fn test_decompress() {
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        io::Write::write_all(&mut gz, b"a,b\nc,d\n").unwrap();
+        let gz = gz.finish().unwrap();
+        let zst = zstd::encode_all(&b"e,f\n"[..], 0).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = [("a.csv.gz", &gz[..]), ("b.zst", &zst[..]), ("c.csv", b"g\n")]
+            .iter()
+            .map(|(name, bytes)| {
+                let path = dir.path().join(name);
+                std::fs::write(&path, bytes).unwrap();
+                path
+            })
+            .collect();
+        let lines: Vec<String> = Concat::new(open(&paths, None).unwrap()).lines()
+            .map(|l| l.unwrap())
+            .collect();
+        assert_eq!(lines, vec!["a,b", "c,d", "e,f", "g"]);
+        let lines: Vec<String> = Concat::new(open(&paths, Some(3)).unwrap()).lines()
+            .map(|l| l.unwrap())
+            .collect();
+        assert_eq!(lines, vec!["a,b", "c,d", "e,f", "g"]);
+        let compressed: Vec<bool> = paths.iter()
+            .map(|p| is_compressed(&File::open(p).unwrap()).unwrap())
+            .collect();
+        assert_eq!(compressed, vec![true, true, false]);
+    }
+    #[test]
+    fn test_opened_when_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["a.csv", "b.csv"].iter().map(|n| dir.path().join(n)).collect();
+        std::fs::write(&paths[0], "a\n").unwrap();
+        std::fs::write(&paths[1], "b\n").unwrap();
+        let parts = open(&paths, None).unwrap();
+        std::fs::write(&paths[1], "c\n").unwrap();
+        let lines: Vec<String> = Concat::new(parts).lines().map(|l| l.unwrap()).collect();
+        assert_eq!(lines, vec!["a", "c"]);
+    }
+    #[test]
+    fn test_read_at_keeps_cursor() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.csv");
+        std::fs::write(&path, "a,b\nc,d\n").unwrap();
+        let mut file = File::open(&path).unwrap();
+        let mut buf = [0; 3];
+        assert_eq!(read_at(&file, &mut buf, 4).unwrap(), 3);
+        assert_eq!(&buf, b"c,d");
+        let mut rest = String::new();
+        file.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "a,b\nc,d\n");
+    }
+    #[test]
+    fn test_missing_file_fails() {
+        let error = open(&[PathBuf::from("/nonexistent/a.csv")], None).err().unwrap();
+        assert!(error.to_string().contains("/nonexistent/a.csv"));
+    }
+}
@@ -0,0 +1,578 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::collate;
+use crate::dialect::Dialect;
+use crate::expr;
+use crate::ipaddr;
+use crate::key;
+use crate::line;
+use crate::parse_args;
+
+/// A value ranges are compared on. Values of different kinds never compare, so an IPv4 address
+/// can't fall within a range of IPv6 addresses or of numbers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Point {
+    Num(f64),
+    Ip4(u32),
+    Ip6(u128),
+    /// Milliseconds since the Unix epoch
+    Time(i64),
+}
+
+impl Point {
+    /// Reads a field as a number, an IP address or a timestamp, in that order
+    pub fn parse(field: &str) -> Option<Point> {
+        if let Some(x) = expr::parse_num(field).filter(|x| x.is_finite()) {
+            return Some(Point::Num(x));
+        }
+        match ipaddr::parse_ip(field) {
+            Some(IpAddr::V4(ip)) => return Some(Point::Ip4(u32::from(ip))),
+            Some(IpAddr::V6(ip)) => return Some(Point::Ip6(u128::from(ip))),
+            None => {},
+        }
+        key::parse_datetime(field).map(|dt| Point::Time(dt.and_utc().timestamp_millis()))
+    }
+}
+
+impl PartialOrd for Point {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Point::Num(a), Point::Num(b)) => Some(a.total_cmp(b)),
+            (Point::Ip4(a), Point::Ip4(b)) => Some(a.cmp(b)),
+            (Point::Ip6(a), Point::Ip6(b)) => Some(a.cmp(b)),
+            (Point::Time(a), Point::Time(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+/// Closed intervals `[start, end]` with a value each, for finding those containing a point. The
+/// intervals are kept sorted by start as an implicit balanced tree: each subtree is a slice whose
+/// middle element is its root, and `max_end` holds the largest end within each root's subtree.
+#[derive(Debug)]
+pub struct IntervalTree<T> {
+    /// Start, end, and the position the interval was inserted at, with its value
+    nodes: Vec<(Point, Point, usize, T)>,
+    max_end: Vec<Point>,
+}
+
+impl<T> IntervalTree<T> {
+    /// Builds the tree. The start and end of each interval must be of the same kind.
+    pub fn new(intervals: Vec<(Point, Point, T)>) -> IntervalTree<T> {
+        let mut nodes: Vec<_> = intervals.into_iter().enumerate()
+            .map(|(i, (start, end, value))| (start, end, i, value))
+            .collect();
+        nodes.sort_by(|a, b| kind_cmp(&a.0, &b.0));
+        let mut max_end: Vec<Point> = nodes.iter().map(|n| n.1).collect();
+        fill_max_end(&mut max_end, 0, nodes.len());
+        IntervalTree { nodes, max_end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The value of the first interval inserted that contains `point`
+    pub fn first(&self, point: Point) -> Option<&T> {
+        let mut best: Option<usize> = None;
+        self.visit(point, 0, self.nodes.len(), &mut |i| {
+            if best.is_none_or(|b| self.nodes[i].2 < self.nodes[b].2) {
+                best = Some(i);
+            }
+        });
+        best.map(|i| &self.nodes[i].3)
+    }
+
+    /// The values of all intervals that contain `point`, in the order they were inserted
+    pub fn all(&self, point: Point) -> Vec<&T> {
+        let mut found = Vec::new();
+        self.visit(point, 0, self.nodes.len(), &mut |i| found.push(i));
+        found.sort_by_key(|i| self.nodes[*i].2);
+        found.into_iter().map(|i| &self.nodes[i].3).collect()
+    }
+
+    /// Calls `f` with the node of every interval in `nodes[lo..hi]` containing `point`
+    fn visit(&self, point: Point, lo: usize, hi: usize, f: &mut dyn FnMut(usize)) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        // Nothing in this subtree ends late enough
+        if kind_cmp(&point, &self.max_end[mid]) == Ordering::Greater {
+            return;
+        }
+        self.visit(point, lo, mid, f);
+        let (start, end, _, _) = &self.nodes[mid];
+        if *start <= point {
+            if point <= *end {
+                f(mid);
+            }
+            // The right subtree starts no earlier than `start`, so it may still hold matches
+            self.visit(point, mid + 1, hi, f);
+        } else if kind_cmp(&point, start) == Ordering::Greater {
+            // `point` is of a kind sorted after this one's, as may be some of the right subtree
+            self.visit(point, mid + 1, hi, f);
+        }
+    }
+}
+
+/// Orders points of different kinds by kind, so that a sorted slice keeps each kind together
+fn kind_cmp(a: &Point, b: &Point) -> Ordering {
+    let rank = |p: &Point| match p {
+        Point::Num(_) => 0,
+        Point::Ip4(_) => 1,
+        Point::Ip6(_) => 2,
+        Point::Time(_) => 3,
+    };
+    a.partial_cmp(b).unwrap_or_else(|| rank(a).cmp(&rank(b)))
+}
+
+/// Sets `max_end` of the root of `lo..hi` to the largest end in that subtree, as ordered by
+/// `kind_cmp`, and the same for each subtree below it
+fn fill_max_end(max_end: &mut [Point], lo: usize, hi: usize) -> Option<Point> {
+    if lo >= hi {
+        return None;
+    }
+    let mid = lo + (hi - lo) / 2;
+    let children = [fill_max_end(max_end, lo, mid), fill_max_end(max_end, mid + 1, hi)];
+    for end in children.iter().flatten() {
+        if kind_cmp(end, &max_end[mid]) == Ordering::Greater {
+            max_end[mid] = *end;
+        }
+    }
+    Some(max_end[mid])
+}
+
+/// `--range-join FILE --on '[KEY=NAMES and ]colN between START,END [as LABEL]'`: tags each record
+/// with the label of the first range in `path` that contains its value in `col`, among the ranges
+/// with the same key if there is one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RangeJoinSpec {
+    pub path: PathBuf,
+    pub col: usize,
+    /// Columns of a record that must equal `file_keys` in the ranges file, e.g. a host name to
+    /// look up that host's maintenance windows. Empty if every range applies to every record.
+    #[serde(default)]
+    pub keys: Vec<usize>,
+    /// Header names of the ranges file's key columns, as many as `keys`
+    #[serde(default)]
+    pub file_keys: Vec<String>,
+    /// Header names of the ranges file's columns holding each range's bounds, both inclusive
+    pub start: String,
+    pub end: String,
+    /// Header name of the column to tag records with; the first column other than the bounds if
+    /// not given
+    pub label: Option<String>,
+    /// Which records and ranges are output when they don't match
+    #[serde(default)]
+    pub join_type: JoinType,
+    /// Written in place of the missing side of a record or range that didn't match
+    #[serde(default)]
+    pub fill: String,
+    /// Header names of the ranges file's columns to append instead of the single label
+    #[serde(default)]
+    pub columns: Vec<String>,
+}
+
+/// `--join-type`: which side of a range join is kept whole
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinType {
+    /// Only records that fall in a range
+    Inner,
+    /// Every record, with the fill value for those in no range
+    #[default]
+    Left,
+    /// Records that fall in a range, then every range no record fell in, with the fill value for
+    /// the record's columns
+    Right,
+    /// Every record, then every range no record fell in
+    Full,
+}
+
+impl JoinType {
+    /// Whether records that fall in no range are output
+    pub fn keeps_records(self) -> bool {
+        self == JoinType::Left || self == JoinType::Full
+    }
+
+    /// Whether ranges that no record fell in are output
+    pub fn keeps_ranges(self) -> bool {
+        self == JoinType::Right || self == JoinType::Full
+    }
+}
+
+/// Parses a `--join-type` argument
+pub fn parse_join_type(s: &str) -> JoinType {
+    match s.trim() {
+        "inner" => JoinType::Inner,
+        "left" => JoinType::Left,
+        "right" => JoinType::Right,
+        "full" => JoinType::Full,
+        other => panic!("Invalid join type {}, expected inner, left, right or full", other),
+    }
+}
+
+/// Parses the `--on` argument of `--range-join`, e.g. `col2 between start,end as network`, or
+/// `col1+col3=host+site and col2 between start,end` to match ranges on a key of two columns too.
+/// `offset` is as in `parse_arg_cols`.
+pub fn parse_range_join(path: &str, on: &str, offset: usize) -> RangeJoinSpec {
+    let invalid = || panic!("Invalid --on {}, expected e.g. 'col2 between start,end'", on);
+    let (keys, file_keys, on) = match on.split_once(" and ") {
+        Some((key, rest)) => {
+            let (keys, file_keys) = key.split_once('=').unwrap_or_else(invalid);
+            let keys = parse_args::parse_arg_key(keys, offset);
+            let file_keys: Vec<String> = file_keys.split('+').map(|k| k.trim().to_string())
+                .collect();
+            assert!(keys.len() == file_keys.len(),
+                    "The key of --on {} has a different number of columns on each side", on);
+            (keys, file_keys, rest)
+        },
+        None => (Vec::new(), Vec::new(), on),
+    };
+    let (col, rest) = on.trim().split_once(" between ").unwrap_or_else(invalid);
+    let col = col.trim();
+    let col = parse_args::parse_arg_col(col.strip_prefix("col").unwrap_or(col), offset);
+    let (bounds, label) = match rest.split_once(" as ") {
+        Some((bounds, label)) => (bounds, Some(label.trim().to_string())),
+        None => (rest, None),
+    };
+    let (start, end) = bounds.split_once(',').unwrap_or_else(invalid);
+    RangeJoinSpec {
+        path: PathBuf::from(path),
+        col,
+        keys,
+        file_keys,
+        start: start.trim().to_string(),
+        end: end.trim().to_string(),
+        label,
+        join_type: JoinType::default(),
+        fill: String::new(),
+        columns: Vec::new(),
+    }
+}
+
+/// The ranges of a `--range-join`, loaded and ready to look records up in
+#[derive(Debug)]
+pub struct RangeJoin {
+    col: usize,
+    keys: Vec<usize>,
+    fold_case: bool,
+    join_type: JoinType,
+    fill: String,
+    /// The ranges of each key, as indices into `values`
+    ranges: HashMap<Vec<String>, IntervalTree<usize>>,
+    /// The values each range appends to the records in it, in the order of the file
+    values: Vec<Vec<String>>,
+    /// Whether any record fell in each range, kept only when unmatched ranges are output
+    matched: Vec<AtomicBool>,
+    /// How many values each range appends
+    width: usize,
+    /// Names of the ranges file's columns that are appended, for the header
+    names: Vec<String>,
+}
+
+impl RangeJoin {
+    /// Reads the ranges file, which starts with a header naming its columns and is split with
+    /// `dialect`. Fails on a range whose bounds can't be read or are out of order. With
+    /// `fold_case`, keys match regardless of case.
+    pub fn load(spec: &RangeJoinSpec, dialect: &dyn Dialect, fold_case: bool)
+                -> io::Result<RangeJoin> {
+        let file = File::open(&spec.path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", spec.path.display(), e)))?;
+        RangeJoin::from_reader(spec, BufReader::new(file), dialect, fold_case)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", spec.path.display(), e)))
+    }
+
+    fn from_reader<R: BufRead>(spec: &RangeJoinSpec, reader: R, dialect: &dyn Dialect,
+                               fold_case: bool) -> io::Result<RangeJoin> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut lines = reader.lines();
+        let header = match lines.next() {
+            Some(line) => line?,
+            None => return Err(invalid(String::from("no header"))),
+        };
+        let names: Vec<&str> = line::split_line_with(&header, dialect).into_iter()
+            .map(|f| expr::unquote(f.trim()))
+            .collect();
+        let find = |name: &str| names.iter().position(|n| *n == name)
+            .ok_or_else(|| invalid(format!("no column {}", name)));
+        let (start, end) = (find(&spec.start)?, find(&spec.end)?);
+        let file_keys = spec.file_keys.iter().map(|k| find(k)).collect::<io::Result<Vec<_>>>()?;
+        let labels: Vec<usize> = match &spec.label {
+            _ if !spec.columns.is_empty() => {
+                spec.columns.iter().map(|c| find(c)).collect::<io::Result<_>>()?
+            },
+            Some(name) => vec![find(name)?],
+            None => vec![(0..names.len())
+                .find(|i| *i != start && *i != end && !file_keys.contains(i))
+                .ok_or_else(|| invalid(String::from("no column to label records with")))?],
+        };
+
+        let mut ranges: HashMap<Vec<String>, Vec<_>> = HashMap::new();
+        let mut values: Vec<Vec<String>> = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields = line::split_line_with(&line, dialect);
+            let bound = |col: usize| fields.get(col).and_then(|f| Point::parse(f));
+            let (lo, hi) = match (bound(start), bound(end)) {
+                (Some(lo), Some(hi)) if lo <= hi => (lo, hi),
+                _ => return Err(invalid(format!("line {}: invalid range", i + 2))),
+            };
+            ranges.entry(make_key(&fields, &file_keys, fold_case)).or_default()
+                .push((lo, hi, values.len()));
+            values.push(labels.iter()
+                .map(|i| expr::unquote(fields.get(*i).unwrap_or(&"").trim()).to_string())
+                .collect());
+        }
+        let tracked = if spec.join_type.keeps_ranges() { values.len() } else { 0 };
+        Ok(RangeJoin {
+            col: spec.col,
+            keys: spec.keys.clone(),
+            fold_case,
+            join_type: spec.join_type,
+            fill: spec.fill.clone(),
+            ranges: ranges.into_iter().map(|(key, r)| (key, IntervalTree::new(r))).collect(),
+            values,
+            matched: (0..tracked).map(|_| AtomicBool::new(false)).collect(),
+            width: labels.len(),
+            names: labels.iter().map(|i| names[*i].to_string()).collect(),
+        })
+    }
+
+    /// The label of the first range containing the record's value, unquoted
+    pub fn label(&self, fields: &[&str]) -> Option<&str> {
+        self.lookup(fields).map(|values| values[0].as_str())
+    }
+
+    /// The values the first range containing the record's value appends, unquoted
+    pub fn lookup(&self, fields: &[&str]) -> Option<&[String]> {
+        let (ranges, point) = self.ranges_of(fields)?;
+        ranges.first(point).map(|i| self.values[*i].as_slice())
+    }
+
+    fn ranges_of(&self, fields: &[&str]) -> Option<(&IntervalTree<usize>, Point)> {
+        let point = Point::parse(fields.get(self.col)?)?;
+        Some((self.ranges.get(&make_key(fields, &self.keys, self.fold_case))?, point))
+    }
+
+    pub fn join_type(&self) -> JoinType {
+        self.join_type
+    }
+
+    /// The value written in place of a missing side
+    pub fn fill(&self) -> &str {
+        &self.fill
+    }
+
+    /// How many values are appended to each record
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// The names of the values appended to each record, as in the ranges file's header
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The values to append to a record that is output: those of the first range containing it,
+    /// or the fill value. Every range containing it counts as matched.
+    pub fn join(&self, fields: &[&str]) -> Vec<&str> {
+        if !self.matched.is_empty() {
+            if let Some((ranges, point)) = self.ranges_of(fields) {
+                for i in ranges.all(point) {
+                    self.matched[*i].store(true, AtomicOrdering::Relaxed);
+                }
+            }
+        }
+        match self.lookup(fields) {
+            Some(values) => values.iter().map(|v| v.as_str()).collect(),
+            None => vec![self.fill.as_str(); self.width],
+        }
+    }
+
+    /// The values of the ranges no record output so far fell in, in the order of the file, when
+    /// the join type outputs them
+    pub fn unmatched(&self) -> Vec<&[String]> {
+        self.matched.iter().zip(self.values.iter())
+            .filter(|(m, _)| !m.load(AtomicOrdering::Relaxed))
+            .map(|(_, v)| v.as_slice())
+            .collect()
+    }
+}
+
+/// The values of a key's columns, trimmed and unquoted
+fn make_key(fields: &[&str], cols: &[usize], fold_case: bool) -> Vec<String> {
+    cols.iter()
+        .map(|i| {
+            let value = expr::unquote(fields.get(*i).unwrap_or(&"").trim());
+            if fold_case { collate::fold_case(value).into_owned() } else { value.to_string() }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test_interval {
+    use super::*;
+    use crate::dialect::Permissive;
+
+    fn num(x: f64) -> Point {
+        Point::Num(x)
+    }
+
+    #[test]
+    fn test_tree() {
+        let tree = IntervalTree::new(vec![
+            (num(10.0), num(20.0), "a"),
+            (num(0.0), num(5.0), "b"),
+            (num(15.0), num(30.0), "c"),
+            (num(1.0), num(100.0), "d"),
+            (num(40.0), num(40.0), "e"),
+        ]);
+        assert_eq!(tree.len(), 5);
+        assert_eq!(tree.first(num(17.0)), Some(&"a"));
+        assert_eq!(tree.all(num(17.0)), vec![&"a", &"c", &"d"]);
+        assert_eq!(tree.all(num(0.0)), vec![&"b"]);
+        assert_eq!(tree.all(num(40.0)), vec![&"d", &"e"]);
+        assert_eq!(tree.first(num(101.0)), None);
+        assert_eq!(tree.first(num(-1.0)), None);
+    }
+    #[test]
+    fn test_tree_matches_scan() {
+        let intervals: Vec<(Point, Point, usize)> = (0..200)
+            .map(|i| {
+                let start = (i * 37 % 101) as f64;
+                (num(start), num(start + (i * 13 % 17) as f64), i)
+            })
+            .collect();
+        let tree = IntervalTree::new(intervals.clone());
+        for x in 0..130 {
+            let x = num(x as f64);
+            let expected: Vec<&usize> = intervals.iter()
+                .filter(|(lo, hi, _)| *lo <= x && x <= *hi)
+                .map(|(_, _, i)| i)
+                .collect();
+            assert_eq!(tree.all(x), expected);
+        }
+    }
+    #[test]
+    fn test_mixed_kinds() {
+        let ip = |s: &str| Point::parse(s).unwrap();
+        let tree = IntervalTree::new(vec![
+            (ip("10.0.0.0"), ip("10.255.255.255"), "private"),
+            (num(0.0), num(1e12), "numbers"),
+            (ip("2001:db8::"), ip("2001:db8::ffff"), "docs"),
+        ]);
+        assert_eq!(tree.first(ip("10.1.2.3")), Some(&"private"));
+        assert_eq!(tree.first(ip("2001:db8::1")), Some(&"docs"));
+        assert_eq!(tree.first(ip("11.0.0.0")), None);
+        assert_eq!(tree.first(num(167837696.0)), Some(&"numbers"));
+    }
+    #[test]
+    fn test_parse_range_join() {
+        assert_eq!(parse_range_join("nets.csv", "col2 between start,end", 1), RangeJoinSpec {
+            path: PathBuf::from("nets.csv"),
+            col: 1,
+            keys: Vec::new(),
+            file_keys: Vec::new(),
+            start: String::from("start"),
+            end: String::from("end"),
+            label: None,
+            join_type: JoinType::Left,
+            fill: String::new(),
+            columns: Vec::new(),
+        });
+        assert_eq!(parse_range_join("w.csv", "3 between from, to as name", 1).label,
+                   Some(String::from("name")));
+    }
+    #[test]
+    fn test_parse_keys() {
+        let spec = parse_range_join("w.csv", "col1+3=host+site and col2 between a,b", 1);
+        assert_eq!((spec.keys, spec.file_keys), (vec![0, 2], vec![String::from("host"),
+                                                                   String::from("site")]));
+    }
+    #[test]
+    #[should_panic]
+    fn test_parse_range_join_fails() {
+        parse_range_join("nets.csv", "col2 in start,end", 1);
+    }
+    #[test]
+    fn test_range_join() {
+        let spec = parse_range_join("windows.csv", "col1 between from,until", 1);
+        let ranges = "name,from,until\n\
+                      \"deploy\",2024-01-01 10:00,2024-01-01 11:00\n\
+                      backup,2024-01-02,2024-01-02 06:00:00\n";
+        let join = RangeJoin::from_reader(&spec, ranges.as_bytes(), &Permissive, false).unwrap();
+        assert_eq!(join.label(&["2024-01-01 10:30:00"]), Some("deploy"));
+        assert_eq!(join.label(&["2024-01-02T06:00:00Z"]), Some("backup"));
+        assert_eq!(join.label(&["2024-01-01 12:00:00"]), None);
+        assert_eq!(join.label(&["timestamp"]), None);
+        assert_eq!(join.label(&[]), None);
+    }
+    #[test]
+    fn test_keyed() {
+        let spec = parse_range_join("windows.csv", "col1=host and col2 between from,until", 1);
+        let ranges = "host,from,until,why\n\
+                      db1,2024-01-01 10:00,2024-01-01 11:00,upgrade\n\
+                      DB2,2024-01-01 10:30,2024-01-01 12:00,reboot\n";
+        let join = RangeJoin::from_reader(&spec, ranges.as_bytes(), &Permissive, true).unwrap();
+        assert_eq!(join.label(&["db1", "2024-01-01 10:45"]), Some("upgrade"));
+        assert_eq!(join.label(&["db2", "2024-01-01 10:45"]), Some("reboot"));
+        assert_eq!(join.label(&["db1", "2024-01-01 11:30"]), None);
+        assert_eq!(join.label(&["db3", "2024-01-01 10:45"]), None);
+    }
+    #[test]
+    fn test_join_types() {
+        let spec = RangeJoinSpec {
+            join_type: JoinType::Full,
+            fill: String::from("-"),
+            columns: vec![String::from("net"), String::from("site")],
+            ..parse_range_join("nets.csv", "col1 between start,end", 1)
+        };
+        let ranges = "start,end,net,site
+0,9,a,x
+5,19,b,y
+30,39,c,z
+";
+        let join = RangeJoin::from_reader(&spec, ranges.as_bytes(), &Permissive, false).unwrap();
+        assert_eq!(join.width(), 2);
+        assert_eq!(join.join(&["7"]), vec!["a", "x"]);
+        assert_eq!(join.join(&["25"]), vec!["-", "-"]);
+        assert_eq!(join.unmatched(), vec![&[String::from("c"), String::from("z")][..]]);
+        let spec = RangeJoinSpec { join_type: JoinType::Inner, ..spec };
+        let join = RangeJoin::from_reader(&spec, ranges.as_bytes(), &Permissive, false).unwrap();
+        assert_eq!(join.join(&["7"]), vec!["a", "x"]);
+        assert!(join.unmatched().is_empty());
+        assert_eq!(parse_join_type("right"), JoinType::Right);
+    }
+    #[test]
+    #[should_panic]
+    fn test_parse_join_type_fails() {
+        parse_join_type("outer");
+    }
+    #[test]
+    fn test_bad_ranges() {
+        let spec = parse_range_join("nets.csv", "col1 between start,end", 1);
+        let load = |s: &str| RangeJoin::from_reader(&spec, s.as_bytes(), &Permissive, false)
+            .err().unwrap().to_string();
+        assert_eq!(load("start,end,net\n10.0.0.9,10.0.0.1,a\n"), "line 2: invalid range");
+        assert_eq!(load("start,end,net\n1,x,a\n"), "line 2: invalid range");
+        assert_eq!(load("lo,hi,net\n"), "no column start");
+        assert_eq!(load("start,end\n"), "no column to label records with");
+    }
+}
@@ -0,0 +1,131 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::expr;
+
+/// Parses an IPv4 or IPv6 address, ignoring surrounding whitespace and quotes
+pub fn parse_ip(s: &str) -> Option<IpAddr> {
+    expr::unquote(s.trim()).trim().parse().ok()
+}
+
+/// Parses a network in CIDR notation like `10.0.0.0/8`. A bare address is a network of one.
+pub fn parse_cidr(s: &str) -> Option<(IpAddr, u32)> {
+    let s = expr::unquote(s.trim()).trim();
+    let mut parts = s.splitn(2, '/');
+    let addr: IpAddr = parts.next()?.parse().ok()?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match parts.next() {
+        Some(p) => p.parse().ok()?,
+        None => max,
+    };
+    if prefix > max {
+        return None;
+    }
+    Some((addr, prefix))
+}
+
+/// The address as an integer: 32 bits for IPv4, 128 for IPv6
+pub fn ip_to_int(ip: &IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(v4) => u32::from(*v4) as u128,
+        IpAddr::V6(v6) => u128::from(*v6),
+    }
+}
+
+/// Whether `ip` falls within the network `net`/`prefix`. Addresses never match a network of the
+/// other IP version.
+pub fn ip_in(ip: &IpAddr, net: &IpAddr, prefix: u32) -> bool {
+    let bits = match (ip, net) {
+        (IpAddr::V4(_), IpAddr::V4(_)) => 32,
+        (IpAddr::V6(_), IpAddr::V6(_)) => 128,
+        _ => return false,
+    };
+    if prefix == 0 {
+        return true;
+    }
+    let shift = bits - prefix;
+    (ip_to_int(ip) >> shift) == (ip_to_int(net) >> shift)
+}
+
+/// Zeroes the host part of an address for anonymization: the last octet of an IPv4 address, or
+/// the last 80 bits of an IPv6 address
+pub fn anonymize(ip: &IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => IpAddr::V4((u32::from(*v4) & !0xff).into()),
+        IpAddr::V6(v6) => IpAddr::V6((u128::from(*v6) & !((1u128 << 80) - 1)).into()),
+    }
+}
+
+thread_local! {
+    static RDNS_CACHE: RefCell<HashMap<IpAddr, Option<String>>> = RefCell::new(HashMap::new());
+}
+
+/// Looks up the host name of an address, caching results since log files tend to repeat the same
+/// addresses many times over
+pub fn reverse_dns(ip: &IpAddr) -> Option<String> {
+    RDNS_CACHE.with(|cache| {
+        cache.borrow_mut()
+            .entry(*ip)
+            .or_insert_with(|| dns_lookup::lookup_addr(ip).ok())
+            .clone()
+    })
+}
+
+#[cfg(test)]
+mod test_parse {
+    use super::*;
+
+    #[test]
+    fn test_parse_ip() {
+        assert_eq!(parse_ip(" \"10.1.2.3\" "), Some("10.1.2.3".parse().unwrap()));
+        assert_eq!(parse_ip("::1"), Some("::1".parse().unwrap()));
+        assert_eq!(parse_ip("10.1.2"), None);
+    }
+    #[test]
+    fn test_parse_cidr() {
+        assert_eq!(parse_cidr("10.0.0.0/8"), Some(("10.0.0.0".parse().unwrap(), 8)));
+        assert_eq!(parse_cidr("10.0.0.1"), Some(("10.0.0.1".parse().unwrap(), 32)));
+        assert_eq!(parse_cidr("fe80::/10"), Some(("fe80::".parse().unwrap(), 10)));
+        assert_eq!(parse_cidr("10.0.0.0/33"), None);
+        assert_eq!(parse_cidr("10.0.0.0/x"), None);
+    }
+}
+
+#[cfg(test)]
+mod test_ip_in {
+    use super::*;
+
+    fn check(ip: &str, cidr: &str) -> bool {
+        let (net, prefix) = parse_cidr(cidr).unwrap();
+        ip_in(&parse_ip(ip).unwrap(), &net, prefix)
+    }
+
+    #[test]
+    fn test_ip_in() {
+        assert!(check("10.1.2.3", "10.0.0.0/8"));
+        assert!(!check("11.1.2.3", "10.0.0.0/8"));
+        assert!(check("192.168.1.77", "192.168.1.64/26"));
+        assert!(!check("192.168.1.128", "192.168.1.64/26"));
+        assert!(check("8.8.8.8", "0.0.0.0/0"));
+        assert!(check("fe80::1", "fe80::/10"));
+        assert!(!check("10.1.2.3", "::/0"));
+    }
+}
+
+#[cfg(test)]
+mod test_transforms {
+    use super::*;
+
+    #[test]
+    fn test_ip_to_int() {
+        assert_eq!(ip_to_int(&parse_ip("10.0.0.1").unwrap()), 167772161);
+        assert_eq!(ip_to_int(&parse_ip("::1").unwrap()), 1);
+    }
+    #[test]
+    fn test_anonymize() {
+        assert_eq!(anonymize(&parse_ip("10.1.2.3").unwrap()).to_string(), "10.1.2.0");
+        assert_eq!(anonymize(&parse_ip("2001:db8:1:2:3:4:5:6").unwrap()).to_string(),
+                   "2001:db8:1::");
+    }
+}
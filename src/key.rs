@@ -0,0 +1,136 @@
+use std::cmp::Ordering;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::expr;
+
+/// How a column's values should be interpreted when comparing them
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum KeyKind {
+    Text,
+    Num,
+    Date,
+}
+
+/// A typed value extracted from a field, for ordering records
+#[derive(Debug, Clone, PartialEq)]
+pub enum Key {
+    Text(String),
+    Num(f64),
+    Date(NaiveDateTime),
+}
+
+impl Key {
+    /// Interprets a raw field according to `kind`. Returns `None` if the field can't be parsed as
+    /// that kind.
+    pub fn parse(kind: KeyKind, field: &str) -> Option<Key> {
+        match kind {
+            KeyKind::Text => Some(Key::Text(field.to_string())),
+            KeyKind::Num => expr::parse_num(field).map(Key::Num),
+            KeyKind::Date => parse_datetime(field).map(Key::Date),
+        }
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Key::Text(a), Key::Text(b)) => Some(a.cmp(b)),
+            (Key::Num(a), Key::Num(b)) => Some(a.total_cmp(b)),
+            (Key::Date(a), Key::Date(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+/// Parses the key kind suffix of a column argument: `num`, `date`, or `text`
+pub fn parse_kind(s: &str) -> KeyKind {
+    match s.trim() {
+        "text" | "str" => KeyKind::Text,
+        "num" => KeyKind::Num,
+        "date" => KeyKind::Date,
+        other => panic!("Invalid column type {}", other),
+    }
+}
+
+/// Date and time formats accepted in addition to RFC 3339
+const DATETIME_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+    "%Y/%m/%d %H:%M:%S%.f",
+];
+const DATE_FORMATS: &[&str] = &["%Y-%m-%d", "%Y/%m/%d", "%Y%m%d"];
+
+/// Parses a timestamp or date in one of the common ISO-like formats, ignoring surrounding quotes.
+/// Timestamps with a UTC offset are converted to UTC; dates are taken as midnight.
+pub fn parse_datetime(field: &str) -> Option<NaiveDateTime> {
+    let field = expr::unquote(field.trim()).trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(field) {
+        return Some(dt.naive_utc());
+    }
+    for fmt in DATETIME_FORMATS.iter() {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(field, fmt) {
+            return Some(dt);
+        }
+    }
+    for fmt in DATE_FORMATS.iter() {
+        if let Ok(d) = NaiveDate::parse_from_str(field, fmt) {
+            return d.and_hms_opt(0, 0, 0);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test_parse_datetime {
+    use super::*;
+
+    fn dt(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(parse_datetime("2020-01-02"), Some(dt("2020-01-02 00:00:00")));
+        assert_eq!(parse_datetime("2020/01/02"), Some(dt("2020-01-02 00:00:00")));
+        assert_eq!(parse_datetime("2020-01-02 03:04:05"), Some(dt("2020-01-02 03:04:05")));
+        assert_eq!(parse_datetime("\"2020-01-02T03:04\""), Some(dt("2020-01-02 03:04:00")));
+        assert_eq!(parse_datetime("2020-01-02T03:04:05+01:00"), Some(dt("2020-01-02 02:04:05")));
+    }
+    #[test]
+    fn test_invalid() {
+        assert_eq!(parse_datetime("yesterday"), None);
+        assert_eq!(parse_datetime("2020-13-01"), None);
+    }
+}
+
+#[cfg(test)]
+mod test_key {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Key::parse(KeyKind::Num, " 1.5 "), Some(Key::Num(1.5)));
+        assert_eq!(Key::parse(KeyKind::Num, "x"), None);
+        assert_eq!(Key::parse(KeyKind::Text, "x"), Some(Key::Text(String::from("x"))));
+    }
+    #[test]
+    fn test_order() {
+        assert!(Key::Num(9.0) < Key::Num(10.0));
+        assert!(Key::Text(String::from("10")) < Key::Text(String::from("9")));
+        assert_eq!(Key::Num(1.0).partial_cmp(&Key::Text(String::from("1"))), None);
+    }
+    #[test]
+    fn test_parse_kind() {
+        assert_eq!(parse_kind("date"), KeyKind::Date);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_bad_kind_fails() {
+        parse_kind("bool");
+    }
+}
@@ -0,0 +1,75 @@
+//! Like GNU cut but for CSVs: quote-aware splitting of lines into fields, and picking columns out
+//! of them.
+//!
+//! The items re-exported here are the stable API, meant for embedding the splitter in other tools:
+//!
+//! ```
+//! use ccut::{cut_line, split_line, ColumnSpec, RecordParser};
+//!
+//! assert_eq!(split_line(r#"a,"b,c",d"#), vec!["a", r#""b,c""#, "d"]);
+//! let cols: ColumnSpec = "3,1".parse().unwrap();
+//! assert_eq!(cols.cut_line(r#"a,"b,c",d"#), "d,a");
+//! assert_eq!(cut_line("a,b,c", cols.indices()), "c,a");
+//!
+//! let mut parser = RecordParser::new();
+//! let record = parser.parse(r#"a,"b ""x""",c"#);
+//! assert_eq!(record.unquoted(1).unwrap(), r#"b "x""#);
+//! ```
+//!
+//! The modules themselves implement the `ccut` command line, and may change along with it.
+
+pub mod audit;
+pub mod catalog;
+pub mod check;
+pub mod collate;
+pub mod correlate;
+pub mod crosstab;
+pub mod de;
+pub mod decimal;
+pub mod diagnostic;
+pub mod dialect;
+pub mod duration;
+pub mod estimate;
+pub mod expr;
+pub mod fpe;
+pub mod geo;
+pub mod grep;
+pub mod group;
+pub mod help;
+pub mod input;
+pub mod interval;
+pub mod ipaddr;
+pub mod key;
+pub mod limits;
+pub mod line;
+pub mod membership;
+pub mod messages;
+pub mod options;
+pub mod outlier;
+pub mod parse_args;
+pub mod partition;
+pub mod pipeline;
+pub mod plan;
+pub mod precision;
+pub mod pseudonym;
+pub mod record;
+pub mod redact;
+pub mod sample;
+pub mod session;
+pub mod shard;
+pub mod sketch;
+pub mod slice;
+pub mod sort;
+pub mod spool;
+pub mod stats;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod table;
+pub mod top;
+pub mod trailer;
+pub mod transform;
+pub mod unique;
+pub mod web;
+
+pub use line::{cut_line, split_line, split_line_with, ColumnSpec};
+pub use record::{Record, RecordParser, RecordView};
@@ -0,0 +1,139 @@
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+/// What `--cell-overflow` does with a field longer than `--max-cell-bytes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CellOverflow {
+    /// Cut it short
+    #[default]
+    Truncate,
+    /// Leave its record out
+    DropRow,
+    /// Stop with an error
+    Abort,
+}
+
+/// Parses a `--cell-overflow` argument
+pub fn parse_cell_overflow(s: &str) -> CellOverflow {
+    match s.trim() {
+        "truncate" => CellOverflow::Truncate,
+        "drop-row" => CellOverflow::DropRow,
+        "abort" => CellOverflow::Abort,
+        other => panic!("Invalid cell overflow {}, expected truncate, drop-row or abort", other),
+    }
+}
+
+/// `--max-cell-bytes N --cell-overflow ...`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CellLimit {
+    pub max_bytes: usize,
+    pub overflow: CellOverflow,
+}
+
+impl CellLimit {
+    /// The first field longer than the limit, as written, quotes included
+    pub fn first_over(&self, fields: &[&str]) -> Option<usize> {
+        fields.iter().position(|f| f.len() > self.max_bytes)
+    }
+}
+
+/// Cuts a field as written down to at most `max_bytes`, at a character boundary. A quoted field
+/// keeps its quotes, dropping any escape or quote its content would end with, so it still reads
+/// back as one field.
+pub fn truncate_field(field: &str, max_bytes: usize) -> String {
+    if field.len() <= max_bytes {
+        return field.to_string();
+    }
+    let cut = |s: &str, n: usize| {
+        let end = (0..=n.min(s.len())).rev().find(|i| s.is_char_boundary(*i)).unwrap_or(0);
+        s[..end].to_string()
+    };
+    match field.chars().next() {
+        Some(q) if (q == '"' || q == '\'') && max_bytes >= 2 => {
+            let content = cut(&field[1..], max_bytes - 2);
+            format!("{}{}{}", q, content.trim_end_matches(['\\', '"', '\'']), q)
+        },
+        _ => cut(field, max_bytes),
+    }
+}
+
+/// Lowers (or, with the privileges, raises) the scheduling priority of the whole process, like
+/// nice(1): 19 is the lowest priority and -20 the highest
+#[cfg(unix)]
+pub fn set_nice(nice: i32) -> io::Result<()> {
+    // who = 0 is the calling process
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Leaves the priority as it is where there's no nice(1), with an `Unsupported` error for the
+/// caller to warn of
+#[cfg(not(unix))]
+pub fn set_nice(_nice: i32) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "not supported on this platform"))
+}
+
+/// How many worker threads to run on `available` cores. A niced run leaves half of them to other
+/// work, since a low priority alone doesn't stop it from filling every core.
+pub fn threads(available: usize, nice: Option<i32>) -> usize {
+    match nice {
+        Some(n) if n > 0 => (available / 2).max(1),
+        _ => available.max(1),
+    }
+}
+
+/// Caps the size of an in-memory buffer to the `--max-memory` limit
+pub fn cap(size: usize, max_memory: Option<usize>) -> usize {
+    max_memory.map_or(size, |m| size.min(m))
+}
+
+#[cfg(test)]
+mod test_limits {
+    use super::*;
+
+    #[test]
+    fn test_threads() {
+        assert_eq!(threads(8, None), 8);
+        assert_eq!(threads(8, Some(0)), 8);
+        assert_eq!(threads(8, Some(10)), 4);
+        assert_eq!(threads(1, Some(19)), 1);
+        assert_eq!(threads(0, None), 1);
+    }
+    #[test]
+    fn test_cap() {
+        assert_eq!(cap(256, None), 256);
+        assert_eq!(cap(256, Some(64)), 64);
+        assert_eq!(cap(32, Some(64)), 32);
+    }
+    #[test]
+    fn test_cell_limit() {
+        let limit = CellLimit { max_bytes: 4, overflow: parse_cell_overflow("drop-row") };
+        assert_eq!(limit.overflow, CellOverflow::DropRow);
+        assert_eq!(limit.first_over(&["abcd", "", "abcde", "x"]), Some(2));
+        assert_eq!(limit.first_over(&["abcd"]), None);
+    }
+    #[test]
+    fn test_truncate_field() {
+        assert_eq!(truncate_field("abcdef", 4), "abcd");
+        assert_eq!(truncate_field("abc", 4), "abc");
+        assert_eq!(truncate_field("néée", 4), "né");
+        assert_eq!(truncate_field("\"abcdef\"", 5), "\"abc\"");
+        assert_eq!(truncate_field("\"a\\\"bc\"", 5), "\"a\"");
+    }
+    #[test]
+    #[should_panic]
+    fn test_parse_cell_overflow_fails() {
+        parse_cell_overflow("skip");
+    }
+    #[test]
+    #[cfg(unix)]
+    fn test_set_nice() {
+        // Keeping the current priority is always allowed
+        let before = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+        set_nice(before).unwrap();
+    }
+}
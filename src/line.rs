@@ -1,59 +1,221 @@
+use std::borrow::Cow;
+use std::str::FromStr;
+
+use crate::dialect::{Dialect, Permissive};
+use crate::parse_args;
+
+/// A selection of columns, such as `1,3-5,-1`: which fields to keep from each row, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ColumnSpec {
+    cols: Vec<usize>,
+}
+
+impl ColumnSpec {
+    /// Parses a column list as the command line takes it, counting columns from `offset`, 0 or 1.
+    /// Negative indices count from the end of the row and a range may leave out its end, as in
+    /// `parse_args::parse_arg_cols`.
+    pub fn parse(spec: &str, offset: usize) -> Result<ColumnSpec, String> {
+        parse_args::try_parse_arg_cols(spec, offset).map(|cols| ColumnSpec { cols })
+    }
+
+    /// A selection of zero-indexed columns, which may be marked with `OPEN_RANGE` or `FROM_END`
+    pub fn from_indices(cols: Vec<usize>) -> ColumnSpec {
+        ColumnSpec { cols }
+    }
+
+    /// The selected columns, zero-indexed, with open ranges and indices from the end still marked
+    pub fn indices(&self) -> &[usize] {
+        &self.cols
+    }
+
+    /// The zero-indexed columns picked out of a row of `n` fields
+    pub fn resolve(&self, n: usize) -> Vec<usize> {
+        resolve_cols(&self.cols, n)
+    }
+
+    /// Picks the selected fields out of an already split row, as `cut_fields` does
+    pub fn cut<'a>(&self, fields: &[&'a str]) -> Vec<&'a str> {
+        cut_fields(fields, &self.cols)
+    }
+
+    /// Picks every field of an already split row but the selected ones, as `complement_fields`
+    /// does
+    pub fn complement<'a>(&self, fields: &[&'a str]) -> Vec<&'a str> {
+        complement_fields(fields, &self.cols)
+    }
+
+    /// Splits `line` on commas and joins the selected fields back up, as `cut_line` does
+    pub fn cut_line(&self, line: &str) -> String {
+        cut_line(line, &self.cols)
+    }
+}
+
+/// Parses a one-indexed column list, as the command line takes it by default
+impl FromStr for ColumnSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ColumnSpec, String> {
+        ColumnSpec::parse(s, 1)
+    }
+}
+
 /// Implements the cut function per line: this will split `line` by commas (taking both single and
 /// double quotes into account) and return a string consisting of only the fields indicated by the
 /// column indices specified. Absorbs out-of-bounds errors to handle ragged edge CSVs.
-pub fn cut_line(line: &String, cols: &Vec<usize>) -> String {
+pub fn cut_line(line: &str, cols: &[usize]) -> String {
     // Idea: do two passes - the first time to parse and the second time to produce the output.
     // TODO: terminate first pass early if we reached the max field?
 
     // Step 1: parse the fields. We don't use String::split() because we want to escape quotes.
-    let fields = split_line(&line);
+    let fields = split_line(line);
 
     // Step 2: stitch together the output
+    cut_fields(&fields, cols).join(",")
+}
+
+/// Set on a column index to select that column and every one after it, however many a row has,
+/// as in `3-`
+pub const OPEN_RANGE: usize = 1 << (usize::BITS - 1);
+
+/// Set on a column index to count it from the end of the row instead, 1 being the last column,
+/// as in `-1`
+pub const FROM_END: usize = 1 << (usize::BITS - 2);
+
+/// Whether a selected column index starts an open range, and where
+pub fn open_range(col: usize) -> Option<usize> {
+    if col & OPEN_RANGE != 0 { Some(col & !OPEN_RANGE) } else { None }
+}
+
+/// Whether which column a selected column index picks depends on how many fields the row has
+pub fn is_relative(col: usize) -> bool {
+    col & (OPEN_RANGE | FROM_END) != 0
+}
+
+/// The column a single selected column index picks out of a row of `n` fields, if there is one
+/// so far from the end
+fn resolve_col(col: usize, n: usize) -> Option<usize> {
+    if col & FROM_END != 0 { n.checked_sub(col & !FROM_END) } else { Some(col) }
+}
+
+/// The column indices a selection picks out of a row of `n` fields, with open ranges and indices
+/// from the end resolved. Indices from the end past the first column are left out.
+pub fn resolve_cols(cols: &[usize], n: usize) -> Vec<usize> {
+    let mut res = Vec::with_capacity(cols.len());
+    for col in cols.iter() {
+        match open_range(*col) {
+            Some(start) => res.extend(start..n.max(start)),
+            None => res.extend(resolve_col(*col, n)),
+        }
+    }
+    res
+}
+
+/// Picks the fields indicated by the column indices out of an already split line. Out-of-bounds
+/// indices produce empty fields, as in `cut_line`; open ranges pick whatever fields there are.
+pub fn cut_fields<'a>(fields: &[&'a str], cols: &[usize]) -> Vec<&'a str> {
     let mut res: Vec<&str> = Vec::new();
     for i in cols.iter() {
-        if *i >= fields.len() {
-            res.push(&"");
+        if let Some(start) = open_range(*i) {
+            res.extend(fields.iter().skip(start));
         } else {
-            res.push(fields[*i]);
+            res.push(resolve_col(*i, fields.len()).and_then(|i| fields.get(i)).unwrap_or(&""));
         }
     }
+    res
+}
+
+/// Picks every field of an already split line except those the column indices indicate, in their
+/// original order, as `cut --complement` does
+pub fn complement_fields<'a>(fields: &[&'a str], cols: &[usize]) -> Vec<&'a str> {
+    let dropped = resolve_cols(cols, fields.len());
+    fields.iter().enumerate()
+        .filter(|(i, _)| !dropped.contains(i))
+        .map(|(_, f)| *f)
+        .collect()
+}
 
-    res.join(",")
+/// The indices of the fields `cut_fields`, or with `complement` `complement_fields`, picks out
+/// of a row of `n` fields, in order, with `n` standing for an empty field past the last
+pub fn picked_indices(cols: &[usize], complement: bool, n: usize) -> Vec<usize> {
+    if complement {
+        let dropped = resolve_cols(cols, n);
+        return (0..n).filter(|i| !dropped.contains(i)).collect();
+    }
+    let mut res = Vec::with_capacity(cols.len());
+    for col in cols.iter() {
+        match open_range(*col) {
+            Some(start) => res.extend(start..n.max(start)),
+            None => res.push(resolve_col(*col, n).filter(|i| *i < n).unwrap_or(n)),
+        }
+    }
+    res
 }
 
-/// Splits a string `line` on commas, with double and single quotes accounted for
-pub fn split_line(line: &String) -> Vec<&str> {
-    let line = line.trim();
+/// The byte ranges of a line that hold the fields at `picked`, given the byte range of each
+/// field, with each run of adjacent fields merged into one range, delimiters included, so it can
+/// be copied as written. A field past the last is the empty range at the start.
+pub fn pass_through_spans(ranges: &[(usize, usize)], picked: &[usize]) -> Vec<(usize, usize)> {
+    let mut res: Vec<(usize, usize)> = Vec::new();
+    let mut last: Option<usize> = None;
+    for i in picked.iter() {
+        match (ranges.get(*i), res.last_mut()) {
+            (Some((_, end)), Some(span)) if last == Some(i.wrapping_sub(1)) => span.1 = *end,
+            (Some(range), _) => res.push(*range),
+            (None, _) => res.push((0, 0)),
+        }
+        last = ranges.get(*i).map(|_| *i);
+    }
+    res
+}
 
-    enum QuoteState { Normal, SingleQuote, DoubleQuote, SingleEscape, DoubleEscape };
+/// Splits a string `line` on commas, with double and single quotes accounted for, escaped either
+/// with a backslash or by doubling them
+pub fn split_line(line: &str) -> Vec<&str> {
+    split_line_with(line, &Permissive)
+}
 
+/// Splits a string `line` into fields as written in `dialect`
+pub fn split_line_with<'a>(line: &'a str, dialect: &dyn Dialect) -> Vec<&'a str> {
     let mut fields: Vec<&str> = Vec::new();
-    let mut state = QuoteState::Normal;
-    let mut field_start: usize = 0;
-    for (i, c) in line.chars().enumerate() {
-        match (&state, c) {
-            (QuoteState::Normal, ',') => {
-                // it's the end of a field - push it and start a new one
-                fields.push(&line[field_start..i]);
-                field_start = i + 1;
+    dialect.field_ranges(line, &mut |start, end| fields.push(&line[start..end]));
+    fields
+}
+
+/// Quotes a value for output if it contains anything that would otherwise split it into several
+/// fields when read back: a comma, a quote or a newline. Embedded double quotes are backslash
+/// escaped, matching what `split_line` understands.
+pub fn quote_field(s: &str) -> Cow<'_, str> {
+    Permissive.quote(s)
+}
+
+/// Decodes a line that may not be valid UTF-8, writing each invalid byte as a `\\xNN` escape.
+/// Returns the line and where in it each escape starts.
+pub fn escape_invalid(mut bytes: &[u8]) -> (String, Vec<usize>) {
+    let mut res = String::with_capacity(bytes.len());
+    let mut escapes = Vec::new();
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(valid) => {
+                res.push_str(valid);
+                return (res, escapes);
+            },
+            Err(error) => {
+                let (valid, rest) = bytes.split_at(error.valid_up_to());
+                res.push_str(std::str::from_utf8(valid).expect("valid up to here"));
+                // A sequence cut short by the end of the line is escaped whole
+                let n = error.error_len().unwrap_or(rest.len());
+                for b in rest[..n].iter() {
+                    escapes.push(res.len());
+                    res.push_str(&format!("\\x{:02X}", b));
+                }
+                bytes = &rest[n..];
             },
-            // state machine logic for quoting and escaping
-            (QuoteState::Normal,        '\'')   => state = QuoteState::SingleQuote,
-            (QuoteState::SingleQuote,   '\'')   => state = QuoteState::Normal,
-            (QuoteState::Normal,        '"')    => state = QuoteState::DoubleQuote,
-            (QuoteState::DoubleQuote,   '"')    => state = QuoteState::Normal,
-            (QuoteState::SingleQuote,   '\\')   => state = QuoteState::SingleEscape,
-            (QuoteState::SingleEscape,  _)      => state = QuoteState::SingleQuote,
-            (QuoteState::DoubleQuote,   '\\')   => state = QuoteState::DoubleEscape,
-            (QuoteState::DoubleEscape,  _)      => state = QuoteState::DoubleQuote,
-            _ => {},
         }
     }
-    fields.push(&line[field_start..]);
-    fields
 }
 
 #[cfg(test)]
+#[allow(clippy::useless_vec)]
 mod test_cut_line {
     use super::*;
 
@@ -94,6 +256,84 @@ mod test_cut_line {
     }
 }
 
+
+#[cfg(test)]
+mod test_open_range {
+    use super::*;
+
+    #[test]
+    fn test_cut() {
+        let from_2 = OPEN_RANGE | 1;
+        assert_eq!(cut_line("a,b,c,d", &[from_2]), "b,c,d");
+        assert_eq!(cut_line("a,b", &[3, from_2, 0]), ",b,a");
+        assert_eq!(cut_line("a", &[from_2]), "");
+    }
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve_cols(&[0, OPEN_RANGE | 2], 4), vec![0, 2, 3]);
+        assert_eq!(resolve_cols(&[OPEN_RANGE | 5], 4), Vec::<usize>::new());
+        assert_eq!(resolve_cols(&[FROM_END | 1, FROM_END | 5, 1], 4), vec![3, 1]);
+    }
+    #[test]
+    fn test_from_end() {
+        let (last, second_last) = (FROM_END | 1, FROM_END | 2);
+        assert_eq!(cut_line("a,b,c", &[last, second_last]), "c,b");
+        assert_eq!(cut_line("a", &[second_last, last]), ",a");
+        assert_eq!(complement_fields(&["a", "b", "c"], &[last]), vec!["a", "b"]);
+    }
+}
+
+#[cfg(test)]
+mod test_column_spec {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let spec: ColumnSpec = "3,1-2,-1".parse().unwrap();
+        assert_eq!(spec.indices(), &[2, 0, 1, FROM_END | 1]);
+        assert_eq!(ColumnSpec::parse("0,2-", 0).unwrap(),
+                   ColumnSpec::from_indices(vec![0, OPEN_RANGE | 2]));
+        assert!(ColumnSpec::parse("0", 1).is_err());
+        assert!(ColumnSpec::parse("a-2", 1).is_err());
+        assert!(ColumnSpec::parse("3-1", 1).is_err());
+    }
+    #[test]
+    fn test_cut() {
+        let spec: ColumnSpec = "-1,2".parse().unwrap();
+        assert_eq!(spec.cut_line(r#"a,"b,c",d"#), r#"d,"b,c""#);
+        assert_eq!(spec.cut(&["a", "b"]), vec!["b", "b"]);
+        assert_eq!(spec.complement(&["a", "b", "c"]), vec!["a"]);
+        assert_eq!(spec.resolve(3), vec![2, 1]);
+    }
+}
+
+#[cfg(test)]
+mod test_complement {
+    use super::*;
+
+    #[test]
+    fn test_complement() {
+        assert_eq!(complement_fields(&["a", "b", "c", "d", "e"], &[4, 1]), vec!["a", "c", "d"]);
+        assert_eq!(complement_fields(&["a", "b"], &[4, 1]), vec!["a"]);
+        assert_eq!(complement_fields(&["a", "b", "c"], &[OPEN_RANGE | 1]), vec!["a"]);
+        assert_eq!(complement_fields(&["a", "b"], &[]), vec!["a", "b"]);
+    }
+    #[test]
+    fn test_pass_through_spans() {
+        let line = "a,bb,c,d";
+        let ranges = [(0, 1), (2, 4), (5, 6), (7, 8)];
+        let spans = |picked: &[usize]| -> Vec<&str> {
+            pass_through_spans(&ranges, picked).iter().map(|(s, e)| &line[*s..*e]).collect()
+        };
+        assert_eq!(spans(&picked_indices(&[2], true, 4)), vec!["a,bb", "d"]);
+        assert_eq!(spans(&picked_indices(&[OPEN_RANGE | 1], false, 4)), vec!["bb,c,d"]);
+        assert_eq!(spans(&picked_indices(&[5, 0, OPEN_RANGE | 2], false, 4)),
+                   vec!["", "a", "c,d"]);
+        assert_eq!(spans(&picked_indices(&[3, 2], false, 4)), vec!["d", "c"]);
+        assert_eq!(picked_indices(&[FROM_END | 5, FROM_END | 1], false, 4), vec![4, 3]);
+    }
+}
+
 #[cfg(test)]
 mod test_split_line {
     use super::*;
@@ -122,4 +362,47 @@ mod test_split_line {
         let res = split_line(input);
         assert_eq!(res, vec!["a", "\'b,c\'"]);
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_doubled_quote() {
+        let input = &String::from(r#"1,"he said ""hi, there""",2"#);
+        let res = split_line(input);
+        assert_eq!(res, vec!["1", r#""he said ""hi, there""""#, "2"]);
+        let input = &String::from(r#""""",x"#);
+        let res = split_line(input);
+        assert_eq!(res, vec![r#""""""#, "x"]);
+    }
+}
+#[cfg(test)]
+mod test_escape_invalid {
+    use super::*;
+
+    #[test]
+    fn test_escape_invalid() {
+        assert_eq!(escape_invalid("née,1".as_bytes()), (String::from("née,1"), vec![]));
+        assert_eq!(escape_invalid(b"n\xe9e,\xff\xfe"),
+                   (String::from("n\\xE9e,\\xFF\\xFE"), vec![1, 7, 11]));
+        assert_eq!(escape_invalid(b"a\xe2\x82"), (String::from("a\\xE2\\x82"), vec![1, 5]));
+    }
+}
+
+#[cfg(test)]
+mod test_quote_field {
+    use super::*;
+
+    #[test]
+    fn test_plain() {
+        assert_eq!(quote_field("abc"), "abc");
+        assert_eq!(quote_field(""), "");
+    }
+    #[test]
+    fn test_quoted() {
+        assert_eq!(quote_field("a,b"), r#""a,b""#);
+        assert_eq!(quote_field(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(quote_field("it's"), r#""it's""#);
+    }
+    #[test]
+    fn test_round_trip() {
+        let line = [quote_field("a,b"), quote_field(r#"x"y"#)].join(",");
+        assert_eq!(split_line(&line), vec![r#""a,b""#, r#""x\"y""#]);
+    }
+}
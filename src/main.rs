@@ -1,75 +1,2758 @@
-use std::io;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::thread;
 
 extern crate argparse;
-use argparse::{ArgumentParser, Store, StoreTrue, StoreConst};
+use argparse::{ArgumentParser, Collect, Store, StoreConst, StoreOption, StoreTrue};
+use regex::{Regex, RegexBuilder};
+use sha2::{Digest, Sha256};
 
-mod line;
-mod parse_args;
+use ccut::dialect::{self, Dialect, Permissive, Quoting};
+use ccut::pipeline::{Pipeline, Sink, WriterSink};
+use ccut::{audit, catalog, check, collate, correlate, crosstab, decimal, estimate, expr, fpe, grep,
+           group, help, input, interval, limits, line, membership, messages, options, outlier,
+           parse_args, partition, plan, precision, pseudonym, redact, sample, session, shard,
+           sketch, slice, sort, spool, stats, table, top, trailer, transform, unique};
+use ccut::diagnostic::{self, Diagnostic, ErrorsFormat};
 
 fn main() {
+    // Subcommands get their own arguments, named after them in the usage
+    let subcommand = std::env::args().nth(1)
+        .filter(|a| ["convert", "find-column", "catalog", "help"].contains(&a.as_str()));
+    if let Some(name) = subcommand {
+        let args = std::iter::once(format!("ccut {}", name)).chain(std::env::args().skip(2));
+        match name.as_str() {
+            "convert" => convert_main(args.collect()),
+            "catalog" => catalog_main(args.collect()),
+            "help" => help_main(args.collect()),
+            _ => find_column_main(args.collect()),
+        }
+        return;
+    }
     // Parse arguments
     let mut preview = false;
+    let mut preview_rows: Option<usize> = None;
+    let mut preview_sample = false;
+    let mut seed: Option<u64> = None;
+    let mut sample_fraction: Option<f64> = None;
+    let mut sample_n: Option<usize> = None;
+    let mut lang = String::from("");
+    let mut errors_format = String::from("text");
+    let mut header_only = false;
+    let mut dims = false;
     let mut cols = String::from("");
+    let mut files: Vec<String> = Vec::new();
+    let mut delimiter = String::from("");
+    let mut quote: Vec<String> = Vec::new();
+    let mut no_single_quote = false;
+    let mut no_quote = false;
+    let mut unquote = false;
+    let mut requote = String::from("");
+    let mut sniff = false;
     let mut offset: usize = 1;
+    let mut computes: Vec<String> = Vec::new();
+    let mut top_n: usize = 0;
+    let mut by = String::from("");
+    let mut cardinality = String::from("");
+    let mut heavy_hitters = String::from("");
+    let mut k: usize = 20;
+    let mut checks: Vec<String> = Vec::new();
+    let mut wheres: Vec<String> = Vec::new();
+    let mut allow_dns = false;
+    let mut validate = false;
+    let mut max_errors: Option<usize> = None;
+    let mut error_examples: Option<usize> = None;
+    let mut assert_sorted = String::from("");
+    let mut gaps = String::from("");
+    let mut tzs: Vec<String> = Vec::new();
+    let mut fpes: Vec<String> = Vec::new();
+    let mut pseudonymize: Vec<String> = Vec::new();
+    let mut salt_file = String::from("");
+    let mut rotate_salt = false;
+    let mut split_urls: Vec<String> = Vec::new();
+    let mut emit_plan = String::from("");
+    let mut plan_file = String::from("");
+    let mut digest_only: Option<String> = None;
+    let mut seek: u64 = 0;
+    let mut align_record = false;
+    let mut byte_range = String::from("");
+    let mut line_range = String::from("");
+    let mut skip: u64 = 0;
+    let mut limit: Option<u64> = None;
+    let mut auto_shard = false;
+    let mut threads: Option<usize> = None;
+    let mut sort = String::from("");
+    let mut sort_mem = String::from("256M");
+    let mut sort_tmp = String::from("");
+    let mut collate = String::from("bytewise");
+    let mut numeric = false;
+    let mut reverse = false;
+    let mut fold_case = false;
+    let mut redact_patterns: Vec<String> = Vec::new();
+    let mut redact_output = false;
+    let mut audit_file = String::from("");
+    let mut max_memory = String::from("");
+    let mut buffer_bytes = String::from("");
+    let mut nice: Option<i32> = None;
+    let mut trailer_checksum = false;
+    let mut checksum_file = String::from("");
+    let mut partition_by = String::from("");
+    let mut out_dir = String::from("");
+    let mut skip_existing = false;
+    let mut overwrite = false;
+    let mut manifest = false;
+    let mut estimate = false;
+    let mut stats = false;
+    let mut stats_every = String::from("");
+    let mut stats_file = String::from("");
+    let mut correlate = String::from("");
+    let mut crosstab = String::from("");
+    let mut crosstab_max = crosstab::MAX_DISTINCT;
+    let mut regex_cols = String::from("");
+    let mut use_catalog = false;
+    let mut group_by = String::from("");
+    let mut aggs: Vec<String> = Vec::new();
+    let mut agg_nulls = String::from("skip");
+    let mut freq = String::from("");
+    let mut key_sep: Option<String> = None;
+    let mut sessionize = String::from("");
+    let mut flag_outliers = String::from("");
+    let mut outlier_window: Option<usize> = None;
+    let mut outliers_only = false;
+    let mut range_join = String::from("");
+    let mut range_on = String::from("");
+    let mut join_type = String::from("left");
+    let mut fill = String::from("");
+    let mut matched_only_columns = String::from("");
+    let mut in_files: Vec<String> = Vec::new();
+    let mut not_in_files: Vec<String> = Vec::new();
+    let mut greps: Vec<String> = Vec::new();
+    let mut grep_vs: Vec<String> = Vec::new();
+    let mut unique_rows = false;
+    let mut unique_by = String::from("");
+    let mut intersects: Vec<String> = Vec::new();
+    let mut excepts: Vec<String> = Vec::new();
+    let mut set_key = String::from("");
+    let mut multiline = false;
+    let mut complement = false;
+    let mut bloom_fpr: Option<f64> = None;
+    let mut flush = false;
+    let mut header = false;
+    let mut provenance = false;
+    let mut show_invalid_bytes = false;
+    let mut table = false;
+    let mut json = false;
+    let mut markdown = false;
+    let mut verify_roundtrip = false;
+    let mut strip_control = false;
+    let mut show_control = false;
+    let mut newlines_as: Option<String> = None;
+    let mut max_cell_bytes = String::from("");
+    let mut cell_overflow = String::from("");
+    let mut ragged = String::from("");
+    let mut precision: Option<usize> = None;
+    let mut round_mode = String::from("half-even");
+    let mut decimal = false;
+    let mut scales: Vec<String> = Vec::new();
     {
         let mut ap = ArgumentParser::new();
-        ap.set_description("Like cut, but for CSVs");
+        ap.set_description("Like cut, but for CSVs. See 'ccut help' for the column list grammar, \
+                            the dialects and examples, 'ccut convert --help' to convert between \
+                            dialects, 'ccut find-column --help' to find a column among many \
+                            files, and 'ccut catalog --help' to keep a catalog of their headers");
         ap.refer(&mut preview)
-            .add_option(&["-p", "--preview"],
-                        StoreTrue,
-                        "Preview first line with column numbers");
+            .add_option(options::names("--preview"), StoreTrue,
+                        options::help("--preview"));
+        ap.refer(&mut preview_rows)
+            .add_option(options::names("--preview-rows"), StoreOption,
+                        options::help("--preview-rows"));
+        ap.refer(&mut preview_sample)
+            .add_option(options::names("--preview-sample"), StoreTrue,
+                        options::help("--preview-sample"));
+        ap.refer(&mut seed)
+            .add_option(options::names("--seed"), StoreOption,
+                        options::help("--seed"));
+        ap.refer(&mut sample_fraction)
+            .add_option(options::names("--sample"), StoreOption,
+                        options::help("--sample"));
+        ap.refer(&mut sample_n)
+            .add_option(options::names("--sample-n"), StoreOption,
+                        options::help("--sample-n"));
+        ap.refer(&mut header_only)
+            .add_option(options::names("--header-only"), StoreTrue,
+                        options::help("--header-only"));
+        ap.refer(&mut dims)
+            .add_option(options::names("--dims"), StoreTrue,
+                        options::help("--dims"));
+        ap.refer(&mut delimiter)
+            .add_option(options::names("--delimiter"), Store,
+                        options::help("--delimiter"));
+        ap.refer(&mut quote)
+            .add_option(options::names("--quote"), Collect,
+                        options::help("--quote"));
+        ap.refer(&mut no_single_quote)
+            .add_option(options::names("--no-single-quote"), StoreTrue,
+                        options::help("--no-single-quote"));
+        ap.refer(&mut no_quote)
+            .add_option(options::names("--no-quote"), StoreTrue,
+                        options::help("--no-quote"));
+        ap.refer(&mut unquote)
+            .add_option(options::names("--unquote"), StoreTrue,
+                        options::help("--unquote"));
+        ap.refer(&mut requote)
+            .add_option(options::names("--requote"), Store,
+                        options::help("--requote"));
+        ap.refer(&mut sniff)
+            .add_option(options::names("--sniff"), StoreTrue,
+                        options::help("--sniff"));
         ap.refer(&mut offset)
-            .add_option(&["-0", "--zero"],
-                        StoreConst(0),
-                        "Zero-index columns. Ranges are half-open like [a, b)")
-            .add_option(&["-1", "--one"],
-                        StoreConst(1),
-                        "One-index columns (default). Ranges are closed like [a, b]");
+            .add_option(options::names("--zero"), StoreConst(0),
+                        options::help("--zero"))
+            .add_option(options::names("--one"), StoreConst(1),
+                        options::help("--one"));
+        ap.refer(&mut computes)
+            .add_option(options::names("--compute"), Collect,
+                        options::help("--compute"));
+        ap.refer(&mut top_n)
+            .add_option(options::names("--top"), Store,
+                        options::help("--top"));
+        ap.refer(&mut by)
+            .add_option(options::names("--by"), Store,
+                        options::help("--by"));
+        ap.refer(&mut sort)
+            .add_option(options::names("--sort"), Store,
+                        options::help("--sort"));
+        ap.refer(&mut sort_mem)
+            .add_option(options::names("--sort-mem"), Store,
+                        options::help("--sort-mem"));
+        ap.refer(&mut sort_tmp)
+            .add_option(options::names("--sort-tmp"), Store,
+                        options::help("--sort-tmp"));
+        ap.refer(&mut collate)
+            .add_option(options::names("--collate"), Store,
+                        options::help("--collate"));
+        ap.refer(&mut numeric)
+            .add_option(options::names("--numeric"), StoreTrue,
+                        options::help("--numeric"));
+        ap.refer(&mut reverse)
+            .add_option(options::names("--reverse"), StoreTrue,
+                        options::help("--reverse"));
+        ap.refer(&mut fold_case)
+            .add_option(options::names("--fold-case"), StoreTrue,
+                        options::help("--fold-case"));
+        ap.refer(&mut cardinality)
+            .add_option(options::names("--cardinality"), Store,
+                        options::help("--cardinality"));
+        ap.refer(&mut heavy_hitters)
+            .add_option(options::names("--heavy-hitters"), Store,
+                        options::help("--heavy-hitters"));
+        ap.refer(&mut k)
+            .add_option(options::names("--k"), Store,
+                        options::help("--k"));
+        ap.refer(&mut wheres)
+            .add_option(options::names("--where"), Collect,
+                        options::help("--where"));
+        ap.refer(&mut allow_dns)
+            .add_option(options::names("--allow-dns"), StoreTrue,
+                        options::help("--allow-dns"));
+        ap.refer(&mut redact_patterns)
+            .add_option(options::names("--redact-pattern"), Collect,
+                        options::help("--redact-pattern"));
+        ap.refer(&mut redact_output)
+            .add_option(options::names("--redact-output"), StoreTrue,
+                        options::help("--redact-output"));
+        ap.refer(&mut strip_control)
+            .add_option(options::names("--strip-control"), StoreTrue,
+                        options::help("--strip-control"));
+        ap.refer(&mut show_control)
+            .add_option(options::names("--show-control"), StoreTrue,
+                        options::help("--show-control"));
+        ap.refer(&mut newlines_as)
+            .add_option(options::names("--newlines-as"), StoreOption,
+                        options::help("--newlines-as"));
+        ap.refer(&mut max_cell_bytes)
+            .add_option(options::names("--max-cell-bytes"), Store,
+                        options::help("--max-cell-bytes"));
+        ap.refer(&mut cell_overflow)
+            .add_option(options::names("--cell-overflow"), Store,
+                        options::help("--cell-overflow"));
+        ap.refer(&mut ragged)
+            .add_option(options::names("--ragged"), Store,
+                        options::help("--ragged"));
+        ap.refer(&mut tzs)
+            .add_option(options::names("--tz"), Collect,
+                        options::help("--tz"));
+        ap.refer(&mut fpes)
+            .add_option(options::names("--fpe"), Collect,
+                        options::help("--fpe"));
+        ap.refer(&mut pseudonymize)
+            .add_option(options::names("--pseudonymize"), Collect,
+                        options::help("--pseudonymize"));
+        ap.refer(&mut salt_file)
+            .add_option(options::names("--salt-file"), Store,
+                        options::help("--salt-file"));
+        ap.refer(&mut rotate_salt)
+            .add_option(options::names("--rotate-salt"), StoreTrue,
+                        options::help("--rotate-salt"));
+        ap.refer(&mut split_urls)
+            .add_option(options::names("--split-url"), Collect,
+                        options::help("--split-url"));
+        ap.refer(&mut checks)
+            .add_option(options::names("--check"), Collect,
+                        options::help("--check"));
+        ap.refer(&mut assert_sorted)
+            .add_option(options::names("--assert-sorted"), Store,
+                        options::help("--assert-sorted"));
+        ap.refer(&mut gaps)
+            .add_option(options::names("--gaps"), Store,
+                        options::help("--gaps"));
+        ap.refer(&mut validate)
+            .add_option(options::names("--validate"), StoreTrue,
+                        options::help("--validate"));
+        ap.refer(&mut max_errors)
+            .add_option(options::names("--max-errors"), StoreOption,
+                        options::help("--max-errors"));
+        ap.refer(&mut error_examples)
+            .add_option(options::names("--error-examples"), StoreOption,
+                        options::help("--error-examples"));
+        ap.refer(&mut emit_plan)
+            .add_option(options::names("--emit-plan"), Store,
+                        options::help("--emit-plan"));
+        ap.refer(&mut plan_file)
+            .add_option(options::names("--plan"), Store,
+                        options::help("--plan"));
+        ap.refer(&mut digest_only)
+            .add_option(options::names("--digest-only"), StoreOption,
+                        options::help("--digest-only"));
+        ap.refer(&mut seek)
+            .add_option(options::names("--seek"), Store,
+                        options::help("--seek"));
+        ap.refer(&mut align_record)
+            .add_option(options::names("--align-record"), StoreTrue,
+                        options::help("--align-record"));
+        ap.refer(&mut byte_range)
+            .add_option(options::names("--byte-range"), Store,
+                        options::help("--byte-range"));
+        ap.refer(&mut line_range)
+            .add_option(options::names("--line-range"), Store,
+                        options::help("--line-range"));
+        ap.refer(&mut skip)
+            .add_option(options::names("--skip"), Store,
+                        options::help("--skip"));
+        ap.refer(&mut limit)
+            .add_option(options::names("--limit"), StoreOption,
+                        options::help("--limit"));
+        ap.refer(&mut auto_shard)
+            .add_option(options::names("--auto-shard"), StoreTrue,
+                        options::help("--auto-shard"));
+        ap.refer(&mut threads)
+            .add_option(options::names("--threads"), StoreOption,
+                        options::help("--threads"));
+        ap.refer(&mut audit_file)
+            .add_option(options::names("--audit"), Store,
+                        options::help("--audit"));
+        ap.refer(&mut max_memory)
+            .add_option(options::names("--max-memory"), Store,
+                        options::help("--max-memory"));
+        ap.refer(&mut buffer_bytes)
+            .add_option(options::names("--buffer-bytes"), Store,
+                        options::help("--buffer-bytes"));
+        ap.refer(&mut nice)
+            .add_option(options::names("--nice"), StoreOption,
+                        options::help("--nice"));
+        ap.refer(&mut trailer_checksum)
+            .add_option(options::names("--trailer-checksum"), StoreTrue,
+                        options::help("--trailer-checksum"));
+        ap.refer(&mut checksum_file)
+            .add_option(options::names("--checksum-file"), Store,
+                        options::help("--checksum-file"));
+        ap.refer(&mut partition_by)
+            .add_option(options::names("--partition-by"), Store,
+                        options::help("--partition-by"));
+        ap.refer(&mut out_dir)
+            .add_option(options::names("--out-dir"), Store,
+                        options::help("--out-dir"));
+        ap.refer(&mut skip_existing)
+            .add_option(options::names("--skip-existing"), StoreTrue,
+                        options::help("--skip-existing"));
+        ap.refer(&mut overwrite)
+            .add_option(options::names("--overwrite"), StoreTrue,
+                        options::help("--overwrite"));
+        ap.refer(&mut manifest)
+            .add_option(options::names("--manifest"), StoreTrue,
+                        options::help("--manifest"));
+        ap.refer(&mut estimate)
+            .add_option(options::names("--estimate"), StoreTrue,
+                        options::help("--estimate"));
+        ap.refer(&mut stats)
+            .add_option(options::names("--stats"), StoreTrue,
+                        options::help("--stats"));
+        ap.refer(&mut stats_every)
+            .add_option(options::names("--stats-every"), Store,
+                        options::help("--stats-every"));
+        ap.refer(&mut stats_file)
+            .add_option(options::names("--stats-file"), Store,
+                        options::help("--stats-file"));
+        ap.refer(&mut correlate)
+            .add_option(options::names("--correlate"), Store,
+                        options::help("--correlate"));
+        ap.refer(&mut crosstab)
+            .add_option(options::names("--crosstab-count"), Store,
+                        options::help("--crosstab-count"));
+        ap.refer(&mut crosstab_max)
+            .add_option(options::names("--crosstab-max"), Store,
+                        options::help("--crosstab-max"));
+        ap.refer(&mut group_by)
+            .add_option(options::names("--group-by"), Store,
+                        options::help("--group-by"));
+        ap.refer(&mut aggs)
+            .add_option(options::names("--agg"), Collect,
+                        options::help("--agg"));
+        ap.refer(&mut agg_nulls)
+            .add_option(options::names("--agg-nulls"), Store,
+                        options::help("--agg-nulls"));
+        ap.refer(&mut freq)
+            .add_option(options::names("--freq"), Store,
+                        options::help("--freq"));
+        ap.refer(&mut sessionize)
+            .add_option(options::names("--sessionize"), Store,
+                        options::help("--sessionize"));
+        ap.refer(&mut flag_outliers)
+            .add_option(options::names("--flag-outliers"), Store,
+                        options::help("--flag-outliers"));
+        ap.refer(&mut outlier_window)
+            .add_option(options::names("--outlier-window"), StoreOption,
+                        options::help("--outlier-window"));
+        ap.refer(&mut outliers_only)
+            .add_option(options::names("--outliers-only"), StoreTrue,
+                        options::help("--outliers-only"));
+        ap.refer(&mut range_join)
+            .add_option(options::names("--range-join"), Store,
+                        options::help("--range-join"));
+        ap.refer(&mut range_on)
+            .add_option(options::names("--on"), Store,
+                        options::help("--on"));
+        ap.refer(&mut join_type)
+            .add_option(options::names("--join-type"), Store,
+                        options::help("--join-type"));
+        ap.refer(&mut fill)
+            .add_option(options::names("--fill"), Store,
+                        options::help("--fill"));
+        ap.refer(&mut matched_only_columns)
+            .add_option(options::names("--matched-only-columns"), Store,
+                        options::help("--matched-only-columns"));
+        ap.refer(&mut in_files)
+            .add_option(options::names("--in-file"), Collect,
+                        options::help("--in-file"));
+        ap.refer(&mut not_in_files)
+            .add_option(options::names("--not-in-file"), Collect,
+                        options::help("--not-in-file"));
+        ap.refer(&mut greps)
+            .add_option(options::names("--grep"), Collect,
+                        options::help("--grep"));
+        ap.refer(&mut grep_vs)
+            .add_option(options::names("--grep-v"), Collect,
+                        options::help("--grep-v"));
+        ap.refer(&mut unique_rows)
+            .add_option(options::names("--unique"), StoreTrue,
+                        options::help("--unique"));
+        ap.refer(&mut unique_by)
+            .add_option(options::names("--unique-by"), Store,
+                        options::help("--unique-by"));
+        ap.refer(&mut intersects)
+            .add_option(options::names("--intersect"), Collect,
+                        options::help("--intersect"));
+        ap.refer(&mut excepts)
+            .add_option(options::names("--except"), Collect,
+                        options::help("--except"));
+        ap.refer(&mut set_key)
+            .add_option(options::names("--key"), Store,
+                        options::help("--key"));
+        ap.refer(&mut bloom_fpr)
+            .add_option(options::names("--bloom-fpr"), StoreOption,
+                        options::help("--bloom-fpr"));
+        ap.refer(&mut key_sep)
+            .add_option(options::names("--key-sep"), StoreOption,
+                        options::help("--key-sep"));
+        ap.refer(&mut precision)
+            .add_option(options::names("--precision"), StoreOption,
+                        options::help("--precision"));
+        ap.refer(&mut round_mode)
+            .add_option(options::names("--round-mode"), Store,
+                        options::help("--round-mode"));
+        ap.refer(&mut decimal)
+            .add_option(options::names("--decimal"), StoreTrue,
+                        options::help("--decimal"));
+        ap.refer(&mut scales)
+            .add_option(options::names("--scale"), Collect,
+                        options::help("--scale"));
+        ap.refer(&mut multiline)
+            .add_option(options::names("--multiline"), StoreTrue,
+                        options::help("--multiline"));
+        ap.refer(&mut complement)
+            .add_option(options::names("--complement"), StoreTrue,
+                        options::help("--complement"));
+        ap.refer(&mut flush)
+            .add_option(options::names("--flush"), StoreTrue,
+                        options::help("--flush"));
+        ap.refer(&mut header)
+            .add_option(options::names("--header"), StoreTrue,
+                        options::help("--header"));
+        ap.refer(&mut regex_cols)
+            .add_option(options::names("--regex-cols"), Store,
+                        options::help("--regex-cols"));
+        ap.refer(&mut use_catalog)
+            .add_option(options::names("--catalog"), StoreTrue,
+                        options::help("--catalog"));
+        ap.refer(&mut provenance)
+            .add_option(options::names("--provenance"), StoreTrue,
+                        options::help("--provenance"));
+        ap.refer(&mut show_invalid_bytes)
+            .add_option(options::names("--show-invalid-bytes"), StoreTrue,
+                        options::help("--show-invalid-bytes"));
+        ap.refer(&mut table)
+            .add_option(options::names("--table"), StoreTrue,
+                        options::help("--table"));
+        ap.refer(&mut json)
+            .add_option(options::names("--json"), StoreTrue,
+                        options::help("--json"));
+        ap.refer(&mut markdown)
+            .add_option(options::names("--markdown"), StoreTrue,
+                        options::help("--markdown"));
+        ap.refer(&mut verify_roundtrip)
+            .add_option(options::names("--verify-roundtrip"), StoreTrue,
+                        options::help("--verify-roundtrip"));
+        ap.refer(&mut lang)
+            .add_option(options::names("--lang"), Store,
+                        options::help("--lang"));
+        ap.refer(&mut errors_format)
+            .add_option(options::names("--errors-format"), Store,
+                        options::help("--errors-format"));
         ap.refer(&mut cols)
-            .add_argument("cols", Store, "Column indices to print");
-        ap.parse_args_or_exit();
+            .add_argument("cols", Store,
+                          "Column indices to print, e.g. 1,3-5; 3- runs to the end of each row, \
+                           and -1 is its last column and -3--1 its last three");
+        ap.refer(&mut files)
+            .add_argument("files", Collect, "Files to read, one after the other; - or none for \
+                                             stdin");
+        let args = std::env::args().take(1)
+            .chain(options::separate_col_list(std::env::args().skip(1).collect()))
+            .collect();
+        if let Err(code) = ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            process::exit(code);
+        }
+    }
+    if !lang.is_empty() {
+        messages::set_lang(&lang);
+    }
+    // Reported like argparse's own errors, as a usage error rather than a failed run
+    let digest_only: Option<trailer::DigestAlgorithm> = match digest_only.map(|a| a.parse()) {
+        Some(Err(error)) => {
+            eprintln!("ccut: --digest-only: {}", error);
+            process::exit(2);
+        },
+        parsed => parsed.transpose().unwrap(),
+    };
+
+    let redact_patterns: Vec<String> = redact_patterns.iter()
+        .flat_map(|p| redact::resolve_pattern(p))
+        .collect();
+
+    let preview = preview || preview_rows.is_some() || preview_sample;
+    let stats = stats || !stats_every.is_empty();
+    // Where the column list may be left out, a first argument that isn't one is an input file
+    let cols_optional = !plan_file.is_empty() || preview || header_only || dims
+        || !computes.is_empty() || validate
+        || !regex_cols.is_empty() || stats || !group_by.is_empty() || !freq.is_empty()
+        || !cardinality.is_empty() || !heavy_hitters.is_empty() || !correlate.is_empty()
+        || !crosstab.is_empty();
+    if cols_optional && !cols.is_empty() && !parse_args::is_col_list(&cols) {
+        files.insert(0, cols);
+        cols = String::new();
+    }
+    if !plan_file.is_empty() {
+        // Anything else the command line sets would be ignored in favour of the plan
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        let others = parse_args::options_besides(&args, &["--plan", "--lang"]);
+        assert!(others.is_empty(), "--plan sets every option itself, so it can't be combined with \
+                                    {}", others.join(", "));
+        assert!(cols.is_empty(), "--plan already selects the columns, so {} can't", cols);
+    }
+    let inputs: Vec<PathBuf> = files.iter().map(PathBuf::from).collect();
+    // A file the catalog knows, unchanged since, needn't be read for its delimiter or names
+    let cataloged = match input::single_file(&inputs) {
+        Some(path) if use_catalog => {
+            let catalog_path = catalog::default_path();
+            match catalog::Catalog::load(&catalog_path) {
+                Ok(catalog) => catalog.fresh(path).cloned(),
+                Err(error) => {
+                    eprintln!("{}", messages::message("read-catalog",
+                                                      &[&catalog_path.display(), &error]));
+                    process::exit(1);
+                },
+            }
+        },
+        _ => None,
+    };
+    assert!(!sniff || (delimiter.is_empty() && plan_file.is_empty()),
+            "--sniff can't be combined with --delimiter or --plan, which set the delimiter");
+    let delimiter = if sniff {
+        match input::peek(&inputs, dialect::SNIFF_BYTES) {
+            // Commas are the default anyway
+            Ok(sample) => dialect::sniff_delimiter(&String::from_utf8_lossy(&sample))
+                .filter(|d| *d != ','),
+            Err(error) => {
+                eprintln!("{}", messages::message("read-input", &[&error]));
+                process::exit(1);
+            },
+        }
+    } else if delimiter.is_empty() {
+        cataloged.as_ref().map(|entry| entry.delimiter).filter(|d| *d != ',')
+    } else {
+        Some(parse_args::parse_arg_delimiter(&delimiter))
+    };
+    assert!(!no_quote || (quote.is_empty() && !no_single_quote),
+            "--no-quote can't be combined with --quote or --no-single-quote");
+    let quotes = if no_quote {
+        Some(Vec::new())
+    } else if quote.is_empty() && !no_single_quote {
+        None
+    } else {
+        let quotes: Vec<char> = if quote.is_empty() {
+            plan::Plan::default().make_dialect().quotes().to_vec()
+        } else {
+            quote.iter().map(|q| parse_args::parse_arg_quote(q)).collect()
+        };
+        Some(quotes.into_iter().filter(|q| !no_single_quote || *q != '\'').collect())
+    };
+
+    if rotate_salt {
+        assert!(!salt_file.is_empty(), "--rotate-salt needs --salt-file");
+        if let Err(error) = pseudonym::rotate_salt(Path::new(&salt_file)) {
+            eprintln!("{}", messages::message("write-salt", &[&salt_file, &error]));
+            process::exit(1);
+        }
+        return;
+    }
+
+    let buffer_bytes = if buffer_bytes.is_empty() {
+        None
+    } else {
+        Some(parse_args::parse_arg_size(&buffer_bytes))
+    };
+    assert!(buffer_bytes != Some(0), "--buffer-bytes must be at least 1");
+
+    if header_only || dims {
+        assert!(!(header_only && dims), "--header-only and --dims are separate queries");
+        let plan = plan::Plan { delimiter, quotes, multiline, inputs, header, buffer_bytes,
+                                ..plan::Plan::default() };
+        let res = if header_only { header_report(&plan, offset) } else { dims_report(&plan) };
+        if let Err(error) = res {
+            eprintln!("{}", messages::message("read-input", &[&error]));
+            process::exit(1);
+        }
+        return;
     }
 
     if preview {
-        // TODO: dedup
-        let mut line = String::new();
-        match io::stdin().read_line(&mut line) {
-            Ok(n) => {
-                if n == 0 {
-                    return;
-                }
-                let fields: Vec<&str> = line::split_line(&line);
-                let col_nums: Vec<String> = fields.iter().enumerate()
-                    .map(|tpl| (tpl.0 + offset).to_string())
-                    .collect();
-                println!("{}", col_nums.join(","));
-                println!("{}", fields.join(","));
+        let redactor = redact::Redactor::new(&redact_patterns);
+        let plan = plan::Plan { delimiter, quotes, inputs, header, buffer_bytes,
+                                ..plan::Plan::default() };
+        let rows = preview_rows.unwrap_or(if preview_sample { sample::BLOCK_ROWS } else { 1 });
+        let sample_seed = preview_sample.then(|| seed.unwrap_or_else(sample::random_seed));
+        if let Err(error) = preview_report(&plan, rows, sample_seed, &redactor, offset) {
+            eprintln!("{}", messages::message("read-input", &[&error]));
+            process::exit(1);
+        }
+        return;
+    }
+
+    let mut plan = if !plan_file.is_empty() {
+        match plan::Plan::load(Path::new(&plan_file)) {
+            Ok(plan) => plan,
+            Err(error) => {
+                eprintln!("{}", messages::message("read-plan", &[&plan_file, &error]));
+                process::exit(1);
+            },
+        }
+    } else {
+        let mut computes = computes;
+        for col in split_urls.iter() {
+            let col = parse_args::parse_arg_col(col, offset) + offset;
+            for part in ["url_scheme", "url_host", "url_path", "url_query"].iter() {
+                computes.push(format!("{}(col{})", part, col));
+            }
+        }
+        let opt_col = |s: &str| if s.is_empty() {
+            None
+        } else {
+            Some(parse_args::parse_arg_col(s, offset))
+        };
+        assert!(freq.is_empty() || by.is_empty(), "--freq ranks values by count, not --by");
+        let top = if top_n > 0 && freq.is_empty() {
+            assert!(!by.is_empty(), "--top requires --by, or --freq");
+            let (col, desc) = parse_args::parse_arg_by(&by, offset);
+            Some(plan::TopSpec { n: top_n, col, desc })
+        } else {
+            None
+        };
+        assert!(top_n == 0 || sort.is_empty(), "--top already sorts its rows; use --by with it");
+        assert!(!(numeric || reverse) || !sort.is_empty(), "--numeric and --reverse need --sort");
+        assert!(!numeric || collate == "bytewise", "--numeric already sets the collation");
+        let sort = if sort.is_empty() {
+            None
+        } else {
+            let (col, desc) = parse_args::parse_arg_by(&sort, offset);
+            Some(sort::SortSpec {
+                col,
+                desc: desc || reverse,
+                collation: if numeric {
+                    collate::Collation::Numeric
+                } else {
+                    collate::parse_collation(&collate)
+                },
+                mem: parse_args::parse_arg_size(&sort_mem),
+                tmp_dir: if sort_tmp.is_empty() { None } else { Some(sort_tmp.into()) },
+            })
+        };
+        assert!(threads.is_none_or(|n| n > 0), "--threads must be at least 1");
+        // --threads splits the input too, only into chunks of a stream rather than byte ranges
+        let sharded = auto_shard || threads.is_some();
+        let shard_flag = if auto_shard { "--auto-shard" } else { "--threads" };
+        assert!(group_by.is_empty() || freq.is_empty(), "--group-by and --freq both group rows");
+        let report = stats || !group_by.is_empty() || !freq.is_empty() || !cardinality.is_empty()
+            || !heavy_hitters.is_empty() || !correlate.is_empty() || !crosstab.is_empty();
+        assert!(sessionize.is_empty() || !report,
+                "--sessionize adds a column to the records, which reports don't print");
+        assert!(skip == 0 || !(report || auto_shard),
+                "--skip can't be combined with --auto-shard or the reports");
+        assert!(limit.is_none() || !report, "--limit can't be combined with the reports");
+        assert!(!unique_rows || unique_by.is_empty(), "--unique and --unique-by can't be combined");
+        assert!((!unique_rows && unique_by.is_empty()) || !(report || sharded),
+                "--unique and --unique-by can't be combined with {} or the reports", shard_flag);
+        assert!(sample_fraction.is_none() || sample_n.is_none(),
+                "--sample and --sample-n are different samples");
+        assert!((sample_fraction.is_none() && sample_n.is_none()) || !(report || sharded),
+                "--sample and --sample-n can't be combined with {} or the reports", shard_flag);
+        assert!(flag_outliers.is_empty() || !report,
+                "--flag-outliers adds a column to the records, which reports don't print");
+        assert!(!flag_outliers.is_empty() || (outlier_window.is_none() && !outliers_only),
+                "--outlier-window and --outliers-only need --flag-outliers");
+        assert!(outlier_window.is_none_or(|n| n >= 2), "--outlier-window must be at least 2");
+        assert!(range_join.is_empty() || !report,
+                "--range-join adds a column to the records, which reports don't print");
+        assert!(range_join.is_empty() == range_on.is_empty(), "--range-join and --on go together");
+        let join_type = interval::parse_join_type(&join_type);
+        assert!(!range_join.is_empty()
+                || (join_type == interval::JoinType::Left && fill.is_empty()
+                    && matched_only_columns.is_empty()),
+                "--join-type, --fill and --matched-only-columns need --range-join");
+        assert!(!join_type.keeps_ranges()
+                || (sessionize.is_empty() && flag_outliers.is_empty() && partition_by.is_empty()),
+                "--join-type right or full can't be combined with --sessionize, --flag-outliers \
+                 or --partition-by");
+        assert!(!multiline || (seek == 0 && byte_range.is_empty()),
+                "--multiline can't be combined with --seek or --byte-range, which may start in \
+                 the middle of a record");
+        assert!(!header || (seek == 0 && byte_range.is_empty() && line_range.is_empty()
+                            && !auto_shard),
+                "--header can't be combined with --seek, --byte-range, --line-range or \
+                 --auto-shard, which don't start at the first line");
+        assert!(!provenance || (seek == 0 && byte_range.is_empty() && !sharded && !multiline),
+                "--provenance can't be combined with --seek, --byte-range, {} or \
+                 --multiline", shard_flag);
+        assert!(!show_invalid_bytes || (!sharded && !multiline && !report),
+                "--show-invalid-bytes can't be combined with {}, --multiline or the \
+                 reports", shard_flag);
+        assert!(!(table || json || markdown) || (!unquote && requote.is_empty()),
+                "--table, --json and --markdown already write the values unquoted, so they \
+                 can't be combined with --unquote or --requote");
+        assert!([table, json, markdown].iter().filter(|f| **f).count() <= 1,
+                "--table, --json and --markdown are different outputs");
+        assert!(!(table || json || markdown) || (!sharded && partition_by.is_empty()),
+                "--table, --json and --markdown can't be combined with {} or \
+                 --partition-by", shard_flag);
+        assert!(!verify_roundtrip || !(table || json || markdown || sharded || report
+                                       || !partition_by.is_empty()),
+                "--verify-roundtrip only checks the records written to stdout in a single pass, \
+                 so it can't be combined with --table, --json, --markdown, {}, \
+                 --partition-by or the reports", shard_flag);
+        assert!(!(strip_control && show_control),
+                "--strip-control and --show-control contradict each other");
+        assert!(cell_overflow.is_empty() || !max_cell_bytes.is_empty(),
+                "--cell-overflow needs --max-cell-bytes");
+        let cell_limit = if max_cell_bytes.is_empty() {
+            None
+        } else {
+            Some(limits::CellLimit {
+                max_bytes: parse_args::parse_arg_size(&max_cell_bytes),
+                overflow: if cell_overflow.is_empty() {
+                    limits::CellOverflow::default()
+                } else {
+                    limits::parse_cell_overflow(&cell_overflow)
+                },
+            })
+        };
+        assert!(cell_limit.is_none_or(|l| l.overflow == limits::CellOverflow::Truncate)
+                || !sharded,
+                "--cell-overflow drop-row or abort can't be combined with {}", shard_flag);
+        assert!(stats_file.is_empty() || !stats_every.is_empty(),
+                "--stats-file needs --stats-every");
+        assert!(regex_cols.is_empty() || (cols.is_empty() && !report),
+                "--regex-cols selects the columns to print instead of a column list, and can't \
+                 be combined with the reports");
+        assert!(ragged.is_empty() || !sharded, "--ragged can't be combined with {}", shard_flag);
+        assert!(aggs.is_empty() || !group_by.is_empty(), "--agg needs --group-by");
+        let group = if !group_by.is_empty() {
+            Some(group::GroupSpec {
+                keys: parse_args::parse_arg_cols(&group_by, offset),
+                aggs: aggs.iter().map(|a| group::parse_agg(a, offset)).collect(),
+                key_sep: key_sep.clone(),
+                by_count: false,
+                nulls: group::parse_null_policy(&agg_nulls),
+                top: None,
+            })
+        } else if !freq.is_empty() {
+            Some(group::GroupSpec {
+                keys: parse_args::parse_arg_cols(&freq, offset),
+                aggs: vec![group::Agg::Count, group::Agg::Percent],
+                key_sep: key_sep.clone(),
+                by_count: true,
+                nulls: group::NullPolicy::Skip,
+                top: if top_n > 0 { Some(top_n) } else { None },
+            })
+        } else {
+            None
+        };
+        assert!(!complement || !cols.is_empty() || !regex_cols.is_empty(),
+                "--complement needs the columns to leave out");
+        // With only computed columns requested, when just validating, or for the sketch and
+        // stats reports, the column list may be left out
+        assert!(regex_cols.is_empty() || header,
+                "--regex-cols picks the columns by their names in the header, so it needs \
+                 --header");
+        let catalog_cols = cataloged.as_ref().filter(|_| !regex_cols.is_empty()).map(|entry| {
+            let re = regex_cols_pattern(&regex_cols, fold_case);
+            let cols = entry.matching(&re);
+            assert!(!cols.is_empty(), "no column name matches --regex-cols");
+            cols
+        });
+        let selected = if let Some(cols) = &catalog_cols {
+            cols.clone()
+        } else if cols.is_empty() && cols_optional {
+            Vec::new()
+        } else {
+            parse_args::parse_arg_cols(&cols, offset)
+        };
+        let set_key = if set_key.is_empty() {
+            assert!((intersects.is_empty() && excepts.is_empty())
+                    || (!complement && !selected.is_empty()
+                        && selected.iter().all(|c| !line::is_relative(*c))),
+                    "--intersect and --except need --key, or columns listed one by one");
+            selected.clone()
+        } else {
+            assert!(!intersects.is_empty() || !excepts.is_empty(),
+                    "--key needs --intersect or --except");
+            parse_args::parse_arg_key(&set_key, offset)
+        };
+        assert!(group.as_ref()
+                    .is_none_or(|g| g.keys.iter().all(|k| !line::is_relative(*k))),
+                "--group-by and --freq need key columns counted from the start of the row");
+        plan::Plan {
+            offset,
+            cols: selected,
+            transforms: tzs.iter().map(|s| parse_args::parse_arg_tz(s, offset))
+                .chain(strip_control.then_some(transform::Transform::StripControl))
+                .chain(show_control.then_some(transform::Transform::ShowControl))
+                .chain(newlines_as.map(|token| transform::Transform::ReplaceNewlines { token }))
+                .collect(),
+            fpe: fpes.iter().map(|s| fpe::parse_fpe(s, offset)).collect(),
+            pseudonymize: pseudonymize.iter()
+                .map(|s| parse_args::parse_arg_col(s, offset))
+                .collect(),
+            salt_file: if salt_file.is_empty() { None } else { Some(PathBuf::from(&salt_file)) },
+            computes,
+            filters: wheres,
+            checks,
+            assert_sorted: if assert_sorted.is_empty() {
+                None
+            } else {
+                Some(parse_args::parse_arg_typed_col(&assert_sorted, offset))
+            },
+            gaps: if gaps.is_empty() {
+                None
+            } else {
+                Some(parse_args::parse_arg_gaps(&gaps, offset))
+            },
+            top,
+            sort,
+            cardinality: opt_col(&cardinality),
+            heavy_hitters: opt_col(&heavy_hitters),
+            correlate: if correlate.is_empty() {
+                Vec::new()
+            } else {
+                let cols = parse_args::parse_arg_cols(&correlate, offset);
+                assert!(cols.len() >= 2 && cols.iter().all(|c| !line::is_relative(*c)),
+                        "--correlate needs two or more columns counted from the start of the row");
+                cols
+            },
+            k,
+            fold_case,
+            validate,
+            max_errors,
+            error_examples,
+            allow_dns,
+            redact_patterns,
+            redact_output,
+            digest_only,
+            slice: make_slice(seek, align_record, &byte_range, &line_range),
+            skip,
+            limit,
+            sample: sample_fraction.map(sample::SampleSpec::Fraction)
+                .or(sample_n.map(sample::SampleSpec::Count)),
+            // A sample drawn without a seed can still be drawn again from the saved plan
+            seed: if sample_fraction.is_some() || sample_n.is_some() {
+                Some(seed.unwrap_or_else(sample::random_seed))
+            } else {
+                seed
+            },
+            errors_format: diagnostic::parse_errors_format(&errors_format),
+            unique: if unique_rows {
+                Some(unique::Unique::Rows)
+            } else if unique_by.is_empty() {
+                None
+            } else {
+                Some(unique::Unique::By(parse_args::parse_arg_cols(&unique_by, offset)))
+            },
+            auto_shard,
+            audit: if audit_file.is_empty() { None } else { Some(audit_file.into()) },
+            max_memory: if max_memory.is_empty() {
+                None
+            } else {
+                Some(parse_args::parse_arg_size(&max_memory))
+            },
+            buffer_bytes,
+            nice,
+            trailer_checksum,
+            checksum_file: if checksum_file.is_empty() { None } else { Some(checksum_file.into()) },
+            partition_by: opt_col(&partition_by),
+            out_dir: if out_dir.is_empty() { None } else { Some(out_dir.into()) },
+            existing: match (skip_existing, overwrite) {
+                (false, false) => partition::Existing::Fail,
+                (false, true) => partition::Existing::Overwrite,
+                (true, false) => partition::Existing::Skip,
+                (true, true) => panic!("--skip-existing and --overwrite contradict each other"),
+            },
+            manifest,
+            estimate,
+            stats,
+            group,
+            precision: precision.map(|digits| precision::Precision {
+                digits,
+                mode: precision::parse_round_mode(&round_mode),
+            }),
+            decimal: if decimal || !scales.is_empty() {
+                Some(decimal::DecimalSpec {
+                    scales: scales.iter().map(|s| decimal::parse_scale(s, offset)).collect(),
+                })
+            } else {
+                None
+            },
+            delimiter,
+            quotes,
+            greps: greps.iter().map(|s| grep::parse_grep(s, true, offset))
+                .chain(grep_vs.iter().map(|s| grep::parse_grep(s, false, offset)))
+                .collect(),
+            requote: if unquote {
+                assert!(requote.is_empty(), "--unquote and --requote can't be combined");
+                Some(dialect::Requote::Never)
+            } else if requote.is_empty() {
+                None
+            } else {
+                Some(dialect::parse_requote(&requote))
+            },
+            sessionize: if sessionize.is_empty() {
+                None
+            } else {
+                Some(session::parse_sessionize(&sessionize, offset))
+            },
+            range_join: if range_join.is_empty() {
+                None
+            } else {
+                let spec = interval::parse_range_join(&range_join, &range_on, offset);
+                assert!(spec.label.is_none() || matched_only_columns.is_empty(),
+                        "--matched-only-columns replaces the label of --on");
+                Some(interval::RangeJoinSpec {
+                    join_type,
+                    fill,
+                    columns: matched_only_columns.split(',')
+                        .map(|c| c.trim().to_string())
+                        .filter(|c| !c.is_empty())
+                        .collect(),
+                    ..spec
+                })
+            },
+            memberships: in_files.iter().map(|s| membership::parse_membership(s, true, offset))
+                .chain(not_in_files.iter()
+                       .map(|s| membership::parse_membership(s, false, offset)))
+                .chain(intersects.iter()
+                       .map(|s| membership::set_operation(s, true, set_key.clone())))
+                .chain(excepts.iter()
+                       .map(|s| membership::set_operation(s, false, set_key.clone())))
+                .collect(),
+            multiline,
+            complement,
+            flush,
+            header,
+            provenance,
+            show_invalid_bytes,
+            table,
+            json,
+            markdown,
+            cell_limit,
+            regex_cols: if regex_cols.is_empty() || catalog_cols.is_some() {
+                None
+            } else {
+                Some(regex_cols)
+            },
+            stats_every: if stats_every.is_empty() {
+                None
+            } else {
+                Some(stats::parse_every(&stats_every))
+            },
+            stats_file: if stats_file.is_empty() { None } else { Some(PathBuf::from(stats_file)) },
+            crosstab: if crosstab.is_empty() {
+                None
+            } else {
+                match parse_args::parse_arg_cols(&crosstab, offset)[..] {
+                    [row_col, col_col] if !line::is_relative(row_col)
+                                          && !line::is_relative(col_col) => {
+                        assert!(crosstab_max > 0, "--crosstab-max must be at least 1");
+                        Some(crosstab::CrosstabSpec { row_col, col_col,
+                                                      max_distinct: crosstab_max })
+                    },
+                    _ => panic!("Invalid --crosstab-count {}, expected COL1,COL2", crosstab),
+                }
+            },
+            ragged: if ragged.is_empty() { None } else { Some(check::parse_ragged(&ragged)) },
+            threads,
+            verify_roundtrip,
+            flag_outliers: if flag_outliers.is_empty() {
+                None
+            } else {
+                Some(outlier::OutlierSpec { window: outlier_window, only: outliers_only,
+                                            ..outlier::parse_outliers(&flag_outliers, offset) })
+            },
+            bloom_fpr: bloom_fpr.inspect(|r| {
+                assert!(*r > 0.0 && *r < 1.0, "--bloom-fpr must be between 0 and 1");
+            }),
+            ..plan::Plan::default()
+        }
+    };
+    plan.inputs = inputs;
+
+    if !emit_plan.is_empty() {
+        // The columns were picked for the names in this header, which a replay must find again
+        if plan.header {
+            match read_header_names(&plan) {
+                Ok(names) => plan.header_names = names,
+                Err(error) => {
+                    eprintln!("{}", messages::message("read-input", &[&error]));
+                    process::exit(1);
+                },
+            }
+        }
+        if let Err(error) = plan.save(Path::new(&emit_plan)) {
+            eprintln!("{}", messages::message("write-plan", &[&emit_plan, &error]));
+            process::exit(1);
+        }
+        return;
+    }
+
+    run(&plan);
+}
+
+/// Resolves the options restricting processing to part of the input
+fn make_slice(seek: u64, align_record: bool, byte_range: &str, line_range: &str) -> slice::Slice {
+    let mut slice = slice::Slice { seek, align_record, ..slice::Slice::default() };
+    if !byte_range.is_empty() {
+        assert!(seek == 0, "--byte-range and --seek both set where to start");
+        let (start, end) = parse_args::parse_arg_range(byte_range);
+        slice.seek = start;
+        slice.align_record = true;
+        slice.end = end;
+    }
+    if !line_range.is_empty() {
+        let (first, last) = parse_args::parse_arg_range(line_range);
+        slice.lines = Some((first.max(1), last));
+    }
+    slice
+}
+
+/// Processes the inputs according to a plan
+fn run(plan: &plan::Plan) {
+    if let Some(nice) = plan.nice {
+        match limits::set_nice(nice) {
+            Err(error) if error.kind() == io::ErrorKind::Unsupported => {
+                eprintln!("{}", messages::message("ignore-priority", &[&nice, &error]));
             },
             Err(error) => {
-                println!("Error while reading stdin: {}", error);
-                return;
+                eprintln!("{}", messages::message("set-priority", &[&nice, &error]));
+                process::exit(1);
             },
+            Ok(()) => {},
+        }
+    }
+    if plan.estimate {
+        if let Err(error) = estimate_report(plan) {
+            eprintln!("{}", messages::message("read-input", &[&error]));
+            process::exit(1);
+        }
+        return;
+    }
+    let mut record = audit::AuditRecord::new(plan);
+    let failed = if plan.stats || plan.group.is_some() || plan.cardinality.is_some()
+        || plan.heavy_hitters.is_some() || !plan.correlate.is_empty() || plan.crosstab.is_some() {
+        let report = |reader: &mut dyn BufRead| if plan.stats {
+            stats_report(reader, plan)
+        } else if !plan.correlate.is_empty() {
+            correlate_report(reader, plan)
+        } else if plan.crosstab.is_some() {
+            crosstab_report(reader, plan)
+        } else if plan.group.is_some() {
+            group_report(reader, plan)
+        } else {
+            sketch_report(reader, plan)
+        };
+        match read_input(plan, None, report) {
+            Ok(((read, written), digests)) => {
+                record.rows_read = read;
+                record.rows_written = written;
+                record.inputs = digests;
+            },
+            Err(error) => {
+                eprintln!("{}", input_error(&error, plan.errors_format));
+                record.status = format!("error: {}", error);
+            },
+        }
+        record.status != "ok"
+    } else {
+        run_cutter(plan, &mut record)
+    };
+
+    if let Some(path) = &plan.audit {
+        if let Err(error) = record.append(path) {
+            eprintln!("{}", messages::message("write-audit", &[&path.display(), &error]));
+            process::exit(1);
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// Cuts the inputs, filling in the counts and status of `record`. Returns whether the run failed,
+/// including failing to read the input.
+fn run_cutter(plan: &plan::Plan, record: &mut audit::AuditRecord) -> bool {
+    let mut cutter = Cutter::new(plan);
+    if let Some(dir) = &plan.out_dir {
+        let stamp = match (input::single_file(&plan.inputs), plan.inputs.len()) {
+            (Some(path), _) => partition::Stamp::of(path),
+            (None, 0) | (None, 1) => partition::Stamp::of(Path::new("/dev/stdin")),
+            // Several inputs are never considered up to date
+            _ => None,
+        };
+        let parts = partition::Partitions::new(dir, plan.existing, &plan.fingerprint(), stamp,
+                                               cutter.pipeline.get_dialect());
+        let parts = parts.map(|p| if plan.manifest { p.with_manifest() } else { p });
+        match parts {
+            Ok(parts) if parts.up_to_date() => {
+                eprintln!("{}", messages::message("up-to-date", &[&dir.display()]));
+                record.status = String::from("skipped");
+                return false;
+            },
+            Ok(parts) => cutter.partitions = Some(parts),
+            Err(error) => {
+                eprintln!("{}", messages::message("open-dir", &[&dir.display(), &error]));
+                process::exit(1);
+            },
+        }
+    }
+    let res = if plan.auto_shard || plan.threads.is_some() {
+        let flag = if plan.auto_shard { "--auto-shard" } else { "--threads" };
+        assert!(cutter.top.is_none() && cutter.sorter.is_none() && cutter.checks.is_empty()
+                && cutter.sort_check.is_none() && cutter.gap_check.is_none() && !cutter.validate
+                && cutter.sessions.is_none() && cutter.outliers.is_none() && !plan.multiline
+                && !cutter.pipeline.has_spilled_lookups(),
+                "{} can't be combined with --top, --sort, --sessionize, --flag-outliers, \
+                 --multiline, checks, or --in-file lists larger than --max-memory, which need a \
+                 single pass", flag);
+        // Chunks are read in one pass, so only shard a spooled copy if it has to be seeked
+        if plan.threads.is_some() && !cutter.pipeline.needs_totals()
+            && plan.slice == slice::Slice::default() {
+            read_input(plan, None, |reader| cutter.cut_chunked(reader))
+                .map(|((), digests)| cutter.digests = digests)
+        } else {
+            // Byte ranges start anywhere, so only a pass from the start knows the header
+            assert!(!plan.header && plan.skip == 0,
+                    "--header and --skip can't be combined with {} when the input is read twice, \
+                     for totals, or sliced", flag);
+            cutter.cut_sharded(&plan.inputs, &plan.slice)
+        }
+    } else if cutter.pipeline.needs_totals() || plan.slice.needs_seek() {
+        assert!(cutter.origin.is_none() || cutter.input_names.len() == 1,
+                "--provenance can't tell several inputs apart when they're read twice, as for \
+                 totals or --in-file lists larger than --max-memory");
+        cutter.cut_spooled(&plan.inputs, &plan.slice)
+    } else {
+        let origin = cutter.origin.clone();
+        read_input(plan, origin, |reader| {
+            let lines = cutter.lines(&plan.slice, reader, 0);
+            cutter.cut_lines(lines)
+        })
+            .map(|((), digests)| cutter.digests = digests)
+    };
+    // Whatever was cut before an error or abort still goes out, ahead of any message, unless
+    // it's held back until the checks pass
+    let _ = cutter.out.finish();
+    let mut discarded = false;
+    if let Some(held) = cutter.out.get_mut().held.take() {
+        if res.is_ok() && !cutter.aborted {
+            if let Err(error) = held.release() {
+                eprintln!("{}", messages::message("write-output", &[&error]));
+                process::exit(1);
+            }
+        } else {
+            discarded = true;
+        }
+    }
+    if let Err(error) = &res {
+        eprintln!("{}", input_error(error, plan.errors_format));
+    }
+
+    let rows_written = if discarded {
+        0
+    } else {
+        cutter.out.records() + cutter.sharded_rows
+            + cutter.partitions.as_ref().map_or(0, |p| p.records())
+    };
+    if !cutter.aborted {
+        if let Some(hasher) = cutter.out.get_mut().hasher.take() {
+            let digest = format!("{:x}", hasher.finalize());
+            if plan.digest_only.is_some() {
+                println!("{}", digest);
+            }
+            if plan.trailer_checksum {
+                println!("{}", trailer::format_trailer(rows_written, &digest));
+            }
+            if let Some(path) = &plan.checksum_file {
+                if let Err(error) = trailer::write_sidecar(path, "-", rows_written, &digest) {
+                    eprintln!("{}", messages::message("write-checksum",
+                                                      &[&path.display(), &error]));
+                    process::exit(1);
+                }
+            }
+        }
+        cutter.summarize();
+    }
+
+    record.rows_read = cutter.rows_read;
+    record.rows_written = rows_written;
+    record.inputs = std::mem::take(&mut cutter.digests);
+    let failed = res.is_err() || cutter.aborted || cutter.budget.failed();
+    record.status = match res {
+        Err(error) => format!("error: {}", error),
+        Ok(()) if cutter.aborted => String::from("aborted"),
+        Ok(()) if cutter.budget.failed() => String::from("failed"),
+        Ok(()) => String::from("ok"),
+    };
+    failed
+}
+
+/// `ccut convert`: rewrites every record of the inputs from one dialect to another, keeping all
+/// the fields and their values and changing only how they're delimited, quoted and escaped
+fn convert_main(args: Vec<String>) {
+    let mut from = String::from("permissive");
+    let mut to = String::from("rfc4180");
+    let mut files: Vec<String> = Vec::new();
+    let from_help = format!("Dialect to read, one of {} (default permissive)",
+                            dialect::DIALECT_NAMES.join(", "));
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Convert CSVs between dialects, e.g. to TSV, keeping every field");
+        ap.refer(&mut from)
+            .add_option(&["--from"], Store, &from_help);
+        ap.refer(&mut to)
+            .add_option(&["--to"], Store, "Dialect to write, as for --from (default rfc4180)");
+        ap.refer(&mut files)
+            .add_argument("files", Collect, "Files to read, one after the other; - or none for \
+                                             stdin");
+        if let Err(code) = ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            process::exit(code);
+        }
+    }
+    let by_name = |name: &str| dialect::by_name(name)
+        .unwrap_or_else(|| panic!("Unknown dialect {}, expected one of {}", name,
+                                  dialect::DIALECT_NAMES.join(", ")));
+    let (from, to) = (by_name(&from), by_name(&to));
+    let inputs: Vec<PathBuf> = files.iter().map(PathBuf::from).collect();
+    let res = input::open(&inputs, None).and_then(|parts| {
+        let reader = input::Concat::new(parts);
+        let mut out = WriterSink::new(io::BufWriter::new(io::stdout().lock()), to.as_ref());
+        let quoting = Some(Quoting::of(from.as_ref()));
+        for record in input::Records::new(input::raw_lines(reader), quoting) {
+            let record = record?;
+            let fields: Vec<Cow<str>> = line::split_line_with(&record, from.as_ref()).iter()
+                .map(|f| dialect::convert_field(f, from.as_ref(), to.as_ref()))
+                .collect();
+            let fields: Vec<&str> = fields.iter().map(|f| f.as_ref()).collect();
+            out.write_record(&fields)?;
+        }
+        out.finish()
+    });
+    if let Err(error) = res {
+        eprintln!("{}", messages::message("convert-input", &[&error]));
+        process::exit(1);
+    }
+}
+
+/// The regex `--regex-cols` matches whole header names with, ignoring case with --fold-case
+fn regex_cols_pattern(pattern: &str, fold_case: bool) -> Regex {
+    RegexBuilder::new(&format!("^(?:{})$", pattern))
+        .case_insensitive(fold_case)
+        .build()
+        .unwrap_or_else(|e| panic!("Invalid --regex-cols {}: {}", pattern, e))
+}
+
+/// `ccut help`: prints one of the help topics, or lists them
+fn help_main(args: Vec<String>) {
+    let mut name = String::new();
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Explain column lists, dialects or common tasks in more depth than \
+                            --help");
+        ap.refer(&mut name)
+            .add_argument("topic", Store, "columns, dialects or examples");
+        if let Err(code) = ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            process::exit(code);
+        }
+    }
+    if name.is_empty() {
+        println!("Topics, as ccut help TOPIC:");
+        for (name, covers) in help::TOPICS.iter() {
+            println!("  {:10}{}", name, covers);
         }
         return;
     }
+    match help::topic(&name) {
+        Some(text) => print!("{}", text),
+        None => {
+            let names: Vec<&str> = help::TOPICS.iter().map(|(name, _)| *name).collect();
+            eprintln!("Unknown help topic {}, expected one of {}", name, names.join(", "));
+            process::exit(1);
+        },
+    }
+}
+
+/// `ccut catalog`: adds files to the catalog, one by one or every table under some directories,
+/// or lists what it holds
+fn catalog_main(args: Vec<String>) {
+    let mut action = String::new();
+    let mut paths: Vec<String> = Vec::new();
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Keep a catalog of the headers and delimiters of files, so find-column \
+                            and --regex-cols can use them without reading the files; it's kept \
+                            in .ccut-catalog.json, or the file CCUT_CATALOG names");
+        ap.refer(&mut action)
+            .add_argument("action", Store, "add FILES, scan DIRS for .csv, .tsv and .psv files, \
+                                            or list")
+            .required();
+        ap.refer(&mut paths)
+            .add_argument("paths", Collect, "Files to add or directories to scan");
+        if let Err(code) = ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            process::exit(code);
+        }
+    }
+    let path = catalog::default_path();
+    let mut catalog = catalog::Catalog::load(&path).unwrap_or_else(|error| {
+        eprintln!("{}", messages::message("read-catalog", &[&path.display(), &error]));
+        process::exit(1);
+    });
+    let mut failed = false;
+    let files: Vec<PathBuf> = match action.as_str() {
+        "add" => paths.iter().map(PathBuf::from).collect(),
+        "scan" => paths.iter()
+            .flat_map(|dir| catalog::scan(Path::new(dir)).unwrap_or_else(|error| {
+                eprintln!("{}", messages::message("scan-dir", &[&dir, &error]));
+                failed = true;
+                Vec::new()
+            }))
+            .collect(),
+        "list" => {
+            assert!(paths.is_empty(), "catalog list takes no paths");
+            let mut out = io::BufWriter::new(io::stdout().lock());
+            let res = writeln!(out, "file,delimiter,columns").and_then(|_| {
+                for (file, entry) in catalog.files.iter() {
+                    writeln!(out, "{},{},{}", Permissive.quote(&file.display().to_string()),
+                             Permissive.quote(&entry.delimiter.to_string()), entry.header.len())?;
+                }
+                out.flush()
+            });
+            if let Err(error) = res {
+                eprintln!("{}", messages::message("write-output", &[&error]));
+                process::exit(1);
+            }
+            return;
+        },
+        other => panic!("Unknown catalog action {}, expected add, scan or list", other),
+    };
+    for file in files.iter() {
+        if let Err(error) = catalog.add(file) {
+            eprintln!("{}", messages::message("read-file", &[&file.display(), &error]));
+            failed = true;
+        }
+    }
+    if let Err(error) = catalog.save(&path) {
+        eprintln!("{}", messages::message("write-catalog", &[&path.display(), &error]));
+        process::exit(1);
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// `ccut find-column`: prints the file, column number and name of every column whose name in the
+/// header of one of the files matches the query, reading only the first line of each, or nothing
+/// for the files in the catalog with `--catalog`. Exits with 1 if none does.
+fn find_column_main(args: Vec<String>) {
+    let mut query = String::new();
+    let mut files: Vec<String> = Vec::new();
+    let mut regex = false;
+    let mut case_sensitive = false;
+    let mut delimiter = String::new();
+    let mut offset: usize = 1;
+    let mut use_catalog = false;
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Find the files whose header has a column named like the query");
+        ap.refer(&mut regex)
+            .add_option(&["--regex"], StoreTrue, "Match names against the query as a regex");
+        ap.refer(&mut case_sensitive)
+            .add_option(&["--case-sensitive"],
+                        StoreTrue,
+                        "Tell upper and lower case apart, which matching doesn't by default");
+        ap.refer(&mut delimiter)
+            .add_option(&["-d", "--delimiter"],
+                        Store,
+                        "Split the headers on this character instead of commas");
+        ap.refer(&mut offset)
+            .add_option(&["-0", "--zero"], StoreConst(0), "Zero-index columns");
+        ap.refer(&mut use_catalog)
+            .add_option(&["--catalog"],
+                        StoreTrue,
+                        "Take the headers of the files in the catalog from it instead of reading \
+                         them, and search every file in it if none are given");
+        ap.refer(&mut query)
+            .add_argument("query", Store, "Text that column names contain")
+            .required();
+        ap.refer(&mut files)
+            .add_argument("files", Collect, "Files whose headers to search");
+        if let Err(code) = ap.parse(args, &mut io::stdout(), &mut io::stderr()) {
+            process::exit(code);
+        }
+    }
+    let pattern = if regex { query } else { regex::escape(&query) };
+    let matcher = regex::RegexBuilder::new(&pattern)
+        .case_insensitive(!case_sensitive)
+        .build()
+        .unwrap_or_else(|e| panic!("Invalid query {}: {}", pattern, e));
+    let dialect = plan::Plan {
+        delimiter: if delimiter.is_empty() {
+            None
+        } else {
+            Some(parse_args::parse_arg_delimiter(&delimiter))
+        },
+        ..plan::Plan::default()
+    }.make_dialect();
+    let catalog = if use_catalog {
+        let path = catalog::default_path();
+        catalog::Catalog::load(&path).unwrap_or_else(|error| {
+            eprintln!("{}", messages::message("read-catalog", &[&path.display(), &error]));
+            process::exit(1);
+        })
+    } else {
+        catalog::Catalog::default()
+    };
+    if use_catalog && files.is_empty() {
+        files = catalog.files.keys().map(|p| p.display().to_string()).collect();
+    }
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let mut found = false;
+    let mut failed = false;
+    for file in files.iter() {
+        let names = match catalog.get(Path::new(file)) {
+            Some(entry) => Ok(entry.header.clone()),
+            None => input::open(&[PathBuf::from(file)], None).and_then(|mut parts| {
+                let (_, mut reader) = parts.pop().expect("one input was opened");
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                Ok(line::split_line_with(&line, dialect.as_ref()).iter()
+                   .map(|name| dialect.unquote(name.trim()).into_owned())
+                   .collect::<Vec<String>>())
+            }),
+        };
+        let names = match names {
+            Ok(names) => names,
+            Err(error) => {
+                eprintln!("{}", messages::message("read-file", &[&file, &error]));
+                failed = true;
+                continue;
+            },
+        };
+        for (i, name) in names.iter().enumerate() {
+            if matcher.is_match(name) {
+                found = true;
+                let res = writeln!(out, "{},{},{}", Permissive.quote(file), i + offset,
+                                   Permissive.quote(name));
+                if let Err(error) = res {
+                    eprintln!("{}", messages::message("write-output", &[&error]));
+                    process::exit(1);
+                }
+            }
+        }
+    }
+    if let Err(error) = out.flush() {
+        eprintln!("{}", messages::message("write-output", &[&error]));
+        process::exit(1);
+    }
+    if failed || !found {
+        process::exit(1);
+    }
+}
+
+/// Prints the column numbers, then the first `rows` lines of the input with their columns padded
+/// to line up under them, or with `sample_seed`, rows from around a file picked with that seed
+fn preview_report(plan: &plan::Plan, rows: usize, sample_seed: Option<u64>,
+                  redactor: &redact::Redactor, offset: usize) -> io::Result<()> {
+    let dialect = plan.make_dialect();
+    let dialect = dialect.as_ref();
+    // The header, if there is one, comes on top of the rows asked for
+    let head_rows = rows + plan.header as usize;
+    let file = match (input::single_file(&plan.inputs), sample_seed) {
+        (Some(path), Some(seed)) => Some((File::open(path)?, seed)),
+        _ => None,
+    };
+    let blocks = match file {
+        Some((file, seed)) if file.metadata()?.is_file() && !input::is_compressed(&file)? => {
+            let len = file.metadata()?.len();
+            sample::sample_file(&mut io::BufReader::new(file), len, head_rows, rows, seed)?
+        },
+        // Streams and compressed files can only be previewed from their head
+        _ => {
+            let reader = input::Concat::new(input::open(&plan.inputs, plan.buffer_bytes)?);
+            let lines = reader.lines().take(head_rows).collect::<io::Result<Vec<String>>>()?;
+            let end = lines.iter().map(|l| l.len() as u64 + 1).sum();
+            vec![sample::Block { part: "head", start: 0, end, lines }]
+        },
+    };
+    let split = |line: &String| -> Vec<String> {
+        line::split_line_with(line, dialect).iter()
+            .map(|f| redactor.redact(f).into_owned())
+            .collect()
+    };
+    let blocks: Vec<(&sample::Block, Vec<Vec<String>>)> = blocks.iter()
+        .map(|b| (b, b.lines.iter().map(split).collect()))
+        .collect();
+    let records: Vec<&Vec<String>> = blocks.iter().flat_map(|(_, records)| records).collect();
+    let n = match records.iter().map(|r| r.len()).max() {
+        Some(n) => n,
+        None => return Ok(()),
+    };
+    let col_nums: Vec<String> = (0..n).map(|i| (i + offset).to_string()).collect();
+    let widths: Vec<usize> = (0..n)
+        .map(|i| records.iter().filter_map(|r| r.get(i))
+             .chain(std::iter::once(&col_nums[i]))
+             .map(|f| f.chars().count())
+             .max()
+             .unwrap_or(0))
+        .collect();
+    let sep = dialect.delimiter().to_string();
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let pad = |record: &[String]| -> String {
+        let padded: Vec<String> = record.iter().enumerate()
+            .map(|(i, f)| if i + 1 == record.len() {
+                f.clone()
+            } else {
+                format!("{:width$}", f, width = widths[i])
+            })
+            .collect();
+        padded.join(&sep)
+    };
+    writeln!(out, "{}", pad(&col_nums))?;
+    if let Some(seed) = sample_seed {
+        writeln!(out, "# seed {}", seed)?;
+    }
+    for (block, records) in blocks.iter() {
+        if sample_seed.is_some() {
+            writeln!(out, "# {}: bytes {}-{}", block.part, block.start, block.end)?;
+        }
+        for record in records {
+            writeln!(out, "{}", pad(record))?;
+        }
+    }
+    out.flush()
+}
 
-    let cols = parse_args::parse_arg_cols(&cols, offset);
+/// Prints the column numbers and names of the first record, one per line, reading no further
+fn header_report(plan: &plan::Plan, offset: usize) -> io::Result<()> {
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    for (i, name) in read_header_names(plan)?.unwrap_or_default().iter().enumerate() {
+        writeln!(out, "{}\t{}", i + offset, name)?;
+    }
+    out.flush()
+}
 
-    let mut line = String::new();
+/// The names in the first record of the inputs, unquoted, or `None` if they're empty
+fn read_header_names(plan: &plan::Plan) -> io::Result<Option<Vec<String>>> {
+    let reader = input::Concat::new(input::open(&plan.inputs, plan.buffer_bytes)?);
+    let mut records = input::Records::new(input::raw_lines(reader), plan.quoting());
+    let first = records.next().transpose()?;
+    Ok(first.map(|line| header_names(&line, plan.make_dialect().as_ref())))
+}
+
+/// The names in a header line, unquoted
+fn header_names(line: &str, dialect: &dyn Dialect) -> Vec<String> {
+    line::split_line_with(line, dialect).iter()
+        .map(|name| dialect.unquote(if dialect.trim() { name.trim() } else { name }).into_owned())
+        .collect()
+}
+
+/// Prints the number of records, not counting a header, and of fields in the first one. Unless
+/// records may span lines, only the first is split; the rest are just counted.
+fn dims_report(plan: &plan::Plan) -> io::Result<()> {
+    let mut reader = input::Concat::new(input::open(&plan.inputs, plan.buffer_bytes)?);
+    let (first, rest) = if plan.multiline {
+        let mut records = input::Records::new(input::raw_lines(reader), plan.quoting());
+        let first = records.next().transpose()?;
+        (first, records.try_fold(0, |n, r| r.map(|_| n + 1))?)
+    } else {
+        let mut first = String::new();
+        let first = match reader.read_line(&mut first)? {
+            0 => None,
+            _ => Some(first),
+        };
+        (first, count_lines(&mut reader)?)
+    };
+    let cols = first.as_ref()
+        .map_or(0, |line| line::split_line_with(line, plan.make_dialect().as_ref()).len());
+    let rows = (first.is_some() as u64 + rest).saturating_sub(plan.header as u64);
+    println!("rows,columns");
+    println!("{},{}", rows, cols);
+    Ok(())
+}
+
+/// Counts the lines left in `reader`, the last one whether or not it ends in a newline
+fn count_lines<R: BufRead>(reader: &mut R) -> io::Result<u64> {
+    let (mut n, mut open) = (0, false);
     loop {
-        match io::stdin().read_line(&mut line) {
-            Ok(n) => {
-                if n == 0 {
-                    break;
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(n + open as u64);
+        }
+        n += buf.iter().filter(|b| **b == b'\n').count() as u64;
+        open = buf.last() != Some(&b'\n');
+        let len = buf.len();
+        reader.consume(len);
+    }
+}
+
+/// Prints the rows and bytes the plan would output, extrapolated from a sample of the input
+fn estimate_report(plan: &plan::Plan) -> io::Result<()> {
+    let mut cutter = Cutter::new(plan);
+    let single = match (input::single_file(&plan.inputs), plan.inputs.len()) {
+        (Some(path), _) => Some(File::open(path)?),
+        (None, 0) | (None, 1) => File::open("/dev/stdin").ok(),
+        _ => None,
+    };
+    let est = match single {
+        Some(file) if file.metadata()?.is_file() && !input::is_compressed(&file)? => {
+            estimate::sample_file(&file, &mut cutter.pipeline)?
+        },
+        _ => {
+            let reader = input::Concat::new(input::open(&plan.inputs, plan.buffer_bytes)?);
+            estimate::sample_stream(reader, &mut cutter.pipeline)?
+        },
+    };
+    let est = match &plan.top {
+        Some(top) => est.cap_rows(top.n as u64),
+        None => est,
+    };
+    let approx = if est.exact() { "" } else { "~" };
+    println!("rows: {}{}", approx, est.rows);
+    println!("bytes: {}{}", approx, est.bytes);
+    println!("sampled {} of {} input bytes", est.sampled_bytes, est.input_bytes);
+    Ok(())
+}
+
+/// Calls `f` with the inputs read one after the other, also hashing each of them if the run is
+/// audited
+fn read_input<T, F>(plan: &plan::Plan, origin: Option<Rc<Cell<input::Origin>>>, f: F)
+                    -> io::Result<(T, Vec<audit::InputDigest>)>
+    where F: FnOnce(&mut dyn BufRead) -> io::Result<T> {
+    let parts = input::open(&plan.inputs, plan.buffer_bytes)?;
+    if plan.audit.is_some() {
+        let parts = parts.into_iter()
+            .map(|(name, reader)| (name, audit::HashingReader::new(reader)))
+            .collect();
+        let mut reader = input::Concat::new(parts);
+        if let Some(origin) = origin {
+            reader.track_origin(origin);
+        }
+        let res = f(&mut reader)?;
+        let digests = reader.into_parts().into_iter()
+            .map(|(name, reader)| reader.finish(&name))
+            .collect::<io::Result<_>>()?;
+        Ok((res, digests))
+    } else {
+        let mut reader = input::Concat::new(parts);
+        if let Some(origin) = origin {
+            reader.track_origin(origin);
+        }
+        Ok((f(&mut reader)?, Vec::new()))
+    }
+}
+
+/// Makes the inputs rewindable, also hashing each of them if `audit` is set
+fn spool_input(paths: &[PathBuf], buffer_bytes: Option<usize>, audit: bool)
+               -> io::Result<(spool::Spool, Vec<audit::InputDigest>)> {
+    let names = input::names(paths);
+    if !audit {
+        return Ok((spool::Spool::from_inputs(paths, buffer_bytes)?, Vec::new()));
+    }
+    if names.len() == 1 {
+        let mut spool = spool::Spool::from_inputs(paths, buffer_bytes)?;
+        let digest = audit::digest(spool.rewind()?, &names[0])?;
+        return Ok((spool, vec![digest]));
+    }
+    let parts = input::open(paths, buffer_bytes)?.into_iter()
+        .map(|(name, reader)| (name, audit::HashingReader::new(reader)))
+        .collect();
+    let mut reader = input::Concat::new(parts);
+    let spool = spool::Spool::from_reader(&mut reader)?;
+    let digests = reader.into_parts().into_iter()
+        .map(|(name, reader)| reader.finish(&name))
+        .collect::<io::Result<_>>()?;
+    Ok((spool, digests))
+}
+
+/// Passes records on with more fields at the end
+struct Append<'a> {
+    sink: &'a mut dyn Sink,
+    fields: &'a [&'a str],
+}
+
+impl Sink for Append<'_> {
+    fn write_record(&mut self, fields: &[&str]) -> io::Result<()> {
+        let mut fields = fields.to_vec();
+        fields.extend(self.fields);
+        self.sink.write_record(&fields)
+    }
+}
+
+/// Where the cut records go: stdout, a running digest of what's written, or both. Stdout stays
+/// locked and is written through a buffer, flushed at each line with `--flush`.
+struct Output {
+    stdout: Option<Box<dyn Write>>,
+    /// Takes the place of `stdout` with `--max-errors`, until every row has been checked
+    held: Option<spool::Held<Box<dyn Write>>>,
+    hasher: Option<Sha256>,
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = match (&mut self.held, &mut self.stdout) {
+            (Some(held), _) => held.write(buf),
+            (None, Some(stdout)) => stdout.write(buf),
+            (None, None) => Ok(buf.len()),
+        }.map_err(output_error)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match (&mut self.held, &mut self.stdout) {
+            (Some(held), _) => held.flush(),
+            (None, Some(stdout)) => stdout.flush(),
+            (None, None) => Ok(()),
+        }.map_err(output_error)
+    }
+}
+
+/// A failure to write the output, to report as such rather than as one to read the input
+fn output_error(error: io::Error) -> io::Error {
+    messages::failure("write-output", error.kind(), error)
+}
+
+/// Everything that happens to each record between reading it and writing it out
+struct Cutter {
+    pipeline: Pipeline,
+    out: WriterSink<Output>,
+    top: Option<top::TopN>,
+    sorter: Option<sort::ExternalSorter>,
+    checks: check::Checks,
+    sort_check: Option<check::SortCheck>,
+    gap_check: Option<check::GapCheck>,
+    ragged_check: Option<check::RaggedCheck>,
+    budget: check::ErrorBudget,
+    /// Only evaluate the checks, without printing any records
+    validate: bool,
+    /// Set when the error budget ran out and processing stopped early
+    aborted: bool,
+    /// Records of the input left to leave out
+    skip: u64,
+    /// Records left to write, once `--limit` was given
+    limit: Option<u64>,
+    /// Picks the records to keep with `--sample` or `--sample-n`
+    sampler: Option<sample::Sampler<(String, Vec<String>)>>,
+    /// Drops duplicate records with `--unique` or `--unique-by`
+    dedup: Option<unique::Dedup>,
+    rows_read: u64,
+    /// Records written by `cut_sharded`, which bypasses `out`'s count
+    sharded_rows: u64,
+    /// `--buffer-bytes`, for `cut_spooled` and `cut_sharded` to read the inputs with
+    buffer_bytes: Option<usize>,
+    /// Whether to hash the inputs for an audit record
+    audit: bool,
+    digests: Vec<audit::InputDigest>,
+    /// Workers for `cut_sharded` and `cut_chunked`
+    threads: usize,
+    partitions: Option<partition::Partitions>,
+    partition_col: usize,
+    fold_case: bool,
+    sessions: Option<session::Sessionizer>,
+    outliers: Option<outlier::OutlierFlagger>,
+    /// Where records end if they may span several lines
+    quoting: Option<Quoting>,
+    /// The first line is a header
+    header: bool,
+    /// Selects the columns whose header names match, once the header is read
+    regex_cols: Option<Regex>,
+    /// The header a replayed plan was made for, which the input's must match
+    header_names: Option<Vec<String>>,
+    /// Where the line read last started, to append to each record with `--provenance` and to
+    /// locate JSON diagnostics
+    origin: Option<Rc<Cell<input::Origin>>>,
+    /// Append the origin of each record to it
+    provenance: bool,
+    errors_format: ErrorsFormat,
+    /// Names of the inputs, which `origin` refers to by index
+    input_names: Vec<String>,
+    /// Where the escapes of invalid UTF-8 in the line read last start, with
+    /// `--show-invalid-bytes`
+    escapes: Option<Rc<Cell<Vec<usize>>>>,
+    /// Lines that held invalid UTF-8
+    invalid_lines: u64,
+    /// `--max-cell-bytes` with `--cell-overflow drop-row` or `abort`, which `cut_lines` handles
+    cell_limit: Option<limits::CellLimit>,
+    /// Records left out for a field over `cell_limit`
+    overflow_rows: u64,
+    offset: usize,
+}
+
+impl Cutter {
+    fn new(plan: &plan::Plan) -> Cutter {
+        let mut pipeline = Pipeline::new().dialect(plan.make_dialect()).select(plan.cols.clone());
+        if plan.complement {
+            pipeline = pipeline.complement();
+        }
+        // Oversized fields are cut down before anything else looks at them
+        let truncate = plan.cell_limit.filter(|l| l.overflow == limits::CellOverflow::Truncate);
+        if let Some(limit) = truncate {
+            pipeline = pipeline.transform(transform::Transform::TruncateCells {
+                max_bytes: limit.max_bytes,
+            });
+        }
+        for t in plan.transforms.iter() {
+            pipeline = pipeline.transform(t.clone());
+        }
+        for s in plan.filters.iter() {
+            pipeline = pipeline.filter(expr::parse_expr(s, plan.offset));
+        }
+        for spec in plan.greps.iter() {
+            match grep::ColumnGrep::new(spec, plan.fold_case) {
+                Ok(g) => pipeline = pipeline.grep(g),
+                Err(error) => panic!("Invalid --grep regex {}: {}", spec.pattern, error),
+            }
+        }
+        for spec in plan.memberships.iter() {
+            match membership::Membership::load(spec, plan.make_dialect().as_ref(), plan.fold_case,
+                                                 plan.max_memory, plan.bloom_fpr) {
+                Ok(m) => pipeline = pipeline.membership(m),
+                Err(error) => {
+                    eprintln!("{}", messages::message("read-lookup", &[&error]));
+                    process::exit(1);
+                },
+            }
+        }
+        for s in plan.computes.iter() {
+            pipeline = pipeline.compute_named(expr::parse_expr(s, plan.offset), s);
+        }
+        if let Some(p) = plan.precision {
+            pipeline = pipeline.precision(p);
+        }
+        if let Some(spec) = &plan.decimal {
+            pipeline = pipeline.decimal(spec.clone());
+        }
+        if let Some(spec) = &plan.range_join {
+            match interval::RangeJoin::load(spec, plan.make_dialect().as_ref(), plan.fold_case) {
+                Ok(join) => pipeline = pipeline.range_join(join),
+                Err(error) => {
+                    eprintln!("{}", messages::message("read-ranges", &[&error]));
+                    process::exit(1);
+                },
+            }
+        }
+        for spec in plan.fpe.iter() {
+            match fpe::Fpe::from_env(spec) {
+                Ok(f) => pipeline = pipeline.encrypt(f),
+                Err(error) => {
+                    eprintln!("{}", messages::message("read-key", &[&error]));
+                    process::exit(1);
+                },
+            }
+        }
+        assert!(plan.pseudonymize.is_empty() == plan.salt_file.is_none(),
+                "--pseudonymize and --salt-file go together");
+        if let Some(path) = &plan.salt_file {
+            match pseudonym::load_salt(path) {
+                Ok(salt) => {
+                    pipeline = pipeline.pseudonymize(pseudonym::Pseudonymizer::new(
+                        plan.pseudonymize.clone(), &salt, plan.fold_case));
+                },
+                Err(error) => {
+                    eprintln!("{}", messages::message("read-salt", &[&error]));
+                    process::exit(1);
+                },
+            }
+        }
+        if let Some(style) = plan.requote {
+            pipeline = pipeline.requote(style);
+        }
+        if plan.redact_output && !plan.redact_patterns.is_empty() {
+            pipeline = pipeline.redact(redact::Redactor::new(&plan.redact_patterns));
+        }
+        assert!(plan.allow_dns
+                || !pipeline.exprs().iter().chain(pipeline.filters()).any(|e| e.calls("rdns")),
+                "rdns() looks up every address over the network; pass --allow-dns to enable it");
+        let out = match plan.digest_only {
+            None => Output {
+                stdout: Some(if plan.flush {
+                    Box::new(io::LineWriter::new(io::stdout().lock()))
+                } else {
+                    Box::new(io::BufWriter::new(io::stdout().lock()))
+                }),
+                held: None,
+                hasher: if plan.trailer_checksum || plan.checksum_file.is_some() {
+                    Some(Sha256::new())
+                } else {
+                    None
+                },
+            },
+            Some(trailer::DigestAlgorithm::Sha256) => {
+                assert!(!plan.trailer_checksum, "--digest-only doesn't print the output to append \
+                                                 a trailer to");
+                Output { stdout: None, held: None, hasher: Some(Sha256::new()) }
+            },
+        };
+        // Checks come before cutting: with a budget for bad rows, nothing goes out until every
+        // row has been checked and the budget held
+        let mut out = out;
+        if plan.max_errors.is_some() && !plan.validate {
+            if let Some(stdout) = out.stdout.take() {
+                match spool::Held::new(stdout) {
+                    Ok(held) => out.held = Some(held),
+                    Err(error) => {
+                        eprintln!("{}", messages::message("write-output", &[&error]));
+                        process::exit(1);
+                    },
                 }
-                let res = line::cut_line(&line, &cols);
-                println!("{}", res);
+            }
+        }
+        let out = WriterSink::new(out, pipeline.get_dialect());
+        let out = if plan.table { out.table(table::SAMPLE_ROWS) } else { out };
+        let out = if plan.json { out.json(plan.make_dialect()) } else { out };
+        let out = if plan.markdown { out.markdown(plan.make_dialect()) } else { out };
+        let out = if plan.verify_roundtrip {
+            out.verify_roundtrip(plan.make_dialect())
+        } else {
+            out
+        };
+        assert!(plan.partition_by.is_some() == plan.out_dir.is_some(),
+                "--partition-by and --out-dir go together");
+        assert!(!plan.manifest || plan.out_dir.is_some(), "--manifest needs --out-dir");
+        assert!(plan.out_dir.is_none() || (!plan.auto_shard && plan.threads.is_none()
+                                           && plan.digest_only.is_none()
+                                           && !plan.trailer_checksum
+                                           && plan.checksum_file.is_none()),
+                "--partition-by can't be combined with {} or the output digests",
+                if plan.auto_shard { "--auto-shard" } else { "--threads" });
+        Cutter {
+            pipeline,
+            out,
+            top: plan.top.as_ref()
+                .map(|t| top::TopN::new(t.n, t.col, t.desc).with_dialect(plan.make_dialect())),
+            sorter: plan.sort.clone().map(|mut spec| {
+                spec.mem = limits::cap(spec.mem, plan.max_memory);
+                sort::ExternalSorter::new(spec).with_dialect(plan.make_dialect())
+            }),
+            checks: check::Checks::new(&plan.checks, plan.offset),
+            sort_check: plan.assert_sorted.map(|(col, kind)| check::SortCheck::new(col, kind)),
+            gap_check: plan.gaps.map(|(col, kind, step)| check::GapCheck::new(col, kind, step)),
+            ragged_check: plan.ragged.map(check::RaggedCheck::new),
+            budget: check::ErrorBudget::new(plan.max_errors, plan.error_examples),
+            validate: plan.validate,
+            aborted: false,
+            skip: plan.skip,
+            limit: plan.limit,
+            sampler: plan.sample.map(|spec| {
+                sample::Sampler::new(spec, plan.seed.unwrap_or_else(sample::random_seed))
+            }),
+            dedup: plan.unique.clone().map(|spec| unique::Dedup::new(spec, plan.fold_case)),
+            rows_read: 0,
+            sharded_rows: 0,
+            buffer_bytes: plan.buffer_bytes,
+            audit: plan.audit.is_some(),
+            digests: Vec::new(),
+            threads: plan.threads.unwrap_or_else(|| {
+                limits::threads(thread::available_parallelism().map_or(1, |n| n.get()), plan.nice)
+            }),
+            partitions: None,
+            partition_col: plan.partition_by.unwrap_or(0),
+            fold_case: plan.fold_case,
+            sessions: plan.sessionize.clone()
+                .map(|spec| session::Sessionizer::new(spec, plan.fold_case)),
+            outliers: plan.flag_outliers.clone().map(outlier::OutlierFlagger::new),
+            quoting: plan.quoting(),
+            header: plan.header,
+            header_names: plan.header_names.clone(),
+            regex_cols: plan.regex_cols.as_ref().map(|re| regex_cols_pattern(re, plan.fold_case)),
+            // Offsets are only right when reading starts at the beginning, line by line
+            origin: if plan.provenance || (plan.errors_format == ErrorsFormat::Json
+                                           && plan.slice.seek == 0 && plan.slice.end.is_none()
+                                           && !plan.multiline) {
+                Some(Rc::new(Cell::new(input::Origin::default())))
+            } else {
+                None
             },
-            Err(error) => {
-                println!("Error while reading stdin: {}", error);
+            provenance: plan.provenance,
+            errors_format: plan.errors_format,
+            input_names: input::names(&plan.inputs),
+            escapes: if plan.show_invalid_bytes {
+                Some(Rc::new(Cell::new(Vec::new())))
+            } else {
+                None
+            },
+            invalid_lines: 0,
+            cell_limit: plan.cell_limit
+                .filter(|l| l.overflow != limits::CellOverflow::Truncate),
+            overflow_rows: 0,
+            offset: plan.offset,
+        }
+    }
+
+    /// The lines of `reader` in `slice`, as `Slice::lines` reads them, with invalid UTF-8
+    /// escaped if asked to, and their line endings left on if records may span them
+    fn lines<R: BufRead>(&self, slice: &slice::Slice, reader: R, start: u64)
+                         -> slice::SliceLines<R> {
+        let lines = match &self.escapes {
+            Some(escapes) => slice.lines(reader, start).escape_invalid(escapes.clone()),
+            None => slice.lines(reader, start),
+        };
+        // Records that span lines keep the line breaks within them as they were
+        if self.quoting.is_some() { lines.keep_endings() } else { lines }
+    }
+
+    /// Cuts every line, either printing them straight away or feeding them through
+    /// `top`
+    fn cut_lines<I>(&mut self, lines: I) -> io::Result<()>
+        where I: IntoIterator<Item = io::Result<String>> {
+        let records = input::Records::new(lines.into_iter(), self.quoting.clone());
+        for (i, line) in records.enumerate() {
+            let line = line?;
+            if let Some(escapes) = self.escapes.as_ref().map(|e| e.take()) {
+                if !escapes.is_empty() {
+                    self.report_invalid(i + 1, &line, &escapes);
+                }
+            }
+            // The header, or else the first record, sets the width the rest are held to
+            let dialect = self.pipeline.get_dialect();
+            let line = match self.ragged_check.as_mut().map(|rc| rc.check(&line, dialect)) {
+                None => line,
+                Some(Ok(fixed)) => fixed.into_owned(),
+                Some(Err(n)) => {
+                    let rc = self.ragged_check.as_ref().unwrap();
+                    if rc.policy() == check::Ragged::Error {
+                        let msg = format!("{} fields, expected {}", n, rc.width().unwrap_or(0));
+                        let diag = Diagnostic::new("ragged", msg).at_line(i as u64 + 1);
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, self.locate(diag)));
+                    }
+                    continue;
+                },
+            };
+            if i == 0 && self.header {
+                self.take_header(&line)?;
+                continue;
+            }
+            // Nothing after the last record written can change the output unless it's ordered
+            if self.limit == Some(0) && self.top.is_none() && self.sorter.is_none() {
                 break;
+            }
+            if self.skip > 0 {
+                self.skip -= 1;
+                continue;
+            }
+            self.rows_read += 1;
+            if let Some(limit) = self.cell_limit {
+                if let Some(col) = limit.first_over(&self.pipeline.split(&line)) {
+                    if limit.overflow == limits::CellOverflow::Abort {
+                        let msg = format!("column {} is over {} bytes", col + self.offset,
+                                          limit.max_bytes);
+                        let diag = Diagnostic::new("cell-overflow", msg)
+                            .at_line(i as u64 + 1)
+                            .at_column(col + self.offset);
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, self.locate(diag)));
+                    }
+                    self.overflow_rows += 1;
+                    continue;
+                }
+            }
+            if !self.checks.is_empty() || self.sort_check.is_some() || self.gap_check.is_some() {
+                let fields = self.pipeline.split(&line);
+                let mut diags: Vec<Diagnostic> = self.checks.check(&fields).iter()
+                    .map(|spec| Diagnostic::new("check-failed", format!("check failed: {}", spec)))
+                    .collect();
+                if let Some(msg) = self.sort_check.as_mut().and_then(|sc| sc.check(&fields)) {
+                    diags.push(Diagnostic::new("unsorted", msg));
+                }
+                if let Some(msg) = self.gap_check.as_mut().and_then(|gc| gc.check(&fields)) {
+                    diags.push(Diagnostic::new("gap", msg));
+                }
+                let bad = !diags.is_empty();
+                if bad && self.budget.record() {
+                    for diag in diags {
+                        self.report(&self.locate(diag.at_line(i as u64 + 1)));
+                    }
+                }
+                if self.budget.exceeded() {
+                    self.summarize();
+                    let msg = format!("aborting: more than {} bad rows",
+                                      self.budget.max_errors().unwrap());
+                    self.report(&Diagnostic::new("aborted", msg));
+                    self.aborted = true;
+                    return Ok(());
+                }
+                // A row that fails the checks is never written, however many are tolerated
+                if bad {
+                    continue;
+                }
+            }
+            if self.validate || !self.pipeline.keep_row(i as u64, &line) {
+                continue;
+            }
+            if let Some(dedup) = &mut self.dedup {
+                let first = match dedup.spec() {
+                    unique::Unique::Rows => {
+                        let mut records: Vec<Vec<String>> = Vec::new();
+                        self.pipeline.cut(&line, &mut records)?;
+                        dedup.first_output(&records)
+                    },
+                    unique::Unique::By(_) => dedup.first_record(&self.pipeline.split(&line)),
+                };
+                if !first {
+                    continue;
+                }
+            }
+            let mut outlier = None;
+            if let Some(outliers) = &mut self.outliers {
+                let flag = outliers.flag(&self.pipeline.split(&line));
+                if !outliers.spec().only {
+                    outlier = Some(flag.map_or_else(String::new, |f| f.to_string()));
+                } else if flag != Some(true) {
+                    continue;
+                }
+            }
+            // The session and outlier flag are worked out in input order and the origin is known
+            // only as the line is read, so they travel with the line as extra fields, through
+            // `top` or `sort` to `emit`
+            let mut extra: Vec<String> = Vec::new();
+            if let Some(sessions) = &mut self.sessions {
+                let session = sessions.session(&self.pipeline.split(&line));
+                extra.push(session.map_or_else(String::new, |n| n.to_string()));
+            }
+            extra.extend(outlier);
+            if let Some(origin) = self.origin.as_ref().filter(|_| self.provenance) {
+                let origin = origin.get();
+                extra.extend([origin.input.to_string(), origin.offset.to_string(),
+                              origin.line.to_string()]);
+            }
+            let (line, extra) = match &mut self.sampler {
+                Some(sampler) => match sampler.push((line, extra)) {
+                    Some(record) => record,
+                    None => continue,
+                },
+                None => (line, extra),
+            };
+            self.dispatch(&line, extra)?;
+        }
+        if let Some(sampler) = self.sampler.take() {
+            for (line, extra) in sampler.finish() {
+                self.dispatch(&line, extra)?;
+            }
+        }
+        if let Some(t) = self.top.take() {
+            for (line, extra) in t.into_sorted() {
+                self.emit(&line, &extra)?;
+            }
+        }
+        if let Some(s) = self.sorter.take() {
+            s.finish(|line, extra| self.emit(line, extra))?;
+        }
+        self.pipeline.finish_join(&mut self.out)?;
+        if let Some(p) = &mut self.partitions {
+            p.finish()?;
+        }
+        self.out.finish()
+    }
+
+    /// Checks the header line against the plan, selects the --regex-cols columns by it and writes
+    /// it
+    fn take_header(&mut self, line: &str) -> io::Result<()> {
+        if let Some(expected) = &self.header_names {
+            let names = header_names(line, self.pipeline.get_dialect());
+            if let Err(msg) = plan::check_header(expected, &names, self.offset) {
+                let diag = Diagnostic::new("header-mismatch", msg).at_line(1);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, diag));
+            }
+        }
+        if let Some(re) = &self.regex_cols {
+            let cols: Vec<usize> = self.pipeline.split(line).iter()
+                .enumerate()
+                .filter(|(_, name)| re.is_match(expr::unquote(name.trim())))
+                .map(|(i, _)| i)
+                .collect();
+            if cols.is_empty() {
+                return Err(messages::failure("bad-options", io::ErrorKind::InvalidData,
+                                             "no column name matches --regex-cols"));
+            }
+            self.pipeline = std::mem::take(&mut self.pipeline).select(cols);
+        }
+        self.write_header(line)
+    }
+
+    /// Passes a record and its extra fields on to `top` or `sort` if they're there to order it,
+    /// or else writes it
+    fn dispatch(&mut self, line: &str, extra: Vec<String>) -> io::Result<()> {
+        match (&mut self.top, &mut self.sorter) {
+            (Some(t), _) => t.push(line, &extra),
+            (None, Some(s)) => s.push(line, extra)?,
+            (None, None) => self.emit(line, &extra)?,
+        }
+        Ok(())
+    }
+
+    /// Reports the columns of line `n` that held invalid UTF-8, escaped at `escapes`
+    fn report_invalid(&mut self, n: usize, line: &str, escapes: &[usize]) {
+        self.invalid_lines += 1;
+        let mut cols: Vec<String> = Vec::new();
+        let mut col = 0;
+        self.pipeline.get_dialect().field_ranges(line, &mut |start, end| {
+            if escapes.iter().any(|e| (start..end).contains(e)) {
+                cols.push((col + self.offset).to_string());
+            }
+            col += 1;
+        });
+        let msg = format!("invalid UTF-8 in column {}", cols.join(", "));
+        let mut diag = Diagnostic::new("invalid-utf8", msg).at_line(n as u64);
+        if let Some(col) = cols.first() {
+            diag = diag.at_column(col.parse().expect("columns are numbers"));
+        }
+        self.report(&self.locate(diag));
+    }
+
+    /// Adds where the record read last starts to a diagnostic about it, when that's tracked
+    fn locate(&self, diag: Diagnostic) -> Diagnostic {
+        match &self.origin {
+            Some(origin) => {
+                let origin = origin.get();
+                diag.at_offset(&self.input_names[origin.input], origin.offset)
+            },
+            None => diag,
+        }
+    }
+
+    /// Writes the header row ahead of the records, or at the top of each partition's file
+    fn write_header(&mut self, line: &str) -> io::Result<()> {
+        if self.validate {
+            return Ok(());
+        }
+        let mut header = self.pipeline.header(line);
+        if self.sessions.is_some() {
+            header.push(String::from("session"));
+        }
+        if self.flags_outliers() {
+            header.push(String::from("outlier"));
+        }
+        if self.provenance {
+            header.extend(["source_file", "source_offset", "source_line"].iter()
+                          .map(|s| s.to_string()));
+        }
+        match &mut self.partitions {
+            Some(parts) => {
+                parts.set_header(header);
+                Ok(())
+            },
+            None => {
+                let fields: Vec<&str> = header.iter().map(|f| f.as_str()).collect();
+                self.out.write_header(&fields)
             },
         }
-        line.clear();
     }
+
+    /// Whether an outlier flag is appended to each record
+    fn flags_outliers(&self) -> bool {
+        self.outliers.as_ref().is_some_and(|o| !o.spec().only)
+    }
+
+    /// Writes the cut of a line to its partition's file when partitioning, and to `out`
+    /// otherwise, with its `extra` fields, the session, outlier flag and origin asked for, at the
+    /// end
+    fn emit(&mut self, line: &str, extra: &[String]) -> io::Result<()> {
+        match &mut self.limit {
+            Some(0) => return Ok(()),
+            Some(n) => *n -= 1,
+            None => {},
+        }
+        let mut extra: Vec<&str> = extra.iter().map(|f| f.as_str()).collect();
+        // The origin carries the input's index, so the name is only copied for records written
+        let file;
+        if self.provenance {
+            let i = extra.len() - 3;
+            let input: usize = extra[i].parse().expect("records carry their input");
+            file = self.pipeline.get_dialect().quote(&self.input_names[input]).into_owned();
+            extra[i] = &file;
+        }
+        let sink: &mut dyn Sink = match &mut self.partitions {
+            Some(parts) => {
+                let fields = self.pipeline.split(line);
+                let key = expr::unquote(fields.get(self.partition_col).unwrap_or(&"").trim());
+                let key = if self.fold_case { collate::fold_case(key) } else { Cow::Borrowed(key) };
+                match parts.sink(&key)? {
+                    Some(sink) => sink,
+                    None => return Ok(()),
+                }
+            },
+            None => &mut self.out,
+        };
+        if extra.is_empty() {
+            self.pipeline.cut(line, sink)
+        } else {
+            self.pipeline.cut(line, &mut Append { sink, fields: &extra })
+        }
+    }
+
+    /// Cuts the inputs as a seekable file, restricted to `slice`. If the computed columns need
+    /// totals, they're gathered in a first pass, since they're needed before the first row can be
+    /// written.
+    fn cut_spooled(&mut self, inputs: &[PathBuf], slice: &slice::Slice) -> io::Result<()> {
+        let (mut spool, digests) = spool_input(inputs, self.buffer_bytes, self.audit)?;
+        self.digests = digests;
+        // Offsets into the spool are only those of the input when there's just one
+        if inputs.len() > 1 {
+            self.origin = None;
+        }
+        if self.pipeline.needs_totals() {
+            let mut reader = spool.rewind()?;
+            let start = slice.start(&mut reader)?;
+            let lines = self.lines(slice, reader, start);
+            let records = input::Records::new(lines, self.quoting.clone());
+            self.pipeline.gather_totals(records)?;
+        }
+        let mut reader = spool.rewind()?;
+        let start = slice.start(&mut reader)?;
+        match self.origin.clone() {
+            Some(origin) => {
+                let mut reader = input::Concat::new(vec![(String::new(), reader)]);
+                reader.track_origin(origin);
+                let lines = self.lines(slice, reader, start);
+                self.cut_lines(lines)
+            },
+            None => {
+                let lines = self.lines(slice, reader, start);
+                self.cut_lines(lines)
+            },
+        }
+    }
+
+    /// Cuts the inputs as a seekable file in parallel shards, one per core
+    fn cut_sharded(&mut self, inputs: &[PathBuf], slice: &slice::Slice) -> io::Result<()> {
+        let (mut spool, digests) = spool_input(inputs, self.buffer_bytes, self.audit)?;
+        self.digests = digests;
+        if self.pipeline.needs_totals() {
+            let mut reader = spool.rewind()?;
+            let start = slice.start(&mut reader)?;
+            self.pipeline.gather_totals(slice.lines(reader, start))?;
+        }
+        let (read, written) = shard::run(spool.file(), slice, &self.pipeline, self.threads,
+                                         self.limit, self.out.get_mut())?;
+        self.rows_read += read;
+        self.sharded_rows += written;
+        self.pipeline.finish_join(&mut self.out)?;
+        self.out.finish()
+    }
+
+    /// Cuts a stream in parallel chunks, for input that can't be sharded without spooling it
+    /// first
+    fn cut_chunked(&mut self, reader: &mut dyn BufRead) -> io::Result<()> {
+        // The header and the records skipped come before the first chunk, as in a single pass
+        {
+            let mut lines = self.lines(&slice::Slice::default(), &mut *reader, 0);
+            if self.header {
+                if let Some(line) = lines.next() {
+                    self.take_header(&line?)?;
+                }
+            }
+            for line in lines.by_ref().take(self.skip as usize) {
+                line?;
+            }
+            self.skip = 0;
+        }
+        let (read, written) = shard::run_chunked(reader, &self.pipeline, self.threads,
+                                                 self.limit, self.out.get_mut())?;
+        self.rows_read += read;
+        self.sharded_rows += written;
+        self.pipeline.finish_join(&mut self.out)?;
+        self.out.finish()
+    }
+
+    /// Reports the summary of each check
+    fn summarize(&self) {
+        for line in self.checks.summary() {
+            self.report(&Diagnostic::new("check-summary", line));
+        }
+        if let Some(sc) = &self.sort_check {
+            self.report(&Diagnostic::new("sort-summary", sc.summary()));
+        }
+        if let Some(gc) = &self.gap_check {
+            self.report(&Diagnostic::new("gap-summary", gc.summary()));
+        }
+        if self.invalid_lines > 0 {
+            self.report(&summary("invalid-utf8", &[&self.invalid_lines]));
+        }
+        // An error stops at the first ragged row, which has been reported already
+        let ragged = self.ragged_check.as_ref()
+            .filter(|rc| rc.ragged() > 0 && rc.policy() != check::Ragged::Error);
+        if let Some(rc) = ragged {
+            let id = match rc.policy() {
+                check::Ragged::Pad => "ragged-padded",
+                check::Ragged::Truncate => "ragged-truncated",
+                check::Ragged::Skip | check::Ragged::Error => "ragged-skipped",
+            };
+            self.report(&summary(id, &[&rc.ragged(), &rc.width().unwrap_or(0)]));
+        }
+        if self.overflow_rows > 0 {
+            self.report(&summary("overflow-rows", &[
+                &self.overflow_rows, &self.cell_limit.map_or(0, |l| l.max_bytes),
+            ]));
+        }
+        if self.budget.unreported() > 0 {
+            self.report(&summary("unreported-rows", &[&self.budget.unreported()]));
+        }
+        if let Some(m) = self.budget.max_errors() {
+            if !self.budget.exceeded() {
+                self.report(&summary("within-budget", &[&self.budget.bad_rows(), &m]));
+            }
+        }
+    }
+
+    /// Reports a check result: it's the output in validate mode, and a diagnostic otherwise
+    fn report(&self, diag: &Diagnostic) {
+        let msg = diag.render(self.errors_format);
+        if self.validate {
+            println!("{}", msg);
+        } else {
+            eprintln!("{}", msg);
+        }
+    }
+}
+
+/// A summary line, with the message's id for its code
+fn summary(id: &str, args: &[&dyn std::fmt::Display]) -> Diagnostic {
+    Diagnostic::new(id, messages::message(id, args))
+}
+
+/// The message for an error that stopped reading the input: where it is if it's about a record,
+/// and in the format asked for
+fn input_error(error: &io::Error, format: ErrorsFormat) -> String {
+    let diag = error.get_ref().and_then(|e| e.downcast_ref::<Diagnostic>());
+    let id = messages::failure_id(error);
+    match (format, diag) {
+        (ErrorsFormat::Text, _) => messages::message(id, &[error]),
+        (ErrorsFormat::Json, Some(diag)) => diag.render(format),
+        (ErrorsFormat::Json, None) => Diagnostic::new(id, error.to_string()).render(format),
+    }
+}
+
+/// Prints the correlations of each pair of the `--correlate` columns, with the number of rows
+/// where both are numbers. Returns the number of rows read and lines printed.
+fn correlate_report<R: BufRead>(reader: R, plan: &plan::Plan) -> io::Result<(u64, u64)> {
+    let mut correlations = correlate::Correlations::new(plan.correlate.clone());
+    let mut read = 0;
+    let dialect = plan.make_dialect();
+    let mut names: Vec<String> = Vec::new();
+    for (i, line) in input::Records::new(input::raw_lines(reader), plan.quoting()).enumerate() {
+        let line = line?;
+        let fields = line::split_line_with(&line, dialect.as_ref());
+        if i == 0 && plan.header {
+            names = fields.iter()
+                .map(|f| Permissive.quote(expr::unquote(f.trim())).into_owned())
+                .collect();
+            continue;
+        }
+        read += 1;
+        correlations.insert(&fields);
+    }
+    let name = |i: usize| names.get(i).cloned().unwrap_or_else(|| (i + plan.offset).to_string());
+    let format = |r: Option<f64>| r.map_or_else(String::new, |r| format!("{:.3}", r));
+    let results = correlations.results();
+    println!("col1,col2,n,pearson,spearman");
+    for r in results.iter() {
+        println!("{},{},{},{},{}", name(r.a), name(r.b), r.n, format(r.pearson),
+                 format(r.spearman));
+    }
+    Ok((read, results.len() as u64 + 1))
+}
+
+/// Prints the approximate distinct count of the `--cardinality` column and/or the heavy hitters of
+/// the `--heavy-hitters` column, for the rows the filters keep, gathered in a single
+/// bounded-memory pass. Returns the number of rows read and lines printed.
+fn sketch_report<R: BufRead>(reader: R, plan: &plan::Plan) -> io::Result<(u64, u64)> {
+    let pipeline = Cutter::new(plan).pipeline;
+    if pipeline.has_spilled_lookups() {
+        return Err(messages::failure("bad-options", io::ErrorKind::InvalidInput,
+                                     "--in-file lists larger than --max-memory need the input \
+                                      twice, which --cardinality and --heavy-hitters don't read"));
+    }
+    let (card_col, hh_col) = (plan.cardinality, plan.heavy_hitters);
+    let mut hll = sketch::HyperLogLog::default();
+    let mut ss = sketch::SpaceSaving::new(plan.k);
+    let (mut read, mut written) = (0, 0);
+    let records = input::Records::new(input::raw_lines(reader), plan.quoting());
+    for line in records.skip(plan.header as usize) {
+        let line = line?;
+        read += 1;
+        if !pipeline.keep(&line) {
+            continue;
+        }
+        let fields = pipeline.split(&line);
+        if let Some(i) = card_col {
+            hll.insert(&sketch_value(&fields, i, plan.fold_case));
+        }
+        if let Some(i) = hh_col {
+            ss.insert(&sketch_value(&fields, i, plan.fold_case));
+        }
+    }
+    if card_col.is_some() {
+        println!("{}", hll.estimate().round());
+        written += 1;
+    }
+    if hh_col.is_some() {
+        for (value, count, error) in ss.top() {
+            println!("{},{},{}", Permissive.quote(value), count, error);
+            written += 1;
+        }
+    }
+    Ok((read, written))
+}
+
+/// Prints one record per group of `plan.group`, with its aggregates, for the rows the filters keep.
+/// Returns the number of rows read and records printed.
+fn group_report<R: BufRead>(reader: R, plan: &plan::Plan) -> io::Result<(u64, u64)> {
+    let pipeline = Cutter::new(plan).pipeline;
+    if pipeline.has_spilled_lookups() {
+        return Err(messages::failure("bad-options", io::ErrorKind::InvalidInput,
+                                     "--in-file lists larger than --max-memory need the input \
+                                      twice, which --group-by doesn't read"));
+    }
+    let spec = plan.group.clone().unwrap();
+    let mut group = group::GroupBy::new(spec.clone(), plan.fold_case)
+        .with_precision(plan.precision)
+        .with_decimal(plan.decimal.clone());
+    let mut read = 0;
+    let mut header = None;
+    for (i, line) in input::Records::new(input::raw_lines(reader), plan.quoting()).enumerate() {
+        let line = line?;
+        if i == 0 && plan.header {
+            header = Some(spec.header(&pipeline.split(&line), plan.offset,
+                                      pipeline.get_dialect()));
+            continue;
+        }
+        read += 1;
+        if pipeline.keep(&line) {
+            if let Err((i, error)) = group.insert(&pipeline.split(&line)) {
+                let agg = spec.aggs[i].describe(plan.offset);
+                let diag = Diagnostic::new("bad-value", format!("{}: {}", agg, error))
+                    .at_line(read);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, diag));
+            }
+        }
+    }
+    let bad = group.bad_values().to_vec();
+    let mut out = report_sink(plan, pipeline.get_dialect());
+    if let Some(header) = &header {
+        let fields: Vec<&str> = header.iter().map(|f| f.as_str()).collect();
+        out.write_header(&fields)?;
+    }
+    for record in group.into_records(pipeline.get_dialect()) {
+        let fields: Vec<&str> = record.iter().map(|f| f.as_str()).collect();
+        out.write_record(&fields)?;
+    }
+    out.finish()?;
+    let id = if spec.nulls == group::NullPolicy::Zero { "agg-zeroed" } else { "agg-skipped" };
+    for (agg, n) in spec.aggs.iter().zip(bad).filter(|(_, n)| *n > 0) {
+        eprintln!("{}", messages::message(id, &[&agg.describe(plan.offset), &n]));
+    }
+    Ok((read, out.records()))
+}
+
+/// Prints how many of the rows the filters keep have each combination of the values of the
+/// `--crosstab-count` columns. Returns the number of rows read and records printed.
+fn crosstab_report<R: BufRead>(reader: R, plan: &plan::Plan) -> io::Result<(u64, u64)> {
+    let pipeline = Cutter::new(plan).pipeline;
+    if pipeline.has_spilled_lookups() {
+        return Err(messages::failure("bad-options", io::ErrorKind::InvalidInput,
+                                     "--in-file lists larger than --max-memory need the input \
+                                      twice, which --crosstab-count doesn't read"));
+    }
+    let spec = plan.crosstab.clone().unwrap();
+    let mut names = [(spec.row_col + plan.offset).to_string(),
+                     (spec.col_col + plan.offset).to_string()];
+    let mut crosstab = crosstab::Crosstab::new(spec.clone(), plan.fold_case);
+    let mut read = 0;
+    for (i, line) in input::Records::new(input::raw_lines(reader), plan.quoting()).enumerate() {
+        let line = line?;
+        let fields = pipeline.split(&line);
+        if i == 0 && plan.header {
+            for (name, col) in names.iter_mut().zip([spec.row_col, spec.col_col].iter()) {
+                if let Some(field) = fields.get(*col) {
+                    *name = expr::unquote(field.trim()).to_string();
+                }
+            }
+            continue;
+        }
+        read += 1;
+        if pipeline.keep(&line) {
+            crosstab.insert(&fields);
+        }
+    }
+    let dialect = pipeline.get_dialect();
+    let mut out = report_sink(plan, dialect);
+    for (i, row) in crosstab.into_rows(&names.join("/")).iter().enumerate() {
+        let cells: Vec<Cow<str>> = row.iter().map(|c| dialect.quote(c)).collect();
+        let fields: Vec<&str> = cells.iter().map(|c| c.as_ref()).collect();
+        if i == 0 { out.write_header(&fields)? } else { out.write_record(&fields)? }
+    }
+    out.finish()?;
+    Ok((read, out.records()))
+}
+
+/// Where reports that print records write them: stdout, as a table, JSON or Markdown if asked
+fn report_sink(plan: &plan::Plan, dialect: &dyn Dialect)
+               -> WriterSink<io::BufWriter<io::StdoutLock<'static>>> {
+    let out = WriterSink::new(io::BufWriter::new(io::stdout().lock()), dialect);
+    let out = if plan.table { out.table(table::SAMPLE_ROWS) } else { out };
+    let out = if plan.json { out.json(plan.make_dialect()) } else { out };
+    if plan.markdown { out.markdown(plan.make_dialect()) } else { out }
+}
+
+/// Prints the count, emptiness, distinct values, uniqueness ratio, entropy and likely kinds of
+/// personal data of each column (or of the selected columns), to help decide what to mask before
+/// sharing an extract. Returns the number of rows read and lines printed.
+fn stats_report<R: BufRead>(reader: R, plan: &plan::Plan) -> io::Result<(u64, u64)> {
+    let mut stats = stats::Stats::default();
+    let mut ticker = plan.stats_every.map(stats::Ticker::new);
+    let mut read = 0;
+    let dialect = plan.make_dialect();
+    let mut names: Vec<String> = Vec::new();
+    for (i, line) in input::Records::new(input::raw_lines(reader), plan.quoting()).enumerate() {
+        let line = line?;
+        if i == 0 && plan.header {
+            names = line::split_line_with(&line, dialect.as_ref()).iter()
+                .map(|f| Permissive.quote(expr::unquote(f.trim())).into_owned())
+                .collect();
+            continue;
+        }
+        read += 1;
+        let values: Vec<Cow<str>> = line::split_line_with(&line, dialect.as_ref()).iter()
+            .map(|f| {
+                let value = expr::unquote(f.trim());
+                if plan.fold_case { collate::fold_case(value) } else { Cow::Borrowed(value) }
+            })
+            .collect();
+        stats.insert(&values);
+        if ticker.as_mut().is_some_and(|t| t.tick()) {
+            match &plan.stats_file {
+                Some(path) => {
+                    // Replaced whole, so that readers never see half of it
+                    let tmp = path.with_extension("tmp");
+                    let mut file = io::BufWriter::new(File::create(&tmp)?);
+                    write_stats(&mut file, &stats, &names, plan)?;
+                    file.flush()?;
+                    std::fs::rename(&tmp, path)?;
+                },
+                None => {
+                    write_stats(&mut io::stderr().lock(), &stats, &names, plan)?;
+                },
+            }
+        }
+    }
+    let written = write_stats(&mut io::stdout().lock(), &stats, &names, plan)?;
+    Ok((read, written))
+}
+
+/// Writes the statistics of the selected columns as CSV, returning the number of lines written
+fn write_stats(out: &mut dyn Write, stats: &stats::Stats, names: &[String], plan: &plan::Plan)
+               -> io::Result<u64> {
+    let cols: Vec<usize> = if plan.cols.is_empty() {
+        (0..stats.columns().len()).collect()
+    } else if plan.complement {
+        let dropped = line::resolve_cols(&plan.cols, stats.columns().len());
+        (0..stats.columns().len()).filter(|c| !dropped.contains(c)).collect()
+    } else {
+        line::resolve_cols(&plan.cols, stats.columns().len()).into_iter()
+            .filter(|c| *c < stats.columns().len())
+            .collect()
+    };
+    writeln!(out, "col,count,empty,distinct,uniqueness,entropy,pii")?;
+    for i in cols.iter() {
+        let col = &stats.columns()[*i];
+        let name = names.get(*i).cloned().unwrap_or_else(|| (i + plan.offset).to_string());
+        writeln!(out, "{},{},{},{},{:.3},{:.3},{}", name, col.count(), col.empty(),
+                 col.distinct(), col.uniqueness(), col.entropy(), stats.pii_flags(*i).join("|"))?;
+    }
+    out.flush()?;
+    Ok(cols.len() as u64 + 1)
+}
+
+/// The value counted by the sketches for column `i`, trimmed and unquoted as for `--group-by`
+fn sketch_value<'a>(fields: &[&'a str], i: usize, fold_case: bool) -> Cow<'a, str> {
+    let field = expr::unquote(fields.get(i).copied().unwrap_or("").trim());
+    if fold_case { collate::fold_case(field) } else { Cow::Borrowed(field) }
 }
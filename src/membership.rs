@@ -0,0 +1,383 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collate;
+use crate::dialect::Dialect;
+use crate::expr;
+use crate::line;
+use crate::parse_args;
+use crate::sketch::BloomFilter;
+
+/// `--in-file FILE@KEY` and `--not-in-file FILE@KEY`: keeps only the records whose key is, or
+/// isn't, listed in `path`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MembershipSpec {
+    pub path: PathBuf,
+    /// Columns making up the key of a record
+    pub cols: Vec<usize>,
+    /// Columns of the file holding the same key, which is read as records when given. Without
+    /// them, each line of the file is one value if the key is one column, or else a record whose
+    /// first fields are the key.
+    pub file_cols: Option<Vec<usize>>,
+    /// Keep the records whose key is listed, rather than those whose key isn't
+    pub keep: bool,
+}
+
+/// Parses a `FILE@KEY[=FILE_KEY]` argument of `--in-file` (`keep`) or `--not-in-file`, where the
+/// keys are columns joined with `+` such as `ids.csv@1+2=2+1`. `offset` is as in
+/// `parse_arg_cols`.
+pub fn parse_membership(spec: &str, keep: bool, offset: usize) -> MembershipSpec {
+    let (path, key) = spec.rsplit_once('@')
+        .unwrap_or_else(|| panic!("Invalid file filter {}, expected FILE@COL", spec));
+    let (cols, file_cols) = match key.split_once('=') {
+        Some((cols, file_cols)) => (cols, Some(parse_args::parse_arg_key(file_cols, offset))),
+        None => (key, None),
+    };
+    let cols = parse_args::parse_arg_key(cols, offset);
+    assert!(file_cols.as_ref().is_none_or(|f| f.len() == cols.len()),
+            "The key of {} has a different number of columns on each side", spec);
+    MembershipSpec { path: PathBuf::from(path), cols, file_cols, keep }
+}
+
+/// The filter of `--intersect` (`keep`) or `--except`: records are compared with those of
+/// another file of the same layout on the columns of `key`
+pub fn set_operation(path: &str, keep: bool, key: Vec<usize>) -> MembershipSpec {
+    MembershipSpec { path: PathBuf::from(path), cols: key.clone(), file_cols: Some(key), keep }
+}
+
+/// Most partitions a lookup file is spilled into
+const MAX_PARTS: usize = 128;
+
+/// The keys of a `MembershipSpec`'s file, loaded and ready to check records against
+#[derive(Debug)]
+pub struct Membership {
+    cols: Vec<usize>,
+    keep: bool,
+    fold_case: bool,
+    keys: Keys,
+}
+
+#[derive(Debug)]
+enum Keys {
+    /// Each key as its values, so that keys can't run together the way joined strings could
+    Memory(HashSet<Vec<String>>),
+    /// Too many keys to hold in memory, so the records are matched as in a grace hash join: keys
+    /// and records are partitioned alike on disk by the hash of their key, then each partition
+    /// of keys is loaded in turn and checked against its partition of records
+    Spilled(Spill),
+}
+
+#[derive(Debug)]
+struct Spill {
+    /// Partitions of the file's keys, one JSON array per line
+    parts: Vec<BufWriter<File>>,
+    /// Partitions of the records' keys with their rows, written by `gather`
+    probes: Vec<BufWriter<File>>,
+    /// Keys written to `parts`, duplicates included
+    keys: usize,
+    /// Bit set of the rows whose key is listed, filled in by `finish_gather`
+    matched: Vec<u64>,
+    /// Rules out most records whose key isn't listed, so that only the rest are written to
+    /// `probes` and checked exactly
+    bloom: Option<BloomFilter>,
+}
+
+impl Membership {
+    /// Reads the file, splitting its lines with `dialect` if they hold several columns. Blank
+    /// lines are ignored, and values are trimmed and unquoted as fields are. With `fold_case`,
+    /// keys match regardless of case. If the keys would take more than `max_memory` bytes, they're
+    /// spilled to disk instead, and records have to be gathered before they can be checked. With
+    /// `bloom_fpr`, spilled keys also go in a Bloom filter with that false positive rate, which
+    /// settles most records not listed without writing them to disk.
+    pub fn load(spec: &MembershipSpec, dialect: &dyn Dialect, fold_case: bool,
+                max_memory: Option<usize>, bloom_fpr: Option<f64>) -> io::Result<Membership> {
+        let file = File::open(&spec.path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", spec.path.display(), e)))?;
+        let size = file.metadata()?.len();
+        let limits = Limits { max_memory, bloom_fpr, size };
+        Membership::from_reader(spec, BufReader::new(file), dialect, fold_case, limits)
+    }
+
+    /// Reads keys as `load` does
+    fn from_reader<R: BufRead>(spec: &MembershipSpec, reader: R, dialect: &dyn Dialect,
+                               fold_case: bool, limits: Limits) -> io::Result<Membership> {
+        let Limits { max_memory, bloom_fpr, size } = limits;
+        let whole_lines = spec.file_cols.is_none() && spec.cols.len() == 1;
+        let file_cols: Vec<usize> = match &spec.file_cols {
+            Some(cols) => cols.clone(),
+            None => (0..spec.cols.len()).collect(),
+        };
+        let mut keys = Keys::Memory(HashSet::new());
+        let (mut bytes, mut read) = (0, 0);
+        for line in reader.lines() {
+            let line = line?;
+            read += line.len() as u64 + 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let key = if whole_lines {
+                vec![normalize(&line, fold_case)]
+            } else {
+                let fields = line::split_line_with(&line, dialect);
+                make_key(&fields, &file_cols, fold_case)
+            };
+            match &mut keys {
+                Keys::Memory(set) => {
+                    bytes += key_size(&key);
+                    set.insert(key);
+                    if max_memory.is_some_and(|max| bytes > max) {
+                        let n = (2 * size / max_memory.unwrap() as u64 + 1) as usize;
+                        // As many keys again in the rest of the file as in what's been read
+                        let expected = set.len() as u64 * size.max(read) / read;
+                        let bloom = bloom_fpr.map(|fpr| BloomFilter::new(expected as usize, fpr));
+                        let mut spill = Spill::new(n.clamp(2, MAX_PARTS), bloom)?;
+                        for key in mem::take(set) {
+                            spill.add(&key)?;
+                        }
+                        keys = Keys::Spilled(spill);
+                    }
+                },
+                Keys::Spilled(spill) => spill.add(&key)?,
+            }
+        }
+        Ok(Membership { cols: spec.cols.clone(), keep: spec.keep, fold_case, keys })
+    }
+
+    /// Number of keys listed. Duplicates count once, unless the keys were spilled to disk.
+    pub fn len(&self) -> usize {
+        match &self.keys {
+            Keys::Memory(set) => set.len(),
+            Keys::Spilled(spill) => spill.keys,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the keys were spilled to disk, so that every record has to go through `gather`
+    /// before any is checked
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.keys, Keys::Spilled(_))
+    }
+
+    /// Notes the key of the `row`th record, for a spilled lookup
+    pub fn gather(&mut self, row: u64, fields: &[&str]) -> io::Result<()> {
+        if let Keys::Spilled(spill) = &mut self.keys {
+            let key = make_key(fields, &self.cols, self.fold_case);
+            if spill.bloom.as_ref().is_some_and(|b| !b.contains(&key)) {
+                return Ok(());
+            }
+            let probe = &mut spill.probes[partition(&key, spill.parts.len())];
+            serde_json::to_writer(&mut *probe, &(row, &key))?;
+            writeln!(probe)?;
+        }
+        Ok(())
+    }
+
+    /// Matches the gathered records against the keys of a spilled lookup, one partition at a
+    /// time
+    pub fn finish_gather(&mut self) -> io::Result<()> {
+        if let Keys::Spilled(spill) = &mut self.keys {
+            for (part, probe) in spill.parts.iter_mut().zip(spill.probes.iter_mut()) {
+                part.flush()?;
+                let file = part.get_mut();
+                file.seek(SeekFrom::Start(0))?;
+                let mut keys: HashSet<Vec<String>> = HashSet::new();
+                for line in BufReader::new(&mut *file).lines() {
+                    keys.insert(serde_json::from_str(&line?)?);
+                }
+                probe.flush()?;
+                let file = probe.get_mut();
+                file.seek(SeekFrom::Start(0))?;
+                for line in BufReader::new(&mut *file).lines() {
+                    let (row, key): (u64, Vec<String>) = serde_json::from_str(&line?)?;
+                    if keys.contains(&key) {
+                        let (word, bit) = ((row / 64) as usize, row % 64);
+                        if spill.matched.len() <= word {
+                            spill.matched.resize(word + 1, 0);
+                        }
+                        spill.matched[word] |= 1 << bit;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a record passes: its key is listed when keeping listed keys, or isn't otherwise.
+    /// Missing columns have empty values. A spilled lookup needs the `row` the record was
+    /// gathered as.
+    pub fn keep(&self, row: Option<u64>, fields: &[&str]) -> bool {
+        let listed = match &self.keys {
+            Keys::Memory(set) => set.contains(&make_key(fields, &self.cols, self.fold_case)),
+            Keys::Spilled(spill) => {
+                let row = row.expect("lookups spilled to disk need the row");
+                spill.matched.get((row / 64) as usize).is_some_and(|w| w & (1 << (row % 64)) != 0)
+            },
+        };
+        listed == self.keep
+    }
+}
+
+impl Spill {
+    fn new(n: usize, bloom: Option<BloomFilter>) -> io::Result<Spill> {
+        let files = || (0..n).map(|_| tempfile::tempfile().map(BufWriter::new))
+            .collect::<io::Result<Vec<_>>>();
+        let (parts, probes) = (files()?, files()?);
+        Ok(Spill { parts, probes, keys: 0, matched: Vec::new(), bloom })
+    }
+
+    fn add(&mut self, key: &[String]) -> io::Result<()> {
+        if let Some(bloom) = &mut self.bloom {
+            bloom.insert(key);
+        }
+        let n = self.parts.len();
+        let part = &mut self.parts[partition(key, n)];
+        serde_json::to_writer(&mut *part, key)?;
+        writeln!(part)?;
+        self.keys += 1;
+        Ok(())
+    }
+}
+
+/// How a lookup file is held in memory
+#[derive(Clone, Copy, Default)]
+struct Limits {
+    max_memory: Option<usize>,
+    bloom_fpr: Option<f64>,
+    /// Length of the file in bytes, to decide how many partitions to spill into and how many keys
+    /// to size the Bloom filter for
+    size: u64,
+}
+
+/// Which of `n` partitions a key belongs in
+fn partition(key: &[String], n: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % n as u64) as usize
+}
+
+/// Roughly the bytes a key takes up in a hash set
+fn key_size(key: &[String]) -> usize {
+    let values: usize = key.iter().map(|v| v.len() + mem::size_of::<String>()).sum();
+    values + mem::size_of::<Vec<String>>() + mem::size_of::<u64>()
+}
+
+fn make_key(fields: &[&str], cols: &[usize], fold_case: bool) -> Vec<String> {
+    cols.iter().map(|i| normalize(fields.get(*i).unwrap_or(&""), fold_case)).collect()
+}
+
+fn normalize(value: &str, fold_case: bool) -> String {
+    let value = expr::unquote(value.trim());
+    if fold_case { collate::fold_case(value).into_owned() } else { value.to_string() }
+}
+
+#[cfg(test)]
+mod test_membership {
+    use super::*;
+    use crate::dialect::Permissive;
+
+    fn load(spec: &str, keep: bool, fold_case: bool, ids: &str) -> Membership {
+        let spec = parse_membership(spec, keep, 1);
+        Membership::from_reader(&spec, ids.as_bytes(), &Permissive, fold_case, Limits::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_membership("lists/ids@v2.txt@3", false, 1), MembershipSpec {
+            path: PathBuf::from("lists/ids@v2.txt"),
+            cols: vec![2],
+            file_cols: None,
+            keep: false,
+        });
+        let spec = parse_membership("pairs.csv@1+3=2+1", true, 1);
+        assert_eq!((spec.cols, spec.file_cols), (vec![0, 2], Some(vec![1, 0])));
+    }
+    #[test]
+    fn test_keep() {
+        let ids = load("ids.txt@2", true, false, "17\n \"42\" \n\nAbc\na,b\n");
+        assert_eq!(ids.len(), 4);
+        assert!(ids.keep(None, &["x", "17"]));
+        assert!(ids.keep(None, &["x", " \"42\""]));
+        assert!(ids.keep(None, &["x", "\"a,b\""]));
+        assert!(!ids.keep(None, &["x", "abc"]));
+        assert!(!ids.keep(None, &["x", ""]));
+        assert!(!ids.keep(None, &["x"]));
+    }
+    #[test]
+    fn test_drop() {
+        let ids = load("ids.txt@1", false, true, "ABC\nStraße\n");
+        assert!(!ids.keep(None, &["abc"]));
+        assert!(!ids.keep(None, &["STRASSE"]));
+        assert!(ids.keep(None, &["abd"]));
+        assert!(ids.keep(None, &[]));
+    }
+    #[test]
+    fn test_composite() {
+        let pairs = load("pairs.csv@1+2", true, false, "a,b\nab,\n");
+        assert!(pairs.keep(None, &["a", "b", "x"]));
+        assert!(pairs.keep(None, &["ab", ""]));
+        assert!(!pairs.keep(None, &["a", "bx"]));
+        assert!(!pairs.keep(None, &["b", "a"]));
+        let swapped = load("pairs.csv@1+2=2+1", true, false, "b,a,x\n");
+        assert!(swapped.keep(None, &["a", "b"]));
+        assert!(!swapped.keep(None, &["b", "a"]));
+    }
+    #[test]
+    fn test_set_operation() {
+        let spec = set_operation("yesterday.csv", false, vec![0, 2]);
+        let yesterday = "1,x,a\n2,y,b\n";
+        let new = Membership::from_reader(&spec, yesterday.as_bytes(), &Permissive, false,
+                                          Limits::default()).unwrap();
+        assert!(!new.keep(None, &["1", "z", "a"]));
+        assert!(new.keep(None, &["2", "y", "c"]));
+        assert!(new.keep(None, &["3", "x", "a"]));
+    }
+    #[test]
+    fn test_spilled() {
+        spilled(None);
+    }
+    #[test]
+    fn test_bloom_filter() {
+        spilled(Some(0.01));
+    }
+
+    fn spilled(bloom_fpr: Option<f64>) {
+        let spec = parse_membership("ids.txt@2", false, 1);
+        let ids: String = (0..1000).map(|i| format!("{}\n", i * 2)).collect();
+        let limits = Limits { max_memory: Some(4096), bloom_fpr, size: 4890 };
+        let mut ids = Membership::from_reader(&spec, ids.as_bytes(), &Permissive, false, limits)
+            .unwrap();
+        assert!(ids.is_spilled());
+        assert_eq!(ids.len(), 1000);
+        let rows: Vec<Vec<String>> = (0..500).map(|i| vec![String::new(), (i * 3).to_string()])
+            .collect();
+        for (i, row) in rows.iter().enumerate() {
+            let fields: Vec<&str> = row.iter().map(|f| f.as_str()).collect();
+            ids.gather(i as u64, &fields).unwrap();
+        }
+        ids.finish_gather().unwrap();
+        let kept: Vec<u64> = (0..500).filter(|i| ids.keep(Some(*i), &[])).collect();
+        let expected: Vec<u64> = (0..500).filter(|i| i * 3 % 2 == 1).collect();
+        assert_eq!(kept, expected);
+    }
+    #[test]
+    #[should_panic]
+    fn test_missing_col_fails() {
+        parse_membership("ids.txt", true, 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_uneven_key_fails() {
+        parse_membership("ids.txt@1+2=1", true, 1);
+    }
+}
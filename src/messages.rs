@@ -0,0 +1,220 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::OnceLock;
+
+/// Languages messages can be shown in, English first
+pub const LANGS: &[&str] = &["en", "es", "de"];
+
+/// Every message, by an id that stays the same whatever the language and however the wording
+/// changes, with its text in each of `LANGS`. `{0}`, `{1}`... stand for the arguments.
+const CATALOG: &[(&str, [&str; 3])] = &[
+    ("read-input", ["Error while reading input: {0}",
+                    "Error al leer la entrada: {0}",
+                    "Fehler beim Lesen der Eingabe: {0}"]),
+    ("write-output", ["Error while writing output: {0}",
+                      "Error al escribir la salida: {0}",
+                      "Fehler beim Schreiben der Ausgabe: {0}"]),
+    ("bad-options", ["Invalid options: {0}",
+                     "Opciones no válidas: {0}",
+                     "Ungültige Optionen: {0}"]),
+    ("convert-input", ["Error while converting input: {0}",
+                       "Error al convertir la entrada: {0}",
+                       "Fehler beim Umwandeln der Eingabe: {0}"]),
+    ("read-file", ["Error while reading {0}: {1}",
+                   "Error al leer {0}: {1}",
+                   "Fehler beim Lesen von {0}: {1}"]),
+    ("read-lookup", ["Error while reading {0}",
+                     "Error al leer {0}",
+                     "Fehler beim Lesen von {0}"]),
+    ("scan-dir", ["Error while scanning {0}: {1}",
+                  "Error al recorrer {0}: {1}",
+                  "Fehler beim Durchsuchen von {0}: {1}"]),
+    ("open-dir", ["Error while opening {0}: {1}",
+                  "Error al abrir {0}: {1}",
+                  "Fehler beim Öffnen von {0}: {1}"]),
+    ("read-catalog", ["Error while reading catalog {0}: {1}",
+                      "Error al leer el catálogo {0}: {1}",
+                      "Fehler beim Lesen des Katalogs {0}: {1}"]),
+    ("write-catalog", ["Error while writing catalog {0}: {1}",
+                       "Error al escribir el catálogo {0}: {1}",
+                       "Fehler beim Schreiben des Katalogs {0}: {1}"]),
+    ("read-plan", ["Error while reading plan {0}: {1}",
+                   "Error al leer el plan {0}: {1}",
+                   "Fehler beim Lesen des Plans {0}: {1}"]),
+    ("write-plan", ["Error while writing plan {0}: {1}",
+                    "Error al escribir el plan {0}: {1}",
+                    "Fehler beim Schreiben des Plans {0}: {1}"]),
+    ("read-salt", ["Error while reading salt {0}",
+                   "Error al leer la sal {0}",
+                   "Fehler beim Lesen des Salts {0}"]),
+    ("write-salt", ["Error while writing salt {0}: {1}",
+                    "Error al escribir la sal {0}: {1}",
+                    "Fehler beim Schreiben des Salts {0}: {1}"]),
+    ("read-key", ["Error while reading key {0}",
+                  "Error al leer la clave {0}",
+                  "Fehler beim Lesen des Schlüssels {0}"]),
+    ("read-ranges", ["Error while reading ranges {0}",
+                     "Error al leer los rangos {0}",
+                     "Fehler beim Lesen der Bereiche {0}"]),
+    ("write-audit", ["Error while writing audit record to {0}: {1}",
+                     "Error al escribir el registro de auditoría en {0}: {1}",
+                     "Fehler beim Schreiben des Prüfprotokolls nach {0}: {1}"]),
+    ("write-checksum", ["Error while writing checksum to {0}: {1}",
+                        "Error al escribir la suma de comprobación en {0}: {1}",
+                        "Fehler beim Schreiben der Prüfsumme nach {0}: {1}"]),
+    ("set-priority", ["Error while setting priority {0}: {1}",
+                      "Error al fijar la prioridad {0}: {1}",
+                      "Fehler beim Setzen der Priorität {0}: {1}"]),
+    ("ignore-priority", ["Ignoring priority {0}: {1}",
+                         "Se ignora la prioridad {0}: {1}",
+                         "Priorität {0} wird ignoriert: {1}"]),
+    ("up-to-date", ["{0} is up to date",
+                    "{0} está al día",
+                    "{0} ist aktuell"]),
+    ("invalid-utf8", ["{0} lines held invalid UTF-8",
+                      "{0} líneas contenían UTF-8 no válido",
+                      "{0} Zeilen enthielten ungültiges UTF-8"]),
+    ("ragged-padded", ["{0} rows didn't have {1} fields, padded where short",
+                       "{0} filas no tenían {1} campos; se completaron las cortas",
+                       "{0} Zeilen hatten nicht {1} Felder; kurze wurden aufgefüllt"]),
+    ("ragged-truncated", ["{0} rows didn't have {1} fields, truncated where long",
+                          "{0} filas no tenían {1} campos; se recortaron las largas",
+                          "{0} Zeilen hatten nicht {1} Felder; lange wurden gekürzt"]),
+    ("ragged-skipped", ["{0} rows didn't have {1} fields, skipped",
+                        "{0} filas no tenían {1} campos; se omitieron",
+                        "{0} Zeilen hatten nicht {1} Felder; sie wurden übersprungen"]),
+    ("overflow-rows", ["{0} rows dropped for a field over {1} bytes",
+                       "{0} filas descartadas por un campo de más de {1} bytes",
+                       "{0} Zeilen verworfen wegen eines Felds über {1} Bytes"]),
+    ("unreported-rows", ["{0} more bad rows not shown",
+                         "{0} filas erróneas más sin mostrar",
+                         "{0} weitere fehlerhafte Zeilen nicht angezeigt"]),
+    ("within-budget", ["{0} bad rows, within the tolerated {1}",
+                       "{0} filas erróneas, dentro de las {1} toleradas",
+                       "{0} fehlerhafte Zeilen, innerhalb der tolerierten {1}"]),
+    ("agg-skipped", ["{0}: skipped {1} value(s) that aren't numbers or overflow",
+                     "{0}: se omitieron {1} valor(es) que no son números o desbordan",
+                     "{0}: {1} Wert(e) übersprungen, die keine Zahlen sind oder überlaufen"]),
+    ("agg-zeroed", ["{0}: zeroed {1} value(s) that aren't numbers or overflow",
+                    "{0}: se pusieron a cero {1} valor(es) que no son números o desbordan",
+                    "{0}: {1} Wert(e) auf null gesetzt, die keine Zahlen sind oder überlaufen"]),
+];
+
+static LANG: OnceLock<usize> = OnceLock::new();
+
+/// Shows messages in `lang`, one of `LANGS`, rather than the locale's language. Only has an
+/// effect before the first message.
+pub fn set_lang(lang: &str) {
+    let i = LANGS.iter().position(|l| *l == lang)
+        .unwrap_or_else(|| panic!("Unknown language {}, expected one of {}", lang,
+                                  LANGS.join(", ")));
+    let _ = LANG.set(i);
+}
+
+/// The language of a locale such as `es_ES.UTF-8`, if it's one of `LANGS`
+pub fn locale_lang(locale: &str) -> Option<&'static str> {
+    let lang = locale.split(['_', '.', '@']).next().unwrap_or("");
+    LANGS.iter().copied().find(|l| *l == lang.to_ascii_lowercase())
+}
+
+/// The language set with `set_lang`, or else that of the locale, as `LC_ALL`, `LC_MESSAGES` or
+/// `LANG` give it, in that order, or else English
+fn lang() -> usize {
+    *LANG.get_or_init(|| {
+        let locale = ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        let lang = locale_lang(&locale).unwrap_or("en");
+        LANGS.iter().position(|l| *l == lang).unwrap_or(0)
+    })
+}
+
+/// Message `id` with its arguments, in the current language. Messages in other languages than
+/// English end with their id in brackets, for scripts to match whatever the language.
+pub fn message(id: &str, args: &[&dyn fmt::Display]) -> String {
+    message_in(lang(), id, args)
+}
+
+fn message_in(lang: usize, id: &str, args: &[&dyn fmt::Display]) -> String {
+    let (_, texts) = CATALOG.iter().find(|(i, _)| *i == id)
+        .unwrap_or_else(|| panic!("No message {}", id));
+    let mut res = texts[lang].to_string();
+    for (i, arg) in args.iter().enumerate() {
+        res = res.replace(&format!("{{{}}}", i), &arg.to_string());
+    }
+    if lang != 0 {
+        res.push_str(&format!(" [{}]", id));
+    }
+    res
+}
+
+/// An error that comes up while the input is read but isn't a failure to read it, such as an
+/// output file that already exists, with the message to report it under
+#[derive(Debug)]
+pub struct Failure {
+    pub id: &'static str,
+    pub error: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl Error for Failure {}
+
+/// `error` as an `io::Error` of `kind`, to report under message `id`
+pub fn failure(id: &'static str, kind: io::ErrorKind, error: impl fmt::Display) -> io::Error {
+    io::Error::new(kind, Failure { id, error: error.to_string() })
+}
+
+/// The message an error that stopped reading the input is reported under: its own if it's a
+/// `Failure`, else `read-input`
+pub fn failure_id(error: &io::Error) -> &'static str {
+    error.get_ref().and_then(|e| e.downcast_ref::<Failure>()).map_or("read-input", |f| f.id)
+}
+
+#[cfg(test)]
+mod test_messages {
+    use super::*;
+
+    #[test]
+    fn test_message() {
+        assert_eq!(message_in(0, "read-file", &[&"a.csv", &"not found"]),
+                   "Error while reading a.csv: not found");
+        assert_eq!(message_in(2, "up-to-date", &[&"out"]), "out ist aktuell [up-to-date]");
+    }
+    #[test]
+    fn test_translations_keep_arguments() {
+        for (id, texts) in CATALOG.iter() {
+            let args = |text: &str| (0..3).filter(|i| text.contains(&format!("{{{}}}", i)))
+                .collect::<Vec<_>>();
+            for text in texts[1..].iter() {
+                assert_eq!(args(text), args(texts[0]), "{}", id);
+            }
+        }
+    }
+    #[test]
+    fn test_failure_id() {
+        let error = failure("write-output", io::ErrorKind::AlreadyExists, "out/a.csv exists");
+        assert_eq!(failure_id(&error), "write-output");
+        assert_eq!(error.to_string(), "out/a.csv exists");
+        assert_eq!(failure_id(&io::Error::new(io::ErrorKind::InvalidData, "bad")), "read-input");
+    }
+    #[test]
+    fn test_locale_lang() {
+        assert_eq!(locale_lang("es_ES.UTF-8"), Some("es"));
+        assert_eq!(locale_lang("de"), Some("de"));
+        assert_eq!(locale_lang("C"), None);
+        assert_eq!(locale_lang("pt_BR"), None);
+    }
+    #[test]
+    #[should_panic]
+    fn test_unknown_lang_fails() {
+        set_lang("xx");
+    }
+}
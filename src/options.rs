@@ -0,0 +1,522 @@
+use crate::parse_args::is_col_list;
+
+/// An option of the main command, as its argument parser registers it and `ccut help` describes
+/// it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptionDef {
+    /// Its names, any short one first, e.g. `["-d", "--delimiter"]`
+    pub names: &'static [&'static str],
+    /// Whether the argument after it is its value rather than another argument
+    pub takes_value: bool,
+    pub help: &'static str,
+}
+
+/// Every option of the main command, in the order `--help` lists them
+pub const OPTIONS: &[OptionDef] = &[
+    OptionDef { names: &["-p", "--preview"], takes_value: false,
+                help: "Preview first line with column numbers" },
+    OptionDef { names: &["--preview-rows"], takes_value: true,
+                help: "Preview this many rows under the column numbers, aligned, instead of \
+                       just the first; implies --preview" },
+    OptionDef { names: &["--preview-sample"], takes_value: false,
+                help: "Preview rows from the head, a random place in the middle and the tail of \
+                       a file, each under the byte offsets they came from; --preview-rows sets \
+                       the rows from each place. Implies --preview" },
+    OptionDef { names: &["--seed"], takes_value: true,
+                help: "Seed whatever is picked at random, such as the records --sample keeps \
+                       or the place --preview-sample reads from, so a run can be repeated \
+                       exactly; --preview-sample prints the seed it used" },
+    OptionDef { names: &["--sample"], takes_value: true,
+                help: "Keep each record with this probability, e.g. 0.01, after the filters" },
+    OptionDef { names: &["--sample-n"], takes_value: true,
+                help: "Keep this many records picked at random from those that pass the \
+                       filters, holding only that many at a time, in input order" },
+    OptionDef { names: &["--header-only"], takes_value: false,
+                help: "Print the names in the first line one per line, after their column \
+                       numbers, reading nothing past it" },
+    OptionDef { names: &["--dims"], takes_value: false,
+                help: "Print the number of rows (less the --header) and of columns in the first \
+                       row, counting lines without splitting them unless --multiline" },
+    OptionDef { names: &["-d", "--delimiter"], takes_value: true,
+                help: "Split fields on this character instead of commas, e.g. '|' or '\\t' \
+                       for tabs; the output uses it too" },
+    OptionDef { names: &["--quote"], takes_value: true,
+                help: "Recognize this character as a quote instead of single and double quotes, \
+                       e.g. '`'; may be given several times, and the first quotes the output" },
+    OptionDef { names: &["--no-single-quote"], takes_value: false,
+                help: "Don't treat single quotes as quotes, so apostrophes in unquoted text \
+                       such as it's don't swallow the delimiters after them" },
+    OptionDef { names: &["--no-quote"], takes_value: false,
+                help: "Don't treat any character as a quote and split on every delimiter, which \
+                       is much faster for machine-generated files that never quote fields" },
+    OptionDef { names: &["--unquote"], takes_value: false,
+                help: "Write the bare values of the fields, with their quotes removed and \
+                       escapes resolved; the same as --requote never" },
+    OptionDef { names: &["--requote"], takes_value: true,
+                help: "Quote the output fields afresh: always, minimal (only those that need it) \
+                       or never" },
+    OptionDef { names: &["--sniff"], takes_value: false,
+                help: "Guess the delimiter from the first few KB of input: whichever of comma, \
+                       tab, semicolon or pipe splits the lines most consistently" },
+    OptionDef { names: &["-0", "--zero"], takes_value: false,
+                help: "Zero-index columns. Ranges are half-open like [a, b)" },
+    OptionDef { names: &["-1", "--one"], takes_value: false,
+                help: "One-index columns (default). Ranges are closed like [a, b]. Given \
+                       without a column list, -1 is the last column instead" },
+    OptionDef { names: &["-c", "--compute"], takes_value: true,
+                help: "Append a computed column, e.g. 'zscore(col3)', 'pct_of_total(col3)', \
+                       'seconds(col2)', 'duration(col5 - col4)', 'round(col6, 2)', \
+                       'geohash(col6, col7)', 'haversine(col6, col7, col8, col9)', \
+                       'url_host(col10)' or 'ua_family(col11)'. May be repeated" },
+    OptionDef { names: &["--top"], takes_value: true,
+                help: "Only print the N rows with the smallest (or largest, see --by) values; \
+                       with --freq, the N most frequent values" },
+    OptionDef { names: &["--by"], takes_value: true,
+                help: "Column to rank rows by for --top, as COL[:desc]" },
+    OptionDef { names: &["--sort"], takes_value: true,
+                help: "Sort rows by a column, as COL[:desc]. The sort is stable, and spills to \
+                       disk when the input doesn't fit in --sort-mem" },
+    OptionDef { names: &["--sort-mem"], takes_value: true,
+                help: "Memory to sort in before spilling sorted runs to disk, e.g. 512M \
+                       (default 256M)" },
+    OptionDef { names: &["--sort-tmp"], takes_value: true,
+                help: "Directory for the sorted runs spilled by --sort (default: the system \
+                       temp directory)" },
+    OptionDef { names: &["--collate"], takes_value: true,
+                help: "How to order text for --sort: bytewise (default), locale (ignoring case \
+                       and accents), natural (file2 before file10) or numeric (-5 before 10, \
+                       with values that aren't numbers last)" },
+    OptionDef { names: &["--numeric"], takes_value: false,
+                help: "Sort by the value of the --sort column as a number; the same as \
+                       --collate numeric" },
+    OptionDef { names: &["--reverse"], takes_value: false,
+                help: "Sort in descending order; the same as --sort COL:desc" },
+    OptionDef { names: &["--fold-case"], takes_value: false,
+                help: "Match and count values case-insensitively (with Unicode case folding), \
+                       e.g. for --cardinality, --heavy-hitters, --unique and --regex-cols" },
+    OptionDef { names: &["--cardinality"], takes_value: true,
+                help: "Estimate the number of distinct values in a column (HyperLogLog)" },
+    OptionDef { names: &["--heavy-hitters"], takes_value: true,
+                help: "Report the most frequent values in a column with their counts and \
+                       maximum overcount (space-saving)" },
+    OptionDef { names: &["--k"], takes_value: true,
+                help: "Number of values tracked by --heavy-hitters (default 20)" },
+    OptionDef { names: &["-w", "--where"], takes_value: true,
+                help: "Only keep rows for which an expression is true, e.g. 'col5 > 100', \
+                       'col3 == \"ERROR\"' or 'contains(col3, \"timeout\")'. May be repeated \
+                       to require all of them" },
+    OptionDef { names: &["--allow-dns"], takes_value: false,
+                help: "Allow expressions to make network requests with rdns()" },
+    OptionDef { names: &["--redact-pattern"], takes_value: true,
+                help: "Mask matches of a regex in --preview, or one of the built-in patterns \
+                       email, credit-card, aws-key, or secrets for all three. May be repeated" },
+    OptionDef { names: &["--redact-output"], takes_value: false,
+                help: "Also mask the --redact-pattern matches in the cut output" },
+    OptionDef { names: &["--strip-control"], takes_value: false,
+                help: "Remove control characters such as NUL, vertical tabs and stray carriage \
+                       returns from every field" },
+    OptionDef { names: &["--show-control"], takes_value: false,
+                help: "Write control characters in every field as \\xNN escapes, to see where \
+                       they are" },
+    OptionDef { names: &["--newlines-as"], takes_value: true,
+                help: "Replace line breaks within quoted fields with this, e.g. '\\n', ' ' or \
+                       '\u{2424}', so every record is written on one line" },
+    OptionDef { names: &["--max-cell-bytes"], takes_value: true,
+                help: "Longest a field may be as written, like 64K or 1M; see --cell-overflow" },
+    OptionDef { names: &["--cell-overflow"], takes_value: true,
+                help: "What to do with fields over --max-cell-bytes: truncate them (the \
+                       default), drop-row to leave their records out, or abort" },
+    OptionDef { names: &["--ragged"], takes_value: true,
+                help: "What to do with records with fewer or more fields than the header (or \
+                       the first record): pad short ones with empty fields, skip them, stop \
+                       with an error, or truncate long ones" },
+    OptionDef { names: &["--tz"], takes_value: true,
+                help: "Convert a timestamp column between time zones, as \
+                       COL:from=ZONE:to=ZONE (e.g. 2:from=UTC:to=America/New_York). May be \
+                       repeated" },
+    OptionDef { names: &["--fpe"], takes_value: true,
+                help: "Encrypt a column in the output with FF1 (NIST SP 800-38G), keeping \
+                       digits digits and letters letters, as COL:key=ENVVAR with the key in that \
+                       environment variable; the same value always encrypts the same way, so \
+                       extracts can still be joined. Fails on values with under a million \
+                       possible letters and digits, such as fewer than six digits. Filters and \
+                       computed columns see the original. May be repeated" },
+    OptionDef { names: &["--pseudonymize"], takes_value: true,
+                help: "Replace a column in the output with a pseudonym, a hash of its value \
+                       salted with --salt-file, which stays the same across runs with the same \
+                       salt. May be repeated" },
+    OptionDef { names: &["--salt-file"], takes_value: true,
+                help: "File holding the secret salt of --pseudonymize" },
+    OptionDef { names: &["--rotate-salt"], takes_value: false,
+                help: "Write a new random salt to --salt-file and exit, so that later \
+                       pseudonyms no longer match earlier ones" },
+    OptionDef { names: &["--split-url"], takes_value: true,
+                help: "Append the scheme, host, path and query of a URL column as four \
+                       computed columns. May be repeated" },
+    OptionDef { names: &["--check"], takes_value: true,
+                help: "Assert an expression such as 'col3 <= col4' holds for every row, \
+                       reporting the lines where it doesn't. May be repeated" },
+    OptionDef { names: &["--assert-sorted"], takes_value: true,
+                help: "Check that the input is sorted by a column, as COL[:num|:date], \
+                       reporting out-of-order records" },
+    OptionDef { names: &["--gaps"], takes_value: true,
+                help: "Report missing values in a contiguous sequence column, as \
+                       COL:interval=STEP where STEP is a number or a time like 1m" },
+    OptionDef { names: &["--validate"], takes_value: false,
+                help: "Only evaluate the --check assertions and print their report instead \
+                       of the cut rows" },
+    OptionDef { names: &["--max-errors"], takes_value: true,
+                help: "Tolerate up to N rows failing checks, aborting as soon as there are \
+                       more; the rows cut are only printed once every row has been checked" },
+    OptionDef { names: &["--error-examples"], takes_value: true,
+                help: "Number of failing rows to report in detail (default 10 with \
+                       --max-errors, otherwise all)" },
+    OptionDef { names: &["--emit-plan"], takes_value: true,
+                help: "Save the fully resolved configuration to a JSON file instead of \
+                       processing any input, with the header's names if there's a header" },
+    OptionDef { names: &["--plan"], takes_value: true,
+                help: "Process input exactly as described by a JSON plan saved with \
+                       --emit-plan; only input files and --lang can go with it, and a header \
+                       must have the names the plan was made for" },
+    OptionDef { names: &["--digest-only"], takes_value: true,
+                help: "Run everything but print only a digest of the output, to compare runs \
+                       without keeping the output. The only algorithm is sha256" },
+    OptionDef { names: &["--seek"], takes_value: true,
+                help: "Start processing the input at a byte offset, e.g. to split one large \
+                       file between several machines" },
+    OptionDef { names: &["--align-record"], takes_value: false,
+                help: "With --seek, skip ahead to the start of the next record" },
+    OptionDef { names: &["--byte-range"], takes_value: true,
+                help: "Only process the records that start within bytes [A, B) of the input, \
+                       as A-B, so that adjacent ranges split the input between workers" },
+    OptionDef { names: &["--line-range"], takes_value: true,
+                help: "Only process lines A to B (1-indexed, inclusive), as A-B, counted from \
+                       where reading starts" },
+    OptionDef { names: &["--skip"], takes_value: true,
+                help: "Leave out the first N records of the input, after the header with \
+                       --header, before any filtering" },
+    OptionDef { names: &["-n", "--limit"], takes_value: true,
+                help: "Stop after writing N records, without reading or decompressing the \
+                       rest of the input unless --top, --sort or totals need it; with \
+                       --auto-shard or --threads, every worker stops too" },
+    OptionDef { names: &["--auto-shard"], takes_value: false,
+                help: "Split the input into one byte range per core and process them in \
+                       parallel, keeping the output in input order" },
+    OptionDef { names: &["--threads"], takes_value: true,
+                help: "Process the input on N threads, in chunks that end at line breaks, \
+                       keeping the output in input order; works on streams too, and after a \
+                       --header or --skip" },
+    OptionDef { names: &["--audit"], takes_value: true,
+                help: "Append a JSON record of the run (time, arguments, input digest, row \
+                       counts) to a file, to show how an extract was produced" },
+    OptionDef { names: &["--max-memory"], takes_value: true,
+                help: "Cap the memory used for buffering, e.g. by --sort or --in-file, like \
+                       512M or 2G, so jobs on shared hosts leave room for others; larger \
+                       buffers spill to disk" },
+    OptionDef { names: &["--buffer-bytes"], takes_value: true,
+                help: "Read the input this many bytes at a time, like 64K or 4M, instead of in \
+                       chunks that start at 64K and grow, up to 16M, while records are longer" },
+    OptionDef { names: &["--nice"], takes_value: true,
+                help: "Run at a lower scheduling priority, as for nice(1). A positive value also \
+                       halves the threads --auto-shard uses. Ignored, with a warning, off Unix" },
+    OptionDef { names: &["--trailer-checksum"], takes_value: false,
+                help: "After the output, print a '#ccut-trailer rows=N sha256=HEX' line with \
+                       the number of records and the digest of everything before it, so \
+                       consumers can check they received all of it" },
+    OptionDef { names: &["--checksum-file"], takes_value: true,
+                help: "Write the number of records and the sha256 of the output to a sidecar \
+                       file that sha256sum --check can read" },
+    OptionDef { names: &["--partition-by"], takes_value: true,
+                help: "Write each record to a file in --out-dir named after its value in this \
+                       column" },
+    OptionDef { names: &["--out-dir"], takes_value: true,
+                help: "Directory for the files written by --partition-by" },
+    OptionDef { names: &["--skip-existing"], takes_value: false,
+                help: "Leave output files alone if they were made from the same input (by size \
+                       and modification time) with the same options, so re-running a batch job \
+                       only redoes what changed" },
+    OptionDef { names: &["--overwrite"], takes_value: false,
+                help: "Replace output files that already exist, which is otherwise an error" },
+    OptionDef { names: &["--manifest"], takes_value: false,
+                help: "Also write a manifest.json to --out-dir listing each file with its \
+                       partition value, row count and size" },
+    OptionDef { names: &["--estimate"], takes_value: false,
+                help: "Instead of any output, print how many rows and bytes it would have, \
+                       extrapolated from a sample of the input" },
+    OptionDef { names: &["--stats"], takes_value: false,
+                help: "Print per-column counts, distinct values, uniqueness, entropy and \
+                       likely personal data (email, phone, ssn), for all columns or those given" },
+    OptionDef { names: &["--stats-every"], takes_value: true,
+                help: "Also print the --stats so far to stderr every so many rows or so long, \
+                       e.g. 100000rows or 1m, to watch a live feed; implies --stats" },
+    OptionDef { names: &["--stats-file"], takes_value: true,
+                help: "Write the --stats-every statistics to this file, replacing it each time, \
+                       instead of to stderr" },
+    OptionDef { names: &["--correlate"], takes_value: true,
+                help: "Print the Pearson and Spearman correlations of every pair of these \
+                       numeric columns, e.g. 2,4-6, to spot redundant measures" },
+    OptionDef { names: &["--crosstab-count"], takes_value: true,
+                help: "Print how many rows have each combination of the values of two columns, \
+                       given as COL1,COL2, with a row per value of the first and a column per \
+                       value of the second" },
+    OptionDef { names: &["--crosstab-max"], takes_value: true,
+                help: "Distinct values of each --crosstab-count column to count on their own \
+                       (default 50); later ones are counted together as (other)" },
+    OptionDef { names: &["--group-by"], takes_value: true,
+                help: "Print one row per distinct combination of values in these columns, \
+                       e.g. 1,3, followed by the --agg values of its rows" },
+    OptionDef { names: &["--agg"], takes_value: true,
+                help: "Aggregate to compute for each --group-by group: count, percent (of all \
+                       rows), sum(col3), mean(col3), min(col3), max(col3), wavg(col3, col4) \
+                       (col3 weighted by col4), sumproduct(col3, col4) or ratio(sum(col3), \
+                       sum(col4))" },
+    OptionDef { names: &["--agg-nulls"], takes_value: true,
+                help: "What --agg does with values that aren't numbers, or that overflow: \
+                       skip (default) or zero them, reporting how many, or abort" },
+    OptionDef { names: &["--freq"], takes_value: true,
+                help: "Count the rows with each combination of values in these columns, most \
+                       frequent first, with their share of the rows in percent; --top K keeps \
+                       the K most frequent" },
+    OptionDef { names: &["--sessionize"], takes_value: true,
+                help: "Append a session number, counting up for each key whenever the time \
+                       since its previous record exceeds the gap, as 'key=COL time=COL \
+                       gap=30m'; records should be in time order" },
+    OptionDef { names: &["--flag-outliers"], takes_value: true,
+                help: "Append an 'outlier' column, true where a column's value is more than N \
+                       standard deviations from the mean of the values before it, as \
+                       'COL:zscore>N'" },
+    OptionDef { names: &["--outlier-window"], takes_value: true,
+                help: "Compare with only the previous N values for --flag-outliers, to follow \
+                       a drifting level" },
+    OptionDef { names: &["--outliers-only"], takes_value: false,
+                help: "Keep only the --flag-outliers outliers instead of appending the flag" },
+    OptionDef { names: &["--range-join"], takes_value: true,
+                help: "Append the label of the range in this file that each record's --on \
+                       value falls in, e.g. to map addresses to networks; the file has a \
+                       header, and its ranges may be numbers, IP addresses or timestamps" },
+    OptionDef { names: &["--on"], takes_value: true,
+                help: "The --range-join column and the range file's columns holding each \
+                       range's first and last values, as 'col2 between start,end', optionally \
+                       followed by 'as LABEL'; the label is otherwise the first other column. \
+                       To only match ranges with the same key, start with e.g. \
+                       'col1+col3=host+site and'" },
+    OptionDef { names: &["--join-type"], takes_value: true,
+                help: "Which records and ranges --range-join outputs: inner (only records in \
+                       a range), left (every record; the default), right (records in a range, \
+                       then ranges no record fell in) or full (both)" },
+    OptionDef { names: &["--fill"], takes_value: true,
+                help: "With --range-join, write this in place of the missing side of a record \
+                       or range that didn't match; empty by default" },
+    OptionDef { names: &["--matched-only-columns"], takes_value: true,
+                help: "With --range-join, append these columns of the range file, e.g. \
+                       net,site, instead of the single label" },
+    OptionDef { names: &["--in-file"], takes_value: true,
+                help: "Keep only the records whose value in a column is one of the lines of a \
+                       file, as FILE@COL, e.g. ids.txt@3; for a key of several columns, the \
+                       file's records are matched as in pairs.csv@1+2, or pairs.csv@1+2=3+1 \
+                       to name the file's columns" },
+    OptionDef { names: &["--not-in-file"], takes_value: true,
+                help: "Drop the records whose value in a column is one of the lines of a \
+                       file, as FILE@COL" },
+    OptionDef { names: &["--grep"], takes_value: true,
+                help: "Keep only the records whose value in a column matches a regex, as \
+                       COL:REGEX, e.g. '3:^(WARN|ERROR)$'; quotes around the value are \
+                       ignored, and so is case with --fold-case. May be repeated" },
+    OptionDef { names: &["--grep-v"], takes_value: true,
+                help: "Drop the records whose value in a column matches a regex, as COL:REGEX" },
+    OptionDef { names: &["--unique"], takes_value: false,
+                help: "Drop the records that would be written the same as one before them, \
+                       keeping every distinct record in memory" },
+    OptionDef { names: &["--unique-by"], takes_value: true,
+                help: "Drop the records with the same values in these columns, e.g. 1,2, as one \
+                       before them, keeping the first of each" },
+    OptionDef { names: &["--intersect"], takes_value: true,
+                help: "Keep only the records that also appear in this file, of the same \
+                       layout, compared on the --key columns or else the selected ones" },
+    OptionDef { names: &["--except"], takes_value: true,
+                help: "Drop the records that also appear in this file, as for --intersect, \
+                       e.g. to find what's new in today's export since yesterday's" },
+    OptionDef { names: &["--key"], takes_value: true,
+                help: "Columns --intersect and --except compare records on, e.g. 1+3" },
+    OptionDef { names: &["--bloom-fpr"], takes_value: true,
+                help: "For --in-file lists spilled to disk past --max-memory, first screen \
+                       records with a Bloom filter with this false positive rate, e.g. 0.01, \
+                       checking only those it lets through exactly; uses about 1.2 bytes per \
+                       key at 1%" },
+    OptionDef { names: &["--key-sep"], takes_value: true,
+                help: "Print a --group-by or --freq key as one field joined with this \
+                       separator, backslash-escaping it within values" },
+    OptionDef { names: &["--precision"], takes_value: true,
+                help: "Print computed and aggregated numbers with exactly this many decimals" },
+    OptionDef { names: &["--round-mode"], takes_value: true,
+                help: "How --precision rounds halves: half-even (default) or half-up" },
+    OptionDef { names: &["--decimal"], takes_value: false,
+                help: "Do arithmetic in computed columns and aggregates in exact decimals \
+                       rather than floating point, e.g. for money" },
+    OptionDef { names: &["--scale"], takes_value: true,
+                help: "With --decimal, read this column with a fixed number of decimals, as \
+                       in 3=2, rounding half-even; implies --decimal" },
+    OptionDef { names: &["--multiline"], takes_value: false,
+                help: "Let quoted fields hold line breaks, continuing a record on the next \
+                       line while a quote is open" },
+    OptionDef { names: &["--complement"], takes_value: false,
+                help: "Print every column except the ones given, as with cut --complement" },
+    OptionDef { names: &["--flush"], takes_value: false,
+                help: "Flush the output after every line rather than when the buffer fills, \
+                       for reading it as it comes through a pipe" },
+    OptionDef { names: &["--header"], takes_value: false,
+                help: "Treat the first line of the input as a header: it's cut like the \
+                       records and printed first, and reports name columns after it" },
+    OptionDef { names: &["--regex-cols"], takes_value: true,
+                help: "Select the columns whose whole header name matches a regex, e.g. \
+                       'ts_.*|status', in header order, instead of listing them; needs \
+                       --header, and ignores case with --fold-case" },
+    OptionDef { names: &["--catalog"], takes_value: false,
+                help: "Take the delimiter and the --regex-cols columns of the input file from \
+                       the catalog (see 'ccut catalog --help') if it's there and unchanged" },
+    OptionDef { names: &["--provenance"], takes_value: false,
+                help: "Append the input file each record came from, and the byte offset and \
+                       line number it starts at there" },
+    OptionDef { names: &["--show-invalid-bytes"], takes_value: false,
+                help: "Write bytes that aren't valid UTF-8 as \\xNN escapes instead of failing, \
+                       and report the lines and columns they're in" },
+    OptionDef { names: &["--table"], takes_value: false,
+                help: "Print the records unquoted and lined up in columns, for reading; widths \
+                       are worked out from the first rows" },
+    OptionDef { names: &["--json"], takes_value: false,
+                help: "Print each record as a JSON object on a line of its own, keyed by the \
+                       --header names, or col_1, col_2... without one" },
+    OptionDef { names: &["--markdown"], takes_value: false,
+                help: "Print the records as a GitHub-flavored Markdown table, headed by the \
+                       --header names, or col_1, col_2... without one" },
+    OptionDef { names: &["--verify-roundtrip"], takes_value: false,
+                help: "Check that every record written reads back as the same fields, and stop \
+                       at the first that doesn't, e.g. for an unquoted delimiter, instead of \
+                       corrupting the output silently" },
+    OptionDef { names: &["--lang"], takes_value: true,
+                help: "Language of errors and summaries: en, es or de, instead of the locale's. \
+                       Messages not in English end with an id that stays the same in every \
+                       language, for scripts to match" },
+    OptionDef { names: &["--errors-format"], takes_value: true,
+                help: "How to write bad rows, check failures and the error that stops a run: \
+                       text, or json for one object per line with its code, line, column, \
+                       file, byte offset and message" },
+];
+
+/// The option going by a name, short or long, if there is one
+pub fn find(name: &str) -> Option<&'static OptionDef> {
+    OPTIONS.iter().find(|o| o.names.contains(&name))
+}
+
+fn by_name(name: &str) -> &'static OptionDef {
+    find(name).unwrap_or_else(|| panic!("Unknown option {}", name))
+}
+
+/// The names of an option, for the argument parser to register it under
+pub fn names(name: &str) -> &'static [&'static str] {
+    by_name(name).names
+}
+
+/// The help of an option, for the argument parser and `ccut help` to show
+pub fn help(name: &str) -> &'static str {
+    by_name(name).help
+}
+
+/// Command line arguments rearranged so that a column list starting with `-`, such as `-2` or
+/// `-3--1`, reaches the argument parser as the column list rather than as options: the
+/// arguments other than options and their values move after a `--`, in their order. The column
+/// list is the first such argument. A bare `-1` is --one, unless no other argument is a column
+/// list, when it's the last column.
+pub fn separate_col_list(args: Vec<String>) -> Vec<String> {
+    let mut options = Vec::new();
+    let mut arguments = Vec::new();
+    let mut one = None;
+    let mut rest = args.into_iter();
+    while let Some(arg) = rest.next() {
+        if arg == "--" {
+            arguments.extend(rest.by_ref());
+        } else if arg == "-1" && one.is_none() {
+            one = Some(options.len());
+            options.push(arg);
+        } else if arg.starts_with('-') && arg.len() > 1 && !is_negative_list(&arg) {
+            // A short option's value may follow its letter in the same argument, as in -n5
+            let takes_value = if arg.starts_with("--") {
+                !arg.contains('=') && find(&arg).is_some_and(|o| o.takes_value)
+            } else {
+                let letters: Vec<char> = arg.chars().skip(1).collect();
+                letters.iter().position(|c| find(&format!("-{}", c)).is_some_and(|o| o.takes_value))
+                    .is_some_and(|i| i == letters.len() - 1)
+            };
+            options.push(arg);
+            if takes_value {
+                options.extend(rest.next());
+            }
+        } else {
+            arguments.push(arg);
+        }
+    }
+    if let Some(i) = one {
+        if !arguments.first().is_some_and(|a| is_col_list(a)) {
+            arguments.insert(0, options.remove(i));
+        }
+    }
+    if !arguments.iter().any(|a| a.starts_with('-') && a.len() > 1) {
+        options.extend(arguments);
+        return options;
+    }
+    options.push(String::from("--"));
+    options.extend(arguments);
+    options
+}
+
+fn is_negative_list(arg: &str) -> bool {
+    arg != "-0" && arg != "-1" && is_col_list(arg)
+}
+
+#[cfg(test)]
+mod test_options {
+    use super::*;
+
+    #[test]
+    fn test_names_unique() {
+        let mut names: Vec<&str> = OPTIONS.iter().flat_map(|o| o.names.iter().cloned()).collect();
+        let count = names.len();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), count);
+    }
+    #[test]
+    fn test_find() {
+        assert_eq!(names("-d"), &["-d", "--delimiter"]);
+        assert_eq!(names("--delimiter"), &["-d", "--delimiter"]);
+        assert!(find("--delimiter").unwrap().takes_value);
+        assert!(!find("--header").unwrap().takes_value);
+        assert!(find("--nothing").is_none());
+    }
+    #[test]
+    fn test_separate_col_list() {
+        let separate = |args: &[&str]| {
+            let args = args.iter().map(|a| a.to_string()).collect();
+            separate_col_list(args).join(" ")
+        };
+        assert_eq!(separate(&["-3--1", "a.csv"]), "-- -3--1 a.csv");
+        assert_eq!(separate(&["--header", "-2", "a.csv", "-d", ";"]),
+                   "--header -d ; -- -2 a.csv");
+        assert_eq!(separate(&["--skip", "2", "-2,1", "a.csv", "b.csv"]),
+                   "--skip 2 -- -2,1 a.csv b.csv");
+        assert_eq!(separate(&["-n", "-1", "-2"]), "-n -1 -- -2");
+        assert_eq!(separate(&["-n5", "-2"]), "-n5 -- -2");
+        assert_eq!(separate(&["--top=3", "-2"]), "--top=3 -- -2");
+        assert_eq!(separate(&["-1", "a.csv"]), "-- -1 a.csv");
+        assert_eq!(separate(&["-0", "-1"]), "-0 -- -1");
+        assert_eq!(separate(&["-1", "3", "a.csv"]), "-1 3 a.csv");
+        assert_eq!(separate(&["2", "--", "-a.csv"]), "-- 2 -a.csv");
+        assert_eq!(separate(&["2", "-"]), "2 -");
+        assert_eq!(separate(&["2", "a.csv"]), "2 a.csv");
+    }
+    #[test]
+    #[should_panic(expected = "Unknown option --nothing")]
+    fn test_help_unknown() {
+        help("--nothing");
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::expr;
+use crate::parse_args;
+
+/// `--flag-outliers COL:zscore>T`: flags the rows whose value in a column is more than `threshold`
+/// standard deviations from the mean of the values before it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutlierSpec {
+    pub col: usize,
+    pub threshold: f64,
+    /// Compare with only this many previous values instead of all of them
+    pub window: Option<usize>,
+    /// Keep only the outliers instead of appending a flag to every row
+    pub only: bool,
+}
+
+/// Parses a `--flag-outliers` argument such as `3:zscore>4`. `offset` is as in `parse_arg_cols`.
+pub fn parse_outliers(spec: &str, offset: usize) -> OutlierSpec {
+    let (col, threshold) = spec.split_once(':')
+        .and_then(|(col, test)| {
+            let t = test.trim().strip_prefix("zscore")?.trim().strip_prefix('>')?;
+            Some((col, t.trim().parse::<f64>().ok()?))
+        })
+        .unwrap_or_else(|| panic!("Invalid --flag-outliers {}, expected COL:zscore>N", spec));
+    assert!(threshold > 0.0 && threshold.is_finite(), "The z-score threshold must be positive");
+    OutlierSpec { col: parse_args::parse_arg_col(col.trim(), offset), threshold, window: None,
+                  only: false }
+}
+
+/// Running mean and variance of a stream of values, as by Welford's algorithm, which values can
+/// also be taken back out of
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Moments {
+    n: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Moments {
+    fn push(&mut self, x: f64) {
+        self.n += 1;
+        let d = x - self.mean;
+        self.mean += d / self.n as f64;
+        self.m2 += d * (x - self.mean);
+    }
+
+    /// Takes back a value pushed before
+    fn pop(&mut self, x: f64) {
+        if self.n <= 1 {
+            *self = Moments::default();
+            return;
+        }
+        self.n -= 1;
+        let d = x - self.mean;
+        self.mean -= d / self.n as f64;
+        self.m2 = (self.m2 - d * (x - self.mean)).max(0.0);
+    }
+
+    /// How many sample standard deviations `x` is from the mean, or `None` if there are too few
+    /// values. When they're all the same, any other value is infinitely far from them.
+    fn zscore(&self, x: f64) -> Option<f64> {
+        if self.n < 2 {
+            return None;
+        }
+        // Popping values off a window can leave a little rounding error where there's no spread
+        let scale = 1e-12 * self.mean.abs().max(1.0);
+        let sd = (self.m2.max(0.0) / (self.n - 1) as f64).sqrt();
+        if sd > scale {
+            Some((x - self.mean) / sd)
+        } else if (x - self.mean).abs() <= scale {
+            Some(0.0)
+        } else {
+            Some((x - self.mean).signum() * f64::INFINITY)
+        }
+    }
+}
+
+/// Flags outliers as rows come, comparing each value with those before it, so a spike neither
+/// waits for the end of the input nor hides itself by shifting the mean
+#[derive(Debug, Clone)]
+pub struct OutlierFlagger {
+    spec: OutlierSpec,
+    moments: Moments,
+    /// The values in the window, oldest first, when there is one
+    window: VecDeque<f64>,
+}
+
+impl OutlierFlagger {
+    pub fn new(spec: OutlierSpec) -> OutlierFlagger {
+        OutlierFlagger { spec, moments: Moments::default(), window: VecDeque::new() }
+    }
+
+    pub fn spec(&self) -> &OutlierSpec {
+        &self.spec
+    }
+
+    /// Whether the next row is an outlier, or `None` if its value isn't a number. Rows before
+    /// there are two distinct values to compare with are never outliers.
+    pub fn flag(&mut self, fields: &[&str]) -> Option<bool> {
+        let x = fields.get(self.spec.col).and_then(|f| expr::parse_num(f))
+            .filter(|x| x.is_finite())?;
+        let outlier = self.moments.zscore(x).is_some_and(|z| z.abs() > self.spec.threshold);
+        self.moments.push(x);
+        if let Some(size) = self.spec.window {
+            self.window.push_back(x);
+            if self.window.len() > size {
+                let old = self.window.pop_front().expect("the window is over its size");
+                self.moments.pop(old);
+            }
+        }
+        Some(outlier)
+    }
+}
+
+#[cfg(test)]
+mod test_outlier {
+    use super::*;
+
+    fn flags(spec: OutlierSpec, values: &[&str]) -> Vec<Option<bool>> {
+        let mut flagger = OutlierFlagger::new(spec);
+        values.iter().map(|v| flagger.flag(&["x", v])).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_outliers("2:zscore>4", 1),
+                   OutlierSpec { col: 1, threshold: 4.0, window: None, only: false });
+        assert_eq!(parse_outliers("2: zscore > 2.5", 1).threshold, 2.5);
+    }
+    #[test]
+    #[should_panic]
+    fn test_unknown_test_fails() {
+        parse_outliers("2:iqr>4", 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_negative_threshold_fails() {
+        parse_outliers("2:zscore>-1", 1);
+    }
+    #[test]
+    fn test_flag() {
+        let spec = parse_outliers("2:zscore>3", 1);
+        assert_eq!(flags(spec, &["10", "11", "x", "9", "10", "100", "10", "-150"]),
+                   vec![Some(false), Some(false), None, Some(false), Some(false), Some(true),
+                        Some(false), Some(true)]);
+        // A spike after a constant history is as far out as can be
+        let spec = parse_outliers("2:zscore>1", 1);
+        assert_eq!(flags(spec, &["5", "5", "5", "5", "500", "5"]),
+                   vec![Some(false), Some(false), Some(false), Some(false), Some(true),
+                        Some(false)]);
+        let spec = parse_outliers("2:zscore>1", 1);
+        assert_eq!(flags(spec, &["5", "5", "-5"])[2], Some(true));
+    }
+    #[test]
+    fn test_window() {
+        let values = ["1", "2", "1", "2", "100", "101", "100", "101", "100"];
+        let all = flags(parse_outliers("2:zscore>1.5", 1), &values);
+        assert_eq!(all[8], Some(false));
+        assert_eq!(all[5], Some(true));
+        let spec = OutlierSpec { window: Some(3), ..parse_outliers("2:zscore>1.5", 1) };
+        let windowed = flags(spec, &values);
+        // After the jump the window only holds the new level
+        assert_eq!(windowed[4], Some(true));
+        assert_eq!(windowed[8], Some(false));
+    }
+    #[test]
+    fn test_moments_pop() {
+        let mut m = Moments::default();
+        for x in [5.0, 1.0, 2.0, 3.0].iter() {
+            m.push(*x);
+        }
+        m.pop(5.0);
+        let mut expected = Moments::default();
+        for x in [1.0, 2.0, 3.0].iter() {
+            expected.push(*x);
+        }
+        assert!((m.mean - expected.mean).abs() < 1e-12 && (m.m2 - expected.m2).abs() < 1e-12);
+    }
+}
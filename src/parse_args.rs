@@ -1,41 +1,246 @@
+use chrono_tz::Tz;
+
+use crate::duration;
+use crate::key::{self, KeyKind};
+use crate::line::{FROM_END, OPEN_RANGE};
+use crate::transform::Transform;
+
 /// This function parses a string representing the indices of columns to output.  `offset` (either
 /// 0 or 1) indicates the index of the first column, which also affects how ranges are interpreted.
 /// The result vector always zero-indexes columns so we don't have to worry about this offset
-/// business elsewhere.
-pub fn parse_arg_cols(cols: &String, offset: usize) -> Vec<usize> {
-    assert!(offset == 0 || offset == 1, "Invalid offset, {}", offset);
+/// business elsewhere. Negative indices count from the end of each row, `-1` being the last
+/// column, and are kept marked with `line::FROM_END`. A range may leave out its end (`3-`),
+/// running to the end of each row; it's kept as the start column marked with `line::OPEN_RANGE`.
+/// Both are resolved once the row is split.
+pub fn parse_arg_cols(cols: &str, offset: usize) -> Vec<usize> {
+    try_parse_arg_cols(cols, offset).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Like `parse_arg_cols`, but returns what's wrong with an invalid list instead of panicking
+pub fn try_parse_arg_cols(cols: &str, offset: usize) -> Result<Vec<usize>, String> {
+    if offset != 0 && offset != 1 {
+        return Err(format!("Invalid offset, {}", offset));
+    }
+    let check = |ok: bool, msg: String| if ok { Ok(()) } else { Err(msg) };
 
     let mut res = Vec::new();
-    // Columns are either ints or ranges ("int-int") separated by commas
+    // Columns are either ints or ranges ("int-int") separated by commas, where either int may be
+    // negative
     for elem in cols.split(',') {
         let elem = elem.trim();
-        if elem.contains("-") {
+        let split = elem.get(1..).and_then(|rest| rest.find('-')).map(|i| i + 1);
+        if let Some(split) = split {
             // It's a range
-            let rg: Vec<&str> = elem.split('-').collect();
-            assert!(rg.len() == 2, "Invalid range {} ({} parts)", elem, rg.len());
-            let a: usize = rg[0].parse()
-                .expect("Invalid range: start index is not an integer");
-            let b: usize = rg[1].parse()
-                .expect("Invalid range: end index is not an integer");
+            let a: isize = elem[..split].parse()
+                .map_err(|_| format!("Invalid range {}: start index is not an integer", elem))?;
+            let b = &elem[split + 1..];
+            if b.is_empty() {
+                // Open-ended
+                if a < 0 {
+                    res.extend((1..=a.unsigned_abs()).rev().map(|i| i | FROM_END));
+                } else {
+                    check(offset as isize <= a,
+                          format!("Start index must be at least {}", offset))?;
+                    res.push((a as usize - offset) | OPEN_RANGE);
+                }
+                continue;
+            }
+            let b: isize = b.parse()
+                .map_err(|_| format!("Invalid range {}: end index is not an integer", elem))?;
+            if a >= 0 && b == -1 && offset == 1 {
+                // Through the last column
+                check(offset as isize <= a, format!("Start index must be at least {}", offset))?;
+                res.push((a as usize - offset) | OPEN_RANGE);
+                continue;
+            }
+            check((a < 0) == (b < 0),
+                  format!("Invalid range {}: its ends count from different ends of the row",
+                          elem))?;
             // Validation
-            assert!(offset <= a, "Start index must be at least {}", offset);
+            check(b < 0 || offset as isize <= a,
+                  format!("Start index must be at least {}", offset))?;
             if offset == 0 {
-                assert!(a < b, "Overlapping end-points [{}, {})", a, b);
+                check(a < b, format!("Overlapping end-points [{}, {})", a, b))?;
             } else {
-                assert!(a <= b, "Overlapping end-points [{}, {}]", a, b);
+                check(a <= b, format!("Overlapping end-points [{}, {}]", a, b))?;
             }
             // Push all the indices in the range
-            for i in a..b + offset {
-                res.push(i - offset);
+            for i in a..b + offset as isize {
+                res.push(if i < 0 { i.unsigned_abs() | FROM_END } else { i as usize - offset });
             }
         } else {
             // It's a single number
-            let i: usize = elem.parse()
-                .expect("Invalid index");
-            res.push(i - offset);
+            let i: isize = elem.parse().map_err(|_| format!("Invalid index {:?}", elem))?;
+            if i < 0 {
+                res.push(i.unsigned_abs() | FROM_END);
+            } else {
+                check(offset as isize <= i, format!("Index must be at least {}", offset))?;
+                res.push(i as usize - offset);
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Whether an argument looks like a list of columns for `parse_arg_cols`, rather than e.g. a file
+/// name
+pub fn is_col_list(s: &str) -> bool {
+    s.chars().any(|c| c.is_ascii_digit())
+        && s.chars().all(|c| c.is_ascii_digit() || c == ',' || c == '-' || c.is_whitespace())
+}
+
+/// The options among command line arguments other than those in `allowed`, by name, e.g. to
+/// find what would conflict with a `--plan`. Column lists are arguments rather than options,
+/// however they start, and everything after `--` is an argument.
+pub fn options_besides(args: &[String], allowed: &[&str]) -> Vec<String> {
+    args.iter()
+        .take_while(|a| *a != "--")
+        .filter(|a| a.starts_with('-') && a.len() > 1 && !is_col_list(a))
+        .map(|a| a.split('=').next().unwrap_or(a).to_string())
+        .filter(|name| !allowed.contains(&name.as_str()))
+        .collect()
+}
+
+/// Parses a `--delimiter` argument: a single character, or `\t` or `tab` for a tab
+pub fn parse_arg_delimiter(spec: &str) -> char {
+    match spec {
+        "\\t" | "tab" => '\t',
+        _ => {
+            let mut chars = spec.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => panic!("Invalid delimiter {:?}, expected a single character", spec),
+            }
+        },
+    }
+}
+
+/// Parses a `--quote` argument: a single character
+pub fn parse_arg_quote(spec: &str) -> char {
+    let mut chars = spec.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => c,
+        _ => panic!("Invalid quote {:?}, expected a single character", spec),
+    }
+}
+
+/// Parses a single column index, returning it zero-indexed. `offset` is as in `parse_arg_cols`.
+pub fn parse_arg_col(col: &str, offset: usize) -> usize {
+    assert!(offset == 0 || offset == 1, "Invalid offset, {}", offset);
+
+    let i: usize = col.trim().parse()
+        .expect("Invalid column index");
+    assert!(offset <= i, "Column index must be at least {}", offset);
+    i - offset
+}
+
+/// Parses a key of one or more columns joined with `+`, such as `1+2` or `col1+col3`, returning
+/// them zero-indexed. `offset` is as in `parse_arg_cols`.
+pub fn parse_arg_key(key: &str, offset: usize) -> Vec<usize> {
+    key.split('+')
+        .map(|col| {
+            let col = col.trim();
+            parse_arg_col(col.strip_prefix("col").unwrap_or(col), offset)
+        })
+        .collect()
+}
+
+/// Parses a sort-key argument of the form `COL[:desc]`, returning the zero-indexed column and
+/// whether the order is descending. `offset` is as in `parse_arg_cols`.
+pub fn parse_arg_by(by: &str, offset: usize) -> (usize, bool) {
+    assert!(offset == 0 || offset == 1, "Invalid offset, {}", offset);
+
+    let mut parts = by.trim().splitn(2, ':');
+    let i = parse_arg_col(parts.next().unwrap(), offset);
+    let desc = match parts.next().map(|s| s.trim()) {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(other) => panic!("Invalid sort order {}", other),
+    };
+    (i, desc)
+}
+
+/// Parses a column argument with an optional type suffix, `COL[:num|:date|:text]`, returning the
+/// zero-indexed column and how to interpret its values (text if not given)
+pub fn parse_arg_typed_col(spec: &str, offset: usize) -> (usize, KeyKind) {
+    let mut parts = spec.trim().splitn(2, ':');
+    let i = parse_arg_col(parts.next().unwrap(), offset);
+    let kind = parts.next().map(key::parse_kind).unwrap_or(KeyKind::Text);
+    (i, kind)
+}
+
+/// Parses a gap-detection argument of the form `COL:interval=STEP`. A plain number step makes the
+/// column numeric; a duration step (e.g. `1m` or `1h30m`) makes it a timestamp column, with the
+/// step returned in seconds. The interval defaults to 1.
+pub fn parse_arg_gaps(spec: &str, offset: usize) -> (usize, KeyKind, f64) {
+    let mut parts = spec.trim().split(':');
+    let i = parse_arg_col(parts.next().unwrap(), offset);
+    let mut kind = KeyKind::Num;
+    let mut step = 1.0;
+    for part in parts {
+        let kv: Vec<&str> = part.splitn(2, '=').collect();
+        assert!(kv.len() == 2 && kv[0].trim() == "interval", "Invalid gaps option {}", part);
+        let val = kv[1].trim();
+        match val.parse() {
+            Ok(n) => step = n,
+            Err(_) => {
+                kind = KeyKind::Date;
+                step = duration::parse_duration(val).expect("Invalid interval");
+            },
+        }
+    }
+    assert!(step > 0.0, "Interval must be positive");
+    (i, kind, step)
+}
+
+/// Parses a time zone conversion argument of the form `COL:from=ZONE:to=ZONE`, where zones are
+/// IANA names like `America/New_York`. Either zone defaults to UTC.
+pub fn parse_arg_tz(spec: &str, offset: usize) -> Transform {
+    let mut parts = spec.trim().split(':');
+    let col = parse_arg_col(parts.next().unwrap(), offset);
+    let mut from = Tz::UTC;
+    let mut to = Tz::UTC;
+    for part in parts {
+        let kv: Vec<&str> = part.splitn(2, '=').collect();
+        assert!(kv.len() == 2, "Invalid time zone option {}", part);
+        let zone: Tz = kv[1].trim().parse()
+            .unwrap_or_else(|_| panic!("Unknown time zone {}", kv[1]));
+        match kv[0].trim() {
+            "from" => from = zone,
+            "to" => to = zone,
+            other => panic!("Invalid time zone option {}", other),
         }
     }
-    return res;
+    Transform::Tz { col, from, to }
+}
+
+/// Parses a size in bytes such as `512M`, with an optional binary `K`, `M` or `G` suffix
+pub fn parse_arg_size(spec: &str) -> usize {
+    let spec = spec.trim();
+    let (num, scale) = match spec.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&spec[..i], 1 << 10),
+        Some((i, 'M')) | Some((i, 'm')) => (&spec[..i], 1 << 20),
+        Some((i, 'G')) | Some((i, 'g')) => (&spec[..i], 1 << 30),
+        _ => (spec, 1),
+    };
+    let n: f64 = num.trim().parse().unwrap_or_else(|_| panic!("Invalid size {}", spec));
+    assert!(n >= 0.0, "Invalid size {}", spec);
+    (n * scale as f64) as usize
+}
+
+/// Parses a range of bytes or lines of the form `A-B`, where either end may be left out, as in
+/// `A-` or `-B`. What the bounds mean (closed or half-open) is up to the option.
+pub fn parse_arg_range(spec: &str) -> (u64, Option<u64>) {
+    let parts: Vec<&str> = spec.trim().splitn(2, '-').collect();
+    assert!(parts.len() == 2, "Invalid range {}, expected A-B", spec);
+    let parse = |s: &str| s.trim().parse::<u64>()
+        .unwrap_or_else(|_| panic!("Invalid range bound {}", s));
+    let start = if parts[0].trim().is_empty() { 0 } else { parse(parts[0]) };
+    let end = if parts[1].trim().is_empty() { None } else { Some(parse(parts[1])) };
+    if let Some(end) = end {
+        assert!(start <= end, "Range {} ends before it starts", spec);
+    }
+    (start, end)
 }
 
 #[cfg(test)]
@@ -69,6 +274,75 @@ mod test_parse_cols {
         let res = parse_arg_cols(&String::from("5,1-3,0"), 0);
         assert_eq!(res, vec![5, 1, 2, 0]);
     }
+    #[test]
+    fn test_parse_open_range() {
+        let res = parse_arg_cols(&String::from("3-"), 1);
+        assert_eq!(res, vec![2 | OPEN_RANGE]);
+        let res = parse_arg_cols(&String::from("4,1-"), 0);
+        assert_eq!(res, vec![4, 1 | OPEN_RANGE]);
+        let res = parse_arg_cols(&String::from("2--1"), 1);
+        assert_eq!(res, vec![1 | OPEN_RANGE]);
+    }
+    #[test]
+    fn test_parse_from_end() {
+        let res = parse_arg_cols(&String::from("-1"), 1);
+        assert_eq!(res, vec![1 | FROM_END]);
+        let res = parse_arg_cols(&String::from("1,-3--1"), 1);
+        assert_eq!(res, vec![0, 3 | FROM_END, 2 | FROM_END, 1 | FROM_END]);
+        let res = parse_arg_cols(&String::from("-3--1"), 0);
+        assert_eq!(res, vec![3 | FROM_END, 2 | FROM_END]);
+        let res = parse_arg_cols(&String::from("-2-"), 0);
+        assert_eq!(res, vec![2 | FROM_END, 1 | FROM_END]);
+    }
+    #[test]
+    #[should_panic]
+    fn test_mixed_range_fails() {
+        parse_arg_cols(&String::from("-3-2"), 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_reversed_from_end_fails() {
+        parse_arg_cols(&String::from("-1--3"), 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_empty_range_fails() {
+        parse_arg_cols(&String::from("-"), 1);
+    }
+    #[test]
+    fn test_parse_delimiter() {
+        assert_eq!(parse_arg_delimiter("|"), '|');
+        assert_eq!(parse_arg_delimiter("\\t"), '\t');
+        assert_eq!(parse_arg_delimiter("\t"), '\t');
+        assert_eq!(parse_arg_delimiter("tab"), '\t');
+    }
+    #[test]
+    #[should_panic]
+    fn test_long_delimiter_fails() {
+        parse_arg_delimiter("||");
+    }
+    #[test]
+    fn test_parse_quote() {
+        assert_eq!(parse_arg_quote("`"), '`');
+    }
+    #[test]
+    #[should_panic]
+    fn test_empty_quote_fails() {
+        parse_arg_quote("");
+    }
+    #[test]
+    fn test_is_col_list() {
+        assert!(is_col_list("1-3, 5"));
+        assert!(!is_col_list("-"));
+        assert!(!is_col_list("data.csv"));
+        assert!(!is_col_list("2024-01.csv"));
+    }
+    #[test]
+    fn test_options_besides() {
+        let args: Vec<String> = ["--plan=p.json", "-1", "--where", "col1 > 2", "-n", "5", "-",
+                                 "--", "--header"].iter().map(|a| a.to_string()).collect();
+        assert_eq!(options_besides(&args, &["--plan"]), vec!["--where", "-n"]);
+    }
 
     #[test]
     #[should_panic]
@@ -126,3 +400,181 @@ mod test_parse_cols {
         parse_arg_cols(&String::from("a"), 0);
     }
 }
+
+#[cfg(test)]
+mod test_parse_col {
+    use super::*;
+
+    #[test]
+    fn test_parse_col() {
+        assert_eq!(parse_arg_col("3", 1), 2);
+        assert_eq!(parse_arg_col(" 3 ", 0), 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_range_fails() {
+        parse_arg_col("1-3", 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_offset_fails() {
+        parse_arg_col("0", 1);
+    }
+}
+
+#[cfg(test)]
+mod test_parse_key {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_arg_key("3", 1), vec![2]);
+        assert_eq!(parse_arg_key("1+2", 1), vec![0, 1]);
+        assert_eq!(parse_arg_key("col4 + col1", 0), vec![4, 1]);
+    }
+    #[test]
+    #[should_panic]
+    fn test_empty_part_fails() {
+        parse_arg_key("1++2", 1);
+    }
+}
+
+#[cfg(test)]
+mod test_parse_by {
+    use super::*;
+
+    #[test]
+    fn test_parse_by() {
+        assert_eq!(parse_arg_by("3", 1), (2, false));
+        assert_eq!(parse_arg_by("3:desc", 1), (2, true));
+        assert_eq!(parse_arg_by("3:asc", 0), (3, false));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_bad_order_fails() {
+        parse_arg_by("3:up", 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_offset_fails() {
+        parse_arg_by("0:desc", 1);
+    }
+}
+
+#[cfg(test)]
+mod test_parse_typed_col {
+    use super::*;
+
+    #[test]
+    fn test_parse_typed_col() {
+        assert_eq!(parse_arg_typed_col("2", 1), (1, KeyKind::Text));
+        assert_eq!(parse_arg_typed_col("2:num", 1), (1, KeyKind::Num));
+        assert_eq!(parse_arg_typed_col("2:date", 0), (2, KeyKind::Date));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_bad_type_fails() {
+        parse_arg_typed_col("2:bool", 1);
+    }
+}
+
+#[cfg(test)]
+mod test_parse_gaps {
+    use super::*;
+
+    #[test]
+    fn test_parse_gaps() {
+        assert_eq!(parse_arg_gaps("2", 1), (1, KeyKind::Num, 1.0));
+        assert_eq!(parse_arg_gaps("2:interval=5", 1), (1, KeyKind::Num, 5.0));
+        assert_eq!(parse_arg_gaps("2:interval=0.5", 0), (2, KeyKind::Num, 0.5));
+        assert_eq!(parse_arg_gaps("2:interval=1m", 1), (1, KeyKind::Date, 60.0));
+        assert_eq!(parse_arg_gaps("2:interval=2h", 1), (1, KeyKind::Date, 7200.0));
+        assert_eq!(parse_arg_gaps("2:interval=1h30m", 1), (1, KeyKind::Date, 5400.0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_bad_option_fails() {
+        parse_arg_gaps("2:step=1", 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_bad_unit_fails() {
+        parse_arg_gaps("2:interval=1y", 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_zero_interval_fails() {
+        parse_arg_gaps("2:interval=0", 1);
+    }
+}
+
+#[cfg(test)]
+mod test_parse_tz {
+    use super::*;
+
+    #[test]
+    fn test_parse_tz() {
+        assert_eq!(parse_arg_tz("3:from=UTC:to=America/New_York", 1),
+                   Transform::Tz { col: 2, from: Tz::UTC, to: Tz::America__New_York });
+        assert_eq!(parse_arg_tz("3:to=Europe/Paris", 0),
+                   Transform::Tz { col: 3, from: Tz::UTC, to: Tz::Europe__Paris });
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_unknown_zone_fails() {
+        parse_arg_tz("3:to=Mars/Olympus_Mons", 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_bad_option_fails() {
+        parse_arg_tz("3:into=UTC", 1);
+    }
+}
+
+#[cfg(test)]
+mod test_parse_size {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_arg_size("512M"), 512 << 20);
+        assert_eq!(parse_arg_size("1.5k"), 1536);
+        assert_eq!(parse_arg_size("2G"), 2 << 30);
+        assert_eq!(parse_arg_size("100"), 100);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_bad_unit_fails() {
+        parse_arg_size("5T");
+    }
+}
+
+#[cfg(test)]
+mod test_parse_range {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_arg_range("100-200"), (100, Some(200)));
+        assert_eq!(parse_arg_range("100-"), (100, None));
+        assert_eq!(parse_arg_range("-200"), (0, Some(200)));
+        assert_eq!(parse_arg_range(" 5 - 5 "), (5, Some(5)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_missing_dash_fails() {
+        parse_arg_range("100");
+    }
+    #[test]
+    #[should_panic]
+    fn test_backwards_fails() {
+        parse_arg_range("200-100");
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::dialect::Dialect;
+use crate::messages;
+use crate::pipeline::{Sink, WriterSink};
+
+/// Name of the file in the output directory recording what each output was produced from
+const MANIFEST: &str = ".ccut-manifest.json";
+/// Name of the manifest written for downstream consumers
+pub const PUBLIC_MANIFEST: &str = "manifest.json";
+
+/// What to do about output files that already exist
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Existing {
+    /// Refuse to touch them
+    #[default]
+    Fail,
+    /// Replace them
+    Overwrite,
+    /// Leave them alone if they're up to date, and replace them otherwise
+    Skip,
+}
+
+/// The size and modification time of an input, to tell whether outputs made from it are stale
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+    pub size: u64,
+    pub mtime_ns: u64,
+}
+
+impl Stamp {
+    /// The stamp of a regular file; pipes and terminals have none, so their outputs are never
+    /// considered up to date
+    pub fn of(path: &Path) -> Option<Stamp> {
+        let meta = fs::metadata(path).ok()?;
+        if !meta.is_file() {
+            return None;
+        }
+        let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Stamp { size: meta.len(), mtime_ns: mtime.as_nanos() as u64 })
+    }
+}
+
+/// One file of a partitioned output, as listed in `manifest.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputFile {
+    /// Name of the file within the output directory
+    pub file: String,
+    /// The value of the partition column its records share
+    pub partition: String,
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+/// `manifest.json`: every file of a partitioned output, with totals
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct PublicManifest {
+    pub files: Vec<OutputFile>,
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+/// An output file and the stamp of the input it was produced from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Produced {
+    #[serde(flatten)]
+    output: OutputFile,
+    input: Option<Stamp>,
+}
+
+/// What an output directory was last produced from
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Manifest {
+    /// Fingerprint of the configuration that produced the outputs
+    plan: String,
+    input: Option<Stamp>,
+    /// The output files, by name
+    files: BTreeMap<String, Produced>,
+}
+
+/// Writes each record to a file in `dir` named after its value in the partition column
+pub struct Partitions {
+    dir: PathBuf,
+    existing: Existing,
+    plan: String,
+    input: Option<Stamp>,
+    old: Manifest,
+    /// Also write `manifest.json`
+    manifest: bool,
+    delimiter: String,
+    terminator: String,
+    /// Whether the old manifest was removed, since outputs it describes are being rewritten
+    invalidated: bool,
+    /// Written at the top of every file
+    header: Option<Vec<String>>,
+    /// `None` for a partition that's up to date and being skipped
+    sinks: HashMap<String, Option<WriterSink<BufWriter<File>>>>,
+}
+
+impl Partitions {
+    /// `plan` fingerprints the configuration, so that a changed configuration makes every output
+    /// stale; `input` is the stamp of the input, if it has one
+    pub fn new(dir: &Path, existing: Existing, plan: &str, input: Option<Stamp>,
+               dialect: &dyn Dialect) -> io::Result<Partitions> {
+        fs::create_dir_all(dir)?;
+        let old = match File::open(dir.join(MANIFEST)) {
+            Ok(file) => serde_json::from_reader(io::BufReader::new(file)).unwrap_or_default(),
+            Err(_) => Manifest::default(),
+        };
+        Ok(Partitions {
+            dir: dir.to_path_buf(),
+            existing,
+            plan: plan.to_string(),
+            input,
+            old,
+            manifest: false,
+            delimiter: dialect.delimiter().to_string(),
+            terminator: dialect.terminator().to_string(),
+            invalidated: false,
+            header: None,
+            sinks: HashMap::new(),
+        })
+    }
+
+    /// Also writes a `manifest.json` listing every file with its partition value, row count and
+    /// size, for downstream jobs to pick the outputs up from
+    pub fn with_manifest(mut self) -> Partitions {
+        self.manifest = true;
+        self
+    }
+
+    /// Starts every file with this header row, which isn't counted as a record
+    pub fn set_header(&mut self, header: Vec<String>) {
+        self.header = Some(header);
+    }
+
+    /// Whether every output was already produced from this very input with this configuration,
+    /// so with `Existing::Skip` there's nothing to do
+    pub fn up_to_date(&self) -> bool {
+        self.existing == Existing::Skip && self.input.is_some() && self.old.input == self.input
+            && self.old.plan == self.plan
+            && self.old.files.keys().all(|name| self.dir.join(name).is_file())
+    }
+
+    /// The sink for records whose partition column is `key`, or `None` if that partition is up
+    /// to date and skipped
+    pub fn sink(&mut self, key: &str)
+                -> io::Result<Option<&mut WriterSink<BufWriter<File>>>> {
+        if !self.sinks.contains_key(key) {
+            let sink = self.open(&file_name(key))?;
+            self.sinks.insert(key.to_string(), sink);
+        }
+        Ok(self.sinks.get_mut(key).unwrap().as_mut())
+    }
+
+    fn open(&mut self, name: &str) -> io::Result<Option<WriterSink<BufWriter<File>>>> {
+        let path = self.dir.join(name);
+        let mut options = OpenOptions::new();
+        options.write(true);
+        match self.existing {
+            Existing::Fail => options.create_new(true),
+            Existing::Overwrite => options.create(true).truncate(true),
+            Existing::Skip => {
+                let fresh = self.input.is_some() && self.old.plan == self.plan
+                    && self.old.files.get(name).is_some_and(|f| f.input == self.input)
+                    && path.is_file();
+                if fresh {
+                    return Ok(None);
+                }
+                options.create(true).truncate(true)
+            },
+        };
+        let file = options.open(&path).map_err(|error| match error.kind() {
+            io::ErrorKind::AlreadyExists => messages::failure(
+                "write-output", error.kind(),
+                format!("{} already exists; pass --overwrite or --skip-existing",
+                        path.display())),
+            _ => messages::failure("write-output", error.kind(),
+                                   format!("{}: {}", path.display(), error)),
+        })?;
+        // If the run doesn't finish, the directory must not look up to date
+        if !self.invalidated {
+            match fs::remove_file(self.dir.join(MANIFEST)) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+                _ => self.invalidated = true,
+            }
+        }
+        let mut sink = WriterSink::with_separators(BufWriter::new(file), &self.delimiter,
+                                                   &self.terminator);
+        if let Some(header) = &self.header {
+            let fields: Vec<&str> = header.iter().map(|f| f.as_str()).collect();
+            sink.write_header(&fields)?;
+        }
+        Ok(Some(sink))
+    }
+
+    /// Number of records written, not counting skipped partitions
+    pub fn records(&self) -> u64 {
+        self.sinks.values().flatten().map(|s| s.records()).sum()
+    }
+
+    /// Flushes every output and records what they were produced from
+    pub fn finish(&mut self) -> io::Result<()> {
+        let mut files = BTreeMap::new();
+        for (key, sink) in self.sinks.iter_mut() {
+            let name = file_name(key);
+            let produced = match sink {
+                Some(sink) => {
+                    sink.finish()?;
+                    let output = OutputFile {
+                        file: name.clone(),
+                        partition: key.clone(),
+                        rows: sink.records(),
+                        bytes: sink.get_mut().get_ref().metadata()?.len(),
+                    };
+                    Produced { output, input: self.input }
+                },
+                // Skipped because it's up to date, so it's as the last run left it
+                None => self.old.files[&name].clone(),
+            };
+            files.insert(name, produced);
+        }
+        if self.manifest {
+            let files: Vec<OutputFile> = files.values().map(|f| f.output.clone()).collect();
+            let manifest = PublicManifest {
+                rows: files.iter().map(|f| f.rows).sum(),
+                bytes: files.iter().map(|f| f.bytes).sum(),
+                files,
+            };
+            let file = File::create(self.dir.join(PUBLIC_MANIFEST))?;
+            serde_json::to_writer_pretty(file, &manifest)?;
+        }
+        let manifest = Manifest { plan: self.plan.clone(), input: self.input, files };
+        let file = File::create(self.dir.join(MANIFEST))?;
+        serde_json::to_writer_pretty(file, &manifest)?;
+        Ok(())
+    }
+}
+
+/// The output file for a partition value. Characters that aren't safe in a file name are
+/// percent-encoded, as is a leading dot, so different values never share a file and no value can
+/// name a hidden file or a path outside the directory.
+pub fn file_name(key: &str) -> String {
+    if key.is_empty() {
+        // Can't come out of the encoding, which follows every `%` with two hex digits
+        return String::from("%empty.csv");
+    }
+    let mut name = String::with_capacity(key.len() + 4);
+    for (i, b) in key.bytes().enumerate() {
+        if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || (b == b'.' && i > 0) {
+            name.push(b as char);
+        } else {
+            name.push_str(&format!("%{:02X}", b));
+        }
+    }
+    name.push_str(".csv");
+    name
+}
+
+#[cfg(test)]
+mod test_partitions {
+    use super::*;
+    use crate::dialect::Permissive;
+
+    const STAMP: Stamp = Stamp { size: 10, mtime_ns: 1 };
+
+    fn write(dir: &Path, existing: Existing, input: Option<Stamp>, rows: &[(&str, &str)])
+             -> io::Result<Partitions> {
+        let mut parts = Partitions::new(dir, existing, "plan", input, &Permissive)?;
+        for (key, value) in rows.iter() {
+            if let Some(sink) = parts.sink(key)? {
+                sink.write_record(&[value])?;
+            }
+        }
+        parts.finish()?;
+        Ok(parts)
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("us-east_1.a"), "us-east_1.a.csv");
+        assert_eq!(file_name("a/b"), "a%2Fb.csv");
+        assert_eq!(file_name(".."), "%2E..csv");
+        assert_eq!(file_name(""), "%empty.csv");
+        assert_ne!(file_name("a b"), file_name("a_b"));
+    }
+    #[test]
+    fn test_partition() {
+        let dir = tempfile::tempdir().unwrap();
+        let parts = write(dir.path(), Existing::Fail, None, &[("a", "1"), ("b", "2"), ("a", "3")])
+            .unwrap();
+        assert_eq!(parts.records(), 3);
+        assert_eq!(fs::read_to_string(dir.path().join("a.csv")).unwrap(), "1\n3\n");
+        assert_eq!(fs::read_to_string(dir.path().join("b.csv")).unwrap(), "2\n");
+    }
+    #[test]
+    fn test_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut parts = Partitions::new(dir.path(), Existing::Fail, "plan", None, &Permissive)
+            .unwrap();
+        parts.set_header(vec![String::from("value")]);
+        for (key, value) in [("a", "1"), ("b", "2"), ("a", "3")].iter() {
+            parts.sink(key).unwrap().unwrap().write_record(&[value]).unwrap();
+        }
+        parts.finish().unwrap();
+        assert_eq!(parts.records(), 3);
+        assert_eq!(fs::read_to_string(dir.path().join("a.csv")).unwrap(), "value\n1\n3\n");
+        assert_eq!(fs::read_to_string(dir.path().join("b.csv")).unwrap(), "value\n2\n");
+    }
+    #[test]
+    fn test_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut parts = Partitions::new(dir.path(), Existing::Skip, "plan", Some(STAMP),
+                                        &Permissive).unwrap().with_manifest();
+        for (key, value) in [("b", "22"), ("a", "1"), ("b", "3")].iter() {
+            parts.sink(key).unwrap().unwrap().write_record(&[value]).unwrap();
+        }
+        parts.finish().unwrap();
+        let read = || -> PublicManifest {
+            let file = File::open(dir.path().join(PUBLIC_MANIFEST)).unwrap();
+            serde_json::from_reader(file).unwrap()
+        };
+        let expected = PublicManifest {
+            files: vec![
+                OutputFile { file: "a.csv".into(), partition: "a".into(), rows: 1, bytes: 2 },
+                OutputFile { file: "b.csv".into(), partition: "b".into(), rows: 2, bytes: 5 },
+            ],
+            rows: 3,
+            bytes: 7,
+        };
+        assert_eq!(read(), expected);
+        // Skipped partitions are still listed
+        let mut parts = Partitions::new(dir.path(), Existing::Skip, "plan", Some(STAMP),
+                                        &Permissive).unwrap().with_manifest();
+        assert!(parts.sink("a").unwrap().is_none());
+        assert!(parts.sink("b").unwrap().is_none());
+        parts.finish().unwrap();
+        assert_eq!(read(), expected);
+    }
+    #[test]
+    fn test_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), Existing::Fail, Some(STAMP), &[("a", "1")]).unwrap();
+        let error = write(dir.path(), Existing::Fail, Some(STAMP), &[("a", "2")]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+
+        write(dir.path(), Existing::Overwrite, Some(STAMP), &[("a", "2")]).unwrap();
+        assert_eq!(fs::read_to_string(dir.path().join("a.csv")).unwrap(), "2\n");
+    }
+    #[test]
+    fn test_skip() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), Existing::Skip, Some(STAMP), &[("a", "1")]).unwrap();
+        let parts = Partitions::new(dir.path(), Existing::Skip, "plan", Some(STAMP), &Permissive)
+            .unwrap();
+        assert!(parts.up_to_date());
+        // A changed input or configuration makes the outputs stale
+        let changed = Stamp { size: 11, ..STAMP };
+        let parts = write(dir.path(), Existing::Skip, Some(changed), &[("a", "2"), ("b", "3")])
+            .unwrap();
+        assert!(!parts.up_to_date());
+        assert_eq!(fs::read_to_string(dir.path().join("a.csv")).unwrap(), "2\n");
+        let parts = Partitions::new(dir.path(), Existing::Skip, "other", Some(changed),
+                                    &Permissive).unwrap();
+        assert!(!parts.up_to_date());
+        // Without a stamp, as for a pipe, nothing is ever up to date
+        let parts = write(dir.path(), Existing::Skip, None, &[("a", "4")]).unwrap();
+        assert!(!parts.up_to_date());
+        assert_eq!(fs::read_to_string(dir.path().join("a.csv")).unwrap(), "4\n");
+    }
+    #[test]
+    fn test_skip_fresh_partition() {
+        let dir = tempfile::tempdir().unwrap();
+        let rows = [("a", "1"), ("b", "2")];
+        write(dir.path(), Existing::Skip, Some(STAMP), &rows).unwrap();
+        // Same input, but a partition is missing from the directory: only it is rewritten
+        fs::remove_file(dir.path().join("a.csv")).unwrap();
+        let parts = write(dir.path(), Existing::Skip, Some(STAMP), &rows).unwrap();
+        assert_eq!(parts.records(), 1);
+        let parts = write(dir.path(), Existing::Skip, Some(STAMP), &rows).unwrap();
+        assert_eq!(parts.records(), 0);
+        assert!(parts.up_to_date());
+    }
+    #[test]
+    fn test_unfinished_is_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), Existing::Skip, Some(STAMP), &[("a", "1")]).unwrap();
+        fs::remove_file(dir.path().join("a.csv")).unwrap();
+        let mut parts = Partitions::new(dir.path(), Existing::Skip, "plan", Some(STAMP),
+                                        &Permissive).unwrap();
+        parts.sink("a").unwrap();
+        drop(parts);
+        let parts = Partitions::new(dir.path(), Existing::Skip, "plan", Some(STAMP), &Permissive)
+            .unwrap();
+        assert!(!parts.up_to_date());
+    }
+}
@@ -0,0 +1,911 @@
+use std::borrow::Cow;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::decimal::DecimalSpec;
+use crate::dialect::{self, Dialect, Permissive, Requote};
+use crate::expr::{self, Expr, Totals, Value};
+use crate::fpe::Fpe;
+use crate::grep::ColumnGrep;
+use crate::interval::RangeJoin;
+use crate::line;
+use crate::membership::Membership;
+use crate::messages;
+use crate::precision::{self, Precision};
+use crate::pseudonym::Pseudonymizer;
+use crate::redact::Redactor;
+use crate::table::Table;
+use crate::transform::{self, Transform};
+
+/// Receives the records a pipeline produces. Fields arrive as they should be written out, already
+/// quoted for the pipeline's dialect where necessary.
+pub trait Sink {
+    fn write_record(&mut self, fields: &[&str]) -> io::Result<()>;
+
+    /// Writes the header row, ahead of any record
+    fn write_header(&mut self, fields: &[&str]) -> io::Result<()> {
+        self.write_record(fields)
+    }
+
+    /// Called once after the last record
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether writing a run of fields as one, the text they were read from with the `delimiter`
+    /// between them, gives the same output as writing them one by one
+    fn takes_spans(&self, _delimiter: char) -> bool {
+        false
+    }
+}
+
+/// Collects records in memory
+impl Sink for Vec<Vec<String>> {
+    fn write_record(&mut self, fields: &[&str]) -> io::Result<()> {
+        self.push(fields.iter().map(|f| f.to_string()).collect());
+        Ok(())
+    }
+}
+
+/// Writes records as delimited text, e.g. to stdout
+pub struct WriterSink<W: Write> {
+    writer: W,
+    delimiter: String,
+    terminator: String,
+    records: u64,
+    layout: Layout,
+    /// Checks that each record reads back as written in this dialect
+    verify: Option<Box<dyn Dialect>>,
+}
+
+/// How a `WriterSink` lays records out
+enum Layout {
+    Delimited,
+    /// Lined up into columns
+    Table(Table),
+    /// One JSON object per record, keyed by the names in the header, with the fields unquoted
+    /// as `dialect` quoted them
+    Json { dialect: Box<dyn Dialect>, keys: Vec<String> },
+    /// A GitHub-flavored Markdown table, with the fields unquoted as `dialect` quoted them.
+    /// `started` once its header row is written.
+    Markdown { dialect: Box<dyn Dialect>, started: bool },
+}
+
+impl<W: Write> WriterSink<W> {
+    /// Writes with the delimiter and record terminator of `dialect`
+    pub fn new(writer: W, dialect: &dyn Dialect) -> WriterSink<W> {
+        WriterSink::with_separators(writer, &dialect.delimiter().to_string(), dialect.terminator())
+    }
+
+    /// Writes with the given field delimiter and record terminator
+    pub fn with_separators(writer: W, delimiter: &str, terminator: &str) -> WriterSink<W> {
+        WriterSink {
+            writer,
+            delimiter: delimiter.to_string(),
+            terminator: terminator.to_string(),
+            records: 0,
+            layout: Layout::Delimited,
+            verify: None,
+        }
+    }
+
+    /// Fails on any record that wouldn't read back as the same fields in `dialect`, before
+    /// writing it, rather than corrupting the output silently. Only delimited records are checked.
+    pub fn verify_roundtrip(mut self, dialect: Box<dyn Dialect>) -> WriterSink<W> {
+        self.verify = Some(dialect);
+        self
+    }
+
+    /// Writes the records as a table for reading instead, their fields unquoted and padded into
+    /// columns two spaces apart, with widths worked out from the first `sample` records
+    pub fn table(mut self, sample: usize) -> WriterSink<W> {
+        self.delimiter = String::from("  ");
+        self.layout = Layout::Table(Table::new(sample));
+        self
+    }
+
+    /// Writes each record as a JSON object on a line of its own instead, its fields unquoted as
+    /// written by `dialect`. The keys are the names in the header, if one is written first, and
+    /// `col_N` for the Nth field otherwise.
+    pub fn json(mut self, dialect: Box<dyn Dialect>) -> WriterSink<W> {
+        self.terminator = String::from("\n");
+        self.layout = Layout::Json { dialect, keys: Vec::new() };
+        self
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Number of records written so far
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    fn write_fields(&mut self, fields: &[&str]) -> io::Result<()> {
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                self.writer.write_all(self.delimiter.as_bytes())?;
+            }
+            self.writer.write_all(field.as_bytes())?;
+        }
+        self.writer.write_all(self.terminator.as_bytes())
+    }
+
+    /// Writes the records as a GitHub-flavored Markdown table instead, its fields unquoted as
+    /// written by `dialect`. The header row holds the names in the header, if one is written
+    /// first, and `col_N` for the Nth field otherwise.
+    pub fn markdown(mut self, dialect: Box<dyn Dialect>) -> WriterSink<W> {
+        self.terminator = String::from("\n");
+        self.layout = Layout::Markdown { dialect, started: false };
+        self
+    }
+
+    /// Writes a row of a Markdown table, with pipes and line breaks in the fields escaped, and
+    /// the separator row under it if it's the header
+    fn write_markdown(&mut self, fields: &[&str], header: bool) -> io::Result<()> {
+        let dialect = match &self.layout {
+            Layout::Markdown { dialect, .. } => dialect,
+            _ => unreachable!("only called for Markdown"),
+        };
+        let cells: Vec<String> = fields.iter()
+            .map(|f| {
+                let value = dialect.unquote(if dialect.trim() { f.trim() } else { f });
+                value.replace('|', "\\|").replace("\r\n", "<br>").replace('\n', "<br>")
+            })
+            .collect();
+        let mut res = format!("| {} |{}", cells.join(" | "), self.terminator);
+        if header {
+            res.push_str(&format!("|{}{}", " --- |".repeat(cells.len()), self.terminator));
+        }
+        self.writer.write_all(res.as_bytes())
+    }
+
+    fn write_json(&mut self, fields: &[&str]) -> io::Result<()> {
+        let (dialect, keys) = match &self.layout {
+            Layout::Json { dialect, keys } => (dialect, keys),
+            _ => unreachable!("only called for JSON"),
+        };
+        let mut res = String::from("{");
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                res.push(',');
+            }
+            let key = keys.get(i).cloned().unwrap_or_else(|| format!("col_{}", i + 1));
+            let field = if dialect.trim() { field.trim() } else { field };
+            let value = dialect.unquote(field);
+            res.push_str(&serde_json::to_string(&key)?);
+            res.push(':');
+            res.push_str(&serde_json::to_string(&value)?);
+        }
+        res.push('}');
+        res.push_str(&self.terminator);
+        self.writer.write_all(res.as_bytes())
+    }
+
+    fn write_rows(&mut self, rows: Vec<Vec<String>>) -> io::Result<()> {
+        for row in rows.iter() {
+            let fields: Vec<&str> = row.iter().map(|f| f.as_str()).collect();
+            self.write_fields(&fields)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Sink for WriterSink<W> {
+    fn write_record(&mut self, fields: &[&str]) -> io::Result<()> {
+        self.records += 1;
+        let rows = match &mut self.layout {
+            Layout::Delimited => {
+                if let Some(dialect) = &self.verify {
+                    if let Err((i, read)) = dialect::check_roundtrip(fields, dialect.as_ref()) {
+                        return Err(messages::failure("write-output",
+                                                     io::ErrorKind::InvalidData,
+                                                     format!("record {} doesn't read back \
+                                                              as written: field {} {:?} reads \
+                                                              as {:?}",
+                                                             self.records, i + 1,
+                                                             fields.get(i).unwrap_or(&""),
+                                                             read)));
+                    }
+                }
+                return self.write_fields(fields);
+            },
+            Layout::Table(table) => table.push(fields),
+            Layout::Json { .. } => return self.write_json(fields),
+            Layout::Markdown { started, .. } => {
+                if !*started {
+                    *started = true;
+                    let names: Vec<String> = (1..=fields.len())
+                        .map(|i| format!("col_{}", i))
+                        .collect();
+                    let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+                    self.write_markdown(&names, true)?;
+                }
+                return self.write_markdown(fields, false);
+            },
+        };
+        self.write_rows(rows)
+    }
+
+    /// Like a record, but not counted in `records`. For JSON it only names the keys.
+    fn write_header(&mut self, fields: &[&str]) -> io::Result<()> {
+        if let Layout::Json { dialect, keys } = &mut self.layout {
+            *keys = fields.iter()
+                .map(|f| dialect.unquote(if dialect.trim() { f.trim() } else { f }).into_owned())
+                .collect();
+            return Ok(());
+        }
+        if let Layout::Markdown { started, .. } = &mut self.layout {
+            *started = true;
+            return self.write_markdown(fields, true);
+        }
+        self.write_record(fields)?;
+        self.records -= 1;
+        Ok(())
+    }
+
+    fn takes_spans(&self, delimiter: char) -> bool {
+        matches!(self.layout, Layout::Delimited) && self.verify.is_none()
+            && self.delimiter.chars().eq([delimiter])
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Layout::Table(table) = &mut self.layout {
+            let rows = table.finish();
+            self.write_rows(rows)?;
+        }
+        self.writer.flush()
+    }
+}
+
+/// The stages every record goes through, in order: split by the dialect, kept or dropped by the
+/// filters, rewritten by the transforms, then cut down to the selected columns with the computed
+/// columns and any range join's label appended. This is what the command line runs; the builder
+/// methods let applications assemble the same thing.
+#[derive(Debug)]
+pub struct Pipeline {
+    dialect: Box<dyn Dialect>,
+    /// Selected columns, or `None` to keep every field
+    cols: Option<Vec<usize>>,
+    /// Keep every field except the selected columns instead
+    complement: bool,
+    transforms: Vec<Transform>,
+    filters: Vec<Expr>,
+    /// Filters on whether a column's value is listed in a file
+    memberships: Vec<Membership>,
+    greps: Vec<ColumnGrep>,
+    exprs: Vec<Expr>,
+    /// Header names of the computed columns
+    names: Vec<String>,
+    totals: Totals,
+    /// Encrypts columns as they're cut, after the computed columns have seen them
+    fpes: Vec<Fpe>,
+    /// Replaces columns with pseudonyms as they're cut, like `fpes`
+    pseudonyms: Option<Pseudonymizer>,
+    /// Masks secrets in the output fields
+    redactor: Option<Redactor>,
+    /// Quotes the output fields afresh, before they're redacted
+    requote: Option<Requote>,
+    /// Decimals computed numbers are rounded to
+    precision: Option<Precision>,
+    /// Evaluates arithmetic computed columns in fixed-point decimals
+    decimal: Option<DecimalSpec>,
+    /// Looks up a label for each record, appended after the computed columns
+    range_join: Option<RangeJoin>,
+    /// Most fields written for a record before its range join values, which is how many fill
+    /// values the ranges no record matched get
+    record_width: AtomicUsize,
+}
+
+impl Default for Pipeline {
+    fn default() -> Pipeline {
+        Pipeline {
+            dialect: Box::new(Permissive),
+            cols: None,
+            complement: false,
+            transforms: Vec::new(),
+            filters: Vec::new(),
+            memberships: Vec::new(),
+            greps: Vec::new(),
+            exprs: Vec::new(),
+            names: Vec::new(),
+            totals: Totals::new(),
+            fpes: Vec::new(),
+            pseudonyms: None,
+            redactor: None,
+            requote: None,
+            precision: None,
+            decimal: None,
+            range_join: None,
+            record_width: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    pub fn dialect(mut self, dialect: Box<dyn Dialect>) -> Pipeline {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Keeps only these (zero-indexed) columns, in this order
+    pub fn select(mut self, cols: Vec<usize>) -> Pipeline {
+        self.cols = Some(cols);
+        self
+    }
+
+    /// Keeps every column except the selected ones, in their original order
+    pub fn complement(mut self) -> Pipeline {
+        self.complement = true;
+        self
+    }
+
+    pub fn transform(mut self, transform: Transform) -> Pipeline {
+        self.transforms.push(transform);
+        self
+    }
+
+    /// Only keeps records for which `filter` is true. Filters see the fields as read, before any
+    /// transforms.
+    pub fn filter(mut self, filter: Expr) -> Pipeline {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Only keeps records that pass `membership`, which sees the fields as read like `filter`
+    pub fn membership(mut self, membership: Membership) -> Pipeline {
+        self.memberships.push(membership);
+        self
+    }
+
+    /// Only keeps records whose value in a column `grep` keeps, as read like `filter`
+    pub fn grep(mut self, grep: ColumnGrep) -> Pipeline {
+        self.greps.push(grep);
+        self
+    }
+
+    /// Appends a computed column
+    pub fn compute(self, expr: Expr) -> Pipeline {
+        self.compute_named(expr, "")
+    }
+
+    /// Appends a computed column, with the name it gets in the header
+    pub fn compute_named(mut self, expr: Expr, name: &str) -> Pipeline {
+        self.exprs.push(expr);
+        self.names.push(name.to_string());
+        self
+    }
+
+    /// Encrypts a column of the output, keeping its format. Filters, computed columns and range
+    /// joins see the value as it was.
+    pub fn encrypt(mut self, fpe: Fpe) -> Pipeline {
+        self.fpes.push(fpe);
+        self
+    }
+
+    /// Replaces columns of the output with pseudonyms. Like `encrypt`, this leaves the values
+    /// other stages see alone.
+    pub fn pseudonymize(mut self, pseudonyms: Pseudonymizer) -> Pipeline {
+        self.pseudonyms = Some(pseudonyms);
+        self
+    }
+
+    /// Masks whatever `redactor` matches in the output, as the very last stage
+    pub fn redact(mut self, redactor: Redactor) -> Pipeline {
+        self.redactor = Some(redactor);
+        self
+    }
+
+    /// Quotes every field of the output, and of the header, as `style` says instead of leaving
+    /// them as they were read
+    pub fn requote(mut self, style: Requote) -> Pipeline {
+        self.requote = Some(style);
+        self
+    }
+
+    /// Rounds the numbers in computed columns to a fixed number of decimals
+    pub fn precision(mut self, precision: Precision) -> Pipeline {
+        self.precision = Some(precision);
+        self
+    }
+
+    /// Computes arithmetic-only columns exactly in decimals rather than in floating point
+    pub fn decimal(mut self, spec: DecimalSpec) -> Pipeline {
+        self.decimal = Some(spec);
+        self
+    }
+
+    /// Appends the label of the range each record falls in, or an empty field if none
+    pub fn range_join(mut self, join: RangeJoin) -> Pipeline {
+        self.range_join = Some(join);
+        self
+    }
+
+    pub fn get_dialect(&self) -> &dyn Dialect {
+        self.dialect.as_ref()
+    }
+
+    pub fn exprs(&self) -> &[Expr] {
+        &self.exprs
+    }
+
+    pub fn filters(&self) -> &[Expr] {
+        &self.filters
+    }
+
+    /// Splits a line into raw fields
+    pub fn split<'a>(&self, line: &'a str) -> Vec<&'a str> {
+        line::split_line_with(line, self.dialect.as_ref())
+    }
+
+    /// Whether the computed columns need totals over the whole input, or a lookup was spilled to
+    /// disk, in which case `gather_totals` must see the input before any record is written
+    pub fn needs_totals(&self) -> bool {
+        self.exprs.iter().any(|e| !e.total_cols().is_empty()) || self.has_spilled_lookups()
+    }
+
+    /// Whether a lookup was too large for memory, so records have to be checked with `keep_row`
+    pub fn has_spilled_lookups(&self) -> bool {
+        self.memberships.iter().any(|m| m.is_spilled())
+    }
+
+    /// First pass over the input lines for computed columns that need totals, and for lookups
+    /// spilled to disk
+    pub fn gather_totals<I>(&mut self, lines: I) -> io::Result<()>
+        where I: IntoIterator<Item = io::Result<String>> {
+        let mut total_cols: Vec<usize> = self.exprs.iter().flat_map(|e| e.total_cols()).collect();
+        total_cols.sort_unstable();
+        total_cols.dedup();
+
+        for (row, line) in lines.into_iter().enumerate() {
+            let line = line?;
+            let fields = self.split(&line);
+            for i in total_cols.iter() {
+                if let Some(x) = fields.get(*i).and_then(|f| expr::parse_num(f)) {
+                    self.totals.entry(*i).or_default().push(x);
+                }
+            }
+            for m in self.memberships.iter_mut() {
+                m.gather(row as u64, &fields)?;
+            }
+        }
+        for m in self.memberships.iter_mut() {
+            m.finish_gather()?;
+        }
+        Ok(())
+    }
+
+    /// Whether a line passes the filters. Spilled lookups need `keep_row` instead.
+    pub fn keep(&self, line: &str) -> bool {
+        self.passes(None, line)
+    }
+
+    /// Whether the `row`th of the lines `gather_totals` saw passes the filters
+    pub fn keep_row(&self, row: u64, line: &str) -> bool {
+        self.passes(Some(row), line)
+    }
+
+    fn passes(&self, row: Option<u64>, line: &str) -> bool {
+        let inner = self.range_join.as_ref().filter(|j| !j.join_type().keeps_records());
+        if self.filters.is_empty() && self.memberships.is_empty() && self.greps.is_empty()
+            && inner.is_none() {
+            return true;
+        }
+        let fields = self.split(line);
+        self.greps.iter().all(|g| g.keep(&fields, self.dialect.as_ref()))
+            && self.memberships.iter().all(|m| m.keep(row, &fields))
+            && self.filters.iter().all(|f| expr::is_true(&f.eval(&fields, &self.totals)))
+            && inner.is_none_or(|j| j.lookup(&fields).is_some())
+    }
+
+    /// Transforms and cuts one line, and appends any computed columns, without filtering it
+    pub fn cut(&self, line: &str, sink: &mut dyn Sink) -> io::Result<()> {
+        if self.exprs.is_empty() && self.transforms.is_empty() && self.range_join.is_none()
+            && self.fpes.is_empty() && self.pseudonyms.is_none() {
+            if self.passes_through() && sink.takes_spans(self.dialect.delimiter()) {
+                return self.write_spans(line, sink);
+            }
+            return self.write(&self.pick(&self.split(line)), sink);
+        }
+        let fields = self.split(line);
+        let transformed = transform::apply_all(&self.transforms, &fields);
+        let fields: Vec<&str> = transformed.iter().map(|f| f.as_ref()).collect();
+        let computed: Vec<String> = self.exprs.iter()
+            .map(|e| {
+                let value = match &self.decimal {
+                    Some(spec) if e.is_arithmetic() => e.eval_decimal(&fields, spec)
+                        .map(|d| precision::format_dec(d, self.precision.as_ref()))
+                        .unwrap_or_default(),
+                    _ => match e.eval(&fields, &self.totals) {
+                        Value::Num(x) => precision::format_num(x, self.precision.as_ref()),
+                        value => value.to_string(),
+                    },
+                };
+                self.dialect.quote(&value).into_owned()
+            })
+            .collect();
+        let mut encrypted: Vec<Cow<str>> = Vec::new();
+        let fields = if self.fpes.is_empty() && self.pseudonyms.is_none() {
+            fields
+        } else {
+            encrypted.extend(fields.iter().map(|f| Cow::Borrowed(*f)));
+            for fpe in self.fpes.iter() {
+                if let Some(field) = encrypted.get_mut(fpe.col()) {
+                    let value = fpe.encrypt(field)
+                        .map_err(|msg| io::Error::new(io::ErrorKind::InvalidData, msg))?;
+                    *field = Cow::Owned(value);
+                }
+            }
+            if let Some(p) = &self.pseudonyms {
+                for col in p.cols() {
+                    if let Some(field) = encrypted.get_mut(*col) {
+                        *field = Cow::Owned(p.pseudonym(field));
+                    }
+                }
+            }
+            encrypted.iter().map(|f| f.as_ref()).collect()
+        };
+        let mut res = self.pick(&fields);
+        res.extend(computed.iter().map(|s| s.as_str()));
+        let joined: Vec<String> = match &self.range_join {
+            Some(join) => {
+                self.record_width.fetch_max(res.len(), Ordering::Relaxed);
+                join.join(&fields).iter().map(|v| self.dialect.quote(v).into_owned()).collect()
+            },
+            None => Vec::new(),
+        };
+        res.extend(joined.iter().map(|s| s.as_str()));
+        self.write(&res, sink)
+    }
+
+    /// The header row that goes with the records `cut` writes, from the input's header line: its
+    /// names of the selected columns, then those of the computed columns and of the range join's
+    pub fn header(&self, line: &str) -> Vec<String> {
+        let mut res: Vec<String> = self.pick(&self.split(line)).iter()
+            .map(|f| f.to_string())
+            .collect();
+        let joined = self.range_join.iter().flat_map(|j| j.names());
+        res.extend(self.names.iter().chain(joined).map(|n| self.dialect.quote(n).into_owned()));
+        match self.requote {
+            Some(style) => res.iter()
+                .map(|f| dialect::requote_field(f, &*self.dialect, style).into_owned())
+                .collect(),
+            None => res,
+        }
+    }
+
+    /// Writes the ranges no record fell in, for a right or full range join, with the fill value
+    /// for the record's columns. Called once every record has been cut.
+    pub fn finish_join(&self, sink: &mut dyn Sink) -> io::Result<()> {
+        let join = match &self.range_join {
+            Some(join) => join,
+            None => return Ok(()),
+        };
+        let width = match self.record_width.load(Ordering::Relaxed) {
+            0 => match &self.cols {
+                Some(cols) if !self.complement
+                    && cols.iter().all(|c| line::open_range(*c).is_none()) => {
+                    cols.len() + self.exprs.len()
+                },
+                _ => self.exprs.len(),
+            },
+            n => n,
+        };
+        let fill = self.dialect.quote(join.fill()).into_owned();
+        for values in join.unmatched() {
+            let quoted: Vec<Cow<str>> = values.iter().map(|v| self.dialect.quote(v)).collect();
+            let mut res: Vec<&str> = vec![fill.as_str(); width];
+            res.extend(quoted.iter().map(|v| v.as_ref()));
+            self.write(&res, sink)?;
+        }
+        Ok(())
+    }
+
+    /// The fields of a record that are output: the selected columns, or all but them
+    fn pick<'a>(&self, fields: &[&'a str]) -> Vec<&'a str> {
+        match &self.cols {
+            Some(cols) if self.complement => line::complement_fields(fields, cols),
+            Some(cols) => line::cut_fields(fields, cols),
+            None => fields.to_vec(),
+        }
+    }
+
+    /// Whether the selection keeps most fields as they are, being a complement or having an open
+    /// range, and they're written as read
+    fn passes_through(&self) -> bool {
+        let cols = match &self.cols {
+            Some(cols) => cols,
+            None => return false,
+        };
+        (self.complement || cols.iter().any(|c| line::open_range(*c).is_some()))
+            && self.requote.is_none() && self.redactor.is_none()
+    }
+
+    /// Writes the fields `pick` would, with each run of adjacent ones copied from `line` as a
+    /// single span rather than split out field by field
+    fn write_spans(&self, line: &str, sink: &mut dyn Sink) -> io::Result<()> {
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        self.dialect.field_ranges(line, &mut |start, end| ranges.push((start, end)));
+        let cols = self.cols.as_deref().unwrap_or_default();
+        let picked = line::picked_indices(cols, self.complement, ranges.len());
+        let spans: Vec<&str> = line::pass_through_spans(&ranges, &picked).iter()
+            .map(|(start, end)| &line[*start..*end])
+            .collect();
+        sink.write_record(&spans)
+    }
+
+    fn write(&self, fields: &[&str], sink: &mut dyn Sink) -> io::Result<()> {
+        let requoted: Vec<Cow<str>>;
+        let fields = match self.requote {
+            Some(style) => {
+                requoted = fields.iter()
+                    .map(|f| dialect::requote_field(f, &*self.dialect, style))
+                    .collect();
+                requoted.iter().map(|f| f.as_ref()).collect()
+            },
+            None => fields.to_vec(),
+        };
+        let fields = &fields[..];
+        match &self.redactor {
+            Some(r) => {
+                let redacted: Vec<Cow<str>> = fields.iter().map(|f| r.redact(f)).collect();
+                let fields: Vec<&str> = redacted.iter().map(|f| f.as_ref()).collect();
+                sink.write_record(&fields)
+            },
+            None => sink.write_record(fields),
+        }
+    }
+
+    /// Sends every line of `reader` that passes the filters through to `sink`. Totals must
+    /// already have been gathered if they're needed.
+    pub fn run<R: BufRead>(&self, reader: R, sink: &mut dyn Sink) -> io::Result<()> {
+        self.run_lines(reader.lines(), sink)
+    }
+
+    /// Like `run`, for lines that have already been read
+    pub fn run_lines<I>(&self, lines: I, sink: &mut dyn Sink) -> io::Result<()>
+        where I: IntoIterator<Item = io::Result<String>> {
+        self.run_part(lines, sink)?;
+        self.finish_join(sink)?;
+        sink.finish()
+    }
+
+    /// Sends the lines of one of several parts of the input through to `sink` as `run_lines`
+    /// does, leaving the ranges no record fell in for `finish_join` once every part is done, and
+    /// `sink` unfinished
+    pub fn run_part<I>(&self, lines: I, sink: &mut dyn Sink) -> io::Result<()>
+        where I: IntoIterator<Item = io::Result<String>> {
+        for line in lines {
+            let line = line?;
+            if self.keep(&line) {
+                self.cut(&line, sink)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_pipeline {
+    use super::*;
+    use crate::dialect::Tsv;
+    use crate::expr::parse_expr;
+    use crate::grep::parse_grep;
+    use crate::interval::{parse_range_join, JoinType, RangeJoinSpec};
+    use crate::membership::parse_membership;
+
+    #[test]
+    fn test_select() {
+        let pipeline = Pipeline::new().select(vec![2, 0]);
+        let mut out: Vec<Vec<String>> = Vec::new();
+        pipeline.run("a,b,c\nd,e\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![vec!["c", "a"], vec!["", "d"]]);
+    }
+    #[test]
+    fn test_complement() {
+        let pipeline = Pipeline::new().select(vec![2, 0]).complement();
+        let mut out: Vec<Vec<String>> = Vec::new();
+        pipeline.run("a,b,c,d\nd,e\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![vec!["b", "d"], vec!["e"]]);
+    }
+    #[test]
+    fn test_filter_compute() {
+        let pipeline = Pipeline::new()
+            .select(vec![0])
+            .filter(parse_expr("col2 > 1", 1))
+            .compute(parse_expr("col2 * 10", 1));
+        let mut out: Vec<Vec<String>> = Vec::new();
+        pipeline.run("a,1\nb,2\nc,3\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![vec!["b", "20"], vec!["c", "30"]]);
+    }
+    #[test]
+    fn test_grep() {
+        let pipeline = Pipeline::new()
+            .select(vec![0])
+            .grep(ColumnGrep::new(&parse_grep("2:^x", true, 1), false).unwrap())
+            .grep(ColumnGrep::new(&parse_grep("2:z$", false, 1), false).unwrap());
+        let mut out: Vec<Vec<String>> = Vec::new();
+        pipeline.run("a,xy\nb,\"x,z\"\nc,y\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![vec!["a"]]);
+    }
+    #[test]
+    fn test_decimal() {
+        let pipeline = Pipeline::new()
+            .select(vec![])
+            .compute(parse_expr("col1 + col2", 1))
+            .compute(parse_expr("col1 > 0", 1))
+            .decimal(DecimalSpec::default());
+        let mut out: Vec<Vec<String>> = Vec::new();
+        pipeline.run("0.1,0.2\n1.10,x\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![vec!["0.3", "true"], vec!["", "true"]]);
+    }
+    #[test]
+    fn test_totals() {
+        let mut pipeline = Pipeline::new().compute(parse_expr("pct_of_total(col1)", 1));
+        assert!(pipeline.needs_totals());
+        let input = "1\n3\n";
+        pipeline.gather_totals(input.as_bytes().lines()).unwrap();
+        let mut out: Vec<Vec<String>> = Vec::new();
+        pipeline.run(input.as_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![vec!["1", "25"], vec!["3", "75"]]);
+    }
+    #[test]
+    fn test_redact() {
+        let pipeline = Pipeline::new().redact(Redactor::new(&[String::from("[0-9]")]));
+        let mut out: Vec<Vec<String>> = Vec::new();
+        pipeline.run("a1,22\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![vec!["a*", "**"]]);
+    }
+    #[test]
+    fn test_requote() {
+        let input = "\"a\",\"b,c\"\n";
+        let mut out: Vec<Vec<String>> = Vec::new();
+        Pipeline::new().requote(Requote::Never).run(input.as_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![vec!["a", "b,c"]]);
+        let mut out: Vec<Vec<String>> = Vec::new();
+        Pipeline::new().requote(Requote::Minimal).run(input.as_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![vec!["a", "\"b,c\""]]);
+        assert_eq!(Pipeline::new().requote(Requote::Always).header("x,\"y\""),
+                   vec!["\"x\"", "\"y\""]);
+    }
+    #[test]
+    fn test_encrypt() {
+        let fpe = Fpe::new(1, b"key");
+        let expected = fpe.encrypt("555-0100").unwrap();
+        let pipeline = Pipeline::new()
+            .select(vec![1])
+            .compute(parse_expr("col2", 1))
+            .encrypt(fpe);
+        let mut out: Vec<Vec<String>> = Vec::new();
+        pipeline.run("a,555-0100\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![vec![expected.as_str(), "555-0100"]]);
+    }
+    #[test]
+    fn test_pseudonymize() {
+        let pseudonyms = Pseudonymizer::new(vec![0], b"salt", false);
+        let expected = pseudonyms.pseudonym("alice");
+        let pipeline = Pipeline::new().select(vec![0, 1]).pseudonymize(pseudonyms);
+        let mut out: Vec<Vec<String>> = Vec::new();
+        pipeline.run("alice,1\n\"alice\",2\n,3\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![vec![expected.as_str(), "1"], vec![expected.as_str(), "2"],
+                             vec!["", "3"]]);
+    }
+    #[test]
+    fn test_writer_sink() {
+        let pipeline = Pipeline::new().dialect(Box::new(Tsv)).select(vec![1, 0]);
+        let mut buf: Vec<u8> = Vec::new();
+        let mut sink = WriterSink::new(&mut buf, pipeline.get_dialect());
+        pipeline.run("a\tb,c\n".as_bytes(), &mut sink).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "b,c\ta\n");
+    }
+    #[test]
+    fn test_range_join() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nets.csv");
+        std::fs::write(&path, "start,end,net\n10.0.0.0,10.0.0.255,office\n").unwrap();
+        let spec = parse_range_join(path.to_str().unwrap(), "col2 between start,end", 1);
+        let join = RangeJoin::load(&spec, &Permissive, false).unwrap();
+        let pipeline = Pipeline::new().select(vec![0]).range_join(join);
+        let mut out: Vec<Vec<String>> = Vec::new();
+        pipeline.run("a,10.0.0.7\nb,10.0.1.7\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![vec!["a", "office"], vec!["b", ""]]);
+
+        std::fs::write(&path, "start,end,net
+10.0.0.0,10.0.0.255,office
+1,9,lab
+").unwrap();
+        let spec = RangeJoinSpec { join_type: JoinType::Right, fill: String::from("?"), ..spec };
+        let join = RangeJoin::load(&spec, &Permissive, false).unwrap();
+        let pipeline = Pipeline::new().select(vec![0]).compute(parse_expr("col1", 1))
+            .range_join(join);
+        let mut out: Vec<Vec<String>> = Vec::new();
+        pipeline.run("a,10.0.0.7\nb,10.0.1.7\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![vec!["a", "a", "office"], vec!["?", "?", "lab"]]);
+        assert_eq!(pipeline.header("name,ip"), vec!["name", "", "net"]);
+    }
+    #[test]
+    fn test_header() {
+        let pipeline = Pipeline::new()
+            .select(vec![2, 0])
+            .compute_named(parse_expr("col2 * 10", 1), "col2 * 10")
+            .compute_named(parse_expr("round(col2, 1)", 1), "round(col2, 1)");
+        assert_eq!(pipeline.header("a,b,c"), vec!["c", "a", "col2 * 10", "\"round(col2, 1)\""]);
+        let mut out = WriterSink::new(Vec::new(), &Permissive);
+        out.write_header(&["c", "a"]).unwrap();
+        out.write_record(&["3", "1"]).unwrap();
+        assert_eq!(out.records(), 1);
+        assert_eq!(out.get_mut().as_slice(), b"c,a\n3,1\n");
+    }
+    #[test]
+    fn test_pass_through() {
+        let cut = |pipeline: Pipeline, input: &str| {
+            let mut out = WriterSink::new(Vec::new(), &Permissive);
+            pipeline.run(input.as_bytes(), &mut out).unwrap();
+            let mut fields: Vec<Vec<String>> = Vec::new();
+            pipeline.run(input.as_bytes(), &mut fields).unwrap();
+            assert_eq!(out.records(), fields.len() as u64);
+            String::from_utf8(out.get_mut().clone()).unwrap()
+        };
+        let input = "a,\"b,c\",d,e\nf\n";
+        assert_eq!(cut(Pipeline::new().select(vec![2]).complement(), input),
+                   "a,\"b,c\",e\nf\n");
+        assert_eq!(cut(Pipeline::new().select(vec![3, line::OPEN_RANGE | 1]), input),
+                   "e,\"b,c\",d,e\n\n");
+        assert_eq!(cut(Pipeline::new().select(vec![0]).complement().requote(Requote::Always),
+                       input),
+                   "\"b,c\",\"d\",\"e\"\n\n");
+    }
+    #[test]
+    fn test_verify_roundtrip() {
+        let mut out = WriterSink::new(Vec::new(), &Permissive)
+            .verify_roundtrip(Box::new(Permissive));
+        out.write_record(&["1", "\"a,b\""]).unwrap();
+        let error = out.write_record(&["2", "a,b"]).unwrap_err();
+        assert_eq!(error.to_string(),
+                   "record 2 doesn't read back as written: field 2 \"a,b\" reads as \"a\"");
+        assert_eq!(String::from_utf8(out.get_mut().clone()).unwrap(), "1,\"a,b\"\n");
+    }
+    #[test]
+    fn test_markdown() {
+        let markdown = |header: Option<&[&str]>| {
+            let mut out = WriterSink::new(Vec::new(), &Permissive).markdown(Box::new(Permissive));
+            if let Some(header) = header {
+                out.write_header(header).unwrap();
+            }
+            out.write_record(&["1", "\"a|b\nc\""]).unwrap();
+            assert_eq!(out.records(), 1);
+            String::from_utf8(out.get_mut().clone()).unwrap()
+        };
+        assert_eq!(markdown(Some(&["id", "name"])),
+                   "| id | name |\n| --- | --- |\n| 1 | a\\|b<br>c |\n");
+        assert_eq!(markdown(None), "| col_1 | col_2 |\n| --- | --- |\n| 1 | a\\|b<br>c |\n");
+    }
+    #[test]
+    fn test_json() {
+        let mut out = WriterSink::new(Vec::new(), &Permissive).json(Box::new(Permissive));
+        out.write_record(&["1", "\"a, \\\"b\\\"\""]).unwrap();
+        out.write_header(&["id", " 'name' "]).unwrap();
+        out.write_record(&["2", "é\tz", "x"]).unwrap();
+        assert_eq!(out.records(), 2);
+        assert_eq!(String::from_utf8(out.get_mut().clone()).unwrap(),
+                   "{\"col_1\":\"1\",\"col_2\":\"a, \\\"b\\\"\"}\n\
+                    {\"id\":\"2\",\"name\":\"é\\tz\",\"col_3\":\"x\"}\n");
+    }
+    #[test]
+    fn test_membership() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ids.txt");
+        std::fs::write(&path, "2\n3\n").unwrap();
+        let spec = parse_membership(&format!("{}@1", path.display()), false, 1);
+        let ids = Membership::load(&spec, &Permissive, false, None, None).unwrap();
+        let pipeline = Pipeline::new().membership(ids);
+        let mut out: Vec<Vec<String>> = Vec::new();
+        pipeline.run("1,a\n2,b\n3,c\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![vec!["1", "a"]]);
+    }
+}
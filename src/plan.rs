@@ -0,0 +1,420 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::check::Ragged;
+use crate::crosstab::CrosstabSpec;
+use crate::decimal::DecimalSpec;
+use crate::diagnostic::ErrorsFormat;
+use crate::fpe::FpeSpec;
+use crate::grep::GrepSpec;
+use crate::dialect::{self, Dialect, Quoting, Requote, WithDelimiter, WithQuotes};
+use crate::group::GroupSpec;
+use crate::interval::RangeJoinSpec;
+use crate::key::KeyKind;
+use crate::limits::CellLimit;
+use crate::membership::MembershipSpec;
+use crate::outlier::OutlierSpec;
+use crate::precision::Precision;
+use crate::sample::SampleSpec;
+use crate::partition::Existing;
+use crate::session::SessionSpec;
+use crate::slice::Slice;
+use crate::sort::SortSpec;
+use crate::stats::Every;
+use crate::trailer::DigestAlgorithm;
+use crate::transform::Transform;
+use crate::unique::Unique;
+
+/// Version of the plan file format, bumped whenever a change would make an old plan mean
+/// something different
+pub const PLAN_VERSION: u32 = 1;
+
+/// The fully resolved configuration of a run: everything needed to process input identically
+/// later, independently of how the command line spelled it. Column indices are resolved to zero-
+/// indexed positions; expressions are kept as written, together with the `offset` they were
+/// written against, since they re-parse to exactly the same thing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Plan {
+    pub version: u32,
+    /// How records are split into fields, as a name understood by `dialect::by_name`. The
+    /// command line only splits permissively so far, with `delimiter` to change the delimiter.
+    pub dialect: String,
+    pub offset: usize,
+    pub cols: Vec<usize>,
+    pub transforms: Vec<Transform>,
+    pub computes: Vec<String>,
+    pub filters: Vec<String>,
+    pub checks: Vec<String>,
+    pub assert_sorted: Option<(usize, KeyKind)>,
+    pub gaps: Option<(usize, KeyKind, f64)>,
+    pub top: Option<TopSpec>,
+    pub sort: Option<SortSpec>,
+    pub cardinality: Option<usize>,
+    pub heavy_hitters: Option<usize>,
+    pub k: usize,
+    /// Compare values case-insensitively wherever they're matched or counted
+    pub fold_case: bool,
+    pub validate: bool,
+    pub max_errors: Option<usize>,
+    pub error_examples: Option<usize>,
+    pub allow_dns: bool,
+    /// Regexes masked in previews, and in the output too if `redact_output`
+    pub redact_patterns: Vec<String>,
+    pub redact_output: bool,
+    /// Print only a digest of the output with this algorithm (`sha256`) instead of the output
+    pub digest_only: Option<DigestAlgorithm>,
+    pub slice: Slice,
+    /// Process the input in parallel byte ranges, one per core
+    pub auto_shard: bool,
+    /// Append a record of each run to this file
+    pub audit: Option<PathBuf>,
+    /// Bytes that in-memory buffers such as the sort buffer may use at most
+    pub max_memory: Option<usize>,
+    /// Scheduling priority to run at, as for nice(1)
+    pub nice: Option<i32>,
+    /// Append a line with the row count and digest of the output
+    pub trailer_checksum: bool,
+    /// Write the row count and digest of the output to this sidecar file
+    pub checksum_file: Option<PathBuf>,
+    /// Write each record to a file in `out_dir` named after its value in this column
+    pub partition_by: Option<usize>,
+    pub out_dir: Option<PathBuf>,
+    /// What to do about output files in `out_dir` that already exist
+    pub existing: Existing,
+    /// Also write a `manifest.json` of the files in `out_dir`
+    pub manifest: bool,
+    /// Only estimate the size of the output from a sample of the input
+    pub estimate: bool,
+    /// Print per-column statistics instead of the records
+    pub stats: bool,
+    /// Print aggregates per group of rows instead of the records
+    pub group: Option<GroupSpec>,
+    /// Decimals to round computed and aggregated numbers to
+    pub precision: Option<Precision>,
+    /// Compute and aggregate in fixed-point decimals instead of floating point
+    pub decimal: Option<DecimalSpec>,
+    /// Files to read, in order, with `-` for stdin; stdin if empty. Not saved, so that a plan
+    /// can be run over other inputs.
+    #[serde(skip)]
+    pub inputs: Vec<PathBuf>,
+    /// Splits and writes fields on this instead of the dialect's delimiter
+    pub delimiter: Option<char>,
+    /// Append the session number of each record
+    pub sessionize: Option<SessionSpec>,
+    /// Append the label of the range each record falls in
+    pub range_join: Option<RangeJoinSpec>,
+    /// Keep only the records whose value is, or isn't, listed in a file
+    pub memberships: Vec<MembershipSpec>,
+    /// Records may span several lines within quoted fields
+    pub multiline: bool,
+    /// Output every column except `cols`
+    pub complement: bool,
+    /// False positive rate of the Bloom filters that screen records against lookups spilled to
+    /// disk
+    pub bloom_fpr: Option<f64>,
+    /// Flush the output after every line
+    pub flush: bool,
+    /// Columns to encrypt in the output
+    pub fpe: Vec<FpeSpec>,
+    /// Columns to replace with pseudonyms salted with the contents of `salt_file`
+    pub pseudonymize: Vec<usize>,
+    pub salt_file: Option<PathBuf>,
+    /// The first line of the input is a header, passed through ahead of the records
+    pub header: bool,
+    /// Append the input file, byte offset and line number each record came from
+    pub provenance: bool,
+    /// Escape invalid UTF-8 in the input instead of failing, and report where it was
+    pub show_invalid_bytes: bool,
+    /// Print the records lined up in columns for reading
+    pub table: bool,
+    /// Print each record as a JSON object
+    pub json: bool,
+    /// Print the records as a Markdown table
+    pub markdown: bool,
+    /// How long fields may be, and what to do with longer ones
+    pub cell_limit: Option<CellLimit>,
+    /// What to do with records that don't have as many fields as the first
+    pub ragged: Option<Ragged>,
+    /// Print the pairwise correlations of these columns instead of the records
+    pub correlate: Vec<usize>,
+    /// Select the columns whose header names match this regex instead of `cols`
+    pub regex_cols: Option<String>,
+    /// Print the counts of each combination of the values of two columns instead of the records
+    pub crosstab: Option<CrosstabSpec>,
+    /// Also print the statistics so far this often with `stats`
+    pub stats_every: Option<Every>,
+    /// Write those to this file instead of stderr
+    pub stats_file: Option<PathBuf>,
+    /// Process the input on this many threads, in chunks of a stream unless it has to be read
+    /// twice or sliced, when it's sharded like `auto_shard`
+    pub threads: Option<usize>,
+    /// Flag, or keep only, the records whose value in a column is far from those before it
+    pub flag_outliers: Option<OutlierSpec>,
+    /// Check that each record written reads back as the same fields
+    pub verify_roundtrip: bool,
+    /// Recognizes these quote characters instead of the dialect's, the first quoting output
+    pub quotes: Option<Vec<char>>,
+    /// Quote the output fields afresh, or write them bare
+    pub requote: Option<Requote>,
+    /// Keep only the records whose value in a column matches, or doesn't match, a regex
+    pub greps: Vec<GrepSpec>,
+    /// Leave out this many records at the start of the input, after the header
+    pub skip: u64,
+    /// Stop after writing this many records
+    pub limit: Option<u64>,
+    /// Keep only some of the records, at random
+    pub sample: Option<SampleSpec>,
+    /// Seeds the random choices, so the same records are picked again
+    pub seed: Option<u64>,
+    /// Write problems found in the input as text or as JSON events
+    pub errors_format: ErrorsFormat,
+    /// Drop the records that duplicate one before them
+    pub unique: Option<Unique>,
+    /// Read the inputs this many bytes at a time, rather than in chunks sized to the records
+    pub buffer_bytes: Option<usize>,
+    /// The names in the header the columns were resolved against, unquoted, which the header of
+    /// the input must match when the plan is replayed
+    pub header_names: Option<Vec<String>>,
+}
+
+/// Whether a header, its names unquoted, is the one a plan's columns were resolved against, its
+/// `header_names`, or else where the first difference is, numbered from `offset`
+pub fn check_header(expected: &[String], names: &[String], offset: usize) -> Result<(), String> {
+    for i in 0..expected.len().max(names.len()) {
+        let (want, got) = (expected.get(i), names.get(i));
+        if want != got {
+            let name = |n: Option<&String>| n.map_or_else(|| String::from("missing"),
+                                                         |n| format!("{:?}", n));
+            return Err(format!("column {} of the header is {}, but the plan was made for {}",
+                               i + offset, name(got), name(want)));
+        }
+    }
+    Ok(())
+}
+
+/// `--top N --by COL[:desc]`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopSpec {
+    pub n: usize,
+    pub col: usize,
+    pub desc: bool,
+}
+
+impl Default for Plan {
+    fn default() -> Plan {
+        Plan {
+            version: PLAN_VERSION,
+            dialect: String::from("permissive"),
+            offset: 1,
+            cols: Vec::new(),
+            transforms: Vec::new(),
+            computes: Vec::new(),
+            filters: Vec::new(),
+            checks: Vec::new(),
+            assert_sorted: None,
+            gaps: None,
+            top: None,
+            sort: None,
+            cardinality: None,
+            heavy_hitters: None,
+            k: 20,
+            fold_case: false,
+            validate: false,
+            max_errors: None,
+            error_examples: None,
+            allow_dns: false,
+            redact_patterns: Vec::new(),
+            redact_output: false,
+            digest_only: None,
+            slice: Slice::default(),
+            auto_shard: false,
+            audit: None,
+            max_memory: None,
+            nice: None,
+            trailer_checksum: false,
+            checksum_file: None,
+            partition_by: None,
+            out_dir: None,
+            existing: Existing::Fail,
+            manifest: false,
+            estimate: false,
+            stats: false,
+            group: None,
+            precision: None,
+            decimal: None,
+            inputs: Vec::new(),
+            delimiter: None,
+            sessionize: None,
+            range_join: None,
+            memberships: Vec::new(),
+            multiline: false,
+            complement: false,
+            bloom_fpr: None,
+            flush: false,
+            fpe: Vec::new(),
+            pseudonymize: Vec::new(),
+            salt_file: None,
+            header: false,
+            provenance: false,
+            show_invalid_bytes: false,
+            table: false,
+            json: false,
+            markdown: false,
+            cell_limit: None,
+            ragged: None,
+            correlate: Vec::new(),
+            regex_cols: None,
+            crosstab: None,
+            stats_every: None,
+            stats_file: None,
+            threads: None,
+            flag_outliers: None,
+            verify_roundtrip: false,
+            quotes: None,
+            requote: None,
+            greps: Vec::new(),
+            skip: 0,
+            limit: None,
+            sample: None,
+            seed: None,
+            errors_format: ErrorsFormat::Text,
+            unique: None,
+            buffer_bytes: None,
+            header_names: None,
+        }
+    }
+}
+
+impl Plan {
+    /// Reads a plan saved with `save`
+    pub fn load(path: &Path) -> io::Result<Plan> {
+        let plan: Plan = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if plan.version != PLAN_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("unsupported plan version {}", plan.version)));
+        }
+        Ok(plan)
+    }
+
+    /// Writes the plan as pretty-printed JSON
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+
+    /// The dialect records are split and written in: `dialect`, with `quotes` and `delimiter` if
+    /// they're set
+    pub fn make_dialect(&self) -> Box<dyn Dialect> {
+        let dialect = dialect::by_name(&self.dialect)
+            .unwrap_or_else(|| panic!("Unknown dialect {}, expected one of {}", self.dialect,
+                                      dialect::DIALECT_NAMES.join(", ")));
+        let dialect: Box<dyn Dialect> = match &self.quotes {
+            Some(quotes) => Box::new(WithQuotes::new(dialect, quotes.clone())),
+            None => dialect,
+        };
+        match self.delimiter {
+            Some(d) => Box::new(WithDelimiter::new(dialect, d)),
+            None => dialect,
+        }
+    }
+
+    /// How to tell where records end if they may span several lines, for `input::Records`
+    pub fn quoting(&self) -> Option<Quoting> {
+        if self.multiline { Some(Quoting::of(self.make_dialect().as_ref())) } else { None }
+    }
+
+    /// A digest of the settings that decide what the output is, leaving out those that only
+    /// change how it's produced, so outputs can be recognized as made by the same configuration
+    pub fn fingerprint(&self) -> String {
+        let plan = Plan {
+            existing: Existing::default(),
+            audit: None,
+            max_memory: None,
+            bloom_fpr: None,
+            flush: false,
+            nice: None,
+            auto_shard: false,
+            stats_every: None,
+            stats_file: None,
+            threads: None,
+            verify_roundtrip: false,
+            errors_format: ErrorsFormat::Text,
+            buffer_bytes: None,
+            ..self.clone()
+        };
+        let json = serde_json::to_vec(&plan).expect("plans always serialize");
+        format!("{:x}", Sha256::digest(&json))
+    }
+}
+
+#[cfg(test)]
+mod test_plan {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let plan = Plan {
+            cols: vec![0, 2],
+            transforms: vec![Transform::Tz { col: 1, from: chrono_tz::Tz::UTC,
+                                             to: chrono_tz::Tz::Asia__Tokyo }],
+            computes: vec![String::from("zscore(col3)")],
+            gaps: Some((1, KeyKind::Date, 60.0)),
+            top: Some(TopSpec { n: 5, col: 2, desc: true }),
+            ..Plan::default()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plan.json");
+        plan.save(&path).unwrap();
+        assert_eq!(Plan::load(&path).unwrap(), plan);
+    }
+    #[test]
+    fn test_missing_fields_default() {
+        let plan: Plan = serde_json::from_str(r#"{"cols": [3]}"#).unwrap();
+        assert_eq!(plan, Plan { cols: vec![3], ..Plan::default() });
+    }
+    #[test]
+    fn test_bad_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plan.json");
+        std::fs::write(&path, r#"{"version": 99}"#).unwrap();
+        assert!(Plan::load(&path).is_err());
+    }
+    #[test]
+    fn test_check_header() {
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let expected = names(&["id", "name"]);
+        assert_eq!(check_header(&expected, &names(&["id", "name"]), 1), Ok(()));
+        assert_eq!(check_header(&expected, &names(&["id", "email", "name"]), 1),
+                   Err(String::from("column 2 of the header is \"email\", but the plan was made \
+                                     for \"name\"")));
+        assert_eq!(check_header(&expected, &names(&["id"]), 1),
+                   Err(String::from("column 2 of the header is missing, but the plan was made \
+                                     for \"name\"")));
+    }
+    #[test]
+    fn test_make_dialect() {
+        let plan = Plan { dialect: String::from("tsv"), ..Plan::default() };
+        assert_eq!(plan.make_dialect().delimiter(), '\t');
+        let plan = Plan { delimiter: Some('|'), ..plan };
+        assert_eq!(plan.make_dialect().delimiter(), '|');
+        // A former quote can be the delimiter once it isn't a quote
+        let plan = Plan { delimiter: Some('\''), quotes: Some(vec!['`']), ..Plan::default() };
+        assert_eq!(plan.make_dialect().quotes(), &['`']);
+        assert_eq!(plan.make_dialect().delimiter(), '\'');
+    }
+    #[test]
+    fn test_fingerprint() {
+        let plan = Plan { cols: vec![1], ..Plan::default() };
+        let rerun = Plan { existing: Existing::Skip, nice: Some(10),
+                           inputs: vec![PathBuf::from("a.csv")], ..plan.clone() };
+        assert_eq!(plan.fingerprint(), rerun.fingerprint());
+        assert_ne!(plan.fingerprint(), Plan { cols: vec![2], ..plan.clone() }.fingerprint());
+    }
+}
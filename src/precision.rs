@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+
+use crate::decimal::Decimal;
+
+/// How a number exactly halfway between two roundings is rounded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RoundMode {
+    /// To the even neighbour, so halves don't bias sums upwards (banker's rounding)
+    #[default]
+    HalfEven,
+    /// Away from zero
+    HalfUp,
+}
+
+/// Parses a `--round-mode` argument
+pub fn parse_round_mode(s: &str) -> RoundMode {
+    match s.trim() {
+        "half-even" => RoundMode::HalfEven,
+        "half-up" => RoundMode::HalfUp,
+        other => panic!("Invalid rounding mode {}, expected half-even or half-up", other),
+    }
+}
+
+/// `--precision N`: numbers are printed with exactly N decimals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Precision {
+    pub digits: usize,
+    #[serde(default)]
+    pub mode: RoundMode,
+}
+
+impl Precision {
+    /// Rounds `x` to `digits` decimals. Rounding works on the shortest decimal that reads back as
+    /// `x`, the way it's printed, rather than on its binary value, so `2.675` rounds half up to
+    /// `2.68` as written, and the result is the same on every platform.
+    pub fn format(&self, x: f64) -> String {
+        if !x.is_finite() {
+            return x.to_string();
+        }
+        self.format_decimal(&x.to_string())
+    }
+
+    /// Rounds a number written in plain decimal notation, such as `-12.345`, to `digits` decimals
+    pub fn format_decimal(&self, s: &str) -> String {
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(abs) => (true, abs),
+            None => (false, s),
+        };
+        let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+        let mut digits: Vec<u8> = int.bytes().chain(frac.bytes()).map(|b| b - b'0').collect();
+        let int_len = int.len();
+        let keep = int_len + self.digits;
+        if digits.len() <= keep {
+            digits.resize(keep, 0);
+        } else {
+            let next = digits[keep];
+            let rest_nonzero = digits[keep + 1..].iter().any(|d| *d != 0);
+            let last_odd = keep > 0 && digits[keep - 1] % 2 == 1;
+            let up = next > 5 || (next == 5 && (rest_nonzero || self.mode == RoundMode::HalfUp
+                                                || last_odd));
+            digits.truncate(keep);
+            if up && !increment(&mut digits) {
+                digits.insert(0, 1);
+            }
+        }
+        let split = digits.len() - self.digits;
+        let mut res = String::with_capacity(digits.len() + 2);
+        if negative && digits.iter().any(|d| *d != 0) {
+            res.push('-');
+        }
+        if split == 0 {
+            res.push('0');
+        }
+        res.extend(digits[..split].iter().map(|d| (b'0' + d) as char));
+        if self.digits > 0 {
+            res.push('.');
+            res.extend(digits[split..].iter().map(|d| (b'0' + d) as char));
+        }
+        res
+    }
+}
+
+/// Adds one to the last digit, carrying. Returns false if it carried out of the first digit,
+/// which is then zero.
+fn increment(digits: &mut [u8]) -> bool {
+    for d in digits.iter_mut().rev() {
+        if *d == 9 {
+            *d = 0;
+        } else {
+            *d += 1;
+            return true;
+        }
+    }
+    false
+}
+
+/// Formats a number with `precision` if there is one, and as the shortest decimal otherwise
+pub fn format_num(x: f64, precision: Option<&Precision>) -> String {
+    match precision {
+        Some(p) => p.format(x),
+        None => x.to_string(),
+    }
+}
+
+/// Formats a decimal with `precision` if there is one, and with its own decimals otherwise
+pub fn format_dec(x: Decimal, precision: Option<&Precision>) -> String {
+    match precision {
+        Some(p) => p.format_decimal(&x.to_string()),
+        None => x.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test_precision {
+    use super::*;
+
+    fn fmt(x: f64, digits: usize, mode: RoundMode) -> String {
+        Precision { digits, mode }.format(x)
+    }
+
+    #[test]
+    fn test_half_even() {
+        let even = |x| fmt(x, 2, RoundMode::HalfEven);
+        assert_eq!(even(2.675), "2.68");
+        assert_eq!(even(2.665), "2.66");
+        assert_eq!(even(2.6651), "2.67");
+        assert_eq!(even(0.1 + 0.2), "0.30");
+        assert_eq!(fmt(2.5, 0, RoundMode::HalfEven), "2");
+        assert_eq!(fmt(3.5, 0, RoundMode::HalfEven), "4");
+    }
+    #[test]
+    fn test_half_up() {
+        assert_eq!(fmt(2.665, 2, RoundMode::HalfUp), "2.67");
+        assert_eq!(fmt(2.5, 0, RoundMode::HalfUp), "3");
+        assert_eq!(fmt(-2.5, 0, RoundMode::HalfUp), "-3");
+    }
+    #[test]
+    fn test_carry_and_padding() {
+        assert_eq!(fmt(9.995, 2, RoundMode::HalfUp), "10.00");
+        assert_eq!(fmt(0.996, 2, RoundMode::HalfEven), "1.00");
+        assert_eq!(fmt(3.0, 3, RoundMode::HalfEven), "3.000");
+        assert_eq!(fmt(0.004, 2, RoundMode::HalfEven), "0.00");
+        assert_eq!(fmt(-0.004, 2, RoundMode::HalfEven), "0.00");
+        assert_eq!(fmt(1234.5678, 1, RoundMode::HalfEven), "1234.6");
+        assert_eq!(fmt(f64::NAN, 2, RoundMode::HalfEven), "NaN");
+    }
+    #[test]
+    fn test_format_dec() {
+        let d = Decimal::parse("-2.345").unwrap();
+        assert_eq!(format_dec(d, Some(&Precision { digits: 2, mode: RoundMode::HalfEven })),
+                   "-2.34");
+        assert_eq!(format_dec(d, None), "-2.345");
+    }
+    #[test]
+    #[should_panic]
+    fn test_bad_round_mode_fails() {
+        parse_round_mode("down");
+    }
+}
@@ -0,0 +1,122 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::collate;
+use crate::expr;
+
+/// Bytes of salt `rotate_salt` writes
+const SALT_BYTES: usize = 32;
+
+/// Hex digits in a pseudonym, i.e. 64 bits of the keyed hash
+const PSEUDONYM_DIGITS: usize = 16;
+
+/// Replaces the values of some columns with pseudonyms: a keyed hash of the value with a secret
+/// salt. The same value gets the same pseudonym in every run with the same salt, so extracts can
+/// still be joined and counted, while the values can't be recovered without the salt; rotating
+/// the salt unlinks later extracts from earlier ones.
+#[derive(Debug, Clone)]
+pub struct Pseudonymizer {
+    cols: Vec<usize>,
+    salt: Vec<u8>,
+    fold_case: bool,
+}
+
+impl Pseudonymizer {
+    /// With `fold_case`, values that differ only in case get the same pseudonym
+    pub fn new(cols: Vec<usize>, salt: &[u8], fold_case: bool) -> Pseudonymizer {
+        Pseudonymizer { cols, salt: salt.to_vec(), fold_case }
+    }
+
+    /// Columns this replaces, zero-indexed
+    pub fn cols(&self) -> &[usize] {
+        &self.cols
+    }
+
+    /// The pseudonym of a field, which is trimmed and unquoted first. Empty fields stay empty,
+    /// rather than all sharing one pseudonym.
+    pub fn pseudonym(&self, field: &str) -> String {
+        let value = expr::unquote(field.trim());
+        if value.is_empty() {
+            return String::new();
+        }
+        let value = if self.fold_case { collate::fold_case(value) } else { value.into() };
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.salt).expect("HMAC takes any key");
+        mac.update(value.as_bytes());
+        mac.finalize().into_bytes().iter()
+            .take(PSEUDONYM_DIGITS / 2)
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// Reads a salt file, ignoring surrounding whitespace. Fails if it's empty.
+pub fn load_salt(path: &Path) -> io::Result<Vec<u8>> {
+    let salt = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    let salt = salt.trim();
+    if salt.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("{}: no salt", path.display())));
+    }
+    Ok(salt.as_bytes().to_vec())
+}
+
+/// Writes a new random salt, in hex, to `path`, replacing any salt there. The file is only
+/// readable by its owner on Unix, and is replaced whole so that a run never sees half a salt.
+pub fn rotate_salt(path: &Path) -> io::Result<()> {
+    let mut salt = [0u8; SALT_BYTES];
+    getrandom::fill(&mut salt).map_err(|e| io::Error::other(e.to_string()))?;
+    let hex: String = salt.iter().map(|b| format!("{:02x}", b)).collect();
+    let tmp = path.with_extension("tmp");
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp)?;
+    writeln!(file, "{}", hex)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod test_pseudonym {
+    use super::*;
+
+    #[test]
+    fn test_pseudonym() {
+        let p = Pseudonymizer::new(vec![0], b"salt", false);
+        let alice = p.pseudonym("alice@example.com");
+        assert_eq!(alice.len(), PSEUDONYM_DIGITS);
+        assert_eq!(p.pseudonym(" \"alice@example.com\""), alice);
+        assert_ne!(p.pseudonym("Alice@example.com"), alice);
+        assert_ne!(Pseudonymizer::new(vec![0], b"pepper", false).pseudonym("alice@example.com"),
+                   alice);
+        assert_eq!(p.pseudonym(""), "");
+        let folded = Pseudonymizer::new(vec![0], b"salt", true);
+        assert_eq!(folded.pseudonym("Alice@Example.com"), folded.pseudonym("alice@example.com"));
+        // HMAC-SHA256 as in RFC 4231, test case 2
+        assert_eq!(Pseudonymizer::new(vec![0], b"Jefe", false)
+                       .pseudonym("what do ya want for nothing?"),
+                   "5bdcc146bf60754e");
+    }
+    #[test]
+    fn test_rotate_salt() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("salt");
+        assert!(load_salt(&path).is_err());
+        rotate_salt(&path).unwrap();
+        let first = load_salt(&path).unwrap();
+        assert_eq!(first.len(), 2 * SALT_BYTES);
+        rotate_salt(&path).unwrap();
+        assert_ne!(load_salt(&path).unwrap(), first);
+        std::fs::write(&path, " \n").unwrap();
+        assert!(load_salt(&path).is_err());
+    }
+}
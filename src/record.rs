@@ -0,0 +1,121 @@
+use std::borrow::Cow;
+
+use crate::dialect::{Dialect, Permissive};
+
+/// A parsed record that borrows its line: fields are kept as byte ranges into the line rather than
+/// copied out, and are only sliced (or unquoted) when asked for
+#[derive(Debug, Clone, Copy)]
+pub struct RecordView<'a> {
+    line: &'a str,
+    ranges: &'a [(usize, usize)],
+    dialect: &'a dyn Dialect,
+}
+
+/// A parsed record, as the library's API calls it; see `RecordView`
+pub type Record<'a> = RecordView<'a>;
+
+/// Parses lines into `RecordView`s, reusing the same range buffer for every record so that
+/// parsing doesn't allocate once the buffer has grown to the widest record
+#[derive(Debug)]
+pub struct RecordParser {
+    ranges: Vec<(usize, usize)>,
+    dialect: Box<dyn Dialect>,
+}
+
+impl Default for RecordParser {
+    fn default() -> RecordParser {
+        RecordParser::with_dialect(Box::new(Permissive))
+    }
+}
+
+impl RecordParser {
+    pub fn new() -> RecordParser {
+        RecordParser::default()
+    }
+
+    pub fn with_dialect(dialect: Box<dyn Dialect>) -> RecordParser {
+        RecordParser { ranges: Vec::new(), dialect }
+    }
+
+    pub fn dialect(&self) -> &dyn Dialect {
+        self.dialect.as_ref()
+    }
+
+    /// Splits `line` into fields as written in the parser's dialect. The view is valid until the
+    /// next call.
+    pub fn parse<'a>(&'a mut self, line: &'a str) -> RecordView<'a> {
+        self.ranges.clear();
+        let ranges = &mut self.ranges;
+        self.dialect.field_ranges(line, &mut |start, end| ranges.push((start, end)));
+        RecordView { line, ranges: &self.ranges, dialect: self.dialect.as_ref() }
+    }
+}
+
+impl<'a> RecordView<'a> {
+    /// The whole line the record was parsed from
+    pub fn line(&self) -> &'a str {
+        self.line
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The raw text of field `i` (zero-indexed), quotes included
+    pub fn get(&self, i: usize) -> Option<&'a str> {
+        self.ranges.get(i).map(|&(start, end)| &self.line[start..end])
+    }
+
+    /// The raw text of every field in order
+    pub fn iter(&self) -> impl Iterator<Item = &'a str> + 'a {
+        let line = self.line;
+        self.ranges.iter().map(move |&(start, end)| &line[start..end])
+    }
+
+    /// The value of field `i` with surrounding quotes removed and escapes resolved. Only
+    /// allocates if the field actually contains escapes.
+    pub fn unquoted(&self, i: usize) -> Option<Cow<'a, str>> {
+        let dialect = self.dialect;
+        self.get(i).map(|field| dialect.unquote(field))
+    }
+}
+
+#[cfg(test)]
+mod test_record_view {
+    use super::*;
+    use crate::dialect::Rfc4180;
+    use crate::line;
+
+    #[test]
+    fn test_get() {
+        let mut parser = RecordParser::new();
+        let record = parser.parse(r#" a,"b,c",'d' "#);
+        assert_eq!(record.len(), 3);
+        assert_eq!(record.get(0), Some("a"));
+        assert_eq!(record.get(1), Some(r#""b,c""#));
+        assert_eq!(record.get(2), Some("'d'"));
+        assert_eq!(record.get(3), None);
+        assert_eq!(record.iter().collect::<Vec<&str>>(), line::split_line(record.line()));
+    }
+    #[test]
+    fn test_unquoted() {
+        let mut parser = RecordParser::new();
+        let record = parser.parse(r#"a,"b,c","x\"y",'it\'s'"#);
+        assert_eq!(record.unquoted(0).unwrap(), "a");
+        assert_eq!(record.unquoted(1).unwrap(), "b,c");
+        assert!(matches!(record.unquoted(1).unwrap(), Cow::Borrowed(_)));
+        assert_eq!(record.unquoted(2).unwrap(), r#"x"y"#);
+        assert_eq!(record.unquoted(3).unwrap(), "it's");
+    }
+    #[test]
+    fn test_dialect() {
+        let mut parser = RecordParser::with_dialect(Box::new(Rfc4180));
+        let record = parser.parse("a,\"x \"\"y\"\"\"\r\n");
+        assert_eq!(record.len(), 2);
+        assert_eq!(record.unquoted(1).unwrap(), r#"x "y""#);
+    }
+}
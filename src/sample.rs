@@ -0,0 +1,264 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+
+use serde::{Deserialize, Serialize};
+
+use crate::slice;
+
+/// Lines `--preview-sample` shows from each place by default
+pub const BLOCK_ROWS: usize = 5;
+
+/// Bytes read at a time while looking for the start of the tail
+const CHUNK: u64 = 64 * 1024;
+
+/// Consecutive lines read from one place in a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    /// Which part of the file this is, `head`, `middle` or `tail`
+    pub part: &'static str,
+    /// Byte offsets of the start of the first line and the end of the last
+    pub start: u64,
+    pub end: u64,
+    pub lines: Vec<String>,
+}
+
+/// Reads `head_rows` lines from the start of a file `len` bytes long, `rows` lines from a place
+/// in its middle picked with `seed`, and its last `rows` lines, since problems often only show far
+/// into a file. A file too short for the three to be apart comes back whole, as a single head
+/// block.
+pub fn sample_file<R: BufRead + Seek>(reader: &mut R, len: u64, head_rows: usize, rows: usize,
+                                      seed: u64) -> io::Result<Vec<Block>> {
+    let head = read_block(reader, "head", 0, head_rows, len)?;
+    let tail_start = tail_start(reader, len, rows)?;
+    if tail_start <= head.end {
+        return Ok(vec![read_block(reader, "head", 0, usize::MAX, len)?]);
+    }
+    let mut blocks = vec![head];
+    let gap = tail_start - blocks[0].end;
+    let start = slice::seek_to_record(reader, blocks[0].end + seed % gap)?;
+    if start < tail_start {
+        blocks.push(read_block(reader, "middle", start, rows, tail_start)?);
+    }
+    blocks.push(read_block(reader, "tail", tail_start, rows, len)?);
+    Ok(blocks)
+}
+
+/// A seed for `sample_file` and `Rng` that differs from run to run
+pub fn random_seed() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Pseudo-random numbers by SplitMix64: not for cryptography, but fast, well spread, and always
+/// the same for the same seed, so a sample can be drawn again exactly
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A number in [0, n), without the bias of taking the remainder
+    pub fn below(&mut self, n: u64) -> u64 {
+        assert!(n > 0, "Nothing to pick from");
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let x = self.next_u64();
+            if x < zone {
+                return x % n;
+            }
+        }
+    }
+}
+
+/// `--sample P` or `--sample-n N`: which records to keep at random
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleSpec {
+    /// Each record with this probability
+    Fraction(f64),
+    /// This many records, or all of them if there are fewer, each as likely as any other
+    Count(usize),
+}
+
+/// Picks records as a `SampleSpec` says, as they come. A fraction is decided record by record;
+/// a count is kept in a reservoir until the end, so only that many records are ever held. A
+/// record is a line by default, or whatever else is to come out with it.
+#[derive(Debug, Clone)]
+pub struct Sampler<T = String> {
+    spec: SampleSpec,
+    rng: Rng,
+    seen: u64,
+    /// The records in the reservoir, with their positions
+    kept: Vec<(u64, T)>,
+}
+
+impl<T> Sampler<T> {
+    pub fn new(spec: SampleSpec, seed: u64) -> Sampler<T> {
+        if let SampleSpec::Fraction(p) = spec {
+            assert!(p > 0.0 && p <= 1.0, "The sample fraction must be in (0, 1]");
+        }
+        Sampler { spec, rng: Rng::new(seed), seen: 0, kept: Vec::new() }
+    }
+
+    /// Offers the next record. It comes back if it's to be written now.
+    pub fn push(&mut self, line: T) -> Option<T> {
+        self.seen += 1;
+        match self.spec {
+            SampleSpec::Fraction(p) => (self.rng.next_f64() < p).then_some(line),
+            SampleSpec::Count(n) if self.kept.len() < n => {
+                self.kept.push((self.seen, line));
+                None
+            },
+            SampleSpec::Count(n) => {
+                let i = self.rng.below(self.seen) as usize;
+                if i < n {
+                    self.kept[i] = (self.seen, line);
+                }
+                None
+            },
+        }
+    }
+
+    /// The records held in the reservoir, in the order they came in
+    pub fn finish(mut self) -> Vec<T> {
+        self.kept.sort_by_key(|(i, _)| *i);
+        self.kept.into_iter().map(|(_, line)| line).collect()
+    }
+}
+
+/// Reads up to `rows` lines starting at byte `start`, stopping at byte `stop`
+fn read_block<R: BufRead + Seek>(reader: &mut R, part: &'static str, start: u64, rows: usize,
+                                 stop: u64) -> io::Result<Block> {
+    reader.seek(SeekFrom::Start(start))?;
+    let mut block = Block { part, start, end: start, lines: Vec::new() };
+    let mut line = String::new();
+    while block.lines.len() < rows && block.end < stop {
+        line.clear();
+        let n = reader.read_line(&mut line)?;
+        if n == 0 {
+            break;
+        }
+        block.end += n as u64;
+        block.lines.push(line.trim_end_matches(&['\r', '\n'][..]).to_string());
+    }
+    Ok(block)
+}
+
+/// The byte offset of the start of the last `rows` lines, reading backwards from the end
+fn tail_start<R: Read + Seek>(reader: &mut R, len: u64, rows: usize) -> io::Result<u64> {
+    // A newline that ends the file doesn't start another line
+    let mut pos = len.saturating_sub(1);
+    let mut found = 0;
+    let mut buf = vec![0; CHUNK as usize];
+    while pos > 0 {
+        let from = pos.saturating_sub(CHUNK);
+        let chunk = &mut buf[..(pos - from) as usize];
+        reader.seek(SeekFrom::Start(from))?;
+        reader.read_exact(chunk)?;
+        for (i, b) in chunk.iter().enumerate().rev() {
+            if *b == b'\n' {
+                found += 1;
+                if found == rows {
+                    return Ok(from + i as u64 + 1);
+                }
+            }
+        }
+        pos = from;
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod test_sample {
+    use super::*;
+    use std::io::Cursor;
+
+    fn input(n: usize) -> String {
+        (0..n).map(|i| format!("{:03},x\n", i)).collect()
+    }
+
+    #[test]
+    fn test_sample_file() {
+        let input = input(100);
+        let mut reader = Cursor::new(input.as_bytes());
+        let blocks = sample_file(&mut reader, input.len() as u64, 3, 2, 250).unwrap();
+        assert_eq!(blocks, vec![
+            Block { part: "head", start: 0, end: 18, lines: vec!["000,x", "001,x", "002,x"]
+                    .into_iter().map(String::from).collect() },
+            // 18 + 250 falls within line 44, so the middle starts at the next one
+            Block { part: "middle", start: 270, end: 282,
+                    lines: vec![String::from("045,x"), String::from("046,x")] },
+            Block { part: "tail", start: 588, end: 600,
+                    lines: vec![String::from("098,x"), String::from("099,x")] },
+        ]);
+    }
+    #[test]
+    fn test_middle_stops_at_tail() {
+        let input = input(10);
+        let mut reader = Cursor::new(input.as_bytes());
+        // A middle that would start where the tail does is left out
+        let blocks = sample_file(&mut reader, input.len() as u64, 3, 5, 11).unwrap();
+        assert_eq!(blocks.iter().map(|b| b.part).collect::<Vec<_>>(), vec!["head", "tail"]);
+        let blocks = sample_file(&mut reader, input.len() as u64, 3, 5, 5).unwrap();
+        assert_eq!(blocks[1].lines, vec![String::from("004,x")]);
+        assert_eq!(blocks[2].start, 30);
+    }
+    #[test]
+    fn test_fraction() {
+        let sample = |seed: u64| {
+            let mut sampler = Sampler::new(SampleSpec::Fraction(0.1), seed);
+            (0..10000).filter_map(|i| sampler.push(i.to_string())).collect::<Vec<_>>()
+        };
+        let kept = sample(7);
+        assert!(kept.len() > 850 && kept.len() < 1150, "{}", kept.len());
+        assert_eq!(kept, sample(7));
+        assert_ne!(kept, sample(8));
+    }
+    #[test]
+    fn test_reservoir() {
+        let sample = |seed: u64, n: usize| {
+            let mut sampler = Sampler::new(SampleSpec::Count(n), seed);
+            for i in 0..1000 {
+                assert_eq!(sampler.push(i.to_string()), None);
+            }
+            sampler.finish()
+        };
+        let kept = sample(1, 10);
+        assert_eq!(kept.len(), 10);
+        assert_eq!(kept, sample(1, 10));
+        let positions: Vec<u32> = kept.iter().map(|l| l.parse().unwrap()).collect();
+        assert!(positions.windows(2).all(|w| w[0] < w[1]));
+        // Later records get in as well as earlier ones
+        assert!(positions.iter().any(|p| *p >= 500));
+        assert_eq!(sample(1, 2000).len(), 1000);
+    }
+    #[test]
+    fn test_below() {
+        let mut rng = Rng::new(3);
+        assert!((0..1000).all(|_| rng.below(7) < 7));
+    }
+    #[test]
+    fn test_short_file() {
+        let input = "a\r\nb\nc";
+        let mut reader = Cursor::new(input.as_bytes());
+        let blocks = sample_file(&mut reader, input.len() as u64, 1, 2, 0).unwrap();
+        assert_eq!(blocks, vec![Block { part: "head", start: 0, end: 6,
+                                        lines: vec!["a", "b", "c"].into_iter()
+                                            .map(String::from).collect() }]);
+    }
+}
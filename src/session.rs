@@ -0,0 +1,143 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collate;
+use crate::duration;
+use crate::expr;
+use crate::key;
+use crate::parse_args;
+
+/// `--sessionize`: numbers the sessions of each key, a new session starting whenever the time
+/// since the key's previous row is more than `gap`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionSpec {
+    /// Column whose values are tracked separately, e.g. a user ID. Without one, all rows are
+    /// one stream.
+    pub key: Option<usize>,
+    /// Column holding a timestamp, or a number of seconds such as a Unix time
+    pub time: usize,
+    /// Longest pause within a session, in seconds
+    pub gap: f64,
+}
+
+/// Parses a `--sessionize` argument such as `key=3 time=1 gap=30m`. The gap is a duration as in
+/// `--gaps`, or a plain number of seconds. `offset` is as in `parse_arg_cols`.
+pub fn parse_sessionize(spec: &str, offset: usize) -> SessionSpec {
+    let (mut key, mut time, mut gap) = (None, None, None);
+    for part in spec.split_whitespace() {
+        let (name, value) = part.split_once('=')
+            .unwrap_or_else(|| panic!("Invalid sessionize option {}, expected NAME=VALUE", part));
+        match name {
+            "key" => key = Some(parse_args::parse_arg_col(value, offset)),
+            "time" => time = Some(parse_args::parse_arg_col(value, offset)),
+            "gap" => {
+                let secs = value.parse().ok().or_else(|| duration::parse_duration(value));
+                gap = Some(secs.unwrap_or_else(|| panic!("Invalid gap {}", value)));
+            },
+            other => panic!("Unknown sessionize option {}, expected key, time or gap", other),
+        }
+    }
+    let gap: f64 = gap.expect("--sessionize needs gap=DURATION");
+    assert!(gap >= 0.0, "The gap can't be negative");
+    SessionSpec { key, time: time.expect("--sessionize needs time=COL"), gap }
+}
+
+/// Assigns session numbers to rows as they come. Rows are expected in time order within each
+/// key; a row earlier than the one before it stays in the same session.
+pub struct Sessionizer {
+    spec: SessionSpec,
+    fold_case: bool,
+    /// Time of each key's latest row, in seconds, and its current session
+    last: HashMap<String, (f64, u64)>,
+}
+
+impl Sessionizer {
+    /// With `fold_case`, keys that differ only in case are the same key
+    pub fn new(spec: SessionSpec, fold_case: bool) -> Sessionizer {
+        Sessionizer { spec, fold_case, last: HashMap::new() }
+    }
+
+    /// The session of the next row, counting from 1 for each key; `None` if its time can't be
+    /// read
+    pub fn session(&mut self, fields: &[&str]) -> Option<u64> {
+        let time = seconds(fields.get(self.spec.time)?)?;
+        let key = match self.spec.key {
+            Some(i) => expr::unquote(fields.get(i).unwrap_or(&"").trim()),
+            None => "",
+        };
+        let key = if self.fold_case { collate::fold_case(key) } else { Cow::Borrowed(key) };
+        let gap = self.spec.gap;
+        match self.last.get_mut(key.as_ref()) {
+            Some((last, session)) => {
+                if time - *last > gap {
+                    *session += 1;
+                }
+                *last = last.max(time);
+                Some(*session)
+            },
+            None => {
+                self.last.insert(key.into_owned(), (time, 1));
+                Some(1)
+            },
+        }
+    }
+}
+
+/// A timestamp as seconds since the Unix epoch, or a plain number of seconds
+fn seconds(field: &str) -> Option<f64> {
+    match key::parse_datetime(field) {
+        Some(dt) => Some(dt.and_utc().timestamp_millis() as f64 / 1000.0),
+        None => expr::parse_num(field).filter(|x| x.is_finite()),
+    }
+}
+
+#[cfg(test)]
+mod test_sessionize {
+    use super::*;
+
+    fn sessions(spec: &str, fold_case: bool, rows: &[&[&str]]) -> Vec<Option<u64>> {
+        let mut sessions = Sessionizer::new(parse_sessionize(spec, 1), fold_case);
+        rows.iter().map(|row| sessions.session(row)).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_sessionize("key=3 time=1 gap=30m", 1),
+                   SessionSpec { key: Some(2), time: 0, gap: 1800.0 });
+        assert_eq!(parse_sessionize(" time=2  gap=90 ", 1),
+                   SessionSpec { key: None, time: 1, gap: 90.0 });
+    }
+    #[test]
+    fn test_per_key() {
+        let rows: &[&[&str]] = &[
+            &["u1", "2024-01-01 10:00:00"],
+            &["u2", "2024-01-01 10:05:00"],
+            &["u1", "2024-01-01 10:20:00"],
+            &["u1", "2024-01-01 11:00:00"],
+            &["u2", "2024-01-01 11:00:00"],
+            &["u1", "not a time"],
+            &["U1", "2024-01-01 11:10:00"],
+        ];
+        assert_eq!(sessions("key=1 time=2 gap=30m", false, rows),
+                   vec![Some(1), Some(1), Some(1), Some(2), Some(2), None, Some(1)]);
+        assert_eq!(sessions("key=1 time=2 gap=30m", true, rows)[6], Some(2));
+    }
+    #[test]
+    fn test_epoch_seconds() {
+        let rows: &[&[&str]] = &[&["100"], &["160"], &["161"], &["50"], &["300"]];
+        assert_eq!(sessions("time=1 gap=60", false, rows),
+                   vec![Some(1), Some(1), Some(1), Some(1), Some(2)]);
+    }
+    #[test]
+    #[should_panic]
+    fn test_missing_gap_fails() {
+        parse_sessionize("key=1 time=2", 1);
+    }
+    #[test]
+    #[should_panic]
+    fn test_unknown_option_fails() {
+        parse_sessionize("time=2 gap=1m window=5m", 1);
+    }
+}
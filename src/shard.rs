@@ -0,0 +1,363 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::input;
+use crate::pipeline::{Pipeline, Sink, WriterSink};
+use crate::slice::Slice;
+
+/// Bytes of input `run_chunked` hands to a worker at a time, give or take the end of a line
+pub const CHUNK_BYTES: usize = 1 << 20;
+
+/// The output of a chunk, and the records it read and wrote
+type ChunkResult = io::Result<(Vec<u8>, u64, u64)>;
+
+/// Passes records on to another sink, counting them where other threads can see
+struct Counted<'a> {
+    sink: &'a mut dyn Sink,
+    count: &'a AtomicU64,
+}
+
+impl Sink for Counted<'_> {
+    fn write_record(&mut self, fields: &[&str]) -> io::Result<()> {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sink.write_record(fields)
+    }
+}
+
+/// Whether records are still wanted after those counted in `counts`, with `limit`
+fn wanted(counts: &[AtomicU64], limit: Option<u64>) -> bool {
+    limit.is_none_or(|n| counts.iter().map(|c| c.load(Ordering::Relaxed)).sum::<u64>() < n)
+}
+
+/// Reads a file at its own position, independently of the file's cursor, so that several threads
+/// can read different parts of one file at the same time
+pub struct FileAt<'a> {
+    file: &'a File,
+    pos: u64,
+}
+
+impl<'a> FileAt<'a> {
+    pub fn new(file: &'a File) -> FileAt<'a> {
+        FileAt { file, pos: 0 }
+    }
+}
+
+impl<'a> Read for FileAt<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = input::read_at(self.file, buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'a> Seek for FileAt<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            SeekFrom::End(n) => self.file.metadata()?.len().checked_add_signed(n),
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            },
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file")),
+        }
+    }
+}
+
+/// Splits the bytes of `slice` in a file of `len` bytes into `n` adjacent byte ranges of about
+/// the same size. Every shard but the first starts at a record boundary; the first starts where
+/// `slice` does.
+pub fn split(slice: &Slice, len: u64, n: usize) -> Vec<Slice> {
+    let start = slice.seek.min(len);
+    let end = slice.end.unwrap_or(len).clamp(start, len);
+    let n = (n.max(1) as u64).min((end - start).max(1));
+    let size = (end - start) / n;
+    (0..n)
+        .map(|i| Slice {
+            seek: start + i * size,
+            align_record: i > 0 || slice.align_record,
+            end: Some(if i == n - 1 { end } else { start + (i + 1) * size }),
+            lines: None,
+        })
+        .collect()
+}
+
+/// Runs `pipeline` over `slice` of `file` in `n` shards on separate threads, each with its own
+/// reader, and writes their output to `out` in input order. Each shard's output is buffered in a
+/// temp file until the shards before it are written. With a `limit`, only that many records are
+/// written, and a shard stops reading once it and the shards before it have written enough.
+/// Returns the number of records read and written.
+pub fn run<W: Write>(file: &File, slice: &Slice, pipeline: &Pipeline, n: usize,
+                     limit: Option<u64>, out: &mut W) -> io::Result<(u64, u64)> {
+    assert!(slice.lines.is_none(), "Line ranges can't be split into shards");
+    let shards = split(slice, file.metadata()?.len(), n);
+    let counts: Vec<AtomicU64> = shards.iter().map(|_| AtomicU64::new(0)).collect();
+    let outputs: Vec<io::Result<(File, u64, u64)>> = thread::scope(|scope| {
+        let handles: Vec<_> = shards.iter().enumerate()
+            .map(|(i, shard)| {
+                let counts = &counts[..=i];
+                scope.spawn(move || run_shard(file, shard, pipeline, limit, counts))
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().expect("shard thread panicked")).collect()
+    });
+    let (mut read, mut written) = (0, 0);
+    for (shard, output) in shards.iter().zip(outputs) {
+        let left = limit.map_or(u64::MAX, |n| n - written);
+        if left == 0 {
+            break;
+        }
+        let (mut tmp, mut shard_read, mut shard_written) = output?;
+        // A shard that got going before the ones ahead of it had enough may have written too
+        // much, so it's cut again up to what's left
+        if shard_written > left {
+            (tmp, shard_read, shard_written) = run_shard(file, shard, pipeline, Some(left),
+                                                         &[AtomicU64::new(0)])?;
+        }
+        io::copy(&mut tmp, out)?;
+        read += shard_read;
+        written += shard_written;
+    }
+    Ok((read, written))
+}
+
+/// Cuts a shard, stopping once the last of `counts`, its own, and the others ahead of it add up
+/// to `limit`
+fn run_shard(file: &File, shard: &Slice, pipeline: &Pipeline, limit: Option<u64>,
+             counts: &[AtomicU64]) -> io::Result<(File, u64, u64)> {
+    let mut tmp = tempfile::tempfile()?;
+    let mut read = 0;
+    let written = {
+        let mut reader = BufReader::new(FileAt::new(file));
+        let start = shard.start(&mut reader)?;
+        let mut sink = WriterSink::new(BufWriter::new(&mut tmp), pipeline.get_dialect());
+        let count = counts.last().expect("a shard counts its own records");
+        let lines = shard.lines(reader, start)
+            .take_while(|_| wanted(counts, limit))
+            .inspect(|_| read += 1);
+        pipeline.run_part(lines, &mut Counted { sink: &mut sink, count })?;
+        sink.finish()?;
+        sink.records()
+    };
+    tmp.seek(SeekFrom::Start(0))?;
+    Ok((tmp, read, written))
+}
+
+/// Runs `pipeline` over a stream on `n` threads, for input that can't be split into byte ranges
+/// like `run` does: chunks of about `CHUNK_BYTES`, ending at line breaks, are read in turn and cut
+/// by whichever worker is free, and their output is written to `out` in input order. At most
+/// `2 * n` chunks are in flight at once. With a `limit`, only that many records are written, and
+/// no more of `reader` is read once they are. Returns the number of records read and written.
+pub fn run_chunked<R: BufRead, W: Write>(reader: R, pipeline: &Pipeline, n: usize,
+                                         limit: Option<u64>, out: &mut W)
+                                         -> io::Result<(u64, u64)> {
+    let n = n.max(1);
+    let (work_tx, work_rx) = mpsc::sync_channel::<(u64, Vec<u8>)>(n);
+    let work_rx = Mutex::new(work_rx);
+    let (done_tx, done_rx) = mpsc::channel::<(u64, Vec<u8>, ChunkResult)>();
+    thread::scope(|scope| {
+        for _ in 0..n {
+            let (work_rx, done_tx) = (&work_rx, done_tx.clone());
+            scope.spawn(move || loop {
+                let next = work_rx.lock().unwrap().recv();
+                match next {
+                    Ok((i, chunk)) => {
+                        let res = run_chunk(&chunk, pipeline, limit);
+                        if done_tx.send((i, chunk, res)).is_err() {
+                            break;
+                        }
+                    },
+                    // The input is used up, or the run failed
+                    Err(_) => break,
+                }
+            });
+        }
+        drop(done_tx);
+        // Dropping `work_tx` when this returns, even early, lets the workers finish
+        feed(reader, work_tx, &done_rx, 2 * n as u64, pipeline, limit, out)
+    })
+}
+
+/// Reads the chunks of `reader` for the workers of `run_chunked`, and writes what they return in
+/// order, waiting for them whenever more than `max_pending` chunks are in flight. Stops reading
+/// once `limit` records are written.
+fn feed<R: BufRead, W: Write>(mut reader: R, work: SyncSender<(u64, Vec<u8>)>,
+                              done: &Receiver<(u64, Vec<u8>, ChunkResult)>, max_pending: u64,
+                              pipeline: &Pipeline, limit: Option<u64>, out: &mut W)
+                              -> io::Result<(u64, u64)> {
+    let mut pending: BTreeMap<u64, (Vec<u8>, ChunkResult)> = BTreeMap::new();
+    let (mut sent, mut next) = (0, 0);
+    let (mut read, mut written) = (0, 0);
+    while limit != Some(written) {
+        let mut chunk = Vec::new();
+        while chunk.len() < CHUNK_BYTES && reader.read_until(b'\n', &mut chunk)? > 0 {}
+        let eof = chunk.is_empty();
+        if !eof {
+            work.send((sent, chunk))
+                .map_err(|_| io::Error::other("the worker threads stopped"))?;
+            sent += 1;
+        }
+        while next < sent && limit != Some(written) {
+            if let Some((chunk, res)) = pending.remove(&next) {
+                let left = limit.map_or(u64::MAX, |n| n - written);
+                let (bytes, r, w) = res?;
+                // Every chunk stops at `limit` on its own, which may be more than is left
+                let (bytes, r, w) = if w > left {
+                    run_chunk(&chunk, pipeline, Some(left))?
+                } else {
+                    (bytes, r, w)
+                };
+                out.write_all(&bytes)?;
+                read += r;
+                written += w;
+                next += 1;
+            } else if eof || sent - next > max_pending {
+                let (i, chunk, res) = done.recv()
+                    .map_err(|_| io::Error::other("the worker threads stopped"))?;
+                pending.insert(i, (chunk, res));
+            } else {
+                match done.try_recv() {
+                    Ok((i, chunk, res)) => {
+                        pending.insert(i, (chunk, res));
+                    },
+                    Err(_) => break,
+                }
+            }
+        }
+        if eof {
+            break;
+        }
+    }
+    Ok((read, written))
+}
+
+/// Cuts a chunk, stopping once it has written `limit` records
+fn run_chunk(chunk: &[u8], pipeline: &Pipeline, limit: Option<u64>) -> ChunkResult {
+    let mut read = 0;
+    let count = AtomicU64::new(0);
+    let mut sink = WriterSink::new(Vec::new(), pipeline.get_dialect());
+    let lines = chunk.lines()
+        .take_while(|_| wanted(std::slice::from_ref(&count), limit))
+        .inspect(|_| read += 1);
+    pipeline.run_part(lines, &mut Counted { sink: &mut sink, count: &count })?;
+    sink.finish()?;
+    let written = sink.records();
+    Ok((std::mem::take(sink.get_mut()), read, written))
+}
+
+#[cfg(test)]
+mod test_split {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let shards = split(&Slice::default(), 100, 3);
+        let bounds: Vec<(u64, Option<u64>, bool)> = shards.iter()
+            .map(|s| (s.seek, s.end, s.align_record))
+            .collect();
+        assert_eq!(bounds, vec![(0, Some(33), false), (33, Some(66), true), (66, Some(100), true)]);
+        let slice = Slice { seek: 10, end: Some(20), ..Slice::default() };
+        assert_eq!(split(&slice, 100, 4)[3].end, Some(20));
+        assert_eq!(split(&Slice::default(), 2, 8).len(), 2);
+        assert_eq!(split(&Slice::default(), 0, 8).len(), 1);
+    }
+}
+
+#[cfg(test)]
+mod test_run {
+    use super::*;
+    use crate::expr::parse_expr;
+
+    #[test]
+    fn test_matches_unsharded() {
+        let mut file = tempfile::tempfile().unwrap();
+        for i in 0..1000 {
+            writeln!(file, "{},\"x,{}\",{}", i, i, i % 7).unwrap();
+        }
+        let pipeline = Pipeline::new().select(vec![1, 0]).filter(parse_expr("col3 != 0", 1));
+
+        let mut expected: Vec<u8> = Vec::new();
+        let mut sink = WriterSink::new(&mut expected, pipeline.get_dialect());
+        file.seek(SeekFrom::Start(0)).unwrap();
+        pipeline.run(BufReader::new(&file), &mut sink).unwrap();
+        let sink_records = sink.records();
+
+        for n in [1, 3, 8].iter() {
+            let mut res: Vec<u8> = Vec::new();
+            let counts = run(&file, &Slice::default(), &pipeline, *n, None, &mut res).unwrap();
+            assert_eq!(res, expected, "{} shards", n);
+            assert_eq!(counts, (1000, sink_records), "{} shards", n);
+        }
+    }
+    #[test]
+    fn test_limit() {
+        let mut file = tempfile::tempfile().unwrap();
+        for i in 0..1000 {
+            writeln!(file, "{},{}", i, i % 7).unwrap();
+        }
+        let pipeline = Pipeline::new().select(vec![0]).filter(parse_expr("col2 != 0", 1));
+        let expected: String = (0..1000).filter(|i| i % 7 != 0).take(500)
+            .map(|i| format!("{}\n", i))
+            .collect();
+        for n in [1, 3, 8].iter() {
+            let mut res: Vec<u8> = Vec::new();
+            let counts = run(&file, &Slice::default(), &pipeline, *n, Some(500), &mut res)
+                .unwrap();
+            assert_eq!(String::from_utf8(res).unwrap(), expected, "{} shards", n);
+            assert_eq!(counts.1, 500, "{} shards", n);
+        }
+        let mut res: Vec<u8> = Vec::new();
+        assert_eq!(run(&file, &Slice::default(), &pipeline, 3, Some(0), &mut res).unwrap().1, 0);
+        assert!(res.is_empty());
+    }
+    #[test]
+    fn test_chunked_matches_unchunked() {
+        let mut input = String::new();
+        // Enough for several chunks
+        for i in 0..200_000 {
+            input.push_str(&format!("{},\"x,{}\",{}\n", i, i, i % 7));
+        }
+        let pipeline = Pipeline::new().select(vec![1, 0]).filter(parse_expr("col3 != 0", 1));
+        let mut expected: Vec<u8> = Vec::new();
+        let mut sink = WriterSink::new(&mut expected, pipeline.get_dialect());
+        pipeline.run(input.as_bytes(), &mut sink).unwrap();
+        let sink_records = sink.records();
+
+        for n in [1, 4].iter() {
+            let mut res: Vec<u8> = Vec::new();
+            let counts = run_chunked(input.as_bytes(), &pipeline, *n, None, &mut res).unwrap();
+            assert!(res == expected, "{} threads", n);
+            assert_eq!(counts, (200_000, sink_records), "{} threads", n);
+        }
+        let mut res: Vec<u8> = Vec::new();
+        assert_eq!(run_chunked(&b""[..], &pipeline, 4, None, &mut res).unwrap(), (0, 0));
+        assert!(run_chunked(&b"1,2,3\n\xff\n"[..], &pipeline, 2, None, &mut res).is_err());
+    }
+    #[test]
+    fn test_chunked_limit() {
+        let mut input = String::new();
+        for i in 0..200_000 {
+            input.push_str(&format!("{},{}\n", i, i % 7));
+        }
+        let pipeline = Pipeline::new().select(vec![0]).filter(parse_expr("col2 != 0", 1));
+        let expected: String = (0..200_000).filter(|i| i % 7 != 0).take(100_000)
+            .map(|i| format!("{}\n", i))
+            .collect();
+        for n in [1, 4].iter() {
+            let mut res: Vec<u8> = Vec::new();
+            let (read, written) = run_chunked(input.as_bytes(), &pipeline, *n, Some(100_000),
+                                              &mut res).unwrap();
+            assert!(String::from_utf8(res).unwrap() == expected, "{} threads", n);
+            assert_eq!(written, 100_000, "{} threads", n);
+            assert!(read < 200_000, "{} threads", n);
+        }
+    }
+}
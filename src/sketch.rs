@@ -0,0 +1,233 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// HyperLogLog distinct-count estimator. With the default precision of 14 this uses 16 KiB of
+/// registers regardless of input size, with a standard error of about 0.8%.
+pub struct HyperLogLog {
+    p: u32,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub fn new(p: u32) -> HyperLogLog {
+        assert!((4..=18).contains(&p), "HyperLogLog precision must be in [4, 18]");
+        HyperLogLog { p, registers: vec![0; 1 << p] }
+    }
+
+    pub fn insert(&mut self, value: &str) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let h = hasher.finish();
+        // The first p bits pick a register, the rest give the run of leading zeros
+        let idx = (h >> (64 - self.p)) as usize;
+        let rest = h << self.p;
+        let rank = (rest.leading_zeros().min(64 - self.p) + 1) as u8;
+        if rank > self.registers[idx] {
+            self.registers[idx] = rank;
+        }
+    }
+
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            // Small-range correction: linear counting is more accurate here
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+}
+
+impl Default for HyperLogLog {
+    fn default() -> HyperLogLog {
+        HyperLogLog::new(14)
+    }
+}
+
+/// Space-saving heavy-hitters sketch tracking at most `k` values. Every value whose true count
+/// exceeds n/k is guaranteed to be reported; each reported count overestimates the true count by
+/// at most the reported error.
+pub struct SpaceSaving {
+    k: usize,
+    counters: HashMap<String, (u64, u64)>,
+}
+
+impl SpaceSaving {
+    pub fn new(k: usize) -> SpaceSaving {
+        assert!(k > 0, "Number of heavy hitters must be positive");
+        SpaceSaving { k, counters: HashMap::with_capacity(k) }
+    }
+
+    pub fn insert(&mut self, value: &str) {
+        if let Some(c) = self.counters.get_mut(value) {
+            c.0 += 1;
+            return;
+        }
+        if self.counters.len() < self.k {
+            self.counters.insert(value.to_string(), (1, 0));
+            return;
+        }
+        // Evict the smallest counter and let the new value inherit its count as error
+        let (min_value, min_count) = self.counters.iter()
+            .min_by(|a, b| (a.1).0.cmp(&(b.1).0).then(a.0.cmp(b.0)))
+            .map(|(v, c)| (v.clone(), c.0))
+            .unwrap();
+        self.counters.remove(&min_value);
+        self.counters.insert(value.to_string(), (min_count + 1, min_count));
+    }
+
+    /// Returns `(value, count, error)` triples, most frequent first
+    pub fn top(&self) -> Vec<(&str, u64, u64)> {
+        let mut res: Vec<(&str, u64, u64)> = self.counters.iter()
+            .map(|(v, c)| (v.as_str(), c.0, c.1))
+            .collect();
+        res.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        res
+    }
+}
+
+/// Bloom filter: a set that can only answer "maybe" or "definitely not". Sized for `n` items, it
+/// answers "maybe" for an item it wasn't given with probability about `fpr`, in about
+/// `-n ln(fpr) / ln(2)^2` bits.
+#[derive(Debug)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    pub fn new(n: usize, fpr: f64) -> BloomFilter {
+        assert!(fpr > 0.0 && fpr < 1.0, "False positive rate must be between 0 and 1");
+        let n = n.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let m = (-n * fpr.ln() / (ln2 * ln2)).ceil().max(64.0);
+        let hashes = (m / n * ln2).round().clamp(1.0, 32.0) as u32;
+        BloomFilter { bits: vec![0; (m as usize).div_ceil(64)], hashes }
+    }
+
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+        for bit in self.bits_of(item) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Whether the item may have been inserted; false only if it definitely wasn't
+    pub fn contains<T: Hash + ?Sized>(&self, item: &T) -> bool {
+        self.bits_of(item).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Bytes the filter takes up
+    pub fn size(&self) -> usize {
+        self.bits.len() * 8
+    }
+
+    fn bits_of<T: Hash + ?Sized>(&self, item: &T) -> impl Iterator<Item = usize> {
+        let hash = |seed: u64| {
+            let mut hasher = DefaultHasher::new();
+            seed.hash(&mut hasher);
+            item.hash(&mut hasher);
+            hasher.finish()
+        };
+        // Double hashing: the bits are h1 + i h2 for two independent hashes, which does about as
+        // well as that many independent hashes
+        let (h1, h2) = (hash(0), hash(1) | 1);
+        let m = self.bits.len() as u64 * 64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % m) as usize)
+    }
+}
+
+#[cfg(test)]
+mod test_hyper_log_log {
+    use super::*;
+
+    #[test]
+    fn test_small() {
+        let mut hll = HyperLogLog::default();
+        for v in ["a", "b", "c", "a", "b"].iter() {
+            hll.insert(v);
+        }
+        assert_eq!(hll.estimate().round(), 3.0);
+    }
+    #[test]
+    fn test_large() {
+        let mut hll = HyperLogLog::default();
+        for i in 0..100_000 {
+            hll.insert(&(i % 50_000).to_string());
+        }
+        let err = (hll.estimate() - 50_000.0).abs() / 50_000.0;
+        assert!(err < 0.03, "estimate off by {}", err);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_bad_precision_fails() {
+        HyperLogLog::new(2);
+    }
+}
+
+#[cfg(test)]
+mod test_space_saving {
+    use super::*;
+
+    #[test]
+    fn test_exact_when_few_values() {
+        let mut ss = SpaceSaving::new(3);
+        for v in ["a", "b", "a", "c", "a", "b"].iter() {
+            ss.insert(v);
+        }
+        assert_eq!(ss.top(), vec![("a", 3, 0), ("b", 2, 0), ("c", 1, 0)]);
+    }
+    #[test]
+    fn test_keeps_heavy_hitter() {
+        let mut ss = SpaceSaving::new(2);
+        for i in 0..100 {
+            ss.insert("x");
+            ss.insert("x");
+            ss.insert(&i.to_string());
+        }
+        let top = ss.top();
+        assert_eq!(top[0].0, "x");
+        assert!(top[0].1 >= 200);
+        assert!(top[0].1 - top[0].2 <= 200);
+    }
+}
+
+#[cfg(test)]
+mod test_bloom_filter {
+    use super::*;
+
+    #[test]
+    fn test_no_false_negatives() {
+        let mut bloom = BloomFilter::new(1000, 0.01);
+        for i in 0..1000 {
+            bloom.insert(&i.to_string());
+        }
+        assert!((0..1000).all(|i| bloom.contains(&i.to_string())));
+    }
+    #[test]
+    fn test_false_positive_rate() {
+        let mut bloom = BloomFilter::new(10_000, 0.01);
+        for i in 0..10_000 {
+            bloom.insert(&vec![i.to_string()]);
+        }
+        let false_positives = (10_000..110_000).filter(|i| bloom.contains(&vec![i.to_string()]))
+            .count();
+        assert!(false_positives < 2000, "{} false positives", false_positives);
+        assert!(bloom.size() < 20_000);
+    }
+    #[test]
+    #[should_panic]
+    fn test_bad_rate_fails() {
+        BloomFilter::new(10, 1.5);
+    }
+}
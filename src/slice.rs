@@ -0,0 +1,220 @@
+use std::cell::Cell;
+use std::io::{self, BufRead, Seek, SeekFrom};
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+/// The part of the input a run is restricted to, so one large file can be split between several
+/// workers
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Slice {
+    /// Byte offset to start reading at
+    pub seek: u64,
+    /// Move forward from `seek` to the start of the next record instead of starting mid-record
+    pub align_record: bool,
+    /// Byte offset before which a record must start to be processed. Records that start before it
+    /// are read to their end even if that's past it, so adjacent byte ranges cover every record
+    /// exactly once.
+    pub end: Option<u64>,
+    /// First and last line (1-indexed, inclusive) to process, counted from where reading starts
+    pub lines: Option<(u64, Option<u64>)>,
+}
+
+impl Slice {
+    /// Whether reading has to start somewhere other than the beginning of the input, which
+    /// needs it to be seekable
+    pub fn needs_seek(&self) -> bool {
+        self.seek > 0
+    }
+
+    /// Positions `reader` at the start of the slice and returns that byte offset
+    pub fn start<R: BufRead + Seek>(&self, reader: &mut R) -> io::Result<u64> {
+        if self.align_record {
+            seek_to_record(reader, self.seek)
+        } else {
+            reader.seek(SeekFrom::Start(self.seek))
+        }
+    }
+
+    /// The lines of `reader` that belong to the slice, without their line endings. `start` is
+    /// the byte offset `reader` is at, as returned by `start`.
+    pub fn lines<R: BufRead>(&self, reader: R, start: u64) -> SliceLines<R> {
+        let (first, last) = self.lines.unwrap_or((1, None));
+        SliceLines { reader, pos: start, end: self.end, first, last, line_no: 0, escapes: None,
+                     last_len: 0, keep_endings: false }
+    }
+}
+
+/// Iterator returned by `Slice::lines`
+pub struct SliceLines<R> {
+    reader: R,
+    pos: u64,
+    end: Option<u64>,
+    first: u64,
+    last: Option<u64>,
+    line_no: u64,
+    /// Set to where the escapes of the line returned last start, when escaping invalid UTF-8
+    escapes: Option<Rc<Cell<Vec<usize>>>>,
+    /// Bytes in the line read last, which the next is read into room for, so a long line isn't
+    /// copied over and over as it's read
+    last_len: usize,
+    /// Leave the line endings on the lines
+    keep_endings: bool,
+}
+
+impl<R> SliceLines<R> {
+    /// Writes bytes that aren't valid UTF-8 as `\\xNN` escapes instead of failing, as
+    /// `line::escape_invalid` does, and keeps `escapes` set to where those of the line returned
+    /// last start
+    pub fn escape_invalid(mut self, escapes: Rc<Cell<Vec<usize>>>) -> SliceLines<R> {
+        self.escapes = Some(escapes);
+        self
+    }
+
+    /// Leaves the line endings on the lines, as `input::raw_lines` does
+    pub fn keep_endings(mut self) -> SliceLines<R> {
+        self.keep_endings = true;
+        self
+    }
+}
+
+impl<R: BufRead> Iterator for SliceLines<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        loop {
+            if self.last.is_some_and(|last| self.line_no >= last)
+                || self.end.is_some_and(|end| self.pos >= end) {
+                return None;
+            }
+            let mut line = String::new();
+            let mut bytes = Vec::new();
+            let read = match &self.escapes {
+                Some(_) => {
+                    bytes.reserve(self.last_len);
+                    self.reader.read_until(b'\n', &mut bytes)
+                },
+                None => {
+                    line.reserve(self.last_len);
+                    self.reader.read_line(&mut line)
+                },
+            };
+            match read {
+                Ok(0) => return None,
+                Ok(n) => {
+                    self.last_len = n;
+                    self.pos += n as u64;
+                    self.line_no += 1;
+                },
+                Err(error) => return Some(Err(error)),
+            }
+            if self.line_no < self.first {
+                continue;
+            }
+            if let Some(escapes) = &self.escapes {
+                let (escaped, at) = crate::line::escape_invalid(&bytes);
+                line = escaped;
+                escapes.set(at);
+            }
+            // Line endings are dropped as by BufRead::lines
+            if !self.keep_endings && line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            return Some(Ok(line));
+        }
+    }
+}
+
+/// Positions `reader` at the first record that starts at or after byte `offset`, and returns its
+/// offset. ccut reads one record per line, so a quote can never hold a record open past the end
+/// of its line, and the next record always starts right after the next newline.
+pub fn seek_to_record<R: BufRead + Seek>(reader: &mut R, offset: u64) -> io::Result<u64> {
+    if offset == 0 {
+        return reader.seek(SeekFrom::Start(0));
+    }
+    // If the byte before `offset` ends a line, a record starts exactly at `offset`
+    reader.seek(SeekFrom::Start(offset - 1))?;
+    let mut skipped = Vec::new();
+    match reader.read_until(b'\n', &mut skipped)? {
+        0 => reader.seek(SeekFrom::Start(offset)),
+        n => Ok(offset - 1 + n as u64),
+    }
+}
+
+#[cfg(test)]
+mod test_seek_to_record {
+    use super::*;
+    use std::io::Cursor;
+
+    fn rest(input: &str, offset: u64, align_record: bool) -> (u64, String) {
+        let mut reader = Cursor::new(input.as_bytes());
+        let slice = Slice { seek: offset, align_record, ..Slice::default() };
+        let start = slice.start(&mut reader).unwrap();
+        let mut res = String::new();
+        io::Read::read_to_string(&mut reader, &mut res).unwrap();
+        (start, res)
+    }
+
+    #[test]
+    fn test_align() {
+        let input = "a,b\n\"c,d\",e\nf,g\n";
+        assert_eq!(rest(input, 0, true), (0, String::from(input)));
+        assert_eq!(rest(input, 2, true), (4, String::from("\"c,d\",e\nf,g\n")));
+        assert_eq!(rest(input, 4, true), (4, String::from("\"c,d\",e\nf,g\n")));
+        assert_eq!(rest(input, 5, true), (12, String::from("f,g\n")));
+        assert_eq!(rest(input, 15, true), (16, String::new()));
+        assert_eq!(rest(input, 100, true), (100, String::new()));
+    }
+    #[test]
+    fn test_unaligned() {
+        assert_eq!(rest("a,b\nc,d\n", 2, false), (2, String::from("b\nc,d\n")));
+    }
+}
+
+#[cfg(test)]
+mod test_slice_lines {
+    use super::*;
+    use std::io::Cursor;
+
+    fn lines(input: &str, slice: Slice) -> Vec<String> {
+        let mut reader = Cursor::new(input.as_bytes());
+        let start = slice.start(&mut reader).unwrap();
+        slice.lines(reader, start).map(|l| l.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_byte_range() {
+        let input = "aa\nbb\ncc\ndd\n";
+        let slice = |seek, end| Slice { seek, align_record: true, end: Some(end), lines: None };
+        assert_eq!(lines(input, slice(0, 4)), vec!["aa", "bb"]);
+        assert_eq!(lines(input, slice(4, 12)), vec!["cc", "dd"]);
+        // Adjacent ranges split mid-record still cover every record once
+        assert_eq!(lines(input, slice(0, 7)), vec!["aa", "bb", "cc"]);
+        assert_eq!(lines(input, slice(7, 12)), vec!["dd"]);
+        assert_eq!(lines(input, slice(3, 3)), Vec::<String>::new());
+    }
+    #[test]
+    fn test_line_range() {
+        let input = "a\r\nb\nc\nd";
+        let slice = |first, last| Slice { lines: Some((first, last)), ..Slice::default() };
+        assert_eq!(lines(input, slice(2, Some(3))), vec!["b", "c"]);
+        assert_eq!(lines(input, slice(3, None)), vec!["c", "d"]);
+        assert_eq!(lines(input, slice(1, Some(1))), vec!["a"]);
+    }
+    #[test]
+    fn test_escape_invalid() {
+        let escapes = Rc::new(Cell::new(Vec::new()));
+        let mut lines = Slice::default().lines(&b"a\xff\r\nb\n"[..], 0)
+            .escape_invalid(escapes.clone());
+        assert_eq!(lines.next().unwrap().unwrap(), "a\\xFF");
+        assert_eq!(escapes.take(), vec![1]);
+        assert_eq!(lines.next().unwrap().unwrap(), "b");
+        assert_eq!(escapes.take(), Vec::<usize>::new());
+        let mut lines = Slice::default().lines(&b"a\xff\n"[..], 0);
+        assert!(lines.next().unwrap().is_err());
+    }
+}
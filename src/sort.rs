@@ -0,0 +1,298 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collate::Collation;
+use crate::dialect::{Dialect, Permissive};
+use crate::expr;
+use crate::line;
+
+/// `--sort COL[:desc]` with its memory and spill settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SortSpec {
+    pub col: usize,
+    pub desc: bool,
+    #[serde(default)]
+    pub collation: Collation,
+    /// Bytes of lines to hold in memory before spilling a sorted run to disk
+    pub mem: usize,
+    /// Where to put spilled runs, instead of the system temp directory
+    pub tmp_dir: Option<PathBuf>,
+}
+
+/// Sorts lines by one column, spilling sorted runs to temp files whenever the buffered lines
+/// exceed the memory limit and merging the runs at the end, so input of any size can be sorted.
+/// The sort is stable: lines with equal keys keep their input order, also when descending. Each
+/// line can bring extra fields along, which come out with it but aren't sorted by.
+pub struct ExternalSorter {
+    spec: SortSpec,
+    buf: Vec<(String, Vec<String>)>,
+    buf_bytes: usize,
+    runs: Vec<File>,
+    dialect: Box<dyn Dialect>,
+}
+
+impl ExternalSorter {
+    pub fn new(spec: SortSpec) -> ExternalSorter {
+        ExternalSorter {
+            spec,
+            buf: Vec::new(),
+            buf_bytes: 0,
+            runs: Vec::new(),
+            dialect: Box::new(Permissive),
+        }
+    }
+
+    /// Splits lines to find their keys as written in `dialect`, rather than permissively
+    pub fn with_dialect(mut self, dialect: Box<dyn Dialect>) -> ExternalSorter {
+        self.dialect = dialect;
+        self
+    }
+
+    pub fn push(&mut self, line: &str, extra: Vec<String>) -> io::Result<()> {
+        self.buf_bytes += line.len() + std::mem::size_of::<String>()
+            + extra.iter().map(|e| e.len() + std::mem::size_of::<String>()).sum::<usize>();
+        self.buf.push((line.to_string(), extra));
+        if self.buf_bytes > self.spec.mem {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Number of runs spilled to disk so far
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Calls `f` with every line in sorted order, and its extra fields
+    pub fn finish<F>(mut self, mut f: F) -> io::Result<()>
+        where F: FnMut(&str, &[String]) -> io::Result<()> {
+        if self.runs.is_empty() {
+            self.sort_buf();
+            for (line, extra) in self.buf.iter() {
+                f(line, extra)?;
+            }
+            return Ok(());
+        }
+        self.spill()?;
+
+        // Multi-way merge: the heap holds the next line of each run
+        let mut readers: Vec<io::Lines<BufReader<File>>> = self.runs.drain(..)
+            .map(|file| BufReader::new(file).lines())
+            .collect();
+        let mut heap: BinaryHeap<Head> = BinaryHeap::with_capacity(readers.len());
+        for (run, reader) in readers.iter_mut().enumerate() {
+            if let Some(record) = reader.next() {
+                heap.push(Head::new(read_spilled(&record?)?, run, &self.spec,
+                                    self.dialect.as_ref()));
+            }
+        }
+        while let Some(head) = heap.pop() {
+            f(&head.line, &head.extra)?;
+            if let Some(record) = readers[head.run].next() {
+                heap.push(Head::new(read_spilled(&record?)?, head.run, &self.spec,
+                                    self.dialect.as_ref()));
+            }
+        }
+        Ok(())
+    }
+
+    fn sort_buf(&mut self) {
+        let spec = &self.spec;
+        let dialect = self.dialect.as_ref();
+        // sort_by_cached_key isn't stable, so build the keys up front and sort by them stably
+        let mut keyed: Vec<(String, (String, Vec<String>))> = self.buf.drain(..)
+            .map(|record| (sort_key(&record.0, spec.col, dialect), record))
+            .collect();
+        keyed.sort_by(|a, b| order(&a.0, &b.0, spec));
+        self.buf = keyed.into_iter().map(|(_, record)| record).collect();
+    }
+
+    /// Writes the buffered lines to a new run, sorted, each with its extra fields as a JSON array
+    /// on a line of its own
+    fn spill(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.sort_buf();
+        let mut file = match &self.spec.tmp_dir {
+            Some(dir) => tempfile::tempfile_in(dir)?,
+            None => tempfile::tempfile()?,
+        };
+        {
+            let mut writer = BufWriter::new(&mut file);
+            for record in self.buf.iter() {
+                serde_json::to_writer(&mut writer, record)?;
+                writeln!(writer)?;
+            }
+            writer.flush()?;
+        }
+        file.seek(SeekFrom::Start(0))?;
+        self.runs.push(file);
+        self.buf.clear();
+        self.buf_bytes = 0;
+        Ok(())
+    }
+}
+
+/// A line and its extra fields as `spill` wrote them
+fn read_spilled(record: &str) -> io::Result<(String, Vec<String>)> {
+    Ok(serde_json::from_str(record)?)
+}
+
+/// The next line of a run during the merge. Ordered so that the max of the heap is the line to
+/// write next; ties go to the earlier run, which holds the earlier input.
+struct Head {
+    key: String,
+    desc: bool,
+    collation: Collation,
+    run: usize,
+    line: String,
+    extra: Vec<String>,
+}
+
+impl Head {
+    fn new((line, extra): (String, Vec<String>), run: usize, spec: &SortSpec,
+           dialect: &dyn Dialect) -> Head {
+        Head {
+            key: sort_key(&line, spec.col, dialect),
+            desc: spec.desc,
+            collation: spec.collation,
+            run,
+            line,
+            extra,
+        }
+    }
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let ord = self.collation.compare(&self.key, &other.key);
+        let ord = if self.desc { ord.reverse() } else { ord };
+        ord.then(self.run.cmp(&other.run)).reverse()
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+/// The value a line is sorted by: its field in column `col`, without quotes
+fn sort_key(line: &str, col: usize, dialect: &dyn Dialect) -> String {
+    let fields = line::split_line_with(line, dialect);
+    expr::unquote(fields.get(col).unwrap_or(&"").trim()).to_string()
+}
+
+fn order(a: &str, b: &str, spec: &SortSpec) -> Ordering {
+    let ord = spec.collation.compare(a, b);
+    if spec.desc { ord.reverse() } else { ord }
+}
+
+#[cfg(test)]
+mod test_external_sorter {
+    use super::*;
+
+    fn sort(lines: &[&str], desc: bool, mem: usize) -> (Vec<String>, usize) {
+        let spec = SortSpec { col: 0, desc, collation: Collation::Bytewise, mem, tmp_dir: None };
+        sort_with(lines, spec)
+    }
+
+    fn sort_with(lines: &[&str], spec: SortSpec) -> (Vec<String>, usize) {
+        let mut sorter = ExternalSorter::new(spec);
+        for line in lines.iter() {
+            sorter.push(line, Vec::new()).unwrap();
+        }
+        let runs = sorter.runs();
+        let mut res = Vec::new();
+        sorter.finish(|line, _| {
+            res.push(line.to_string());
+            Ok(())
+        }).unwrap();
+        (res, runs)
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let (res, runs) = sort(&["b,1", "a,2", "\"c\",3", "a,4"], false, 1 << 20);
+        assert_eq!(runs, 0);
+        assert_eq!(res, vec!["a,2", "a,4", "b,1", "\"c\",3"]);
+    }
+    #[test]
+    fn test_spilled() {
+        let lines: Vec<String> = (0..200).map(|i| format!("{},{}", i % 10, i)).collect();
+        let lines: Vec<&str> = lines.iter().map(|s| s.as_str()).collect();
+        let (in_memory, _) = sort(&lines, false, 1 << 20);
+        let (spilled, runs) = sort(&lines, false, 256);
+        assert!(runs > 1);
+        assert_eq!(spilled, in_memory);
+        // Stable: equal keys keep input order
+        assert_eq!(&spilled[..3], &["0,0", "0,10", "0,20"]);
+    }
+    #[test]
+    fn test_desc_stable() {
+        let lines = ["a,1", "b,2", "a,3", "b,4"];
+        assert_eq!(sort(&lines, true, 1 << 20).0, vec!["b,2", "b,4", "a,1", "a,3"]);
+        assert_eq!(sort(&lines, true, 1).0, vec!["b,2", "b,4", "a,1", "a,3"]);
+    }
+    #[test]
+    fn test_natural() {
+        let lines = ["file10", "file2", "File3", "file1"];
+        for mem in [1, 1 << 20].iter() {
+            let spec = SortSpec {
+                col: 0,
+                desc: false,
+                collation: Collation::Natural,
+                mem: *mem,
+                tmp_dir: None,
+            };
+            assert_eq!(sort_with(&lines, spec).0, vec!["File3", "file1", "file2", "file10"]);
+        }
+    }
+    #[test]
+    fn test_tmp_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = SortSpec {
+            col: 0,
+            desc: false,
+            collation: Collation::Bytewise,
+            mem: 1,
+            tmp_dir: Some(dir.path().into()),
+        };
+        let mut sorter = ExternalSorter::new(spec);
+        sorter.push("b", Vec::new()).unwrap();
+        sorter.push("a", Vec::new()).unwrap();
+        assert_eq!(sorter.runs(), 2);
+    }
+    #[test]
+    fn test_extra_comes_along() {
+        for mem in [1, 1 << 20].iter() {
+            let spec = SortSpec { col: 0, desc: false, collation: Collation::Bytewise, mem: *mem,
+                                  tmp_dir: None };
+            let mut sorter = ExternalSorter::new(spec);
+            sorter.push("b", vec![String::from("2"), String::from("x,\ny")]).unwrap();
+            sorter.push("a", vec![String::from("1")]).unwrap();
+            let mut res = Vec::new();
+            sorter.finish(|line, extra| {
+                res.push((line.to_string(), extra.to_vec()));
+                Ok(())
+            }).unwrap();
+            assert_eq!(res, vec![(String::from("a"), vec![String::from("1")]),
+                                 (String::from("b"), vec![String::from("2"),
+                                                          String::from("x,\ny")])]);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use crate::input;
+
+/// Input that can be read from the beginning more than once, for features that need a full pass
+/// over the data before they can produce any output. A regular file is simply rewound; anything
+/// else (a pipe or a terminal) is first copied into an anonymous temp file.
+pub struct Spool {
+    file: File,
+}
+
+impl Spool {
+    /// Makes stdin rewindable. If stdin is redirected from a regular file we reopen it rather than
+    /// copying it, unless it's compressed, `buffer_bytes` at a time if set.
+    pub fn from_stdin(buffer_bytes: Option<usize>) -> io::Result<Spool> {
+        if let Ok(file) = File::open("/dev/stdin") {
+            if file.metadata()?.is_file() && !input::is_compressed(&file)? {
+                return Ok(Spool { file });
+            }
+        }
+        Spool::from_reader(input::decompress(Box::new(io::stdin().lock()), buffer_bytes)?)
+    }
+
+    /// Makes the inputs rewindable as one. A single uncompressed file is read in place; several
+    /// inputs, or stdin or a compressed file among them, are copied, decompressed, and read
+    /// `buffer_bytes` at a time if set.
+    pub fn from_inputs(paths: &[PathBuf], buffer_bytes: Option<usize>) -> io::Result<Spool> {
+        if paths.is_empty() || paths.len() == 1 && paths[0].as_os_str() == input::STDIN {
+            return Spool::from_stdin(buffer_bytes);
+        }
+        if let Some(path) = input::single_file(paths) {
+            let file = File::open(path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            if file.metadata()?.is_file() && !input::is_compressed(&file)? {
+                return Ok(Spool { file });
+            }
+        }
+        Spool::from_reader(input::Concat::new(input::open(paths, buffer_bytes)?))
+    }
+
+    /// Copies everything from `reader` into a temp file
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Spool> {
+        let mut file = tempfile::tempfile()?;
+        io::copy(&mut reader, &mut file)?;
+        Ok(Spool { file })
+    }
+
+    /// The underlying file, e.g. for reading it at several positions at once
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Returns a reader positioned at the start of the input
+    pub fn rewind(&mut self) -> io::Result<BufReader<&File>> {
+        self.file.seek(SeekFrom::Start(0))?;
+        Ok(BufReader::new(&self.file))
+    }
+}
+
+/// Output held back in a temp file rather than written straight through, for runs that must not
+/// write anything unless they get to the end without failing. Dropping it discards the output.
+pub struct Held<W: Write> {
+    file: BufWriter<File>,
+    inner: W,
+}
+
+impl<W: Write> Held<W> {
+    pub fn new(inner: W) -> io::Result<Held<W>> {
+        Ok(Held { file: BufWriter::new(tempfile::tempfile()?), inner })
+    }
+
+    /// Writes everything held back to the writer behind it, and returns that
+    pub fn release(self) -> io::Result<W> {
+        let Held { file, mut inner } = self;
+        let mut file = file.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(0))?;
+        io::copy(&mut file, &mut inner)?;
+        inner.flush()?;
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for Held<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod test_spool {
+    use super::*;
+    use std::io::BufRead;
+
+    #[test]
+    fn test_read_twice() {
+        let mut spool = Spool::from_reader("a,b\nc,d\n".as_bytes()).unwrap();
+        let first: Vec<String> = spool.rewind().unwrap().lines().map(|l| l.unwrap()).collect();
+        let second: Vec<String> = spool.rewind().unwrap().lines().map(|l| l.unwrap()).collect();
+        assert_eq!(first, vec!["a,b", "c,d"]);
+        assert_eq!(first, second);
+    }
+    #[test]
+    fn test_held() {
+        let mut held = Held::new(Vec::new()).unwrap();
+        held.write_all(b"a,b\n").unwrap();
+        held.flush().unwrap();
+        assert_eq!(held.inner, b"");
+        assert_eq!(held.release().unwrap(), b"a,b\n");
+    }
+}
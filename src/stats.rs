@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::duration;
+
+/// Patterns a column's values are checked against to flag likely personal data. Each has to
+/// match a whole value. Phone numbers need at least 10 digits, so they aren't confused with SSNs
+/// or short numbers.
+pub const PII_PATTERNS: &[(&str, &str)] = &[
+    ("email", r"^[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}$"),
+    ("phone", r"^\+?(?:\d[ .()-]{0,2}){9,14}\d$"),
+    ("ssn", r"^\d{3}-\d{2}-\d{4}$"),
+];
+
+/// Share of a column's non-empty values that must match a PII pattern for it to be flagged
+pub const PII_THRESHOLD: f64 = 0.5;
+
+/// Counts of every value of one column
+#[derive(Debug, Default)]
+pub struct ColumnStats {
+    counts: HashMap<String, u64>,
+    count: u64,
+    empty: u64,
+    pii: Vec<u64>,
+}
+
+impl ColumnStats {
+    /// Non-empty values seen
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn empty(&self) -> u64 {
+        self.empty
+    }
+
+    pub fn distinct(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Distinct values over non-empty values: 1 for a key column, near 0 for a category
+    pub fn uniqueness(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.counts.len() as f64 / self.count as f64 }
+    }
+
+    /// Shannon entropy of the non-empty values, in bits
+    pub fn entropy(&self) -> f64 {
+        let n = self.count as f64;
+        self.counts.values()
+            .map(|c| *c as f64 / n * (n / *c as f64).log2())
+            .sum()
+    }
+}
+
+/// Gathers `ColumnStats` for every column of the records it's given
+pub struct Stats {
+    pii: Vec<(&'static str, Regex)>,
+    columns: Vec<ColumnStats>,
+}
+
+impl Default for Stats {
+    fn default() -> Stats {
+        Stats {
+            pii: PII_PATTERNS.iter()
+                .map(|(name, re)| (*name, Regex::new(re).unwrap()))
+                .collect(),
+            columns: Vec::new(),
+        }
+    }
+}
+
+impl Stats {
+    /// Counts one record's values, already unquoted
+    pub fn insert<S: AsRef<str>>(&mut self, values: &[S]) {
+        if self.columns.len() < values.len() {
+            self.columns.resize_with(values.len(), ColumnStats::default);
+        }
+        for (col, value) in self.columns.iter_mut().zip(values) {
+            let value = value.as_ref();
+            if value.is_empty() {
+                col.empty += 1;
+                continue;
+            }
+            col.count += 1;
+            match col.counts.get_mut(value) {
+                Some(c) => *c += 1,
+                None => {
+                    col.counts.insert(value.to_string(), 1);
+                },
+            }
+            if col.pii.is_empty() {
+                col.pii = vec![0; self.pii.len()];
+            }
+            for (hits, (_, re)) in col.pii.iter_mut().zip(self.pii.iter()) {
+                if re.is_match(value) {
+                    *hits += 1;
+                }
+            }
+        }
+    }
+
+    pub fn columns(&self) -> &[ColumnStats] {
+        &self.columns
+    }
+
+    /// Names of the PII patterns most of a column's values match
+    pub fn pii_flags(&self, col: usize) -> Vec<&'static str> {
+        let stats = &self.columns[col];
+        stats.pii.iter().zip(self.pii.iter())
+            .filter(|(hits, _)| **hits as f64 >= PII_THRESHOLD * stats.count as f64)
+            .map(|(_, (name, _))| *name)
+            .collect()
+    }
+}
+
+/// How often `--stats-every` prints the statistics so far: every so many rows, or seconds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Every {
+    Rows(u64),
+    Secs(f64),
+}
+
+/// Parses a `--stats-every` argument such as `100000rows` or `1m`
+pub fn parse_every(s: &str) -> Every {
+    let s = s.trim();
+    let every = match s.strip_suffix("rows") {
+        Some(n) => n.trim().parse().ok().filter(|n| *n > 0).map(Every::Rows),
+        None => duration::parse_duration(s).filter(|secs| *secs > 0.0).map(Every::Secs),
+    };
+    every.unwrap_or_else(|| panic!("Invalid --stats-every {}, expected e.g. 100000rows or 1m", s))
+}
+
+/// Says when the statistics are next due under `Every`. Time is only checked as rows come in, so
+/// nothing is printed while the input is idle.
+#[derive(Debug, Clone)]
+pub struct Ticker {
+    every: Every,
+    rows: u64,
+    last: Instant,
+}
+
+impl Ticker {
+    pub fn new(every: Every) -> Ticker {
+        Ticker { every, rows: 0, last: Instant::now() }
+    }
+
+    /// Counts a row, returning whether the statistics are due
+    pub fn tick(&mut self) -> bool {
+        self.rows += 1;
+        let due = match self.every {
+            Every::Rows(n) => self.rows.is_multiple_of(n),
+            Every::Secs(secs) => self.last.elapsed() >= Duration::from_secs_f64(secs),
+        };
+        if due {
+            self.last = Instant::now();
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod test_stats {
+    use super::*;
+
+    fn stats(rows: &[&[&str]]) -> Stats {
+        let mut stats = Stats::default();
+        for row in rows.iter() {
+            stats.insert(row);
+        }
+        stats
+    }
+
+    #[test]
+    fn test_entropy_and_uniqueness() {
+        let stats = stats(&[&["a", "1"], &["b", "1"], &["c", "2"], &["d", "2"]]);
+        let (key, category) = (&stats.columns()[0], &stats.columns()[1]);
+        assert_eq!(key.distinct(), 4);
+        assert_eq!(key.uniqueness(), 1.0);
+        assert!((key.entropy() - 2.0).abs() < 1e-9);
+        assert_eq!(category.uniqueness(), 0.5);
+        assert!((category.entropy() - 1.0).abs() < 1e-9);
+    }
+    #[test]
+    fn test_constant_and_empty() {
+        let stats = stats(&[&["x", ""], &["x", ""], &["x"]]);
+        assert_eq!(stats.columns()[0].entropy(), 0.0);
+        assert_eq!(stats.columns()[1].count(), 0);
+        assert_eq!(stats.columns()[1].empty(), 2);
+        assert_eq!(stats.columns()[1].uniqueness(), 0.0);
+        assert!(stats.pii_flags(1).is_empty());
+    }
+    #[test]
+    fn test_pii_flags() {
+        let stats = stats(&[
+            &["ann@example.com", "+1 (555) 123-4567", "123-45-6789", "12"],
+            &["bob@example.org", "555.987.6543", "987-65-4321", "7"],
+            &["n/a", "", "", "3"],
+        ]);
+        assert_eq!(stats.pii_flags(0), vec!["email"]);
+        assert_eq!(stats.pii_flags(1), vec!["phone"]);
+        assert_eq!(stats.pii_flags(2), vec!["ssn"]);
+        assert!(stats.pii_flags(3).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod test_every {
+    use super::*;
+
+    #[test]
+    fn test_parse_every() {
+        assert_eq!(parse_every("100000rows"), Every::Rows(100000));
+        assert_eq!(parse_every("1m"), Every::Secs(60.0));
+    }
+    #[test]
+    #[should_panic]
+    fn test_parse_every_fails() {
+        parse_every("0rows");
+    }
+    #[test]
+    fn test_ticker() {
+        let mut ticker = Ticker::new(Every::Rows(2));
+        assert_eq!((0..5).map(|_| ticker.tick()).collect::<Vec<_>>(),
+                   vec![false, true, false, true, false]);
+        let mut ticker = Ticker::new(Every::Secs(3600.0));
+        assert!(!ticker.tick());
+    }
+}
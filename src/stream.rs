@@ -0,0 +1,61 @@
+use std::io;
+
+use futures::future;
+use futures::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use futures::stream::{Stream, StreamExt};
+
+use crate::pipeline::Pipeline;
+
+/// Reads lines from an async source and yields each record that passes `pipeline`'s filters,
+/// transformed and cut, as owned fields. Nothing blocks: records are produced as lines arrive.
+/// Totals for computed columns that need them must already have been gathered.
+pub fn records<'a, R>(reader: R, pipeline: &'a Pipeline)
+                      -> impl Stream<Item = io::Result<Vec<String>>> + 'a
+    where R: AsyncRead + Unpin + 'a {
+    BufReader::new(reader).lines().filter_map(move |line| future::ready(match line {
+        Ok(line) => cut(pipeline, &line).transpose(),
+        Err(error) => Some(Err(error)),
+    }))
+}
+
+/// Yields the raw fields of each line as split by `pipeline`'s dialect, without filtering or
+/// cutting
+pub fn split_records<'a, R>(reader: R, pipeline: &'a Pipeline)
+                            -> impl Stream<Item = io::Result<Vec<String>>> + 'a
+    where R: AsyncRead + Unpin + 'a {
+    BufReader::new(reader).lines().map(move |line| {
+        line.map(|line| pipeline.split(&line).iter().map(|f| f.to_string()).collect())
+    })
+}
+
+fn cut(pipeline: &Pipeline, line: &str) -> io::Result<Option<Vec<String>>> {
+    if !pipeline.keep(line) {
+        return Ok(None);
+    }
+    let mut out: Vec<Vec<String>> = Vec::with_capacity(1);
+    pipeline.cut(line, &mut out)?;
+    Ok(out.pop())
+}
+
+#[cfg(test)]
+mod test_records {
+    use super::*;
+    use crate::expr::parse_expr;
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_records() {
+        let pipeline = Pipeline::new().select(vec![1]).filter(parse_expr("col1 != \"b\"", 1));
+        let input: &[u8] = b"a,1\nb,2\nc,3\n";
+        let res: Vec<io::Result<Vec<String>>> = block_on(records(input, &pipeline).collect());
+        let res: Vec<Vec<String>> = res.into_iter().map(|r| r.unwrap()).collect();
+        assert_eq!(res, vec![vec!["1"], vec!["3"]]);
+    }
+    #[test]
+    fn test_split_records() {
+        let pipeline = Pipeline::new();
+        let input: &[u8] = b"a,'b,c'\n";
+        let res: Vec<io::Result<Vec<String>>> = block_on(split_records(input, &pipeline).collect());
+        assert_eq!(res[0].as_ref().unwrap(), &vec!["a", "'b,c'"]);
+    }
+}
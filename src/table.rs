@@ -0,0 +1,81 @@
+use crate::expr;
+
+/// Rows `--table` reads before settling the width of each column
+pub const SAMPLE_ROWS: usize = 1000;
+
+/// Lines records up into columns for reading, as `column -t` does but without splitting quoted
+/// fields. The widths are worked out from the first rows, which are held back until there are
+/// enough of them; a later value that's wider than its column pushes the rest of its row over.
+#[derive(Debug, Clone)]
+pub struct Table {
+    sample: usize,
+    widths: Vec<usize>,
+    /// The rows held back while the widths are worked out, `None` once they're settled
+    held: Option<Vec<Vec<String>>>,
+}
+
+impl Table {
+    pub fn new(sample: usize) -> Table {
+        Table { sample, widths: Vec::new(), held: Some(Vec::new()) }
+    }
+
+    /// Takes a row, unquoting its fields, and returns the rows that are ready to write, padded
+    pub fn push(&mut self, fields: &[&str]) -> Vec<Vec<String>> {
+        let row: Vec<String> = fields.iter().map(|f| expr::unquote(f).to_string()).collect();
+        match &mut self.held {
+            Some(held) => {
+                for (i, value) in row.iter().enumerate() {
+                    let width = value.chars().count();
+                    match self.widths.get_mut(i) {
+                        Some(w) => *w = (*w).max(width),
+                        None => self.widths.push(width),
+                    }
+                }
+                held.push(row);
+                if held.len() >= self.sample { self.finish() } else { Vec::new() }
+            },
+            None => vec![self.pad(row)],
+        }
+    }
+
+    /// Returns the rows still held back, padded. Called after the last row.
+    pub fn finish(&mut self) -> Vec<Vec<String>> {
+        let held = self.held.take().unwrap_or_default();
+        held.into_iter().map(|row| self.pad(row)).collect()
+    }
+
+    /// Pads every field but the last to the width of its column, dropping empty fields at the end
+    /// so lines don't end in spaces
+    fn pad(&self, mut row: Vec<String>) -> Vec<String> {
+        let n = row.iter().rposition(|v| !v.is_empty()).map_or(0, |i| i + 1);
+        row.truncate(n);
+        for (i, value) in row.iter_mut().enumerate().take(n.saturating_sub(1)) {
+            let width = self.widths.get(i).copied().unwrap_or(0);
+            let len = value.chars().count();
+            value.extend(std::iter::repeat_n(' ', width.saturating_sub(len)));
+        }
+        row
+    }
+}
+
+#[cfg(test)]
+mod test_table {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let mut table = Table::new(2);
+        assert!(table.push(&["id", "\"Name, full\"", "x"]).is_empty());
+        assert_eq!(table.push(&["1", "né", "y"]),
+                   vec![vec!["id", "Name, full", "x"], vec!["1 ", "né        ", "y"]]);
+        assert_eq!(table.push(&["123", "b", ""]), vec![vec!["123", "b"]]);
+        assert!(table.finish().is_empty());
+    }
+    #[test]
+    fn test_short_input() {
+        let mut table = Table::new(SAMPLE_ROWS);
+        assert!(table.push(&["a", "bb"]).is_empty());
+        assert!(table.push(&["ccc"]).is_empty());
+        assert_eq!(table.finish(), vec![vec!["a  ", "bb"], vec!["ccc"]]);
+    }
+}
@@ -0,0 +1,136 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::dialect::{Dialect, Permissive};
+use crate::expr;
+use crate::line;
+
+/// Keeps the `n` lines with the smallest (or, if `desc`, the largest) numeric values in column
+/// `col`, without holding the rest of the input in memory. Lines whose value isn't numeric are
+/// skipped. Ties are broken by input order so the output is deterministic. Each line can bring
+/// extra fields along, which come out with it but aren't looked at.
+pub struct TopN {
+    n: usize,
+    col: usize,
+    desc: bool,
+    seq: usize,
+    heap: BinaryHeap<Entry>,
+    dialect: Box<dyn Dialect>,
+}
+
+/// A kept line. Entries are ordered so that the max of the heap is the worst kept line, i.e. the
+/// next one to evict.
+struct Entry {
+    rank: f64,
+    seq: usize,
+    line: String,
+    extra: Vec<String>,
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.rank.total_cmp(&other.rank).then(self.seq.cmp(&other.seq))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl TopN {
+    pub fn new(n: usize, col: usize, desc: bool) -> TopN {
+        TopN {
+            n,
+            col,
+            desc,
+            seq: 0,
+            heap: BinaryHeap::with_capacity(n + 1),
+            dialect: Box::new(Permissive),
+        }
+    }
+
+    /// Splits lines as written in `dialect`, rather than permissively
+    pub fn with_dialect(mut self, dialect: Box<dyn Dialect>) -> TopN {
+        self.dialect = dialect;
+        self
+    }
+
+    pub fn push(&mut self, line: &str, extra: &[String]) {
+        let fields = line::split_line_with(line, self.dialect.as_ref());
+        let x = match fields.get(self.col).and_then(|f| expr::parse_num(f)) {
+            Some(x) => x,
+            None => return,
+        };
+        let rank = if self.desc { -x } else { x };
+        self.seq += 1;
+
+        // Only copy the line if it's going to be kept
+        if self.heap.len() == self.n {
+            match self.heap.peek() {
+                Some(worst) if rank < worst.rank => {},
+                _ => return,
+            }
+        }
+        self.heap.push(Entry { rank, seq: self.seq, line: line.to_string(),
+                               extra: extra.to_vec() });
+        if self.heap.len() > self.n {
+            self.heap.pop();
+        }
+    }
+
+    /// Returns the kept lines with their extra fields, best first
+    pub fn into_sorted(self) -> Vec<(String, Vec<String>)> {
+        self.heap.into_sorted_vec().into_iter().map(|e| (e.line, e.extra)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test_top_n {
+    use super::*;
+
+    fn top(lines: &[&str], n: usize, col: usize, desc: bool) -> Vec<String> {
+        let mut t = TopN::new(n, col, desc);
+        for l in lines.iter() {
+            t.push(l, &[]);
+        }
+        t.into_sorted().into_iter().map(|(line, _)| line).collect()
+    }
+
+    #[test]
+    fn test_largest() {
+        let res = top(&["a,5", "b,1", "c,9", "d,7"], 2, 1, true);
+        assert_eq!(res, vec!["c,9", "d,7"]);
+    }
+    #[test]
+    fn test_smallest() {
+        let res = top(&["a,5", "b,1", "c,9", "d,7"], 3, 1, false);
+        assert_eq!(res, vec!["b,1", "a,5", "d,7"]);
+    }
+    #[test]
+    fn test_ties_keep_input_order() {
+        let res = top(&["a,1", "b,1", "c,1"], 2, 1, true);
+        assert_eq!(res, vec!["a,1", "b,1"]);
+    }
+    #[test]
+    fn test_skips_non_numeric() {
+        let res = top(&["name,value", "a,2", "b", "c,\"3\""], 5, 1, true);
+        assert_eq!(res, vec!["c,\"3\"", "a,2"]);
+    }
+    #[test]
+    fn test_extra_comes_along() {
+        let mut t = TopN::new(1, 1, true);
+        t.push("a,5", &[String::from("x")]);
+        t.push("b,9", &[String::from("y,z")]);
+        assert_eq!(t.into_sorted(), vec![(String::from("b,9"), vec![String::from("y,z")])]);
+    }
+}
@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// How `--digest-only` digests the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestAlgorithm {
+    Sha256,
+}
+
+impl FromStr for DigestAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DigestAlgorithm, String> {
+        match s.trim() {
+            "sha256" => Ok(DigestAlgorithm::Sha256),
+            other => Err(format!("unsupported digest algorithm {}, expected sha256", other)),
+        }
+    }
+}
+
+/// Marks the trailer line, which starts with `#` like a comment so most readers can be told to
+/// skip it
+const TRAILER_PREFIX: &str = "#ccut-trailer";
+
+/// The line appended by `--trailer-checksum`, holding the number of records before it and the
+/// SHA-256 of all the bytes before it
+pub fn format_trailer(rows: u64, sha256: &str) -> String {
+    format!("{} rows={} sha256={}", TRAILER_PREFIX, rows, sha256)
+}
+
+/// Reads a line written by `format_trailer` back into its row count and digest
+pub fn parse_trailer(line: &str) -> Option<(u64, String)> {
+    let mut parts = line.trim_end().split(' ');
+    if parts.next() != Some(TRAILER_PREFIX) {
+        return None;
+    }
+    let rows = parts.next()?.strip_prefix("rows=")?.parse().ok()?;
+    let sha256 = parts.next()?.strip_prefix("sha256=")?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((rows, sha256.to_string()))
+}
+
+/// Writes a `.sha256` sidecar for the output `name`, in the format `sha256sum --check` reads,
+/// which skips the leading `#` line with the row count
+pub fn write_sidecar(path: &Path, name: &str, rows: u64, sha256: &str) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "# rows={}", rows)?;
+    writeln!(file, "{}  {}", sha256, name)
+}
+
+#[cfg(test)]
+mod test_trailer {
+    use super::*;
+
+    #[test]
+    fn test_parse_algorithm() {
+        assert_eq!("sha256".parse(), Ok(DigestAlgorithm::Sha256));
+        assert!("md5".parse::<DigestAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let line = format_trailer(3, "abc123");
+        assert_eq!(line, "#ccut-trailer rows=3 sha256=abc123");
+        assert_eq!(parse_trailer(&line), Some((3, String::from("abc123"))));
+        assert_eq!(parse_trailer(&format!("{}\n", line)), Some((3, String::from("abc123"))));
+    }
+    #[test]
+    fn test_not_a_trailer() {
+        assert_eq!(parse_trailer("a,b,c"), None);
+        assert_eq!(parse_trailer("#ccut-trailer rows=x sha256=abc"), None);
+        assert_eq!(parse_trailer("#ccut-trailer rows=1 sha256=abc extra"), None);
+    }
+    #[test]
+    fn test_sidecar() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.csv.sha256");
+        write_sidecar(&path, "out.csv", 2, "abc123").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "# rows=2\nabc123  out.csv\n");
+    }
+}
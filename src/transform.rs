@@ -0,0 +1,139 @@
+use std::borrow::Cow;
+
+use chrono::{DateTime, LocalResult, SecondsFormat, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::key;
+use crate::limits;
+
+/// A rewrite of one field, applied to each record after it's split and before columns are cut or
+/// computed. Fields a transform doesn't understand (such as a header row) are left as they are.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Transform {
+    /// Converts a timestamp column between time zones. Timestamps that carry their own UTC offset
+    /// ignore `from`.
+    Tz { col: usize, from: Tz, to: Tz },
+    /// Removes control characters, such as NUL, vertical tabs and stray carriage returns, from
+    /// every field. Tabs and line breaks are kept.
+    StripControl,
+    /// Writes control characters in every field as `\\xNN` escapes instead, to see where they are
+    ShowControl,
+    /// Replaces line breaks within every field with `token`, so each record is written on one line
+    ReplaceNewlines { token: String },
+    /// Cuts every field down to at most `max_bytes` as written, as `limits::truncate_field` does
+    TruncateCells { max_bytes: usize },
+}
+
+impl Transform {
+    pub fn apply(&self, fields: &mut [Cow<str>]) {
+        match self {
+            Transform::Tz { col, from, to } => {
+                if let Some(field) = fields.get_mut(*col) {
+                    if let Some(res) = convert_tz(field, from, to) {
+                        *field = Cow::Owned(res);
+                    }
+                }
+            },
+            Transform::StripControl | Transform::ShowControl => {
+                for field in fields.iter_mut().filter(|f| f.contains(is_stray_control)) {
+                    let res = field.chars()
+                        .map(|c| match c {
+                            c if !is_stray_control(c) => c.to_string(),
+                            _ if *self == Transform::StripControl => String::new(),
+                            c => format!("\\x{:02X}", c as u32),
+                        })
+                        .collect();
+                    *field = Cow::Owned(res);
+                }
+            },
+            Transform::TruncateCells { max_bytes } => {
+                for field in fields.iter_mut().filter(|f| f.len() > *max_bytes) {
+                    *field = Cow::Owned(limits::truncate_field(field, *max_bytes));
+                }
+            },
+            Transform::ReplaceNewlines { token } => {
+                for field in fields.iter_mut().filter(|f| f.contains('\n')) {
+                    *field = Cow::Owned(field.replace("\r\n", "\n").replace('\n', token));
+                }
+            },
+        }
+    }
+}
+
+/// Applies each transform in turn to a split record
+pub fn apply_all<'a>(transforms: &[Transform], fields: &[&'a str]) -> Vec<Cow<'a, str>> {
+    let mut res: Vec<Cow<str>> = fields.iter().map(|f| Cow::Borrowed(*f)).collect();
+    for t in transforms.iter() {
+        t.apply(&mut res);
+    }
+    res
+}
+
+/// Control characters that don't belong in a field: all but tabs and line breaks
+fn is_stray_control(c: char) -> bool {
+    c.is_control() && c != '\t' && c != '\n'
+}
+
+/// Converts a timestamp to the `to` time zone, formatted as RFC 3339
+fn convert_tz(field: &str, from: &Tz, to: &Tz) -> Option<String> {
+    let dt = match DateTime::parse_from_rfc3339(crate::expr::unquote(field.trim())) {
+        Ok(dt) => dt.with_timezone(to),
+        Err(_) => {
+            let naive = key::parse_datetime(field)?;
+            match from.from_local_datetime(&naive) {
+                // For times repeated by a DST change, take the earlier one
+                LocalResult::Single(dt) | LocalResult::Ambiguous(dt, _) => dt.with_timezone(to),
+                LocalResult::None => return None,
+            }
+        },
+    };
+    Some(dt.to_rfc3339_opts(SecondsFormat::AutoSi, false))
+}
+
+#[cfg(test)]
+mod test_transform {
+    use super::*;
+
+    fn tz(col: usize, from: &str, to: &str) -> Transform {
+        Transform::Tz { col, from: from.parse().unwrap(), to: to.parse().unwrap() }
+    }
+
+    #[test]
+    fn test_tz() {
+        let t = tz(1, "UTC", "America/New_York");
+        let res = apply_all(&[t], &["a", "2020-07-01 12:00:00", "b"]);
+        assert_eq!(res, vec!["a", "2020-07-01T08:00:00-04:00", "b"]);
+    }
+    #[test]
+    fn test_tz_with_offset_ignores_from() {
+        let t = tz(0, "Asia/Tokyo", "UTC");
+        let res = apply_all(&[t], &["2020-01-01T12:00:00+01:00"]);
+        assert_eq!(res, vec!["2020-01-01T11:00:00+00:00"]);
+    }
+    #[test]
+    fn test_tz_passes_through_non_dates() {
+        let t = [tz(0, "UTC", "Europe/Paris")];
+        assert_eq!(apply_all(&t, &["timestamp"]), vec!["timestamp"]);
+        assert_eq!(apply_all(&t, &[]), Vec::<Cow<str>>::new());
+    }
+    #[test]
+    fn test_control() {
+        let fields = ["a\u{0}b", "c\u{b}\r", "d\te\nf", "\u{85}"];
+        assert_eq!(apply_all(&[Transform::StripControl], &fields), vec!["ab", "c", "d\te\nf", ""]);
+        assert_eq!(apply_all(&[Transform::ShowControl], &fields),
+                   vec!["a\\x00b", "c\\x0B\\x0D", "d\te\nf", "\\x85"]);
+    }
+    #[test]
+    fn test_replace_newlines() {
+        let t = [Transform::ReplaceNewlines { token: String::from("\u{2424}") }];
+        assert_eq!(apply_all(&t, &["\"a\r\nb\nc\"", "d\re"]),
+                   vec!["\"a\u{2424}b\u{2424}c\"", "d\re"]);
+    }
+    #[test]
+    fn test_tz_nonexistent_local_time() {
+        // 02:30 doesn't exist in New York on the day clocks go forward
+        let t = tz(0, "America/New_York", "UTC");
+        assert_eq!(apply_all(&[t], &["2020-03-08 02:30"]), vec!["2020-03-08 02:30"]);
+    }
+}
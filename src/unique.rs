@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collate;
+use crate::expr;
+use crate::group::composite_key;
+use crate::line;
+
+/// `--unique` or `--unique-by`: which records are duplicates of one before them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Unique {
+    /// Records that would be written the same
+    Rows,
+    /// Records with the same values in these columns, as `parse_arg_cols` gives them
+    By(Vec<usize>),
+}
+
+/// Drops every record but the first of each key, remembering each key seen in memory
+#[derive(Debug, Clone)]
+pub struct Dedup {
+    spec: Unique,
+    fold_case: bool,
+    seen: HashSet<String>,
+}
+
+impl Dedup {
+    /// With `fold_case`, values that differ only in case are the same
+    pub fn new(spec: Unique, fold_case: bool) -> Dedup {
+        Dedup { spec, fold_case, seen: HashSet::new() }
+    }
+
+    pub fn spec(&self) -> &Unique {
+        &self.spec
+    }
+
+    /// With `Unique::By`, whether a record, split into its input fields, is the first with its
+    /// values in the key columns. Values are compared unquoted, and a missing column is empty.
+    pub fn first_record(&mut self, fields: &[&str]) -> bool {
+        let cols = match &self.spec {
+            Unique::By(cols) => line::resolve_cols(cols, fields.len()),
+            Unique::Rows => panic!("--unique compares records as they're written"),
+        };
+        let values: Vec<String> = cols.iter()
+            .map(|c| {
+                let value = expr::unquote(fields.get(*c).unwrap_or(&"").trim());
+                if self.fold_case { collate::fold_case(value).into_owned() } else { value.into() }
+            })
+            .collect();
+        self.seen.insert(composite_key(&values, KEY_SEP))
+    }
+
+    /// With `Unique::Rows`, whether the records a record is written as, field for field, are the
+    /// first written so
+    pub fn first_output(&mut self, records: &[Vec<String>]) -> bool {
+        let records: Vec<String> = records.iter()
+            .map(|r| if self.fold_case {
+                let folded: Vec<String> = r.iter()
+                    .map(|f| collate::fold_case(f).into_owned())
+                    .collect();
+                composite_key(&folded, KEY_SEP)
+            } else {
+                composite_key(r, KEY_SEP)
+            })
+            .collect();
+        self.seen.insert(composite_key(&records, RECORD_SEP))
+    }
+}
+
+/// Separates the values of a key, and the records of `first_output`; `composite_key` escapes
+/// them within values, so different keys never join the same
+const KEY_SEP: &str = "\u{1f}";
+const RECORD_SEP: &str = "\u{1e}";
+
+#[cfg(test)]
+mod test_unique {
+    use super::*;
+    use crate::parse_args::parse_arg_cols;
+
+    fn strings(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn test_by() {
+        let mut dedup = Dedup::new(Unique::By(parse_arg_cols("1,-1", 1)), false);
+        assert!(dedup.first_record(&["a", "x", "1"]));
+        assert!(!dedup.first_record(&["\"a\"", "y", " 1"]));
+        assert!(dedup.first_record(&["A", "x", "1"]));
+        assert!(!dedup.first_record(&["a", "1"]));
+    }
+    #[test]
+    fn test_fold_case() {
+        let mut dedup = Dedup::new(Unique::By(vec![0]), true);
+        assert!(dedup.first_record(&["Straße"]));
+        assert!(!dedup.first_record(&["STRASSE"]));
+    }
+    #[test]
+    fn test_rows() {
+        let mut dedup = Dedup::new(Unique::Rows, false);
+        assert!(dedup.first_output(&[strings(&["a", "b"])]));
+        assert!(!dedup.first_output(&[strings(&["a", "b"])]));
+        assert!(dedup.first_output(&[strings(&["a\u{1f}b"])]));
+        assert!(dedup.first_output(&[strings(&["a"]), strings(&["b"])]));
+        assert!(dedup.first_output(&[strings(&["\"a\"", "b"])]));
+    }
+}
+
+#[cfg(test)]
+mod test_first_output {
+    use super::*;
+
+    fn strings(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn test_fold_case() {
+        let mut dedup = Dedup::new(Unique::Rows, true);
+        assert!(dedup.first_output(&[strings(&["a", "Straße"])]));
+        assert!(!dedup.first_output(&[strings(&["A", "STRASSE"])]));
+        assert!(dedup.first_output(&[strings(&["b", "Straße"])]));
+        let mut dedup = Dedup::new(Unique::Rows, false);
+        assert!(dedup.first_output(&[strings(&["a"])]));
+        assert!(dedup.first_output(&[strings(&["A"])]));
+    }
+}
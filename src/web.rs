@@ -0,0 +1,133 @@
+/// The parts of a URL that are commonly split out into their own columns
+#[derive(Debug, Default, PartialEq)]
+pub struct UrlParts<'a> {
+    pub scheme: &'a str,
+    pub host: &'a str,
+    pub path: &'a str,
+    pub query: &'a str,
+}
+
+/// Splits a URL into scheme, host (without user info or port), path and query (without the `?`).
+/// Missing parts are empty. This is a lenient splitter for log data, not a validating parser.
+pub fn split_url(url: &str) -> UrlParts<'_> {
+    let url = crate::expr::unquote(url.trim()).trim();
+    let mut parts = UrlParts::default();
+
+    // Drop the fragment, which never reaches the server anyway
+    let url = url.split('#').next().unwrap();
+    let (url, query) = match url.find('?') {
+        Some(i) => (&url[..i], &url[i + 1..]),
+        None => (url, ""),
+    };
+    parts.query = query;
+
+    let rest = match url.find("://") {
+        Some(i) => {
+            parts.scheme = &url[..i];
+            &url[i + 3..]
+        },
+        None if url.starts_with("//") => &url[2..],
+        None => {
+            parts.path = url;
+            return parts;
+        },
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, ""),
+    };
+    parts.path = path;
+    let host = authority.rsplit('@').next().unwrap();
+    parts.host = if host.starts_with('[') {
+        // IPv6 literal, e.g. [::1]:8080
+        host.split(']').next().unwrap().trim_start_matches('[')
+    } else {
+        host.split(':').next().unwrap()
+    };
+    parts
+}
+
+/// Browser families recognized by `ua_family`, checked in order. Several browsers include the
+/// tokens of the ones they're derived from, so the more specific tokens come first.
+const UA_FAMILIES: &[(&str, &str)] = &[
+    ("bot", "Bot"),
+    ("crawler", "Bot"),
+    ("spider", "Bot"),
+    ("curl/", "curl"),
+    ("wget/", "Wget"),
+    ("python-requests/", "python-requests"),
+    ("edg/", "Edge"),
+    ("edge/", "Edge"),
+    ("opr/", "Opera"),
+    ("opera", "Opera"),
+    ("samsungbrowser/", "Samsung Internet"),
+    ("firefox/", "Firefox"),
+    ("fxios/", "Firefox"),
+    ("chromium/", "Chromium"),
+    ("chrome/", "Chrome"),
+    ("crios/", "Chrome"),
+    ("msie ", "IE"),
+    ("trident/", "IE"),
+    ("safari/", "Safari"),
+];
+
+/// Classifies a user-agent string into a browser (or client) family such as `Chrome`, `Firefox`
+/// or `Bot`, or `Other` if it's not recognized
+pub fn ua_family(ua: &str) -> &'static str {
+    let ua = ua.to_ascii_lowercase();
+    if ua.trim().is_empty() {
+        return "Other";
+    }
+    UA_FAMILIES.iter()
+        .find(|(token, _)| ua.contains(token))
+        .map_or("Other", |(_, family)| family)
+}
+
+#[cfg(test)]
+mod test_split_url {
+    use super::*;
+
+    #[test]
+    fn test_full() {
+        let parts = split_url("https://user:pw@example.com:8080/a/b.html?x=1&y=2#top");
+        assert_eq!(parts, UrlParts {
+            scheme: "https", host: "example.com", path: "/a/b.html", query: "x=1&y=2" });
+    }
+    #[test]
+    fn test_partial() {
+        assert_eq!(split_url("http://example.com"),
+                   UrlParts { scheme: "http", host: "example.com", path: "", query: "" });
+        assert_eq!(split_url("\"//cdn.example.com/x.js\""),
+                   UrlParts { scheme: "", host: "cdn.example.com", path: "/x.js", query: "" });
+        assert_eq!(split_url("/search?q=csv"),
+                   UrlParts { scheme: "", host: "", path: "/search", query: "q=csv" });
+        assert_eq!(split_url("http://[::1]:8080/"),
+                   UrlParts { scheme: "http", host: "::1", path: "/", query: "" });
+    }
+}
+
+#[cfg(test)]
+mod test_ua_family {
+    use super::*;
+
+    #[test]
+    fn test_browsers() {
+        assert_eq!(ua_family("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
+                              (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36"), "Chrome");
+        assert_eq!(ua_family("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
+                              (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36 Edg/120.0"),
+                   "Edge");
+        assert_eq!(ua_family("Mozilla/5.0 (X11; Linux x86_64; rv:121.0) Gecko/20100101 \
+                              Firefox/121.0"), "Firefox");
+        assert_eq!(ua_family("Mozilla/5.0 (Macintosh; Intel Mac OS X 14_2) AppleWebKit/605.1.15 \
+                              (KHTML, like Gecko) Version/17.2 Safari/605.1.15"), "Safari");
+        assert_eq!(ua_family("Mozilla/5.0 (compatible; MSIE 10.0; Windows NT 6.2)"), "IE");
+    }
+    #[test]
+    fn test_clients() {
+        assert_eq!(ua_family("Mozilla/5.0 (compatible; Googlebot/2.1)"), "Bot");
+        assert_eq!(ua_family("curl/8.4.0"), "curl");
+        assert_eq!(ua_family("something else"), "Other");
+        assert_eq!(ua_family(""), "Other");
+    }
+}
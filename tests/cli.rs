@@ -0,0 +1,468 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+
+/// Runs ccut with `args`, feeding it `input` on stdin
+fn ccut(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ccut"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // ccut may exit on a usage error before reading any of it
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    child.wait_with_output().unwrap()
+}
+
+/// Runs ccut with `args`, feeding it `input` on stdin but leaving stdin open, and returns its
+/// stdout once it exits by itself, or `None` if it's still waiting for more input after 10s
+fn ccut_open_stdin(args: &[&str], input: &str) -> Option<String> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ccut"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(input.as_bytes()).unwrap();
+    stdin.flush().unwrap();
+    for _ in 0..1000 {
+        if child.try_wait().unwrap().is_some() {
+            return Some(stdout(&child.wait_with_output().unwrap()));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    child.kill().unwrap();
+    None
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+const CHECKED: &str = "a,1\nb,7\nc,8\nd,4\ne,9\n";
+
+#[test]
+fn test_max_errors_holds_back_output() {
+    let output = ccut(&["--check", "col2 < 5", "--max-errors", "2", "1"], CHECKED);
+    assert!(!output.status.success());
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).contains("aborting: more than 2 bad rows"));
+}
+
+#[test]
+fn test_max_errors_drops_bad_rows() {
+    let output = ccut(&["--check", "col2 < 5", "--max-errors", "3", "1"], CHECKED);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "a\nd\n");
+}
+
+#[test]
+fn test_plan_checks_header() {
+    let dir = tempfile::tempdir().unwrap();
+    let (plan, data, moved) = (dir.path().join("p.json"), dir.path().join("a.csv"),
+                               dir.path().join("b.csv"));
+    std::fs::write(&data, "id,name,age\n1,a,30\n").unwrap();
+    std::fs::write(&moved, "id,email,name,age\n1,x,a,30\n").unwrap();
+    let (plan, data, moved) = (plan.to_str().unwrap(), data.to_str().unwrap(),
+                               moved.to_str().unwrap());
+    assert!(ccut(&["--header", "--emit-plan", plan, "1,3", data], "").status.success());
+    assert_eq!(stdout(&ccut(&["--plan", plan, data], "")), "id,age\n1,30\n");
+    let output = ccut(&["--plan", plan, moved], "");
+    assert!(!output.status.success());
+    assert!(!stdout(&output).contains("1,a"));
+}
+
+#[test]
+fn test_plan_rejects_other_options() {
+    let dir = tempfile::tempdir().unwrap();
+    let plan = dir.path().join("p.json");
+    let plan = plan.to_str().unwrap();
+    assert!(ccut(&["--emit-plan", plan, "1"], "").status.success());
+    let output = ccut(&["--plan", plan, "2"], "a,b\n");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("--plan already selects the columns"));
+    let output = ccut(&["--plan", plan, "--where", "col1 > 1"], "a,b\n");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("can't be combined with --where"));
+}
+
+#[test]
+fn test_digest_only() {
+    let input = "a,b,c\n\"d,e\",f,g\n";
+    let output = stdout(&ccut(&["3,1"], input));
+    let digest = stdout(&ccut(&["--digest-only", "sha256", "3,1"], input));
+    assert_eq!(digest, format!("{:x}\n", Sha256::digest(output.as_bytes())));
+}
+
+#[test]
+fn test_digest_only_unsupported() {
+    let output = ccut(&["--digest-only", "md5", "1"], "a\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(stderr(&output), "ccut: --digest-only: unsupported digest algorithm md5, \
+                                 expected sha256\n");
+}
+
+#[test]
+fn test_options_table_matches_parser() {
+    // argparse shows a metavar after the names of the options that take a value
+    let help = stdout(&ccut(&["--help"], ""));
+    let mut seen = 0;
+    for line in help.lines().filter(|l| l.starts_with("  -") && !l.starts_with("  -h,")) {
+        let mut words = line.split_whitespace();
+        let names: Vec<&str> = words.next().unwrap().split(',').collect();
+        let metavar = words.next()
+            .is_some_and(|w| w.chars().all(|c| c.is_ascii_uppercase() || c == '_'));
+        let option = ccut::options::find(names[0]).unwrap_or_else(|| panic!("{}", line));
+        assert_eq!(option.names, &names[..], "{}", line);
+        assert_eq!(option.takes_value, metavar, "{}", line);
+        seen += 1;
+    }
+    assert_eq!(seen, ccut::options::OPTIONS.len());
+}
+
+#[test]
+fn test_negative_col_lists() {
+    let ragged = "a,b,c\n1,2,3,4\n";
+    assert_eq!(stdout(&ccut(&["-1"], ragged)), "c\n4\n");
+    assert_eq!(stdout(&ccut(&["-3--1", "-"], ragged)), "a,b,c\n2,3,4\n");
+    assert_eq!(stdout(&ccut(&["--skip", "1", "-2"], ragged)), "3\n");
+    // Followed by a column list, -1 is still --one
+    assert_eq!(stdout(&ccut(&["-1", "2"], ragged)), "b\n2\n");
+    assert_eq!(stdout(&ccut(&["-0", "-1"], ragged)), "c\n4\n");
+}
+
+#[test]
+fn test_read_errors_go_to_stderr() {
+    for args in [&["1", "missing.csv"][..], &["--estimate", "1", "missing.csv"],
+                 &["--preview", "missing.csv"], &["--stats", "1", "missing.csv"]].iter() {
+        let output = ccut(args, "");
+        assert!(!output.status.success(), "{:?}", args);
+        assert_eq!(stdout(&output), "", "{:?}", args);
+        assert!(stderr(&output).contains("No such file"), "{:?}", args);
+    }
+}
+
+#[test]
+fn test_output_errors_are_not_read_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let out_dir = dir.path().to_str().unwrap();
+    let args = ["--partition-by", "1", "--out-dir", out_dir, "1,2"];
+    assert!(ccut(&args, "a,1\n").status.success());
+    let output = ccut(&args, "a,1\n");
+    assert!(!output.status.success());
+    assert!(stderr(&output).starts_with("Error while writing output: "), "{}", stderr(&output));
+    assert!(stderr(&output).contains("a.csv already exists"));
+}
+
+#[test]
+fn test_flush_writes_each_line() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ccut"))
+        .args(["--flush", "2"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let (send, receive) = mpsc::channel();
+    std::thread::spawn(move || {
+        while let Some(Ok(line)) = lines.next() {
+            if send.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    // Each line comes out while stdin is still open, rather than when the buffer fills
+    for (input, cut) in [("a,1\n", "1"), ("b,2\n", "2")].iter() {
+        stdin.write_all(input.as_bytes()).unwrap();
+        stdin.flush().unwrap();
+        assert_eq!(receive.recv_timeout(Duration::from_secs(10)).as_deref(), Ok(*cut));
+    }
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn test_sketches_filter_and_unquote() {
+    let input = "k,v\n\"a\",1\n a ,2\nb,3\n\"x,y\",9\n";
+    let output = ccut(&["--header", "--cardinality", "1"], input);
+    assert_eq!(stdout(&output), "3\n");
+    let output = ccut(&["--header", "--heavy-hitters", "1", "-w", "col2 > 1"], input);
+    assert_eq!(stdout(&output), "a,1,0\nb,1,0\n\"x,y\",1,0\n");
+}
+
+#[test]
+fn test_provenance_through_sort() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("x,y.csv");
+    std::fs::write(&path, "b,2\na,1\n").unwrap();
+    let path = path.to_str().unwrap();
+    // Spilled to disk between every line, the names still come out whole and quoted
+    let output = ccut(&["--provenance", "--sort", "1", "--sort-mem", "1", "1,2", path], "");
+    assert_eq!(stdout(&output), format!("a,1,\"{0}\",4,2\nb,2,\"{0}\",0,1\n", path));
+}
+
+#[test]
+fn test_where_number_against_text() {
+    let output = ccut(&["-w", "col2 > 5", "1"], "a,7\nb,N/A\nc,\nd,3\ne,10\n");
+    assert_eq!(stdout(&output), "a\ne\n");
+}
+
+#[test]
+fn test_threads_after_header_and_skip() {
+    let input: String = std::iter::once(String::from("n,s\n"))
+        .chain((1..=1000).map(|n| format!("{},x{}\n", n, n)))
+        .collect();
+    let single = stdout(&ccut(&["--header", "--skip", "3", "2,1"], &input));
+    let threaded = stdout(&ccut(&["--threads", "3", "--header", "--skip", "3", "2,1"], &input));
+    assert!(single.starts_with("s,n\nx4,4\n"));
+    assert_eq!(threaded, single);
+}
+
+#[test]
+fn test_threads_errors_name_threads() {
+    let output = ccut(&["--threads", "2", "--unique", "1"], "a\n");
+    assert!(stderr(&output).contains("can't be combined with --threads"), "{}", stderr(&output));
+    let output = ccut(&["--auto-shard", "--unique", "1"], "a\n");
+    assert!(stderr(&output).contains("can't be combined with --auto-shard"));
+}
+
+#[test]
+fn test_fpe_rejects_short_values() {
+    let run = |input: &str| Command::new(env!("CARGO_BIN_EXE_ccut"))
+        .args(["--fpe", "1:key=CCUT_TEST_KEY", "1"])
+        .env("CCUT_TEST_KEY", "secret")
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            child.stdin.take().unwrap().write_all(input.as_bytes())?;
+            child.wait_with_output()
+        })
+        .unwrap();
+    let output = run("555-0100\n");
+    assert!(output.status.success());
+    let encrypted = stdout(&output);
+    assert_eq!(encrypted.len(), 9);
+    assert_eq!(&encrypted[3..4], "-");
+    assert_ne!(encrypted, "555-0100\n");
+    let output = run("12\n");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("fewer than a million"));
+}
+
+const WIDE: &str = "id,ts_a,name,ts_b,status\n1,10,x,20,ok\n22,11,yyy,21,bad\n3,12,z,22,ok\n";
+
+#[test]
+fn test_preview_rows() {
+    let output = ccut(&["--preview-rows", "3"], WIDE);
+    assert_eq!(stdout(&output), "1 ,2   ,3   ,4   ,5\n\
+                                 id,ts_a,name,ts_b,status\n\
+                                 1 ,10  ,x   ,20  ,ok\n\
+                                 22,11  ,yyy ,21  ,bad\n");
+    let output = ccut(&["-p"], WIDE);
+    assert_eq!(stdout(&output), "1 ,2   ,3   ,4   ,5\nid,ts_a,name,ts_b,status\n");
+}
+
+#[test]
+fn test_regex_cols() {
+    let output = ccut(&["--header", "--regex-cols", "ts_.*|status"], WIDE);
+    assert_eq!(stdout(&output), "ts_a,ts_b,status\n10,20,ok\n11,21,bad\n12,22,ok\n");
+    // The whole name has to match
+    let output = ccut(&["--header", "--regex-cols", "ts"], WIDE);
+    assert!(!output.status.success());
+    assert!(stderr(&output).starts_with("Invalid options: no column name matches --regex-cols"));
+    // It doesn't turn on --header itself
+    let output = ccut(&["--regex-cols", "status"], WIDE);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("needs --header"));
+}
+
+#[test]
+fn test_regex_cols_fold_case() {
+    let input = "Id,Status\n1,ok\n";
+    let output = ccut(&["--header", "--regex-cols", "status", "--fold-case"], input);
+    assert_eq!(stdout(&output), "Status\nok\n");
+    let output = ccut(&["--header", "--regex-cols", "status"], input);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_header_only_and_dims() {
+    let output = ccut(&["--header-only"], WIDE);
+    assert_eq!(stdout(&output), "1\tid\n2\tts_a\n3\tname\n4\tts_b\n5\tstatus\n");
+    assert_eq!(stdout(&ccut(&["--dims"], WIDE)), "rows,columns\n4,5\n");
+    assert_eq!(stdout(&ccut(&["--header", "--dims"], WIDE)), "rows,columns\n3,5\n");
+    assert_eq!(stdout(&ccut(&["--dims", "--multiline"], "a,\"b\nc\"\nd,e\n")),
+               "rows,columns\n2,2\n");
+    // --header-only reads nothing past the first line
+    assert_eq!(ccut_open_stdin(&["--header-only"], "a,b\n1,2\n").as_deref(), Some("1\ta\n2\tb\n"));
+}
+
+#[test]
+fn test_find_column() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = |name: &str, text: &str| {
+        let path = dir.path().join(name);
+        std::fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_string()
+    };
+    let (a, b, c) = (file("a.csv", WIDE), file("b.csv", "x,\"Status_Code\"\n1,2\n"),
+                     file("c.csv", "q\n"));
+    let output = ccut(&["find-column", "status", &a, &b, &c], "");
+    assert!(output.status.success());
+    // Names are matched unquoted and in any case
+    assert_eq!(stdout(&output), format!("{},5,status\n{},2,Status_Code\n", a, b));
+    let output = ccut(&["find-column", "revenue", &a, &c], "");
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn test_seed_repeats_samples() {
+    let input: String = (1..=5000).map(|n| format!("{}\n", n)).collect();
+    for sample in [&["--sample", "0.05"][..], &["--sample-n", "20"]].iter() {
+        let run = |seed: &str| {
+            let args: Vec<&str> = sample.iter().cloned().chain(["--seed", seed, "1"]).collect();
+            stdout(&ccut(&args, &input))
+        };
+        assert_eq!(run("7"), run("7"), "{:?}", sample);
+        assert_ne!(run("7"), run("8"), "{:?}", sample);
+    }
+    // --preview-sample prints the seed it picked, which repeats the preview
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), &input).unwrap();
+    let path = file.path().to_str().unwrap();
+    let first = stdout(&ccut(&["--preview-sample", path], ""));
+    let seed = first.lines().find_map(|l| l.strip_prefix("# seed ")).unwrap();
+    assert_eq!(stdout(&ccut(&["--preview-sample", "--seed", seed, path], "")), first);
+}
+
+#[test]
+fn test_skip_and_limit() {
+    let output = ccut(&["--header", "--skip", "1", "-n", "1", "1,3"], WIDE);
+    assert_eq!(stdout(&output), "id,name\n22,yyy\n");
+    assert_eq!(stdout(&ccut(&["--skip", "2", "1"], WIDE)), "22\n3\n");
+    assert_eq!(stdout(&ccut(&["--limit", "0", "1"], WIDE)), "");
+    // The limit stops the run rather than reading to the end of the input
+    assert_eq!(ccut_open_stdin(&["-n", "2", "1"], "a\nb\nc\n").as_deref(), Some("a\nb\n"));
+}
+
+#[test]
+fn test_convert_to_tsv() {
+    let output = ccut(&["convert", "--from", "csv", "--to", "tsv"], "a,b\n\"x,1\",2\n");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "a\tb\nx,1\t2\n");
+}
+
+#[test]
+fn test_catalog() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a.csv");
+    std::fs::write(&a, "id,Status\n1,2\n").unwrap();
+    std::fs::write(dir.path().join("b.tsv"), "x\ty\n").unwrap();
+    let (a, dir_name) = (a.to_str().unwrap(), dir.path().to_str().unwrap());
+    let run = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_ccut"))
+        .args(args)
+        .env("CCUT_CATALOG", dir.path().join("catalog.json"))
+        .output()
+        .unwrap();
+    assert!(run(&["catalog", "add", a]).status.success());
+    assert_eq!(stdout(&run(&["catalog", "list"])),
+               format!("file,delimiter,columns\n{},\",\",2\n", a));
+    assert!(run(&["catalog", "scan", dir_name]).status.success());
+    let listed = stdout(&run(&["catalog", "list"]));
+    assert!(listed.contains("b.tsv,\t,2\n"), "{}", listed);
+    // Given no files, find-column searches every one in the catalog
+    let output = run(&["find-column", "--catalog", "status"]);
+    assert_eq!(stdout(&output), format!("{},2,Status\n", a));
+}
+
+#[test]
+fn test_help() {
+    let output = ccut(&["help"], "");
+    assert!(output.status.success());
+    for topic in ["columns", "dialects", "examples"].iter() {
+        assert!(stdout(&output).contains(&format!("  {} ", topic)), "{}", topic);
+        let output = ccut(&["help", topic], "");
+        assert!(output.status.success(), "{}", topic);
+        assert!(!stdout(&output).is_empty(), "{}", topic);
+    }
+    // Options are described from the same table the parser registers them from
+    assert!(stdout(&ccut(&["help", "columns"], "")).contains("--regex-cols"));
+    assert!(stdout(&ccut(&["help", "dialects"], "")).contains("rfc4180"));
+    let output = ccut(&["help", "nope"], "");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Unknown help topic nope"));
+}
+
+#[test]
+fn test_lang() {
+    let output = ccut(&["--lang", "es", "1", "missing.csv"], "");
+    assert!(!output.status.success());
+    assert!(stderr(&output).starts_with("Error al leer la entrada: missing.csv: "),
+            "{}", stderr(&output));
+    assert!(stderr(&output).trim_end().ends_with("[read-input]"));
+    let output = ccut(&["--lang", "en", "1", "missing.csv"], "");
+    assert!(stderr(&output).starts_with("Error while reading input: missing.csv: "));
+}
+
+#[test]
+fn test_errors_format_json() {
+    let output = ccut(&["--errors-format", "json", "1", "missing.csv"], "");
+    assert!(!output.status.success());
+    let diag: serde_json::Value = serde_json::from_str(stderr(&output).trim()).unwrap();
+    assert_eq!(diag["code"], "read-input");
+    assert!(diag["message"].as_str().unwrap().starts_with("missing.csv: "));
+    // Problems found in a row say where
+    let output = ccut(&["--errors-format", "json", "--ragged", "error", "1,2"], "a,b\nc\n");
+    assert!(!output.status.success());
+    let diag: serde_json::Value = serde_json::from_str(stderr(&output).trim()).unwrap();
+    assert_eq!(diag["code"], "ragged");
+    assert_eq!(diag["line"], 2);
+}
+
+#[test]
+fn test_freq_top() {
+    let input = "k\nc\na\nb\na\nd\na\nb\n";
+    let output = ccut(&["--header", "--freq", "1", "--top", "2"], input);
+    // Most frequent first, cut off after K, with shares of all the rows
+    assert_eq!(stdout(&output), "k,count,percent\na,3,42.86\nb,2,28.57\n");
+    let output = ccut(&["--header", "--freq", "1"], input);
+    assert_eq!(stdout(&output).lines().count(), 5);
+}
+
+#[test]
+fn test_sort_numeric_reverse() {
+    let input = "n,x\n9,a\n10,b\n100,c\n2,d\n10,e\n";
+    for mem in ["1M", "1"].iter() {
+        let args = ["--header", "--sort", "1", "--numeric", "--reverse", "--sort-mem", mem, "1,2"];
+        // Ties keep their input order, also descending
+        assert_eq!(stdout(&ccut(&args, input)), "n,x\n100,c\n10,b\n10,e\n9,a\n2,d\n", "{}", mem);
+    }
+    // Without --numeric, numbers are compared as text
+    assert_eq!(stdout(&ccut(&["--sort", "1", "--reverse", "1"], "9\n10\n100\n2\n")),
+               "9\n2\n100\n10\n");
+}
+
+#[test]
+fn test_buffer_bytes() {
+    let input: String = (1..=2000).map(|n| format!("{},{}\n", n, "x".repeat(n % 50))).collect();
+    let default = ccut(&["2,1"], &input);
+    for n in ["1", "7", "64K"].iter() {
+        let output = ccut(&["--buffer-bytes", n, "2,1"], &input);
+        assert_eq!(stdout(&output), stdout(&default), "{}", n);
+    }
+    let output = ccut(&["--buffer-bytes", "0", "1"], "a\n");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("--buffer-bytes must be at least 1"));
+}